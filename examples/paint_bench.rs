@@ -0,0 +1,58 @@
+// Times filling a full-page opaque background one pixel at a time, the way the
+// canvas used to, and a row at a time, the way it does now
+//
+//   cargo run --release --example paint_bench [width] [height]
+use rust_browser::css::Color;
+use rust_browser::painter::Canvas;
+
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 20;
+
+fn time<F: Fn()>(f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        f();
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    let args: Vec<usize> = env::args().skip(1).map(|arg| arg.parse().expect("a number")).collect();
+    let width = args.first().cloned().unwrap_or(1920);
+    let height = args.get(1).cloned().unwrap_or(1080);
+
+    let color = Color { r: 0x33, g: 0x66, b: 0x99, a: 255 };
+
+    let per_pixel = time(|| {
+        let mut canvas = Canvas::new(width, height);
+        fill_per_pixel(&mut canvas, color);
+        black_box(canvas);
+    });
+    let rows = time(|| {
+        let mut canvas = Canvas::new(width, height);
+        fill_rows(&mut canvas, color);
+        black_box(canvas);
+    });
+    println!("{}x{} background", width, height);
+    println!("per pixel: {:?}", per_pixel);
+    println!("rows:      {:?} ({:.2}x)", rows, per_pixel.as_secs_f64() / rows.as_secs_f64());
+}
+
+// the old loop, a store per pixel through its index
+fn fill_per_pixel(canvas: &mut Canvas, color: Color) {
+    for y in 0..canvas.height {
+        for x in 0..canvas.width {
+            canvas.pixels[y * canvas.width + x] = color;
+        }
+    }
+}
+
+// what the canvas does for an opaque color now
+fn fill_rows(canvas: &mut Canvas, color: Color) {
+    for y in 0..canvas.height {
+        canvas.pixels[y * canvas.width..(y + 1) * canvas.width].fill(color);
+    }
+}
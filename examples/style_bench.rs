@@ -0,0 +1,59 @@
+// Times sequential and parallel style tree construction on a generated DOM
+//
+//   cargo run --release --features parallel --example style_bench [sections] [items]
+use rust_browser::{css, html, style};
+
+use std::env;
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 5;
+
+fn generate(sections: usize, items: usize) -> String {
+    let mut source = String::from("<html><body>");
+    for s in 0..sections {
+        source.push_str(&format!("<section id=s{} class=\"box c{}\"><h2>Section {}</h2><ul>", s, s % 7, s));
+        for i in 0..items {
+            source.push_str(&format!(
+                "<li class=\"item i{}\"><a href=\"#{}\">link <b>{}</b></a> text</li>",
+                i % 5, i, i
+            ));
+        }
+        source.push_str("</ul></section>");
+    }
+    source.push_str("</body></html>");
+    source
+}
+
+const CSS: &str = "
+    body { margin: 8px; font-size: 16px; }
+    section { padding: 4px; border: 1px solid #cccccc; }
+    .c3 h2 { color: #ff0000; font-size: 2em; }
+    ul li { margin: 2px 0; }
+    .i2 a b { font-weight: bold; }
+    section .item a { color: #0000ff; }
+    .box .i4 { background: #eeeeee; }
+";
+
+fn time<F: Fn()>(f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        f();
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    let args: Vec<usize> = env::args().skip(1).map(|arg| arg.parse().expect("a number")).collect();
+    let sections = args.first().cloned().unwrap_or(200);
+    let items = args.get(1).cloned().unwrap_or(50);
+
+    let root = html::parse(generate(sections, items));
+    let stylesheet = css::parse(CSS.to_string());
+    let media = css::MediaContext::default();
+
+    let sequential = time(|| { style::style_tree_with_media(&root, &stylesheet, &media); });
+    let parallel = time(|| { style::par_style_tree_with_media(&root, &stylesheet, &media); });
+    println!("{} sections x {} items", sections, items);
+    println!("sequential: {:?}", sequential);
+    println!("parallel:   {:?} ({:.2}x)", parallel, sequential.as_secs_f64() / parallel.as_secs_f64());
+}
@@ -0,0 +1,110 @@
+// Interned strings for tag and attribute names
+//
+// Every distinct name is allocated once and shared, so comparing or hashing two
+// atoms is a pointer comparison. Interned names are never freed, which is fine
+// for the small vocabulary of tag and attribute names.
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::{fmt, hash, ops};
+
+#[derive(Clone)]
+pub struct Atom(Arc<str>);
+
+static ATOMS: Mutex<Option<HashSet<Arc<str>>>> = Mutex::new(None);
+
+impl Atom {
+    pub fn new(s: &str) -> Atom {
+        let mut atoms = ATOMS.lock().unwrap();
+        let atoms = atoms.get_or_insert_with(HashSet::new);
+        if let Some(atom) = atoms.get(s) {
+            return Atom(atom.clone());
+        }
+        let atom: Arc<str> = Arc::from(s);
+        atoms.insert(atom.clone());
+        Atom(atom)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ops::Deref for Atom {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Atom {
+    fn eq(&self, other: &Atom) -> bool {
+        // all atoms come from the interner, equal names share the allocation
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Atom {}
+
+impl hash::Hash for Atom {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const u8 as usize).hash(state)
+    }
+}
+
+impl PartialEq<str> for Atom {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Atom {
+    fn eq(&self, other: &&'a str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Atom {
+    fn eq(&self, other: &String) -> bool {
+        &*self.0 == other.as_str()
+    }
+}
+
+impl From<&str> for Atom {
+    fn from(s: &str) -> Atom {
+        Atom::new(s)
+    }
+}
+
+impl From<String> for Atom {
+    fn from(s: String) -> Atom {
+        Atom::new(&s)
+    }
+}
+
+impl fmt::Debug for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+#[test]
+fn test_atoms_are_shared() {
+    let a = Atom::from("div".to_string());
+    let b = Atom::new("div");
+    assert!(Arc::ptr_eq(&a.0, &b.0));
+    assert_eq!(a, b);
+    assert_eq!(a, "div");
+    assert_ne!(a, Atom::new("span"));
+    assert_ne!(a, Atom::new("DIV"));
+
+    let mut set = HashSet::new();
+    set.insert(a);
+    assert!(set.contains(&b));
+}
@@ -0,0 +1,164 @@
+// Tree-construction conformance tests in the html5lib format
+// https://github.com/html5lib/html5lib-tests/tree/master/tree-construction
+//
+// Fixtures live in tests/html5lib/*.dat. Comments and doctypes are not kept in
+// the DOM, so their lines are dropped from the expected trees before comparing.
+use crate::dom::{Node, NodeType};
+use crate::html::{self, ParseOptions};
+use crate::dom;
+
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug)]
+struct TreeTest {
+    file: String,
+    line: usize,
+    data: String,
+    fragment: bool,
+    document: Vec<String>,
+}
+
+fn load_fixtures(dir: &Path) -> Vec<TreeTest> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap_or_else(|err| panic!("cannot read {}: {}", dir.display(), err))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "dat"))
+        .collect();
+    paths.sort();
+
+    let mut tests = Vec::new();
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+        let file = path.file_name().unwrap().to_string_lossy().into_owned();
+        tests.extend(parse_fixture(&file, &source));
+    }
+    tests
+}
+
+// a `#name` header and the raw lines under it
+type Section<'a> = (String, Vec<&'a str>);
+
+fn parse_fixture(file: &str, source: &str) -> Vec<TreeTest> {
+    // (line of #data, its sections)
+    let mut raw: Vec<(usize, Vec<Section>)> = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        if line == "#data" {
+            raw.push((i + 1, Vec::new()));
+        }
+        let sections = match raw.last_mut() {
+            Some(&mut (_, ref mut sections)) => sections,
+            None => continue,
+        };
+        if let Some(name) = line.strip_prefix('#') {
+            sections.push((name.to_string(), Vec::new()));
+        } else if let Some(section) = sections.last_mut() {
+            section.1.push(line);
+        }
+    }
+
+    raw.into_iter().map(|(line, sections)| {
+        let mut test = TreeTest {
+            file: file.to_string(),
+            line: line,
+            data: String::new(),
+            fragment: false,
+            document: Vec::new(),
+        };
+        for (name, mut lines) in sections {
+            match &*name {
+                "data" => test.data = lines.join("\n"),
+                "document-fragment" => test.fragment = true,
+                "document" => {
+                    // a blank line separates tests
+                    while lines.last() == Some(&"") {
+                        lines.pop();
+                    }
+                    for line in lines {
+                        if line.starts_with('|') {
+                            test.document.push(line.to_string());
+                        } else if let Some(last) = test.document.last_mut() {
+                            // continuation of a multi-line text node
+                            last.push('\n');
+                            last.push_str(line);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        test.document.retain(|line| {
+            let item = line.trim_start_matches('|').trim_start();
+            !item.starts_with("<!--") && !item.starts_with("<!DOCTYPE")
+        });
+        test
+    }).collect()
+}
+
+// the html5lib tree dump, one `| ` prefixed line per node or attribute
+fn dump(nodes: &[Node], depth: usize, out: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    for node in nodes {
+        match node.data {
+            NodeType::Text(ref body) => out.push(format!("| {}\"{}\"", indent, body)),
+            NodeType::Element(ref elem) => {
+                out.push(format!("| {}<{}>", indent, elem.tag_name));
+                let mut attrs: Vec<_> = elem.attrs.iter().collect();
+                attrs.sort_by(|a, b| a.0.cmp(&b.0));
+                for &(ref name, ref value) in attrs {
+                    out.push(format!("| {}  {}=\"{}\"", indent, name, value));
+                }
+                if let Some(ref content) = elem.template_content {
+                    out.push(format!("| {}  content", indent));
+                    dump(content, depth + 2, out);
+                }
+                dump(&node.children, depth + 1, out);
+            }
+        }
+    }
+}
+
+fn run(test: &TreeTest) -> Result<(), String> {
+    let options = ParseOptions {
+        whitespace: dom::WhitespaceMode::Preserve,
+        ..ParseOptions::default()
+    };
+    let nodes = if test.fragment {
+        html::parse_fragment(test.data.clone())
+    } else {
+        html::try_parse_with_options(test.data.clone(), options).map(|root| vec![root])
+    };
+    let nodes = nodes.map_err(|err| format!("parse error: {}", err))?;
+
+    let mut actual = Vec::new();
+    dump(&nodes, 0, &mut actual);
+    if actual == test.document {
+        Ok(())
+    } else {
+        Err(format!(
+            "expected:\n{}\nactual:\n{}",
+            test.document.join("\n"),
+            actual.join("\n")
+        ))
+    }
+}
+
+#[test]
+fn test_html5lib_tree_construction() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/html5lib");
+    let tests = load_fixtures(&dir);
+    assert!(!tests.is_empty(), "no fixtures in {}", dir.display());
+
+    let failures: Vec<String> = tests.iter()
+        .filter_map(|test| run(test).err().map(|err| {
+            format!("{}:{} {:?}\n{}", test.file, test.line, test.data, err)
+        }))
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{} of {} tree-construction tests failed\n\n{}",
+        failures.len(),
+        tests.len(),
+        failures.join("\n\n")
+    );
+}
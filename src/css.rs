@@ -1,24 +1,152 @@
+use crate::atom::Atom;
+use serde::{Deserialize, Serialize};
+use std::{error, fmt, ops};
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    pub font_faces: Vec<FontFace>,
+    pub keyframes: Vec<Keyframes>,
+}
+
+// https://www.w3.org/TR/css-animations-1/#keyframes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframes {
+    pub name: String,
+    pub frames: Vec<Keyframe>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    pub offsets: Vec<f64>, // 0.0 for `from` to 1.0 for `to`
+    pub declarations: Vec<Declaration>,
+}
+
+// one item of the transition-* lists
+// https://www.w3.org/TR/css-transitions-1/#transition-shorthand-property
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+    pub property: String, // "all" or a property name
+    pub duration: f64,    // seconds
+    pub timing_function: Value,
+    pub delay: f64,       // seconds
+}
+
+// one item of the animation-* lists
+// https://www.w3.org/TR/css-animations-1/#animation
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    pub name: String, // "none" or the name of a @keyframes rule
+    pub duration: f64,
+    pub timing_function: Value,
+    pub delay: f64,
+    pub iteration_count: f64, // infinite is f64::INFINITY
+    pub direction: String,
+    pub fill_mode: String,
+    pub paused: bool,
+}
+
+// https://www.w3.org/TR/css-fonts-3/#font-face-rule
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontFace {
+    pub family: String,
+    pub src: Vec<FontSource>, // in order of preference
+    pub weight: u16,
+    pub style: String, // normal, italic or oblique
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FontSource {
+    Url { url: String, format: Option<String> },
+    Local(String), // a font installed on the system
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    pub media: Vec<MediaList>, // enclosing @media blocks, all of them have to match
+    pub origin: Origin,
+    pub index: usize, // position in the stylesheet, later rules win ties
+}
+
+// a syntax error, `range` is the byte range of the source it was found at
+#[derive(Clone, Debug, PartialEq)]
+pub struct CssError {
+    pub range: ops::Range<usize>,
+    pub message: String,
+}
+
+type ParseResult<T> = Result<T, CssError>;
+
+// what insertRule and deleteRule throw
+// https://drafts.csswg.org/cssom/#dom-cssstylesheet-insertrule
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuleError {
+    IndexSize(usize), // the index is past the end of the rules
+    Syntax(CssError),
+}
+
+// later origins win, https://www.w3.org/TR/css-cascade-4/#cascading-origins
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    UserAgent,
+    User,
+    Author,
+}
+
+// https://www.w3.org/TR/mediaqueries-4/#media
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaList {
+    pub queries: Vec<MediaQuery>, // matches if any query does
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaQuery {
+    pub not: bool,
+    pub media_type: Option<String>, // None is `all`
+    pub features: Vec<MediaFeature>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaFeature {
+    pub name: String,
+    pub value: Option<Value>,
+}
+
+// what media queries are evaluated against
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaContext {
+    pub viewport_width: f64,
+    pub viewport_height: f64,
+    pub media_type: String,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Selector {
     Simple(SimpleSelector),
+    // `nav ul li`, the ancestors outermost first and then the element itself
+    // https://www.w3.org/TR/selectors-4/#descendant-combinators
+    Descendant(Vec<SimpleSelector>, SimpleSelector),
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct SimpleSelector {
-    pub tag_name: Option<String>,
+    pub tag_name: Option<Atom>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub universal: bool, // written with `*`, matches any element and adds no specificity
+    pub pseudo_classes: Vec<PseudoClass>,
+}
+
+// user action pseudo-classes, matched against the element state
+// https://www.w3.org/TR/selectors-4/#useraction-pseudos
+#[derive(Clone, Debug, PartialEq)]
+pub enum PseudoClass {
+    Hover,
+    Focus,
+    Active,
+    Unsupported(String), // as written after the first colon, `::before` too. never matches
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -31,17 +159,41 @@ pub struct Declaration {
 pub enum Value {
     Keyword(String),
     Length(f64, Unit),
+    Number(f64), // unitless, like font-weight: 700
     Color(Color),
+    String(String),
+    Url(String),
+    Function(String, Vec<Value>), // like format("woff2"), arguments as in List
+    List(Vec<Value>), // space separated components, commas are kept as Value::Comma
+    Comma,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// https://www.w3.org/TR/css-values-3/#lengths
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Unit {
     Px,
-    // Pt,
-    // Em,
+    Pt,
+    Em,
+    Rem,
+    Percent,
+    Vw,
+    Vh,
+    S,  // times are values with a unit too
+    Ms,
+}
+
+// what relative lengths are resolved against
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LengthContext {
+    pub font_size: f64,
+    pub root_font_size: f64,
+    pub percent_base: f64, // usually the width of the containing block
+    pub percent_height_base: Option<f64>, // the containing block height when it's definite
+    pub viewport_width: f64,
+    pub viewport_height: f64,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Default, Serialize, Deserialize)]
 pub struct Color {
     pub r: u8,
     pub b: u8,
@@ -49,130 +201,1236 @@ pub struct Color {
     pub a: u8,
 }
 
+impl Unit {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Unit::Px => "px",
+            Unit::Pt => "pt",
+            Unit::Em => "em",
+            Unit::Rem => "rem",
+            Unit::Percent => "%",
+            Unit::Vw => "vw",
+            Unit::Vh => "vh",
+            Unit::S => "s",
+            Unit::Ms => "ms",
+        }
+    }
+}
+
 impl Value {
+    // absolute lengths only, relative ones need a context
     pub fn to_px(&self) -> f64 {
         match *self {
             Value::Length(f, Unit::Px) => f,
+            Value::Length(f, Unit::Pt) => f * 96.0 / 72.0,
             _ => 0.0,
         }
     }
+
+    pub fn to_px_in(&self, ctx: &LengthContext) -> f64 {
+        match *self {
+            Value::Length(f, Unit::Em) => f * ctx.font_size,
+            Value::Length(f, Unit::Rem) => f * ctx.root_font_size,
+            Value::Length(f, Unit::Percent) => f * ctx.percent_base / 100.0,
+            Value::Length(f, Unit::Vw) => f * ctx.viewport_width / 100.0,
+            Value::Length(f, Unit::Vh) => f * ctx.viewport_height / 100.0,
+            _ => self.to_px(),
+        }
+    }
+
+    pub fn to_seconds(&self) -> f64 {
+        match *self {
+            Value::Length(f, Unit::S) => f,
+            Value::Length(f, Unit::Ms) => f / 1000.0,
+            _ => 0.0,
+        }
+    }
+
+    // lengths become px lengths, everything else is left alone
+    pub fn resolve(&self, ctx: &LengthContext) -> Value {
+        match *self {
+            Value::Length(..) => Value::Length(self.to_px_in(ctx), Unit::Px),
+            _ => self.clone(),
+        }
+    }
+}
+
+impl Default for MediaContext {
+    fn default() -> MediaContext {
+        MediaContext {
+            viewport_width: 800.0,
+            viewport_height: 600.0,
+            media_type: "screen".to_string(),
+        }
+    }
+}
+
+impl Rule {
+    pub fn applies_to(&self, ctx: &MediaContext) -> bool {
+        self.media.iter().all(|media| media.matches(ctx))
+    }
+
+    // https://drafts.csswg.org/cssom/#dom-cssstyledeclaration-getpropertyvalue
+    pub fn get_property(&self, name: &str) -> Option<&Value> {
+        self.declarations.iter().rev()
+            .find(|declaration| declaration.name == name)
+            .map(|declaration| &declaration.value)
+    }
+
+    // `value` is css text like "1px 2px", a shorthand sets all of its longhands.
+    // the declaration that counts, the last one, is changed in place so its
+    // position is kept and the ones before it go. an invalid value is ignored
+    // https://drafts.csswg.org/cssom/#dom-cssstyledeclaration-setproperty
+    pub fn set_property(&mut self, name: &str, value: &str) {
+        let (declarations, errors) = try_parse_declarations(&format!("{}: {}", name, value));
+        if !errors.is_empty() {
+            return;
+        }
+        for Declaration { name, value } in declarations {
+            match self.declarations.iter().rposition(|d| d.name == name) {
+                Some(last) => {
+                    self.declarations[last].value = value;
+                    let mut index = 0;
+                    self.declarations.retain(|d| {
+                        index += 1;
+                        index > last || d.name != name
+                    });
+                }
+                None => self.declarations.push(Declaration { name: name, value: value }),
+            }
+        }
+    }
+
+    // returns false if the rule didn't have the property
+    // https://drafts.csswg.org/cssom/#dom-cssstyledeclaration-removeproperty
+    pub fn remove_property(&mut self, name: &str) -> bool {
+        let mut names = longhands(name);
+        if names.is_empty() {
+            names.push(name.to_string());
+        }
+        let len = self.declarations.len();
+        self.declarations.retain(|declaration| !names.contains(&declaration.name));
+        self.declarations.len() != len
+    }
+}
+
+impl MediaList {
+    pub fn matches(&self, ctx: &MediaContext) -> bool {
+        self.queries.is_empty() || self.queries.iter().any(|query| query.matches(ctx))
+    }
+}
+
+impl MediaQuery {
+    pub fn matches(&self, ctx: &MediaContext) -> bool {
+        let type_matches = match self.media_type {
+            Some(ref media_type) => media_type == "all" || media_type.eq_ignore_ascii_case(&ctx.media_type),
+            None => true,
+        };
+        let result = type_matches && self.features.iter().all(|feature| feature.matches(ctx));
+        result != self.not
+    }
+}
+
+impl MediaFeature {
+    // https://www.w3.org/TR/mediaqueries-4/#mq-features, unknown features never match
+    pub fn matches(&self, ctx: &MediaContext) -> bool {
+        // relative lengths in media queries refer to the initial font size
+        let lengths = LengthContext {
+            font_size: 16.0,
+            root_font_size: 16.0,
+            percent_base: 0.0,
+            percent_height_base: None,
+            viewport_width: ctx.viewport_width,
+            viewport_height: ctx.viewport_height,
+        };
+        let (width, height) = (ctx.viewport_width, ctx.viewport_height);
+        let value = match self.value {
+            Some(ref value) => value,
+            // boolean context
+            None => return match &*self.name {
+                "width" => width > 0.0,
+                "height" => height > 0.0,
+                _ => false,
+            },
+        };
+        match (&*self.name, value) {
+            ("orientation", &Value::Keyword(ref orientation)) => match &*orientation.to_ascii_lowercase() {
+                "portrait" => height >= width,
+                "landscape" => width > height,
+                _ => false,
+            },
+            ("width", &Value::Length(..)) => width == value.to_px_in(&lengths),
+            ("min-width", &Value::Length(..)) => width >= value.to_px_in(&lengths),
+            ("max-width", &Value::Length(..)) => width <= value.to_px_in(&lengths),
+            ("height", &Value::Length(..)) => height == value.to_px_in(&lengths),
+            ("min-height", &Value::Length(..)) => height >= value.to_px_in(&lengths),
+            ("max-height", &Value::Length(..)) => height <= value.to_px_in(&lengths),
+            _ => false,
+        }
+    }
+}
+
+impl Default for Stylesheet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stylesheet {
+    pub fn new() -> Stylesheet {
+        Stylesheet {
+            rules: Vec::new(),
+            font_faces: Vec::new(),
+            keyframes: Vec::new(),
+        }
+    }
+
+    pub fn set_origin(&mut self, origin: Origin) {
+        for rule in &mut self.rules {
+            rule.origin = origin;
+        }
+    }
+
+    // inserts the rules in `css_text` before `index` and returns it, like CSSOM
+    // an @media block can give several rules. nothing is inserted when `index`
+    // is past the end or the text has a syntax error
+    // https://drafts.csswg.org/cssom/#dom-cssstylesheet-insertrule
+    pub fn insert_rule(&mut self, css_text: &str, index: usize) -> Result<usize, RuleError> {
+        if index > self.rules.len() {
+            return Err(RuleError::IndexSize(index));
+        }
+        let (inserted, mut errors) = try_parse(css_text);
+        if !errors.is_empty() {
+            return Err(RuleError::Syntax(errors.remove(0)));
+        }
+        let origin = self.rules.first().map_or(Origin::Author, |rule| rule.origin);
+        self.rules.splice(index..index, inserted.rules.into_iter().map(|mut rule| {
+            rule.origin = origin;
+            rule
+        }));
+        self.font_faces.extend(inserted.font_faces);
+        self.keyframes.extend(inserted.keyframes);
+        self.reindex();
+        Ok(index)
+    }
+
+    // https://drafts.csswg.org/cssom/#dom-cssstylesheet-deleterule
+    pub fn delete_rule(&mut self, index: usize) -> Result<Rule, RuleError> {
+        if index >= self.rules.len() {
+            return Err(RuleError::IndexSize(index));
+        }
+        let rule = self.rules.remove(index);
+        self.reindex();
+        Ok(rule)
+    }
+
+    // keep `Rule::index` in step with positions after rules move
+    fn reindex(&mut self) {
+        for (i, rule) in self.rules.iter_mut().enumerate() {
+            rule.index = i;
+        }
+    }
+
+    // `other` comes later in the cascade
+    pub fn append(&mut self, other: Stylesheet) {
+        let offset = self.rules.len();
+        for mut rule in other.rules {
+            rule.index += offset;
+            self.rules.push(rule);
+        }
+        self.font_faces.extend(other.font_faces);
+        self.keyframes.extend(other.keyframes);
+    }
+
+    // the last @keyframes rule with the name wins
+    pub fn keyframes(&self, name: &str) -> Option<&Keyframes> {
+        self.keyframes.iter().rev().find(|keyframes| keyframes.name == name)
+    }
+
+    // the @font-face for a family, preferring the same style and then the closest weight
+    // https://www.w3.org/TR/css-fonts-3/#font-style-matching
+    pub fn font_face(&self, family: &str, weight: u16, style: &str) -> Option<&FontFace> {
+        self.font_faces.iter()
+            .filter(|face| face.family.eq_ignore_ascii_case(family))
+            .min_by_key(|face| (face.style != style, (face.weight as i32 - weight as i32).abs()))
+    }
+}
+
+impl FontFace {
+    fn from_declarations(declarations: &[Declaration]) -> Option<FontFace> {
+        let mut face = FontFace {
+            family: String::new(),
+            src: Vec::new(),
+            weight: 400,
+            style: "normal".to_string(),
+        };
+        for declaration in declarations {
+            match (&*declaration.name, &declaration.value) {
+                ("font-family", family) => face.family = join_words(family),
+                ("src", src) => face.src = font_sources(src),
+                ("font-weight", &Value::Number(weight)) => face.weight = weight as u16,
+                ("font-weight", &Value::Keyword(ref keyword)) if keyword == "bold" => face.weight = 700,
+                ("font-style", &Value::Keyword(ref keyword)) => face.style = keyword.to_ascii_lowercase(),
+                _ => {}
+            }
+        }
+        // both descriptors are required
+        if face.family.is_empty() || face.src.is_empty() {
+            return None;
+        }
+        Some(face)
+    }
+}
+
+// the transitions in transition-* longhands, `lookup` gives the value of a
+// property, e.g. `|name| rule.get_property(name)`. the lists are as long as
+// transition-property, shorter ones repeat
+// https://www.w3.org/TR/css-transitions-1/#transitions
+pub fn transitions<'a, F: Fn(&str) -> Option<&'a Value>>(lookup: F) -> Vec<Transition> {
+    let list = |name: &str| lookup(name).map_or_else(Vec::new, split_commas);
+    let properties = list("transition-property");
+    let (durations, timings, delays) = (list("transition-duration"), list("transition-timing-function"), list("transition-delay"));
+    properties.iter().enumerate().map(|(i, property)| Transition {
+        property: join_words(&Value::List(property.to_vec())),
+        duration: repeated(&durations, i).map_or(0.0, Value::to_seconds),
+        timing_function: repeated(&timings, i).cloned().unwrap_or_else(|| Value::Keyword("ease".to_string())),
+        delay: repeated(&delays, i).map_or(0.0, Value::to_seconds),
+    }).collect()
+}
+
+// the animations in animation-* longhands, like `transitions`
+// https://www.w3.org/TR/css-animations-1/#animation-name
+pub fn animations<'a, F: Fn(&str) -> Option<&'a Value>>(lookup: F) -> Vec<Animation> {
+    let list = |name: &str| lookup(name).map_or_else(Vec::new, split_commas);
+    let names = list("animation-name");
+    let (durations, timings, delays) = (list("animation-duration"), list("animation-timing-function"), list("animation-delay"));
+    let (counts, directions) = (list("animation-iteration-count"), list("animation-direction"));
+    let (fill_modes, play_states) = (list("animation-fill-mode"), list("animation-play-state"));
+    let keyword = |values: &Vec<&[Value]>, i: usize, default: &str| {
+        repeated(values, i).map_or_else(|| default.to_string(), |v| join_words(v).to_ascii_lowercase())
+    };
+    names.iter().enumerate().map(|(i, name)| Animation {
+        name: join_words(&Value::List(name.to_vec())),
+        duration: repeated(&durations, i).map_or(0.0, Value::to_seconds),
+        timing_function: repeated(&timings, i).cloned().unwrap_or_else(|| Value::Keyword("ease".to_string())),
+        delay: repeated(&delays, i).map_or(0.0, Value::to_seconds),
+        iteration_count: match repeated(&counts, i) {
+            Some(&Value::Number(count)) => count,
+            Some(&Value::Keyword(ref keyword)) if keyword.eq_ignore_ascii_case("infinite") => f64::INFINITY,
+            _ => 1.0,
+        },
+        direction: keyword(&directions, i, "normal"),
+        fill_mode: keyword(&fill_modes, i, "none"),
+        paused: keyword(&play_states, i, "running") == "paused",
+    }).collect()
+}
+
+// item `i` of a list that repeats to fill, items are single values
+fn repeated<'a>(items: &[&'a [Value]], i: usize) -> Option<&'a Value> {
+    match items.len() {
+        0 => None,
+        len => items[i % len].first(),
+    }
+}
+
+// the items of a comma separated list
+fn split_commas(value: &Value) -> Vec<&[Value]> {
+    match *value {
+        Value::List(ref values) => values.split(|v| *v == Value::Comma).collect(),
+        ref single => vec![std::slice::from_ref(single)],
+    }
+}
+
+// the first item of a list as one name, unquoted names like Times New Roman are several keywords
+fn join_words(value: &Value) -> String {
+    let items = split_commas(value);
+    let words: Vec<&str> = items[0].iter().filter_map(|word| match *word {
+        Value::Keyword(ref s) | Value::String(ref s) => Some(s.as_str()),
+        _ => None,
+    }).collect();
+    words.join(" ")
+}
+
+// https://www.w3.org/TR/css-fonts-3/#src-desc
+fn font_sources(value: &Value) -> Vec<FontSource> {
+    split_commas(value).into_iter().filter_map(|source| match *source {
+        [Value::Url(ref url)] => Some(FontSource::Url { url: url.clone(), format: None }),
+        [Value::Url(ref url), Value::Function(ref name, ref args)] if name == "format" => Some(FontSource::Url {
+            url: url.clone(),
+            format: Some(join_words(&Value::List(args.clone()))),
+        }),
+        [Value::Function(ref name, ref args)] if name == "local" => {
+            Some(FontSource::Local(join_words(&Value::List(args.clone()))))
+        }
+        _ => None,
+    }).collect()
 }
 
 // https://www.w3.org/TR/selectors/#specificity
 pub type Specificity = (usize, usize, usize);
 
-impl Selector {
-    pub fn specificity(&self) -> Specificity {
-        let Selector::Simple(ref selector) = *self;
-        let a = selector.id.iter().count();
-        let b = selector.class.len();
-        let c = selector.tag_name.iter().count();
-        (a, b, c)
+impl Selector {
+    pub fn specificity(&self) -> Specificity {
+        match *self {
+            Selector::Simple(ref selector) => selector.specificity(),
+            Selector::Descendant(ref ancestors, ref selector) => {
+                ancestors.iter().map(SimpleSelector::specificity).fold(selector.specificity(), |(a, b, c), (x, y, z)| {
+                    (a + x, b + y, c + z)
+                })
+            }
+        }
+    }
+}
+
+impl SimpleSelector {
+    pub fn specificity(&self) -> Specificity {
+        let a = self.id.iter().count();
+        let b = self.class.len() + self.pseudo_classes.len();
+        let c = self.tag_name.iter().count();
+        (a, b, c)
+    }
+}
+
+// Serialization back to CSS text
+// https://www.w3.org/TR/cssom-1/#serializing-css-values
+
+impl fmt::Display for CssError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at bytes {}..{}", self.message, self.range.start, self.range.end)
+    }
+}
+
+impl error::Error for CssError {}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RuleError::IndexSize(index) => write!(f, "rule index {} is out of bounds", index),
+            RuleError::Syntax(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for RuleError {}
+
+impl fmt::Display for Stylesheet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for face in &self.font_faces {
+            write!(f, "{}", face)?;
+        }
+        for keyframes in &self.keyframes {
+            write!(f, "{}", keyframes)?;
+        }
+        for rule in &self.rules {
+            write!(f, "{}", rule)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FontFace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "@font-face {{")?;
+        writeln!(f, "  font-family: {};", quote_string(&self.family))?;
+        let src: Vec<String> = self.src.iter().map(|source| match *source {
+            FontSource::Url { ref url, format: Some(ref format) } => {
+                format!("url({}) format({})", quote_string(url), quote_string(format))
+            }
+            FontSource::Url { ref url, format: None } => format!("url({})", quote_string(url)),
+            FontSource::Local(ref name) => format!("local({})", quote_string(name)),
+        }).collect();
+        writeln!(f, "  src: {};", src.join(", "))?;
+        writeln!(f, "  font-weight: {};", self.weight)?;
+        writeln!(f, "  font-style: {};", self.style)?;
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Keyframes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "@keyframes {} {{", quote_string(&self.name))?;
+        for frame in &self.frames {
+            let offsets: Vec<String> = frame.offsets.iter().map(|offset| format!("{}%", offset * 100.0)).collect();
+            writeln!(f, "  {} {{", offsets.join(", "))?;
+            for declaration in &frame.declarations {
+                writeln!(f, "    {}: {};", declaration.name, declaration.value)?;
+            }
+            writeln!(f, "  }}")?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+// rules from @media blocks get their own block, nested as in the source
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let indent = "  ".repeat(self.media.len());
+        for (depth, media) in self.media.iter().enumerate() {
+            writeln!(f, "{}@media {} {{", "  ".repeat(depth), media)?;
+        }
+        let selectors: Vec<String> = self.selectors.iter().map(|s| s.to_string()).collect();
+        writeln!(f, "{}{} {{", indent, selectors.join(", "))?;
+        for declaration in &self.declarations {
+            writeln!(f, "{}  {}: {};", indent, declaration.name, declaration.value)?;
+        }
+        writeln!(f, "{}}}", indent)?;
+        for depth in (0..self.media.len()).rev() {
+            writeln!(f, "{}}}", "  ".repeat(depth))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for MediaList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.queries.is_empty() {
+            return write!(f, "all");
+        }
+        for (i, query) in self.queries.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", query)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for MediaQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if self.not {
+            // `not` needs a media type
+            parts.push(format!("not {}", self.media_type.as_ref().map_or("all", |t| t.as_str())));
+        } else if let Some(ref media_type) = self.media_type {
+            parts.push(media_type.clone());
+        }
+        for feature in &self.features {
+            parts.push(match feature.value {
+                Some(ref value) => format!("({}: {})", feature.name, value),
+                None => format!("({})", feature.name),
+            });
+        }
+        write!(f, "{}", parts.join(" and "))
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Selector::Simple(ref selector) => write!(f, "{}", selector),
+            Selector::Descendant(ref ancestors, ref selector) => {
+                for ancestor in ancestors {
+                    write!(f, "{} ", ancestor)?;
+                }
+                write!(f, "{}", selector)
+            }
+        }
+    }
+}
+
+impl fmt::Display for SimpleSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.tag_name {
+            Some(ref tag_name) => write!(f, "{}", tag_name)?,
+            None if self.universal || (self.id.is_none() && self.class.is_empty() && self.pseudo_classes.is_empty()) => {
+                write!(f, "*")?
+            }
+            None => {}
+        }
+        if let Some(ref id) = self.id {
+            write!(f, "#{}", id)?;
+        }
+        for class in &self.class {
+            write!(f, ".{}", class)?;
+        }
+        for pseudo_class in &self.pseudo_classes {
+            write!(f, ":{}", pseudo_class.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl PseudoClass {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            PseudoClass::Hover => "hover",
+            PseudoClass::Focus => "focus",
+            PseudoClass::Active => "active",
+            PseudoClass::Unsupported(ref name) => name,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Keyword(ref s) => write!(f, "{}", s),
+            Value::Length(n, unit) => write!(f, "{}{}", n, unit.as_str()),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Color(ref c) if c.a == 255 => write!(f, "#{:02x}{:02x}{:02x}", c.r, c.g, c.b),
+            Value::Color(ref c) => write!(f, "#{:02x}{:02x}{:02x}{:02x}", c.r, c.g, c.b, c.a),
+            Value::String(ref s) => write!(f, "{}", quote_string(s)),
+            Value::Url(ref url) => write!(f, "url({})", quote_string(url)),
+            Value::Function(ref name, ref args) => write!(f, "{}({})", name, Value::List(args.clone())),
+            Value::List(ref values) => {
+                for (i, value) in values.iter().enumerate() {
+                    // no space before a comma
+                    if i > 0 && *value != Value::Comma {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                Ok(())
+            }
+            Value::Comma => write!(f, ","),
+        }
+    }
+}
+
+fn quote_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '\n' => result.push_str("\\a "),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+// the syntax errors go to stderr, the rules around them are kept
+pub fn parse(source: String) -> Stylesheet {
+    let (stylesheet, errors) = try_parse(&source);
+    for err in errors {
+        eprintln!("css: {}", err);
+    }
+    stylesheet
+}
+
+// like `parse` but hands back every syntax error along with the stylesheet.
+// invalid declarations and rules are skipped the way browsers do, so one error
+// only loses what it is in
+// https://www.w3.org/TR/css-syntax-3/#error-handling
+pub fn try_parse(source: &str) -> (Stylesheet, Vec<CssError>) {
+    let mut parser = Parser::new(source.to_string());
+    let mut stylesheet = Stylesheet::new();
+    parser.parse_rule_list(&mut stylesheet, false);
+    (stylesheet, parser.errors)
+}
+
+// a selector list on its own, the argument of querySelector
+// https://drafts.csswg.org/selectors-4/#parse-selector
+pub fn parse_selectors(source: &str) -> Result<Vec<Selector>, CssError> {
+    let mut parser = Parser::new(source.trim().to_string());
+    let selectors = parser.parse_selectors()?;
+    match parser.peek_char() {
+        Some(c) => Err(parser.error_at_char(format!("unexpected '{}' in selector list", c))),
+        None => Ok(selectors),
+    }
+}
+
+// the contents of a style="..." attribute, a declaration list without braces.
+// invalid declarations are skipped and reported to stderr
+// https://drafts.csswg.org/css-style-attr/#syntax
+pub fn parse_declarations(source: String) -> Vec<Declaration> {
+    let (declarations, errors) = try_parse_declarations(&source);
+    for err in errors {
+        eprintln!("css: {}", err);
+    }
+    declarations
+}
+
+// like `parse_declarations` but hands back the errors
+pub fn try_parse_declarations(source: &str) -> (Vec<Declaration>, Vec<CssError>) {
+    let mut parser = Parser::new(source.to_string());
+    let mut declarations = Vec::new();
+    loop {
+        parser.consume_whitespace();
+        if parser.eof() {
+            break;
+        }
+        match parser.parse_declaration() {
+            Ok(parsed) => declarations.extend(parsed),
+            Err(err) => {
+                parser.errors.push(err);
+                parser.skip_declaration();
+                // there is no block here for a stray closing brace to end
+                if parser.peek_char() == Some('}') {
+                    parser.consume_char();
+                }
+            }
+        }
+    }
+    (declarations, parser.errors)
+}
+
+// the longhands a shorthand sets, empty for anything else
+fn longhands(name: &str) -> Vec<String> {
+    if let Some(sides) = box_longhands(name) {
+        return sides.to_vec();
+    }
+    if let Some(sides) = border_sides(name) {
+        return sides.iter()
+            .flat_map(|side| ["width", "style", "color"].iter().map(move |part| format!("border-{}-{}", side, part)))
+            .collect();
+    }
+    let suffixes = match name {
+        "background" => BACKGROUND_LONGHANDS,
+        _ => match timing_longhands(name) {
+            Some(suffixes) => suffixes,
+            None => return Vec::new(),
+        },
+    };
+    suffixes.iter().map(|suffix| format!("{}-{}", name, suffix)).collect()
+}
+
+// top, right, bottom and left longhands of margin, padding and border-width, -style and -color,
+// for border-radius the corners clockwise from the top left
+fn box_longhands(name: &str) -> Option<[String; 4]> {
+    let longhand = |side: &str| match name {
+        "margin" | "padding" => format!("{}-{}", name, side),
+        _ => format!("border-{}{}", side, &name["border".len()..]),
+    };
+    match name {
+        "margin" | "padding" | "border-width" | "border-style" | "border-color" => {
+            Some([longhand("top"), longhand("right"), longhand("bottom"), longhand("left")])
+        }
+        "border-radius" => Some([longhand("top-left"), longhand("top-right"), longhand("bottom-right"), longhand("bottom-left")]),
+        _ => None,
+    }
+}
+
+// margin, padding and border-width/style/color take 1 to 4 values for top, right, bottom, left
+// https://www.w3.org/TR/CSS2/box.html#propdef-margin
+fn expand_box_shorthand(name: &str, values: &[Value]) -> Option<Vec<Declaration>> {
+    let [top_name, right_name, bottom_name, left_name] = box_longhands(name)?;
+    let (top, right, bottom, left) = match *values {
+        [ref all] => (all, all, all, all),
+        [ref vertical, ref horizontal] => (vertical, horizontal, vertical, horizontal),
+        [ref top, ref horizontal, ref bottom] => (top, horizontal, bottom, horizontal),
+        [ref top, ref right, ref bottom, ref left] => (top, right, bottom, left),
+        _ => panic!("{} takes 1 to 4 values", name),
+    };
+    Some(vec![
+        Declaration { name: top_name, value: top.clone() },
+        Declaration { name: right_name, value: right.clone() },
+        Declaration { name: bottom_name, value: bottom.clone() },
+        Declaration { name: left_name, value: left.clone() },
+    ])
+}
+
+// transition and animation take a comma separated list, each item sets one
+// entry of every longhand list. the first time is the duration, the second the delay
+// https://www.w3.org/TR/css-transitions-1/#transition-shorthand-property
+// https://www.w3.org/TR/css-animations-1/#animation
+fn expand_timing_shorthand(name: &str, values: &[Value]) -> Option<Vec<Declaration>> {
+    let longhands = timing_longhands(name)?;
+    let keyword = |s: &str| Value::Keyword(s.to_string());
+    let mut lists: Vec<Vec<Value>> = vec![Vec::new(); longhands.len()];
+    for (i, item) in values.split(|value| *value == Value::Comma).enumerate() {
+        let mut entry: Vec<Option<Value>> = vec![None; longhands.len()];
+        for value in item {
+            let slot = match *value {
+                Value::Length(_, Unit::S) | Value::Length(_, Unit::Ms) => if entry[1].is_none() { 1 } else { 3 },
+                Value::Function(ref f, _) if f == "cubic-bezier" || f == "steps" => 2,
+                Value::Keyword(ref k) => match &*k.to_ascii_lowercase() {
+                    "ease" | "linear" | "ease-in" | "ease-out" | "ease-in-out" | "step-start" | "step-end" => 2,
+                    _ if name == "transition" => 0,
+                    "infinite" => 4,
+                    "normal" | "reverse" | "alternate" | "alternate-reverse" => 5,
+                    "forwards" | "backwards" | "both" => 6,
+                    "none" if entry[0].is_some() => 6,
+                    "running" | "paused" => 7,
+                    _ => 0,
+                },
+                Value::Number(_) if name == "animation" => 4,
+                _ => 0,
+            };
+            entry[slot] = Some(value.clone());
+        }
+        let initial = [
+            keyword(if name == "transition" { "all" } else { "none" }),
+            Value::Length(0.0, Unit::S),
+            keyword("ease"),
+            Value::Length(0.0, Unit::S),
+            Value::Number(1.0),
+            keyword("normal"),
+            keyword("none"),
+            keyword("running"),
+        ];
+        for (slot, value) in entry.into_iter().enumerate() {
+            if i > 0 {
+                lists[slot].push(Value::Comma);
+            }
+            lists[slot].push(value.unwrap_or_else(|| initial[slot].clone()));
+        }
+    }
+    Some(longhands.iter().zip(lists).map(|(longhand, mut list)| Declaration {
+        name: format!("{}-{}", name, longhand),
+        value: if list.len() == 1 { list.remove(0) } else { Value::List(list) },
+    }).collect())
+}
+
+fn border_sides(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        "border" => Some(&["top", "right", "bottom", "left"]),
+        "border-top" => Some(&["top"]),
+        "border-right" => Some(&["right"]),
+        "border-bottom" => Some(&["bottom"]),
+        "border-left" => Some(&["left"]),
+        _ => None,
+    }
+}
+
+// `border: 2px solid #000` in any order, for all sides or one of them. what is
+// left out gets its initial value: medium, none and currentcolor
+// https://www.w3.org/TR/css-backgrounds-3/#border-shorthands
+fn expand_border_shorthand(name: &str, values: &[Value]) -> Option<Vec<Declaration>> {
+    let sides = border_sides(name)?;
+    let keyword = |s: &str| Value::Keyword(s.to_string());
+    let mut width = Value::Length(3.0, Unit::Px);
+    let mut style = keyword("none");
+    let mut color = keyword("currentcolor");
+    for value in values {
+        match *value {
+            Value::Length(..) | Value::Number(_) => width = value.clone(),
+            Value::Keyword(ref k) => match &*k.to_ascii_lowercase() {
+                // https://www.w3.org/TR/css-backgrounds-3/#line-width
+                "thin" => width = Value::Length(1.0, Unit::Px),
+                "medium" => width = Value::Length(3.0, Unit::Px),
+                "thick" => width = Value::Length(5.0, Unit::Px),
+                "none" | "hidden" | "dotted" | "dashed" | "solid" | "double"
+                | "groove" | "ridge" | "inset" | "outset" => style = value.clone(),
+                _ => color = value.clone(),
+            },
+            _ => color = value.clone(),
+        }
+    }
+    let mut declarations = Vec::new();
+    for side in sides {
+        for &(part, value) in [("width", &width), ("style", &style), ("color", &color)].iter() {
+            declarations.push(Declaration {
+                name: format!("border-{}-{}", side, part),
+                value: value.clone(),
+            });
+        }
+    }
+    Some(declarations)
+}
+
+fn timing_longhands(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        "transition" => Some(&["property", "duration", "timing-function", "delay"]),
+        "animation" => Some(&[
+            "name", "duration", "timing-function", "delay",
+            "iteration-count", "direction", "fill-mode", "play-state",
+        ]),
+        _ => None,
+    }
+}
+
+const BACKGROUND_LONGHANDS: &[&str] = &["color", "image", "repeat", "position", "clip"];
+
+// `background: #fff url(x.png) no-repeat center` in any order. comma separated
+// layers fill the image, repeat, position and clip lists, only the last one has
+// a color. of two boxes the first is the origin, which isn't supported
+// https://www.w3.org/TR/css-backgrounds-3/#background
+fn expand_background_shorthand(name: &str, values: &[Value]) -> Option<Vec<Declaration>> {
+    if name != "background" {
+        return None;
+    }
+    let keyword = |s: &str| Value::Keyword(s.to_string());
+    let mut color = keyword("transparent");
+    // image, repeat, position and clip
+    let mut lists: Vec<Vec<Value>> = vec![Vec::new(); 4];
+    for (i, layer) in values.split(|value| *value == Value::Comma).enumerate() {
+        let mut entry: Vec<Vec<Value>> = vec![Vec::new(); 4];
+        for value in layer {
+            match *value {
+                Value::Color(_) => color = value.clone(),
+                Value::Url(_) => entry[0].push(value.clone()),
+                Value::Function(ref f, _) if f.ends_with("gradient") => entry[0].push(value.clone()),
+                Value::Length(..) | Value::Number(_) => entry[2].push(value.clone()),
+                Value::Keyword(ref k) => match &*k.to_ascii_lowercase() {
+                    "none" => entry[0].push(value.clone()),
+                    "repeat" | "repeat-x" | "repeat-y" | "no-repeat" | "space" | "round" => entry[1].push(value.clone()),
+                    "left" | "right" | "top" | "bottom" | "center" => entry[2].push(value.clone()),
+                    "border-box" | "padding-box" | "content-box" => entry[3] = vec![value.clone()],
+                    _ => color = value.clone(), // named colors
+                },
+                _ => {}
+            }
+        }
+        let initial = [
+            vec![keyword("none")],
+            vec![keyword("repeat")],
+            vec![Value::Length(0.0, Unit::Percent), Value::Length(0.0, Unit::Percent)],
+            vec![keyword("border-box")],
+        ];
+        for (slot, values) in entry.into_iter().enumerate() {
+            if i > 0 {
+                lists[slot].push(Value::Comma);
+            }
+            lists[slot].extend(if values.is_empty() { initial[slot].clone() } else { values });
+        }
+    }
+    let mut declarations = vec![Declaration { name: "background-color".to_string(), value: color }];
+    for (suffix, mut list) in BACKGROUND_LONGHANDS[1..].iter().zip(lists) {
+        declarations.push(Declaration {
+            name: format!("background-{}", suffix),
+            value: if list.len() == 1 { list.remove(0) } else { Value::List(list) },
+        });
+    }
+    Some(declarations)
+}
+
+// rgb(255, 0, 0) and rgba(100%, 0%, 0%, 0.5), either takes the alpha
+// https://www.w3.org/TR/css-color-4/#rgb-functions
+fn rgb_function(name: &str, args: &[Value]) -> Option<Color> {
+    if name != "rgb" && name != "rgba" {
+        return None;
+    }
+    let args: Vec<&Value> = args.iter().filter(|arg| **arg != Value::Comma).collect();
+    let channel = |value: &Value, max: f64| match *value {
+        Value::Number(n) => Some(n.max(0.0).min(max) / max),
+        Value::Length(percent, Unit::Percent) => Some(percent.clamp(0.0, 100.0) / 100.0),
+        _ => None,
+    };
+    let alpha = match args.len() {
+        3 => 1.0,
+        4 => channel(args[3], 1.0)?,
+        _ => return None,
+    };
+    let byte = |fraction: f64| (fraction * 255.0).round() as u8;
+    Some(Color {
+        r: byte(channel(args[0], 255.0)?),
+        g: byte(channel(args[1], 255.0)?),
+        b: byte(channel(args[2], 255.0)?),
+        a: byte(alpha),
+    })
+}
+
+fn valid_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' // TODO: char codes
+}
+
+#[derive(Clone, Debug)]
+struct Parser {
+    pos: usize,
+    input: String,
+    errors: Vec<CssError>, // the ones recovered from
+}
+
+impl Parser {
+    fn new(input: String) -> Parser {
+        Parser {
+            pos: 0,
+            input: input,
+            errors: Vec::new(),
+        }
+    }
+
+    // rules up to the end of the input, or up to the closing brace when `nested`
+    fn parse_rule_list(&mut self, stylesheet: &mut Stylesheet, nested: bool) {
+        loop {
+            self.consume_whitespace();
+            if self.eof() {
+                break;
+            }
+            if self.peek_char() == Some('}') {
+                if nested {
+                    self.consume_char();
+                    break;
+                }
+                let err = self.error_at_char("unexpected '}'".to_string());
+                self.errors.push(err);
+                self.consume_char();
+                continue;
+            }
+            if let Err(err) = self.parse_rule_or_at_rule(stylesheet) {
+                self.errors.push(err);
+                self.skip_block();
+            }
+        }
+    }
+
+    fn parse_rule_or_at_rule(&mut self, stylesheet: &mut Stylesheet) -> ParseResult<()> {
+        if self.peek_char() != Some('@') {
+            let mut rule = self.parse_rule()?;
+            rule.index = stylesheet.rules.len();
+            stylesheet.rules.push(rule);
+            return Ok(());
+        }
+        self.consume_char();
+        match &*self.parse_identifier().to_ascii_lowercase() {
+            "media" => self.parse_media_rule(stylesheet)?,
+            "font-face" => {
+                self.consume_whitespace();
+                let declarations = self.parse_declarations()?;
+                stylesheet.font_faces.extend(FontFace::from_declarations(&declarations));
+            }
+            "keyframes" => stylesheet.keyframes.push(self.parse_keyframes()?),
+            _ => self.skip_at_rule(),
+        }
+        Ok(())
+    }
+
+    fn parse_rule(&mut self) -> ParseResult<Rule> {
+        Ok(Rule {
+            selectors: self.parse_selectors()?,
+            declarations: self.parse_declarations()?,
+            media: Vec::new(),
+            origin: Origin::Author,
+            index: 0,
+        })
+    }
+
+    // the nested rules are tagged with the media list
+    fn parse_media_rule(&mut self, stylesheet: &mut Stylesheet) -> ParseResult<()> {
+        let media = self.parse_media_list()?;
+        self.expect_char('{')?;
+        let first = stylesheet.rules.len();
+        self.parse_rule_list(stylesheet, true);
+        for rule in &mut stylesheet.rules[first..] {
+            rule.media.insert(0, media.clone());
+        }
+        Ok(())
     }
-}
 
-// TODO: implement fmt::Diaplay
-pub fn show_css(stylesheet: &Stylesheet) {
-    for rule in &stylesheet.rules {
-        for (i, selector) in rule.selectors.iter().enumerate() {
-            let &Selector::Simple(ref selector) = selector;
-            if let Some(ref id) = selector.id {
-                print!("#{}", id);
-            } else if let Some(ref tag_name) = selector.tag_name {
-                print!("{}", tag_name);
-                for class in &selector.class {
-                    print!(".{}", class);
+    // https://www.w3.org/TR/css-animations-1/#keyframes
+    fn parse_keyframes(&mut self) -> ParseResult<Keyframes> {
+        self.consume_whitespace();
+        let name = match self.peek_char() {
+            Some('"') | Some('\'') => self.parse_string(),
+            _ => self.parse_identifier(),
+        };
+        if name.is_empty() {
+            return Err(self.error_at_char("expected a @keyframes name".to_string()));
+        }
+        self.consume_whitespace();
+        self.expect_char('{')?;
+        let mut frames = Vec::new();
+        loop {
+            self.consume_whitespace();
+            match self.peek_char() {
+                Some('}') => {
+                    self.consume_char();
+                    break;
                 }
-            }
-            if i != rule.selectors.len() -1 {
-                print!(", ");
+                Some(_) => frames.push(Keyframe {
+                    offsets: self.parse_keyframe_selectors()?,
+                    declarations: self.parse_declarations()?,
+                }),
+                None => return Err(self.error_at_char("unclosed @keyframes".to_string())),
             }
         }
-        println!(" {{");
-        for declaration in &rule.declarations {
-            println!(
-                "  {}: {};", 
-                declaration.name,
-                match declaration.value {
-                    Value::Keyword(ref s) => s.clone(),
-                    Value::Length(ref f, Unit::Px) => format!("{}px", f),
-                    Value::Color(ref c) => {
-                        format!("rgba({}, {}, {}, {})", c.r, c.g, c.b, c.a)
-                    }
+        Ok(Keyframes {
+            name: name,
+            frames: frames,
+        })
+    }
+
+    // `from`, `to` and percentages
+    fn parse_keyframe_selectors(&mut self) -> ParseResult<Vec<f64>> {
+        let mut offsets = Vec::new();
+        loop {
+            self.consume_whitespace();
+            let start = self.pos;
+            let offset = match self.peek_char() {
+                Some('0'..='9') => match self.parse_length()? {
+                    Value::Length(percent, Unit::Percent) if percent <= 100.0 => percent / 100.0,
+                    _ => return Err(self.error_from(start, "expected a percentage".to_string())),
+                },
+                _ => match &*self.parse_identifier().to_ascii_lowercase() {
+                    "from" => 0.0,
+                    "to" => 1.0,
+                    _ => return Err(self.error_from(start, "expected a keyframe selector".to_string())),
+                },
+            };
+            offsets.push(offset);
+            self.consume_whitespace();
+            match self.peek_char() {
+                Some(',') => {
+                    self.consume_char();
                 }
-            );
+                _ => return Ok(offsets),
+            }
         }
-        println!("}}");
     }
-}
 
-pub fn parse(source: String) -> Stylesheet {
-    let mut parser = Parser {
-        pos: 0,
-        input: source,
-    };
-    Stylesheet {
-        rules: parser.parse_rules(),
+    // https://www.w3.org/TR/mediaqueries-4/#mq-syntax
+    fn parse_media_list(&mut self) -> ParseResult<MediaList> {
+        let mut queries = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() || self.peek_char() == Some('{') {
+                break;
+            }
+            queries.push(self.parse_media_query()?);
+            self.consume_whitespace();
+            if self.peek_char() == Some(',') {
+                self.consume_char();
+            }
+        }
+        Ok(MediaList { queries: queries })
     }
-}
-
-fn valid_ident_char(c: char) -> bool {
-    c.is_alphanumeric() || c == '-' || c == '_' // TODO: char codes
-}
-
-#[derive(Clone, Debug)]
-struct Parser {
-    pos: usize,
-    input: String,
-}
 
-impl Parser {
-    fn parse_rules(&mut self) -> Vec<Rule> {
-        let mut rules = Vec::new();
+    fn parse_media_query(&mut self) -> ParseResult<MediaQuery> {
+        let mut query = MediaQuery {
+            not: false,
+            media_type: None,
+            features: Vec::new(),
+        };
         loop {
+            self.consume_whitespace();
+            let c = match self.peek_char() {
+                Some(c) => c,
+                None => break,
+            };
+            match c {
+                '(' => query.features.push(self.parse_media_feature()?),
+                c if valid_ident_char(c) => match &*self.parse_identifier().to_ascii_lowercase() {
+                    "not" => query.not = true,
+                    "only" | "and" => {}
+                    media_type => query.media_type = Some(media_type.to_string()),
+                },
+                ',' | '{' => break,
+                c => return Err(self.error_at_char(format!("unexpected '{}' in media query", c))),
+            }
+        }
+        Ok(query)
+    }
+
+    fn parse_media_feature(&mut self) -> ParseResult<MediaFeature> {
+        self.expect_char('(')?;
+        self.consume_whitespace();
+        let name = self.parse_identifier().to_ascii_lowercase();
+        self.consume_whitespace();
+        let mut value = None;
+        if self.peek_char() == Some(':') {
+            self.consume_char();
             self.consume_whitespace();
             if self.eof() {
-                break;
+                return Err(self.error_at_char(format!("{} has no value", name)));
+            }
+            value = Some(self.parse_value()?);
+            self.consume_whitespace();
+        }
+        self.expect_char(')')?;
+        Ok(MediaFeature {
+            name: name,
+            value: value,
+        })
+    }
+
+    // unsupported at-rules end at a semicolon or with their block
+    fn skip_at_rule(&mut self) {
+        let mut depth = 0;
+        while !self.eof() {
+            match self.consume_char() {
+                ';' if depth == 0 => break,
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // after an invalid rule: skip to the end of its block, a closing brace of
+    // the enclosing block is left alone
+    fn skip_block(&mut self) {
+        let mut depth = 0;
+        while let Some(c) = self.peek_char() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => break,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.consume_char();
+                        break;
+                    }
+                }
+                _ => {}
             }
-            rules.push(self.parse_rule());
+            self.consume_char();
         }
-        rules
     }
 
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+    // after an invalid declaration: skip past the next semicolon, stopping at the
+    // closing brace of the block. nested brackets are skipped whole
+    fn skip_declaration(&mut self) {
+        let mut depth = 0;
+        while let Some(c) = self.peek_char() {
+            match c {
+                ';' if depth == 0 => {
+                    self.consume_char();
+                    break;
+                }
+                '}' if depth == 0 => break,
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' if depth > 0 => depth -= 1,
+                _ => {}
+            }
+            self.consume_char();
         }
     }
 
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    fn parse_selectors(&mut self) -> ParseResult<Vec<Selector>> {
         let mut selectors = Vec::new();
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
-            self.consume_whitespace();
-            match self.next_char() {
-                ',' => {
+            // whitespace between compound selectors is the descendant combinator
+            let mut compounds = vec![self.parse_simple_selector()?];
+            loop {
+                let start = self.pos;
+                self.consume_whitespace();
+                match self.peek_char() {
+                    Some(',') | Some('{') | None => break,
+                    Some(_) if self.pos > start => compounds.push(self.parse_simple_selector()?),
+                    Some(_) => break,
+                }
+            }
+            let selector = compounds.pop().unwrap();
+            selectors.push(match compounds.len() {
+                0 => Selector::Simple(selector),
+                _ => Selector::Descendant(compounds, selector),
+            });
+            match self.peek_char() {
+                Some(',') => {
                     self.consume_char();
                     self.consume_whitespace();
                 },
-                '{' => break,
-                c => panic!("Unexpected character {} in selector list", c),
+                Some('{') | None => break,
+                Some(c) => return Err(self.error_at_char(format!("unexpected '{}' in selector list", c))),
             }
         }
         // Sort out selectors by secificity highest order ()
-        selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-        selectors
+        selectors.sort_by_key(|selector| std::cmp::Reverse(selector.specificity()));
+        Ok(selectors)
     }
 
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
+    fn parse_simple_selector(&mut self) -> ParseResult<SimpleSelector> {
+        let start = self.pos;
         let mut selector = SimpleSelector {
             tag_name: None,
             id: None,
             class: Vec::new(),
+            universal: false,
+            pseudo_classes: Vec::new(),
         };
-        while !self.eof() {
-            match self.next_char() {
+        while let Some(c) = self.peek_char() {
+            match c {
                 '#' => {
                     self.consume_char();
                     selector.id = Some(self.parse_identifier());
@@ -182,98 +1440,363 @@ impl Parser {
                     selector.class.push(self.parse_identifier());
                 }
                 '*' => {
-                    self.consume_char(); // universal selector
+                    self.consume_char();
+                    selector.universal = true;
+                }
+                ':' => {
+                    let colon = self.pos;
+                    self.consume_char();
+                    let start = self.pos;
+                    // a pseudo-element
+                    if self.peek_char() == Some(':') {
+                        self.consume_char();
+                    }
+                    if self.parse_identifier().is_empty() {
+                        return Err(self.error_from(colon, "expected a pseudo-class name".to_string()));
+                    }
+                    // the argument of :nth-child(2n+1) and the like
+                    if self.peek_char() == Some('(') {
+                        let mut depth = 0;
+                        while let Some(c) = self.peek_char() {
+                            self.consume_char();
+                            match c {
+                                '(' => depth += 1,
+                                ')' if depth == 1 => break,
+                                ')' => depth -= 1,
+                                _ => {}
+                            }
+                        }
+                    }
+                    // the rule is kept when it has pseudo-classes and pseudo-elements
+                    // that aren't supported, they just don't match
+                    let name = &self.input[start..self.pos];
+                    selector.pseudo_classes.push(match &*name.to_ascii_lowercase() {
+                        "hover" => PseudoClass::Hover,
+                        "focus" => PseudoClass::Focus,
+                        "active" => PseudoClass::Active,
+                        _ => PseudoClass::Unsupported(name.to_string()),
+                    });
                 }
                 c if valid_ident_char(c) => {
-                    selector.tag_name = Some(self.parse_identifier());
+                    // html tag names are lowercased, keep type selectors comparable
+                    selector.tag_name = Some(Atom::from(self.parse_identifier().to_ascii_lowercase()));
                 }
                 _ => break,
             }
         }
-        selector
+        if self.pos == start {
+            return Err(self.error_at_char("expected a selector".to_string()));
+        }
+        Ok(selector)
     }
 
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
-        assert_eq!(self.consume_char(), '{');
+    // an unclosed block ends with the input, that is reported but the
+    // declarations are kept
+    fn parse_declarations(&mut self) -> ParseResult<Vec<Declaration>> {
+        self.expect_char('{')?;
         let mut declarations = Vec::new();
         loop {
             self.consume_whitespace();
-            if self.next_char() == '}' {
+            if self.eof() {
+                let err = self.error_at_char("unclosed block".to_string());
+                self.errors.push(err);
+                break;
+            }
+            if self.peek_char() == Some('}') {
                 self.consume_char();
                 break;
             }
-            // if self.eof() {
-            //     panic!("Unclosed { found");
-            // }
-            declarations.push(self.parse_declaration());
+            match self.parse_declaration() {
+                Ok(parsed) => declarations.extend(parsed),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.skip_declaration();
+                }
+            }
         }
-        declarations
+        Ok(declarations)
     }
 
-    fn parse_declaration(&mut self) -> Declaration {
+    // a shorthand comes back as its longhands
+    fn parse_declaration(&mut self) -> ParseResult<Vec<Declaration>> {
+        let start = self.pos;
         let name = self.parse_identifier();
+        if name.is_empty() {
+            return Err(self.error_at_char("expected a property name".to_string()));
+        }
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), ':');
-        self.consume_whitespace();
-        let value = self.parse_value();
+        self.expect_char(':')?;
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), ';');
+        let mut values = self.parse_component_values(|c| c == ';' || c == '}')?;
+        if values.is_empty() {
+            return Err(self.error_from(start, format!("{} has no value", name)));
+        }
+        if box_longhands(&name).is_some() && values.len() > 4 {
+            return Err(self.error_from(start, format!("{} takes 1 to 4 values", name)));
+        }
+        // the semicolon after the last declaration is optional
+        if !self.eof() && self.peek_char() != Some('}') {
+            self.expect_char(';')?;
+        }
 
-        Declaration {
+        if let Some(longhands) = expand_box_shorthand(&name, &values) {
+            return Ok(longhands);
+        }
+        if let Some(longhands) = expand_timing_shorthand(&name, &values) {
+            return Ok(longhands);
+        }
+        if let Some(longhands) = expand_background_shorthand(&name, &values) {
+            return Ok(longhands);
+        }
+        if let Some(longhands) = expand_border_shorthand(&name, &values) {
+            return Ok(longhands);
+        }
+        Ok(vec![Declaration {
             name: name,
-            value: value,
+            value: match values.len() {
+                1 => values.remove(0),
+                _ => Value::List(values),
+            },
+        }])
+    }
+
+    // whitespace and comma separated values up to (not including) `end`
+    fn parse_component_values<F>(&mut self, end: F) -> ParseResult<Vec<Value>>
+    where
+        F: Fn(char) -> bool,
+    {
+        let mut values = Vec::new();
+        while let Some(c) = self.peek_char().filter(|&c| !end(c)) {
+            if c == ',' {
+                self.consume_char();
+                values.push(Value::Comma);
+                self.consume_whitespace();
+                continue;
+            }
+            let start = self.pos;
+            values.push(self.parse_value()?);
+            if self.pos == start {
+                return Err(self.error_at_char(format!("unexpected '{}' in value", c)));
+            }
+            self.consume_whitespace();
+        }
+        Ok(values)
+    }
+
+    fn parse_value(&mut self) -> ParseResult<Value> {
+        Ok(match self.peek_char() {
+            None => return Err(self.error_at_char("expected a value but found the end of input".to_string())),
+            _ if self.starts_number() => self.parse_length()?,
+            Some('#') => self.parse_color()?,
+            Some('"') | Some('\'') => Value::String(self.parse_string()),
+            _ => {
+                let ident = self.parse_identifier();
+                if ident.eq_ignore_ascii_case("url") && self.peek_char() == Some('(') {
+                    Value::Url(self.parse_url()?)
+                } else if self.peek_char() == Some('(') {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    let args = self.parse_component_values(|c| c == ')' || c == ';' || c == '}')?;
+                    self.expect_char(')')?;
+                    let name = ident.to_ascii_lowercase();
+                    match rgb_function(&name, &args) {
+                        Some(color) => Value::Color(color),
+                        None => Value::Function(name, args),
+                    }
+                } else {
+                    Value::Keyword(ident)
+                }
+            }
+        })
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-string-token
+    fn parse_string(&mut self) -> String {
+        let quote = self.consume_char();
+        let mut result = String::new();
+        while !self.eof() {
+            match self.consume_char() {
+                c if c == quote => break,
+                '\\' => {
+                    if let Some(c) = self.parse_escape() {
+                        result.push(c);
+                    }
+                }
+                c => result.push(c),
+            }
+        }
+        result
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-url-token
+    fn parse_url(&mut self) -> ParseResult<String> {
+        self.expect_char('(')?;
+        self.consume_whitespace();
+        let url = match self.peek_char() {
+            Some('"') | Some('\'') => self.parse_string(),
+            _ => {
+                let mut url = String::new();
+                while self.peek_char().is_some_and(|c| c != ')' && !c.is_whitespace()) {
+                    match self.consume_char() {
+                        '\\' => url.extend(self.parse_escape()),
+                        c => url.push(c),
+                    }
+                }
+                url
+            }
+        };
+        self.consume_whitespace();
+        self.expect_char(')')?;
+        Ok(url)
+    }
+
+    // after a backslash: up to 6 hex digits and one optional whitespace, or a
+    // literal character, an escaped newline is a line continuation
+    // https://www.w3.org/TR/css-syntax-3/#consume-escaped-code-point
+    fn parse_escape(&mut self) -> Option<char> {
+        match self.peek_char() {
+            None => return Some('\u{fffd}'),
+            Some(c) if !c.is_ascii_hexdigit() => {
+                self.consume_char();
+                return if c == '\n' { None } else { Some(c) };
+            }
+            _ => {}
+        }
+        let mut hex = String::new();
+        while hex.len() < 6 && self.peek_char().is_some_and(|c| c.is_ascii_hexdigit()) {
+            hex.push(self.consume_char());
         }
+        if self.peek_char().is_some_and(char::is_whitespace) {
+            self.consume_char();
+        }
+        let code = u32::from_str_radix(&hex, 16).unwrap();
+        Some(match code {
+            0 | 0xd800..=0xdfff => '\u{fffd}',
+            _ => std::char::from_u32(code).unwrap_or('\u{fffd}'),
+        })
     }
 
-    fn parse_value(&mut self) -> Value {
-        match self.next_char() {
-            '0'..='9' => self.parse_length(),
-            '#' => self.parse_color(),
-            _ => Value::Keyword(self.parse_identifier()),
+    // a number without a unit stays a number
+    fn parse_length(&mut self) -> ParseResult<Value> {
+        let f = self.parse_float()?;
+        if !self.peek_char().is_some_and(|c| c == '%' || c.is_alphabetic()) {
+            return Ok(Value::Number(f));
         }
+        Ok(Value::Length(f, self.parse_unit()?))
     }
 
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    // https://www.w3.org/TR/css-syntax-3/#starts-with-a-number
+    fn starts_number(&self) -> bool {
+        let mut chars = self.input[self.pos..].chars();
+        let mut c = chars.next();
+        if c == Some('+') || c == Some('-') {
+            c = chars.next();
+        }
+        match c {
+            Some('0'..='9') => true,
+            Some('.') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+            _ => false,
+        }
     }
 
-    fn parse_float(&mut self) -> f64 {
-        let f = self.consume_while(|c| match c {
+    fn parse_float(&mut self) -> ParseResult<f64> {
+        let start = self.pos;
+        if self.peek_char() == Some('+') || self.peek_char() == Some('-') {
+            self.consume_char();
+        }
+        self.consume_while(|c| match c {
             '0'..='9' | '.' => true,
             _ => false,
         });
-        f.parse().unwrap()
+        let f = &self.input[start..self.pos];
+        f.parse().map_err(|_| self.error_from(start, format!("invalid number {}", f)))
     }
 
-    fn parse_unit(&mut self) -> Unit {
-        match &*self.parse_identifier().to_ascii_lowercase() {
+    fn parse_unit(&mut self) -> ParseResult<Unit> {
+        if self.peek_char() == Some('%') {
+            self.consume_char();
+            return Ok(Unit::Percent);
+        }
+        let start = self.pos;
+        Ok(match &*self.parse_identifier().to_ascii_lowercase() {
             "px" => Unit::Px,
-            _ => panic!("unrecognized unit"),
+            "pt" => Unit::Pt,
+            "em" => Unit::Em,
+            "rem" => Unit::Rem,
+            "vw" => Unit::Vw,
+            "vh" => Unit::Vh,
+            "s" => Unit::S,
+            "ms" => Unit::Ms,
+            unit => return Err(self.error_from(start, format!("unrecognized unit {}", unit))),
+        })
+    }
+
+    // #rgb, #rgba, #rrggbb and #rrggbbaa
+    // https://www.w3.org/TR/css-color-4/#hex-notation
+    fn parse_color(&mut self) -> ParseResult<Value> {
+        let start = self.pos;
+        self.expect_char('#')?;
+        let hex = self.consume_while(|c| c.is_ascii_hexdigit());
+        let digits: Vec<u8> = hex.chars().map(|c| c.to_digit(16).unwrap() as u8).collect();
+        let channels: Vec<u8> = match digits.len() {
+            3 | 4 => digits.iter().map(|d| d * 0x11).collect(),
+            6 | 8 => digits.chunks(2).map(|pair| pair[0] * 16 + pair[1]).collect(),
+            _ => return Err(self.error_from(start, format!("invalid hex color #{}", hex))),
+        };
+        Ok(Value::Color(Color {
+            r: channels[0],
+            g: channels[1],
+            b: channels[2],
+            a: channels.get(3).cloned().unwrap_or(255),
+        }))
+    }
+
+    // an error covering the source from `start` to here, without trailing whitespace
+    fn error_from(&self, start: usize, message: String) -> CssError {
+        let end = start + self.input[start..self.pos].trim_end().len();
+        CssError {
+            range: start..end,
+            message: message,
         }
     }
 
-    fn parse_color(&mut self) -> Value {
-        assert_eq!(self.consume_char(), '#');
-        Value::Color(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
-            a: 255,
-        })
+    // an error covering the next character, or the empty range at the end
+    fn error_at_char(&self, message: String) -> CssError {
+        let len = self.peek_char().map_or(0, char::len_utf8);
+        CssError {
+            range: self.pos..self.pos + len,
+            message: message,
+        }
     }
 
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s = &self.input[self.pos..self.pos+2];
-        self.pos += 2;
-        u8::from_str_radix(s, 16).unwrap()
+    fn expect_char(&mut self, expected: char) -> ParseResult<()> {
+        match self.peek_char() {
+            Some(c) if c == expected => {
+                self.consume_char();
+                Ok(())
+            }
+            Some(c) => Err(self.error_at_char(format!("expected '{}' but found '{}'", expected, c))),
+            None => Err(self.error_at_char(format!("expected '{}' but found the end of input", expected))),
+        }
     }
 
     fn parse_identifier(&mut self) -> String {
         self.consume_while(valid_ident_char)
     }
 
+    // comments count as whitespace, an unterminated one runs to the end
+    // https://www.w3.org/TR/css-syntax-3/#consume-comments
     fn consume_whitespace(&mut self) {
-        self.consume_while(char::is_whitespace);
+        loop {
+            self.consume_while(char::is_whitespace);
+            if !self.input[self.pos..].starts_with("/*") {
+                break;
+            }
+            self.pos = match self.input[self.pos + 2..].find("*/") {
+                Some(end) => self.pos + 2 + end + 2,
+                None => self.input.len(),
+            };
+        }
     }
 
     fn consume_while<F>(&mut self, test: F) -> String
@@ -281,22 +1804,21 @@ impl Parser {
             F: Fn(char) -> bool,
     {
         let mut result = String::new();
-        while !self.eof() && test(self.next_char()) {
+        while self.peek_char().is_some_and(&test) {
             result.push(self.consume_char());
         }
         result
     }
 
+    // callers check for the end of input first
     fn consume_char(&mut self) -> char {
-        let mut iter = self.input[self.pos..].char_indices();
-        let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
-        self.pos += next_pos;
+        let cur_char = self.input[self.pos..].chars().next().unwrap();
+        self.pos += cur_char.len_utf8();
         cur_char
     }
 
-    fn next_char(&mut self) -> char {
-        self.input[self.pos..].chars().next().unwrap()
+    fn peek_char(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
     }
 
     fn eof(&self) -> bool {
@@ -315,11 +1837,16 @@ fn test_parse_css() {
                 Rule {
                     selectors: vec![
                         Selector::Simple(SimpleSelector {
-                            tag_name: Some("div".to_string()),
+                            tag_name: Some(Atom::from("div")),
                             id: None,
                             class: Vec::new(),
+                            universal: false,
+                            pseudo_classes: Vec::new(),
                         }),
                     ],
+                    media: Vec::new(),
+                    origin: Origin::Author,
+                    index: 0,
                     declarations: vec![
                         Declaration {
                             name: "width".to_string(),
@@ -350,6 +1877,421 @@ fn test_parse_css() {
                     ],
                 },
             ],
+            font_faces: Vec::new(),
+            keyframes: Vec::new(),
         }
     );
 }
+
+#[test]
+fn test_parse_declarations() {
+    let declarations = parse_declarations(" color: #ff0000; width: 10px ".to_string());
+    assert_eq!(declarations, vec![
+        Declaration {
+            name: "color".to_string(),
+            value: Value::Color(Color { r: 0xff, g: 0, b: 0, a: 0xff }),
+        },
+        Declaration {
+            name: "width".to_string(),
+            value: Value::Length(10.0, Unit::Px),
+        },
+    ]);
+    assert_eq!(parse("p { display: block }".to_string()).rules[0].declarations.len(), 1);
+
+    // the invalid ones are dropped, the ones around them kept
+    let (declarations, errors) = try_parse_declarations("foo: !!; width: 12qq; color: #12; height: 1px; \
+        margin: 1px 2px 3px 4px 5px; } top: 2px; background: url(a.png");
+    let names: Vec<&str> = declarations.iter().map(|declaration| declaration.name.as_str()).collect();
+    assert_eq!(names, ["height", "top"]);
+    assert_eq!(errors.len(), 6);
+}
+
+#[test]
+fn test_comments() {
+    let src = "/* header */ div /* a */, /**/p/* b */{ /* c */ width: /* d */ 1px /* e */; /* f */ }\n\
+        /* unterminated";
+    let stylesheet = parse(src.to_string());
+    assert_eq!(stylesheet.rules.len(), 1);
+    assert_eq!(stylesheet.rules[0].selectors.len(), 2);
+    assert_eq!(stylesheet.rules[0].declarations, vec![
+        Declaration {
+            name: "width".to_string(),
+            value: Value::Length(1.0, Unit::Px),
+        },
+    ]);
+}
+
+#[test]
+fn test_length_units() {
+    let stylesheet = parse("p { a: 12pt; b: 2em; c: 1.5rem; d: 50%; e: 10vw; f: 10vh; }".to_string());
+    let values: Vec<Value> = stylesheet.rules[0].declarations.iter().map(|d| d.value.clone()).collect();
+    assert_eq!(values[3], Value::Length(50.0, Unit::Percent));
+
+    let ctx = LengthContext {
+        font_size: 20.0,
+        root_font_size: 16.0,
+        percent_base: 300.0,
+        percent_height_base: None,
+        viewport_width: 800.0,
+        viewport_height: 600.0,
+    };
+    let px: Vec<f64> = values.iter().map(|v| v.to_px_in(&ctx)).collect();
+    assert_eq!(px, vec![16.0, 40.0, 24.0, 150.0, 80.0, 60.0]);
+    assert_eq!(values[1].resolve(&ctx), Value::Length(40.0, Unit::Px));
+    assert_eq!(Value::Keyword("auto".to_string()).resolve(&ctx), Value::Keyword("auto".to_string()));
+}
+
+#[test]
+fn test_box_shorthands() {
+    let px = |f| Value::Length(f, Unit::Px);
+    let sides = |declarations: &[Declaration]| -> Vec<(String, Value)> {
+        declarations.iter().map(|d| (d.name.clone(), d.value.clone())).collect()
+    };
+
+    let declarations = parse_declarations("margin: 10px 20px".to_string());
+    assert_eq!(sides(&declarations), vec![
+        ("margin-top".to_string(), px(10.0)),
+        ("margin-right".to_string(), px(20.0)),
+        ("margin-bottom".to_string(), px(10.0)),
+        ("margin-left".to_string(), px(20.0)),
+    ]);
+
+    let declarations = parse_declarations("padding: 1px 2px 3px; border-width: 1px 2px 3px 4px; margin: auto".to_string());
+    assert_eq!(declarations[3], Declaration { name: "padding-left".to_string(), value: px(2.0) });
+    assert_eq!(declarations[6], Declaration { name: "border-bottom-width".to_string(), value: px(3.0) });
+    assert_eq!(declarations[7], Declaration { name: "border-left-width".to_string(), value: px(4.0) });
+    assert_eq!(declarations[11].value, Value::Keyword("auto".to_string()));
+}
+
+#[test]
+fn test_value_lists() {
+    let declarations = parse_declarations("outline: 1px solid #000000; font-family: Times New Roman, serif".to_string());
+    assert_eq!(declarations[0].value, Value::List(vec![
+        Value::Length(1.0, Unit::Px),
+        Value::Keyword("solid".to_string()),
+        Value::Color(Color { r: 0, g: 0, b: 0, a: 0xff }),
+    ]));
+    assert_eq!(declarations[1].value, Value::List(vec![
+        Value::Keyword("Times".to_string()),
+        Value::Keyword("New".to_string()),
+        Value::Keyword("Roman".to_string()),
+        Value::Comma,
+        Value::Keyword("serif".to_string()),
+    ]));
+    assert_eq!(declarations[1].value.to_string(), "Times New Roman, serif");
+}
+
+#[test]
+fn test_hex_colors() {
+    let colors: Vec<Value> = parse_declarations("a: #fff; b: #0f08; c: #00000080; d: #1A2b3C".to_string())
+        .into_iter().map(|d| d.value).collect();
+    assert_eq!(colors, vec![
+        Value::Color(Color { r: 0xff, g: 0xff, b: 0xff, a: 0xff }),
+        Value::Color(Color { r: 0x00, g: 0xff, b: 0x00, a: 0x88 }),
+        Value::Color(Color { r: 0x00, g: 0x00, b: 0x00, a: 0x80 }),
+        Value::Color(Color { r: 0x1a, g: 0x2b, b: 0x3c, a: 0xff }),
+    ]);
+}
+
+#[test]
+fn test_media_rules() {
+    let src = "@charset \"utf-8\"; @import url(x.css); p { width: 1px; } \
+        @media print { p { width: 2px; } } \
+        @media screen and (min-width: 600px), (orientation: portrait) { p { width: 3px; } \
+            @media not print { div { width: 4px; } } }";
+    let stylesheet = parse(src.to_string());
+    assert_eq!(stylesheet.rules.len(), 4);
+    assert_eq!(stylesheet.rules[3].media.len(), 2);
+
+    let applied = |ctx: &MediaContext| -> Vec<usize> {
+        (0..4).filter(|&i| stylesheet.rules[i].applies_to(ctx)).collect()
+    };
+    let screen = |width, height| MediaContext {
+        viewport_width: width,
+        viewport_height: height,
+        media_type: "screen".to_string(),
+    };
+    assert_eq!(applied(&screen(800.0, 600.0)), vec![0, 2, 3]);
+    assert_eq!(applied(&screen(480.0, 360.0)), vec![0]);
+    assert_eq!(applied(&screen(480.0, 640.0)), vec![0, 2, 3]);
+    let print = MediaContext { media_type: "print".to_string(), ..screen(800.0, 600.0) };
+    assert_eq!(applied(&print), vec![0, 1]);
+
+    // a feature cut off by the end of input is an error, not a panic
+    for src in ["@media (min-width:", "@media (min-width: "] {
+        let (stylesheet, errors) = try_parse(src);
+        assert!(stylesheet.rules.is_empty());
+        assert_eq!((errors[0].range.clone(), &*errors[0].message), (src.len()..src.len(), "min-width has no value"));
+    }
+}
+
+#[test]
+fn test_strings_and_urls() {
+    let values: Vec<Value> = parse_declarations(
+        "content: \"hello\"; font-family: 'Noto Sans', serif; a: \"say \\\"hi\\\" \\263a  x\"; \
+         background-image: url(foo.png); b: url( \"a b.png\" ); c: URL(x\\)y)".to_string()
+    ).into_iter().map(|d| d.value).collect();
+    assert_eq!(values, vec![
+        Value::String("hello".to_string()),
+        Value::List(vec![Value::String("Noto Sans".to_string()), Value::Comma, Value::Keyword("serif".to_string())]),
+        Value::String("say \"hi\" \u{263a} x".to_string()),
+        Value::Url("foo.png".to_string()),
+        Value::Url("a b.png".to_string()),
+        Value::Url("x)y".to_string()),
+    ]);
+    assert_eq!(values[2].to_string(), "\"say \\\"hi\\\" \u{263a} x\"");
+}
+
+#[test]
+fn test_font_face() {
+    let src = "@font-face { font-family: \"Noto Sans\"; font-weight: bold; font-style: italic; \
+            src: local(Noto Sans Bold), url(fonts/noto.woff2) format(\"woff2\"), url(noto.ttf); } \
+        @font-face { font-family: Broken; } \
+        p { font-family: Noto Sans; }";
+    let stylesheet = parse(src.to_string());
+    assert_eq!(stylesheet.rules.len(), 1);
+    assert_eq!(stylesheet.font_faces, vec![
+        FontFace {
+            family: "Noto Sans".to_string(),
+            src: vec![
+                FontSource::Local("Noto Sans Bold".to_string()),
+                FontSource::Url { url: "fonts/noto.woff2".to_string(), format: Some("woff2".to_string()) },
+                FontSource::Url { url: "noto.ttf".to_string(), format: None },
+            ],
+            weight: 700,
+            style: "italic".to_string(),
+        },
+    ]);
+    assert!(stylesheet.font_face("noto sans", 400, "normal").is_some());
+    assert!(stylesheet.font_face("Broken", 400, "normal").is_none());
+}
+
+#[test]
+fn test_serialize_round_trip() {
+    let src = "@font-face { font-family: Mono; src: url(m.ttf) format(\"truetype\"), local(Courier New); } \
+        div.a.b, #x, * { margin: 0 auto; color: #0f08; font-family: \"Noto Sans\", serif; } \
+        @media not print, screen and (min-width: 30em) { @media (orientation: landscape) { p { width: 50%; } } } \
+        span { content: \"a \\\"b\\\"\"; background: url(x.png) rgb(1, 2, 3); font-weight: 700; }";
+    let stylesheet = parse(src.to_string());
+    let text = stylesheet.to_string();
+    assert!(text.contains("#x, div.a.b, * {\n  margin-top: 0;\n"), "{}", text); // sorted by specificity
+    assert!(text.contains("@media not print, screen and (min-width: 30em) {\n  @media (orientation: landscape) {\n"), "{}", text);
+    assert_eq!(parse(text), stylesheet);
+}
+
+#[test]
+fn test_stylesheet_mutation() {
+    let mut stylesheet = parse("p { color: #000000; } div { width: 1px; }".to_string());
+    assert_eq!(stylesheet.insert_rule("span { height: 2px; }", 1), Ok(1));
+    assert_eq!(stylesheet.insert_rule("@media print { a { width: 0px; } b { width: 0px; } }", 3), Ok(3));
+    assert_eq!(stylesheet.insert_rule("i { }", 6), Err(RuleError::IndexSize(6)));
+    assert!(matches!(stylesheet.insert_rule("> i { }", 0), Err(RuleError::Syntax(_))));
+    assert_eq!(stylesheet.rules.iter().map(|rule| rule.index).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+    assert_eq!(stylesheet.rules[1].selectors[0].to_string(), "span");
+
+    let removed = stylesheet.delete_rule(0).unwrap();
+    assert_eq!(removed.selectors[0].to_string(), "p");
+    assert_eq!(stylesheet.rules[0].index, 0);
+    assert_eq!(stylesheet.delete_rule(4), Err(RuleError::IndexSize(4)));
+
+    let rule = &mut stylesheet.rules[1]; // div
+    rule.set_property("margin", "1px 2px");
+    rule.set_property("width", "3px");
+    assert_eq!(rule.get_property("width"), Some(&Value::Length(3.0, Unit::Px)));
+    assert_eq!(rule.declarations[0].name, "width");
+    assert_eq!(rule.get_property("margin-left"), Some(&Value::Length(2.0, Unit::Px)));
+    assert!(rule.remove_property("margin"));
+    assert!(!rule.remove_property("margin-top"));
+    assert_eq!(rule.declarations.len(), 1);
+
+    // the last duplicate is the one changed, an invalid value changes nothing
+    let mut rule = parse("p { width: 1px; color: #000000; width: 2px }".to_string()).rules.remove(0);
+    rule.set_property("width", "3px");
+    assert_eq!(rule.get_property("width"), Some(&Value::Length(3.0, Unit::Px)));
+    assert_eq!(rule.declarations.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), ["color", "width"]);
+    rule.set_property("width", "12qq");
+    assert_eq!(rule.get_property("width"), Some(&Value::Length(3.0, Unit::Px)));
+}
+
+#[test]
+fn test_try_parse_errors() {
+    let src = "p { width: 1px; height: ; color: #12 } > a { x: y; } div { margin: 1 2 3 4 5 } span { width: 3qq }";
+    let (stylesheet, errors) = try_parse(src);
+    let found: Vec<(&str, &str)> = errors.iter()
+        .map(|err| (&src[err.range.clone()], err.message.as_str()))
+        .collect();
+    assert_eq!(found, vec![
+        ("height:", "height has no value"),
+        ("#12", "invalid hex color #12"),
+        (">", "expected a selector"),
+        ("margin: 1 2 3 4 5", "margin takes 1 to 4 values"),
+        ("qq", "unrecognized unit qq"),
+    ]);
+    assert_eq!(errors[1].to_string(), "invalid hex color #12 at bytes 33..36");
+    // only the bad declarations and the rule with the bad selector are lost
+    let kept: Vec<String> = stylesheet.rules.iter()
+        .map(|rule| format!("{} {}", rule.selectors[0], rule.declarations.len()))
+        .collect();
+    assert_eq!(kept, ["p 1", "div 0", "span 0"]);
+
+    let (stylesheet, errors) = try_parse("p { width: 1px");
+    assert_eq!((errors[0].range.clone(), &*errors[0].message), (14..14, "unclosed block"));
+    assert_eq!(stylesheet.rules[0].declarations.len(), 1);
+    assert_eq!(try_parse("p { width: 1px } }").1[0].message, "unexpected '}'");
+    assert_eq!(try_parse("a { b: c }"), (parse("a { b: c }".to_string()), Vec::new()));
+    // parse doesn't give up on the sheet either
+    assert_eq!(parse("> a { x: y; } b { width: 1px }".to_string()).rules.len(), 1);
+
+    // a multi-byte character at the end of the input
+    let (declarations, errors) = try_parse_declarations("font-family: café");
+    assert_eq!((declarations[0].value.clone(), errors), (Value::Keyword("café".to_string()), Vec::new()));
+    let (stylesheet, errors) = try_parse("p { font-family: café");
+    assert_eq!((stylesheet.rules[0].declarations.len(), &*errors[0].message), (1, "unclosed block"));
+    assert_eq!(parse_selectors("é").unwrap()[0].to_string(), "é");
+    assert_eq!(try_parse("p { x: 1é }").1[0].message, "unrecognized unit é");
+}
+
+#[test]
+fn test_keyframes_and_transitions() {
+    let stylesheet = parse("@keyframes fade { from { opacity: 0; } 50%, 75% { opacity: 0.5; } to { opacity: 1; } } \
+        a { transition: opacity 300ms ease-in, color 1s 0.5s; } \
+        b { animation: fade 2s infinite alternate both, spin 1s steps(4) paused; }".to_string());
+    let fade = stylesheet.keyframes("fade").unwrap();
+    assert_eq!(fade.frames.iter().map(|frame| frame.offsets.clone()).collect::<Vec<_>>(), vec![vec![0.0], vec![0.5, 0.75], vec![1.0]]);
+    assert_eq!(fade.frames[1].declarations[0].value, Value::Number(0.5));
+    assert!(stylesheet.keyframes("spin").is_none());
+
+    let a = &stylesheet.rules[0];
+    assert_eq!(a.declarations.len(), 4);
+    assert_eq!(a.get_property("transition-delay").unwrap().to_string(), "0s, 0.5s");
+    let transitions = transitions(|name| a.get_property(name));
+    assert_eq!(transitions[0], Transition {
+        property: "opacity".to_string(),
+        duration: 0.3,
+        timing_function: Value::Keyword("ease-in".to_string()),
+        delay: 0.0,
+    });
+    assert_eq!((&*transitions[1].property, transitions[1].duration, transitions[1].delay), ("color", 1.0, 0.5));
+
+    let b = &stylesheet.rules[1];
+    let animations = animations(|name| b.get_property(name));
+    assert_eq!(animations[0].name, "fade");
+    assert_eq!(animations[0].iteration_count, f64::INFINITY);
+    assert_eq!((&*animations[0].direction, &*animations[0].fill_mode, animations[0].paused), ("alternate", "both", false));
+    assert_eq!(animations[1].timing_function, Value::Function("steps".to_string(), vec![Value::Number(4.0)]));
+    assert!(animations[1].paused);
+    assert_eq!(parse(stylesheet.to_string()), stylesheet);
+}
+
+#[test]
+fn test_background_shorthand() {
+    let mut stylesheet = parse("a { background: #ffffff url(x.png) no-repeat center; } \
+        b { background: url(a.png) repeat-x, url(b.png) 10px 20px border-box content-box white; }".to_string());
+    let longhands = |rule: &Rule| -> Vec<String> {
+        rule.declarations.iter().map(|d| format!("{}: {}", d.name, d.value)).collect()
+    };
+    assert_eq!(longhands(&stylesheet.rules[0]), vec![
+        "background-color: #ffffff",
+        "background-image: url(\"x.png\")",
+        "background-repeat: no-repeat",
+        "background-position: center",
+        "background-clip: border-box",
+    ]);
+    assert_eq!(longhands(&stylesheet.rules[1]), vec![
+        "background-color: white",
+        "background-image: url(\"a.png\"), url(\"b.png\")",
+        "background-repeat: repeat-x, repeat",
+        "background-position: 0% 0%, 10px 20px",
+        "background-clip: border-box, content-box",
+    ]);
+    assert!(stylesheet.rules[1].remove_property("background"));
+    assert!(stylesheet.rules[1].declarations.is_empty());
+}
+
+#[test]
+fn test_border_shorthands() {
+    let stylesheet = parse("a { border: 2px solid #000000; border-left: thick dashed; } \
+        b { border-color: #ff0000 #00ff00; border-style: dotted; }".to_string());
+    let a = &stylesheet.rules[0];
+    assert_eq!(a.declarations.len(), 15);
+    assert_eq!(a.get_property("border-top-width"), Some(&Value::Length(2.0, Unit::Px)));
+    assert_eq!(a.get_property("border-bottom-style"), Some(&Value::Keyword("solid".to_string())));
+    assert_eq!(a.get_property("border-right-color").unwrap().to_string(), "#000000");
+    assert_eq!(a.get_property("border-left-width"), Some(&Value::Length(5.0, Unit::Px)));
+    assert_eq!(a.get_property("border-left-color"), Some(&Value::Keyword("currentcolor".to_string())));
+
+    let b = &stylesheet.rules[1];
+    assert_eq!(b.get_property("border-top-color").unwrap().to_string(), "#ff0000");
+    assert_eq!(b.get_property("border-left-color").unwrap().to_string(), "#00ff00");
+    assert_eq!(b.get_property("border-right-style"), Some(&Value::Keyword("dotted".to_string())));
+}
+
+#[test]
+fn test_selector_specificity() {
+    let stylesheet = parse("*, *.a, div, div.a, .a.b, #x, #x.a, div#x.a.b, *#x { }".to_string());
+    let selectors: Vec<(String, Specificity)> = stylesheet.rules[0].selectors.iter()
+        .map(|selector| (selector.to_string(), selector.specificity()))
+        .collect();
+    // highest specificity first, equal ones in source order
+    assert_eq!(selectors, vec![
+        ("div#x.a.b".to_string(), (1, 2, 1)),
+        ("#x.a".to_string(), (1, 1, 0)),
+        ("#x".to_string(), (1, 0, 0)),
+        ("*#x".to_string(), (1, 0, 0)),
+        (".a.b".to_string(), (0, 2, 0)),
+        ("div.a".to_string(), (0, 1, 1)),
+        ("*.a".to_string(), (0, 1, 0)),
+        ("div".to_string(), (0, 0, 1)),
+        ("*".to_string(), (0, 0, 0)),
+    ]);
+    match stylesheet.rules[0].selectors[8] {
+        Selector::Simple(ref universal) => assert!(universal.universal),
+        ref selector => panic!("{:?} is not a simple selector", selector),
+    }
+}
+
+#[test]
+fn test_descendant_selectors() {
+    let stylesheet = parse("nav  ul li.a, div, #main\n p { }".to_string());
+    let selectors = &stylesheet.rules[0].selectors;
+    assert_eq!(selectors.iter().map(|s| s.to_string()).collect::<Vec<_>>(), vec!["#main p", "nav ul li.a", "div"]);
+    assert_eq!(selectors[1].specificity(), (0, 1, 3));
+    match selectors[0] {
+        Selector::Descendant(ref ancestors, ref selector) => {
+            assert_eq!(ancestors[0].id, Some("main".to_string()));
+            assert_eq!(selector.tag_name, Some(Atom::from("p")));
+        }
+        ref selector => panic!("{:?} is not a descendant selector", selector),
+    }
+    assert_eq!(try_parse("a > b { }").1[0].message, "expected a selector");
+}
+
+#[test]
+fn test_pseudo_classes() {
+    let stylesheet = parse("a:hover, #nav LI:Focus:active, :hover { color: red; }".to_string());
+    let selectors = &stylesheet.rules[0].selectors;
+    let serialized: Vec<String> = selectors.iter().map(|selector| selector.to_string()).collect();
+    assert_eq!(serialized, ["#nav li:focus:active", "a:hover", ":hover"]);
+    assert_eq!(selectors[0].specificity(), (1, 2, 1));
+    assert_eq!(selectors[2].specificity(), (0, 1, 0));
+
+    // kept as written, matching nothing
+    let (stylesheet, errors) = try_parse("a:visited, li:nth-child(2n + 1), a:hover::before { color: red; } b { color: blue; }");
+    assert!(errors.is_empty());
+    let serialized: Vec<String> = stylesheet.rules[0].selectors.iter().map(|selector| selector.to_string()).collect();
+    assert_eq!(serialized, ["a:hover::before", "a:visited", "li:nth-child(2n + 1)"]);
+    assert_eq!(stylesheet.rules[0].selectors[1].specificity(), (0, 1, 1));
+    assert_eq!(try_parse("a: { }").1[0].message, "expected a pseudo-class name");
+}
+
+#[test]
+fn test_signed_numbers() {
+    let values: Vec<Value> = parse_declarations("z-index: -1; margin-top: -.5em; order: +2; x: -webkit-box".to_string())
+        .into_iter().map(|declaration| declaration.value).collect();
+    assert_eq!(values, [
+        Value::Number(-1.0),
+        Value::Length(-0.5, Unit::Em),
+        Value::Number(2.0),
+        Value::Keyword("-webkit-box".to_string()),
+    ]);
+}
@@ -1,7 +1,14 @@
-use std::collections::{HashMap, HashSet};
-use std::{fmt, iter};
+use crate::atom::Atom;
+use crate::html;
+use crate::url;
+use std::collections::HashSet;
+use std::{fmt, ops, slice};
 
-pub type AttrMap = HashMap<String, String>;
+// Attributes in source order. Lookups are linear, elements rarely have many attributes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AttrMap {
+    entries: Vec<(Atom, String)>,
+}
 
 #[derive(Clone, Debug)]
 pub struct Node {
@@ -15,10 +22,54 @@ pub enum NodeType {
     Text(String),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WhitespaceMode {
+    Preserve, // text as written
+    Collapse, // runs of whitespace become one space, whitespace-only text is dropped unless it separates inline content
+    Trim,     // like Collapse, and text is trimmed at both ends
+}
+
 #[derive(Clone, Debug)]
 pub struct ElementData {
-    pub tag_name: String,
+    pub tag_name: Atom,
     pub attrs: AttrMap,
+    pub template_content: Option<Vec<Node>>, // children of <template>, kept out of the tree
+    pub state: ElementState,
+}
+
+// what the user is doing with an element, matched by :hover, :focus and :active
+// https://html.spec.whatwg.org/multipage/semantics-other.html#pseudo-classes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ElementState(u8);
+
+impl ElementState {
+    pub const HOVER: ElementState = ElementState(1);
+    pub const FOCUS: ElementState = ElementState(1 << 1);
+    pub const ACTIVE: ElementState = ElementState(1 << 2);
+
+    pub fn empty() -> ElementState {
+        ElementState(0)
+    }
+
+    pub fn contains(self, other: ElementState) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: ElementState) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: ElementState) {
+        self.0 &= !other.0;
+    }
+}
+
+impl ops::BitOr for ElementState {
+    type Output = ElementState;
+
+    fn bitor(self, other: ElementState) -> ElementState {
+        ElementState(self.0 | other.0)
+    }
 }
 
 impl Node {
@@ -29,17 +80,339 @@ impl Node {
         }
     }
 
-    pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
+    pub fn elem<N: Into<Atom>>(name: N, attrs: AttrMap, children: Vec<Node>) -> Node {
         Node {
             children: children,
             data: NodeType::Element(
                 ElementData {
-                    tag_name: name,
+                    tag_name: name.into(),
                     attrs: attrs,
+                    template_content: None,
+                    state: ElementState::empty(),
+                }
+            ),
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/scripting.html#the-template-element
+    pub fn template<N: Into<Atom>>(name: N, attrs: AttrMap, content: Vec<Node>) -> Node {
+        Node {
+            children: Vec::new(),
+            data: NodeType::Element(
+                ElementData {
+                    tag_name: name.into(),
+                    attrs: attrs,
+                    template_content: Some(content),
+                    state: ElementState::empty(),
+                }
+            ),
+        }
+    }
+}
+
+// Attribute Methods
+
+impl AttrMap {
+    pub fn new() -> AttrMap {
+        AttrMap {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries.iter().find(|(n, _)| n == name).map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // the first occurrence wins like browsers do, returns false for a duplicate
+    pub fn insert<N: Into<Atom>>(&mut self, name: N, value: String) -> bool {
+        let name = name.into();
+        if self.entries.iter().any(|(n, _)| *n == name) {
+            return false;
+        }
+        self.entries.push((name, value));
+        true
+    }
+
+    // overwrite the value in place, or append a new attribute
+    pub fn set<N: Into<Atom>>(&mut self, name: N, value: String) {
+        let name = name.into();
+        match self.entries.iter_mut().find(|(n, _)| *n == name) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((name, value)),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let index = self.entries.iter().position(|(n, _)| n == name)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, (Atom, String)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl ops::Index<&str> for AttrMap {
+    type Output = String;
+
+    fn index(&self, name: &str) -> &String {
+        self.get(name).unwrap_or_else(|| panic!("no attribute {}", name))
+    }
+}
+
+impl<'a> IntoIterator for &'a AttrMap {
+    type Item = &'a (Atom, String);
+    type IntoIter = slice::Iter<'a, (Atom, String)>;
+
+    fn into_iter(self) -> slice::Iter<'a, (Atom, String)> {
+        self.entries.iter()
+    }
+}
+
+// Tree queries
+
+impl Node {
+    pub fn text_content(&self) -> String {
+        match self.data {
+            NodeType::Text(ref body) => body.clone(),
+            NodeType::Element(_) => self.children
+                .iter().map(|child| child.text_content()).collect(),
+        }
+    }
+
+    pub fn get_element_by_id(&self, id: &str) -> Option<&Node> {
+        if let NodeType::Element(ref elem) = self.data {
+            if elem.id().map(|s| s.as_str()) == Some(id) {
+                return Some(self);
+            }
+        }
+        self.children.iter().find_map(|child| child.get_element_by_id(id))
+    }
+
+    pub fn get_element_by_id_mut(&mut self, id: &str) -> Option<&mut Node> {
+        let found = match self.data {
+            NodeType::Element(ref elem) => elem.id().map(|s| s.as_str()) == Some(id),
+            NodeType::Text(_) => false,
+        };
+        if found {
+            return Some(self);
+        }
+        self.children.iter_mut().find_map(|child| child.get_element_by_id_mut(id))
+    }
+
+    // href of the first <base> element that has one
+    // https://html.spec.whatwg.org/multipage/semantics.html#the-base-element
+    pub fn base_href(&self) -> Option<&str> {
+        if let NodeType::Element(ref elem) = self.data {
+            if elem.tag_name == "base" {
+                if let Some(href) = elem.attrs.get("href") {
+                    return Some(href);
                 }
+            }
+        }
+        self.children.iter().find_map(|child| child.base_href())
+    }
+
+    // the URL relative references in the document resolve against,
+    // `document_url` is where the document itself was loaded from
+    pub fn base_url(&self, document_url: &str) -> String {
+        match self.base_href() {
+            Some(href) => url::resolve(document_url, href),
+            None => document_url.to_string(),
+        }
+    }
+
+    pub fn resolve_url(&self, document_url: &str, reference: &str) -> String {
+        url::resolve(&self.base_url(document_url), reference)
+    }
+}
+
+// Element state
+
+impl Node {
+    // moves `state` to the element at `path`, the child indices from this node.
+    // hover and active also apply to its ancestors like in browsers, focus only
+    // to the element itself. returns false, with the state cleared everywhere,
+    // when the path doesn't lead to an element
+    // https://html.spec.whatwg.org/multipage/semantics-other.html#selector-hover
+    pub fn set_state_at(&mut self, path: &[usize], state: ElementState) -> bool {
+        self.remove_state(state);
+        self.insert_state_at(path, state)
+    }
+
+    pub fn remove_state(&mut self, state: ElementState) {
+        if let NodeType::Element(ref mut elem) = self.data {
+            elem.state.remove(state);
+        }
+        for child in &mut self.children {
+            child.remove_state(state);
+        }
+    }
+
+    fn insert_state_at(&mut self, path: &[usize], state: ElementState) -> bool {
+        let found = match path.split_first() {
+            Some((&index, rest)) => match self.children.get_mut(index) {
+                Some(child) => child.insert_state_at(rest, state),
+                None => false,
+            },
+            None => matches!(self.data, NodeType::Element(_)),
+        };
+        if let (true, &mut NodeType::Element(ref mut elem)) = (found, &mut self.data) {
+            let mut state = state;
+            if !path.is_empty() {
+                state.remove(ElementState::FOCUS);
+            }
+            elem.state.insert(state);
+        }
+        found
+    }
+}
+
+// Serialization
+// https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+
+impl Node {
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        self.write_html(&mut out, false);
+        out
+    }
+
+    pub fn inner_html(&self) -> String {
+        let (raw_text, children) = match self.data {
+            NodeType::Element(ref elem) => (
+                html::is_raw_text_tag(&elem.tag_name),
+                elem.template_content.as_ref().unwrap_or(&self.children),
             ),
+            NodeType::Text(_) => (false, &self.children),
+        };
+        let mut out = String::new();
+        for child in children {
+            child.write_html(&mut out, raw_text);
+        }
+        out
+    }
+
+    fn write_html(&self, out: &mut String, raw_text: bool) {
+        match self.data {
+            NodeType::Text(ref body) if raw_text => out.push_str(body),
+            NodeType::Text(ref body) => out.push_str(&escape_html(body, false)),
+            NodeType::Element(ref elem) => {
+                out.push('<');
+                out.push_str(&elem.tag_name);
+                for &(ref name, ref value) in elem.attrs.iter() {
+                    out.push_str(&format!(" {}=\"{}\"", name, escape_html(value, true)));
+                }
+                out.push('>');
+                if html::is_self_closing_tag(&elem.tag_name) {
+                    return;
+                }
+                out.push_str(&self.inner_html());
+                out.push_str(&format!("</{}>", elem.tag_name));
+            }
+        }
+    }
+}
+
+fn escape_html(s: &str, in_attribute: bool) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '\u{a0}' => result.push_str("&nbsp;"),
+            '"' if in_attribute => result.push_str("&quot;"),
+            '<' if !in_attribute => result.push_str("&lt;"),
+            '>' if !in_attribute => result.push_str("&gt;"),
+            c => result.push(c),
         }
     }
+    result
+}
+
+// Whitespace handling
+
+// elements whose text is never collapsed (until `white-space` is supported)
+fn preserves_whitespace(tag_name: &str) -> bool {
+    match tag_name {
+        "pre" | "textarea" | "script" | "style" => true,
+        _ => false,
+    }
+}
+
+fn collapse_whitespace(s: &str, mode: WhitespaceMode) -> String {
+    let mut result = String::new();
+    let mut in_space = false;
+    for c in s.chars() {
+        if c.is_ascii_whitespace() {
+            if !in_space {
+                result.push(' ');
+            }
+            in_space = true;
+        } else {
+            result.push(c);
+            in_space = false;
+        }
+    }
+    if mode == WhitespaceMode::Trim {
+        result.trim_matches(' ').to_string()
+    } else {
+        result
+    }
+}
+
+pub fn normalize_whitespace(nodes: &mut Vec<Node>, mode: WhitespaceMode) {
+    if mode == WhitespaceMode::Preserve {
+        return;
+    }
+    for node in nodes.iter_mut() {
+        match node.data {
+            NodeType::Text(ref mut body) => *body = collapse_whitespace(body, mode),
+            NodeType::Element(ref mut elem) => if !preserves_whitespace(&elem.tag_name) {
+                if let Some(ref mut content) = elem.template_content {
+                    normalize_whitespace(content, mode);
+                }
+                normalize_whitespace(&mut node.children, mode);
+            },
+        }
+    }
+    // the space in `<b>a</b> <i>b</i>` is kept, between blocks or at either end
+    // of the parent it would only make an empty line
+    let keep: Vec<bool> = (0..nodes.len()).map(|i| match nodes[i].data {
+        NodeType::Text(ref body) if body == " " => {
+            i > 0 && i + 1 < nodes.len() && is_inline(&nodes[i - 1]) && is_inline(&nodes[i + 1])
+        }
+        NodeType::Text(ref body) => !body.is_empty(),
+        NodeType::Element(_) => true,
+    }).collect();
+    let mut keep = keep.into_iter();
+    nodes.retain(|_| keep.next().unwrap());
+}
+
+// text and the phrasing elements that are laid out in lines, before any styles apply
+// https://html.spec.whatwg.org/multipage/dom.html#phrasing-content
+fn is_inline(node: &Node) -> bool {
+    match node.data {
+        NodeType::Text(_) => true,
+        NodeType::Element(ref elem) => match &*elem.tag_name {
+            "a" | "abbr" | "b" | "bdi" | "bdo" | "br" | "button" | "cite" | "code" | "data" |
+            "dfn" | "em" | "i" | "img" | "input" | "kbd" | "label" | "mark" | "q" | "s" |
+            "samp" | "select" | "small" | "span" | "strong" | "sub" | "sup" | "textarea" |
+            "time" | "u" | "var" => true,
+            _ => false,
+        },
+    }
 }
 
 // Element Methods
@@ -62,9 +435,9 @@ impl ElementData {
 fn walk(node: &Node, indent: usize, f: &mut fmt::Formatter) -> fmt::Result {
     write!(
         f, "{}",
-        iter::repeat(" ").take(indent).collect::<String>()
+        " ".repeat(indent)
     )?;
-    write!(f, "{}\n", node.data)?;
+    writeln!(f, "{}", node.data)?;
     for child in &node.children {
         walk(child, indent + 2, f)?;
     }
@@ -79,14 +452,15 @@ impl fmt::Display for Node { // type Result = Result<(), Error>;
 
 impl fmt::Display for NodeType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &NodeType::Text(ref body) => write!(f, "#text: {}", escape_default(body.as_str())),
-            &NodeType::Element(ElementData {
+        match *self {
+            NodeType::Text(ref body) => write!(f, "#text: {}", escape_default(body.as_str())),
+            NodeType::Element(ElementData {
                 ref tag_name,
                 ref attrs,
+                ..
             }) => {
                 write!(f, "<{}", tag_name)?;
-                for (name, value) in attrs.iter() {
+                for &(ref name, ref value) in attrs.iter() {
                     write!(f, " {}=\"{}\"", name, value)?;
                 }
                 write!(f, ">")
@@ -99,4 +473,60 @@ fn escape_default(s: &str) -> String {
     s.chars()
         .flat_map(|c| c.escape_default())
         .collect()
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_serialize_html() {
+    let src = "<div id=\"a&amp;b\" title='say \"hi\"'>1 &lt; 2<br><script>if (a < b) {}</script>\
+        <p>x&nbsp;y</p></div>";
+    let root = html::parse(src.to_string());
+    let div = &root.children[1].children[0];
+    assert_eq!(
+        div.to_html(),
+        "<div id=\"a&amp;b\" title=\"say &quot;hi&quot;\">1 &lt; 2<br>\
+            <script>if (a < b) {}</script><p>x&nbsp;y</p></div>"
+    );
+    assert_eq!(div.children[3].inner_html(), "x&nbsp;y");
+
+    let reparsed = html::parse(root.to_html());
+    assert_eq!(format!("{}", reparsed), format!("{}", root));
+}
+
+#[test]
+fn test_base_url() {
+    let root = html::parse("<head><base target=_self><base href=\"../assets/\"></head><img src=a.png>".to_string());
+    assert_eq!(root.base_href(), Some("../assets/"));
+    assert_eq!(root.base_url("site/pages/index.html"), "site/assets/");
+    assert_eq!(root.resolve_url("site/pages/index.html", "a.png"), "site/assets/a.png");
+
+    let root = html::parse("<p>no base</p>".to_string());
+    assert_eq!(root.resolve_url("http://example.com/x/y.html", "/z.css"), "http://example.com/z.css");
+}
+
+#[test]
+fn test_element_state() {
+    fn state(node: &Node) -> ElementState {
+        match node.data {
+            NodeType::Element(ref elem) => elem.state,
+            NodeType::Text(_) => panic!("not an element"),
+        }
+    }
+
+    let mut root = html::parse("<div><p>a<a>b</a></p><p>c</p></div>".to_string());
+    let hover_and_focus = ElementState::HOVER | ElementState::FOCUS;
+    assert!(root.set_state_at(&[1, 0, 0, 1], hover_and_focus));
+    let body = &root.children[1];
+    assert_eq!(state(&body.children[0].children[0].children[1]), hover_and_focus);
+    assert_eq!(state(&body.children[0]), ElementState::HOVER);
+    assert_eq!(state(&body.children[0].children[1]), ElementState::empty());
+
+    // moving the hover clears it from the old chain
+    assert!(root.set_state_at(&[1, 0, 1], ElementState::HOVER));
+    let body = &root.children[1];
+    assert_eq!(state(&body.children[0].children[0].children[1]), ElementState::FOCUS);
+    assert!(state(&body.children[0].children[1]).contains(ElementState::HOVER));
+
+    // a text node is not an element
+    assert!(!root.set_state_at(&[1, 0, 0, 0], ElementState::ACTIVE));
+    assert!(!root.set_state_at(&[9], ElementState::ACTIVE));
+}
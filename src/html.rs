@@ -1,20 +1,153 @@
 use crate::dom;
-use std::collections::HashMap;
+use encoding_rs::{Encoding, UTF_8};
+use std::io::Read;
+use std::{error, fmt, str};
+
+#[derive(Clone, Debug)]
+pub struct ParseOptions {
+    pub decode_entities: bool, // false keeps "&amp;" etc. as written in the source
+    pub preserve_case: bool,   // keep tag and attribute names as written instead of lowercasing
+    pub whitespace: dom::WhitespaceMode,
+    pub max_depth: usize,      // open elements allowed at once
+    pub max_input_size: usize, // in bytes
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            decode_entities: true,
+            preserve_case: false,
+            whitespace: dom::WhitespaceMode::Collapse,
+            max_depth: 512,
+            max_input_size: 256 * 1024 * 1024,
+        }
+    }
+}
+
+// positions are byte offsets into the source
+#[derive(Clone, Debug, PartialEq)]
+pub enum HtmlParseError {
+    UnexpectedEof { pos: usize },
+    UnexpectedChar { pos: usize, expected: char, found: char },
+    MissingName { pos: usize },
+    Io { pos: usize, message: String },
+    TooDeep { pos: usize, limit: usize },
+    TooLarge { pos: usize, limit: usize },
+}
+
+impl fmt::Display for HtmlParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HtmlParseError::UnexpectedEof { pos } =>
+                write!(f, "unexpected end of input at byte {}", pos),
+            HtmlParseError::UnexpectedChar { pos, expected, found } =>
+                write!(f, "expected '{}' but found '{}' at byte {}", expected, found, pos),
+            HtmlParseError::MissingName { pos } =>
+                write!(f, "expected a tag or attribute name at byte {}", pos),
+            HtmlParseError::Io { pos, ref message } =>
+                write!(f, "read error at byte {}: {}", pos, message),
+            HtmlParseError::TooDeep { pos, limit } =>
+                write!(f, "elements nested deeper than {} at byte {}", limit, pos),
+            HtmlParseError::TooLarge { pos, limit } =>
+                write!(f, "input larger than {} bytes at byte {}", limit, pos),
+        }
+    }
+}
+
+impl error::Error for HtmlParseError {}
+
+pub type ParseResult<T> = Result<T, HtmlParseError>;
 
 pub fn parse(source: String) -> dom::Node {
-    let mut nodes = Parser {
-        pos: 0,
-        input: source,
-    }.parse_nodes();
+    parse_with_options(source, ParseOptions::default())
+}
+
+pub fn parse_with_options(source: String, options: ParseOptions) -> dom::Node {
+    try_parse_with_options(source, options).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_parse(source: String) -> ParseResult<dom::Node> {
+    try_parse_with_options(source, ParseOptions::default())
+}
+
+pub fn try_parse_with_options(source: String, options: ParseOptions) -> ParseResult<dom::Node> {
+    let whitespace = options.whitespace;
+    let mut nodes = TreeBuilder::document().build(Tokenizer::new(source, options))?;
+    dom::normalize_whitespace(&mut nodes, whitespace);
+    Ok(document_node(nodes))
+}
+
+// parse while reading, only the unparsed part of the source is kept in memory
+pub fn parse_from_reader<'a, R: Read + 'a>(reader: R) -> ParseResult<dom::Node> {
+    parse_from_reader_with_options(reader, ParseOptions::default())
+}
+
+pub fn parse_from_reader_with_options<'a, R: Read + 'a>(
+    reader: R,
+    options: ParseOptions
+) -> ParseResult<dom::Node> {
+    let whitespace = options.whitespace;
+    let mut nodes = TreeBuilder::document().build(Tokenizer::from_reader(reader, options))?;
+    dom::normalize_whitespace(&mut nodes, whitespace);
+    Ok(document_node(nodes))
+}
+
+// decode the source with the encoding from its BOM or <meta charset>, UTF-8 otherwise
+pub fn parse_bytes(source: &[u8]) -> ParseResult<dom::Node> {
+    let (text, _, _) = sniff_encoding(source).decode(source); // also strips a BOM
+    try_parse(text.into_owned())
+}
 
-    if nodes.len() == 1 { // if source has root element, just return
-        nodes.swap_remove(0)
-    } else {
-        dom::Node::elem("html".to_string(), HashMap::new(), nodes)
+// https://html.spec.whatwg.org/multipage/parsing.html#determining-the-character-encoding
+pub fn sniff_encoding(source: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(source) {
+        return encoding;
     }
+    meta_charset(&source[..source.len().min(1024)])
+        .map(|encoding| encoding.output_encoding()) // utf-16 labels in markup mean utf-8
+        .unwrap_or(UTF_8)
+}
+
+// the first charset declared by a <meta> in the prescan window
+fn meta_charset(head: &[u8]) -> Option<&'static Encoding> {
+    let head = head.to_ascii_lowercase();
+    let mut rest = &head[..];
+    while let Some(start) = find_bytes(rest, b"<meta") {
+        rest = &rest[start..];
+        let end = rest.iter().position(|&b| b == b'>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        rest = &rest[end..];
+
+        let at = match find_bytes(tag, b"charset") {
+            Some(at) => at + b"charset".len(),
+            None => continue,
+        };
+        let value = tag[at..].iter()
+            .skip_while(|&&b| b == b' ' || b == b'=' || b == b'"' || b == b'\'')
+            .take_while(|&&b| !(b.is_ascii_whitespace() || b == b'"' || b == b'\'' || b == b';' || b == b'/'))
+            .cloned()
+            .collect::<Vec<u8>>();
+        if let Some(encoding) = Encoding::for_label(&value) {
+            return Some(encoding);
+        }
+    }
+    None
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// parse a markup snippet without wrapping it in a root element
+pub fn parse_fragment(source: String) -> ParseResult<Vec<dom::Node>> {
+    let options = ParseOptions::default();
+    let whitespace = options.whitespace;
+    let mut nodes = TreeBuilder::fragment().build(Tokenizer::new(source, options))?;
+    dom::normalize_whitespace(&mut nodes, whitespace);
+    Ok(nodes)
 }
 
-fn is_self_closing_tag(name: &str) -> bool {
+pub fn is_self_closing_tag(name: &str) -> bool {
     match name {
         "area" | "base" | "br" | "col" | "embed" | "hr" |
         "img" | "input" | "link" | "meta" | "param" | "source" |
@@ -23,93 +156,493 @@ fn is_self_closing_tag(name: &str) -> bool {
     }
 }
 
-struct Parser {
-    pos: usize,
-    input: String,
+fn valid_tag_name_char(c: char) -> bool {
+    !(c.is_whitespace() || c == '/' || c == '>')
 }
 
-impl Parser {
-    fn parse_nodes(&mut self) -> Vec<dom::Node> {
-        let mut nodes = Vec::new();
-        loop {
-            self.consume_whitespace();
-            if self.eof() || self.starts_with("</") {
-                break;
+fn valid_attr_name_char(c: char) -> bool {
+    match c {
+        '/' | '>' | '=' | '"' | '\'' | '<' => false,
+        c => !c.is_whitespace(),
+    }
+}
+
+// elements that belong in <head>
+fn is_metadata_tag(name: &str) -> bool {
+    match name {
+        "base" | "link" | "meta" | "noscript" | "script" | "style" |
+        "template" | "title" => true,
+        _ => false
+    }
+}
+
+pub fn is_raw_text_tag(name: &str) -> bool {
+    match name {
+        "script" | "style" => true,
+        _ => false
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/named-characters.html (commonly used subset)
+fn named_entity(name: &str) -> Option<char> {
+    let c = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '\u{a9}',
+        "reg" => '\u{ae}',
+        "trade" => '\u{2122}',
+        "deg" => '\u{b0}',
+        "plusmn" => '\u{b1}',
+        "times" => '\u{d7}',
+        "divide" => '\u{f7}',
+        "middot" => '\u{b7}',
+        "para" => '\u{b6}',
+        "sect" => '\u{a7}',
+        "cent" => '\u{a2}',
+        "pound" => '\u{a3}',
+        "yen" => '\u{a5}',
+        "euro" => '\u{20ac}',
+        "laquo" => '\u{ab}',
+        "raquo" => '\u{bb}',
+        "lsquo" => '\u{2018}',
+        "rsquo" => '\u{2019}',
+        "ldquo" => '\u{201c}',
+        "rdquo" => '\u{201d}',
+        "ndash" => '\u{2013}',
+        "mdash" => '\u{2014}',
+        "hellip" => '\u{2026}',
+        "bull" => '\u{2022}',
+        _ => return None,
+    };
+    Some(c)
+}
+
+// names recognized without a trailing semicolon, longest first
+const LEGACY_ENTITIES: [&str; 19] = [
+    "divide", "middot", "plusmn", "laquo", "pound", "raquo", "times",
+    "copy", "nbsp", "para", "quot", "sect", "cent", "amp", "deg", "reg",
+    "yen", "gt", "lt",
+];
+
+// https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+fn numeric_char(code: u32) -> char {
+    match code {
+        0 | 0xd800..=0xdfff => std::char::REPLACEMENT_CHARACTER,
+        0x80..=0x9f => windows_1252_c1(code)
+            .unwrap_or_else(|| std::char::from_u32(code).unwrap()),
+        _ => std::char::from_u32(code).unwrap_or(std::char::REPLACEMENT_CHARACTER),
+    }
+}
+
+// references to C1 controls are taken as windows-1252 bytes
+fn windows_1252_c1(code: u32) -> Option<char> {
+    let c = match code {
+        0x80 => '\u{20ac}', 0x82 => '\u{201a}', 0x83 => '\u{0192}', 0x84 => '\u{201e}',
+        0x85 => '\u{2026}', 0x86 => '\u{2020}', 0x87 => '\u{2021}', 0x88 => '\u{02c6}',
+        0x89 => '\u{2030}', 0x8a => '\u{0160}', 0x8b => '\u{2039}', 0x8c => '\u{0152}',
+        0x8e => '\u{017d}', 0x91 => '\u{2018}', 0x92 => '\u{2019}', 0x93 => '\u{201c}',
+        0x94 => '\u{201d}', 0x95 => '\u{2022}', 0x96 => '\u{2013}', 0x97 => '\u{2014}',
+        0x98 => '\u{02dc}', 0x99 => '\u{2122}', 0x9a => '\u{0161}', 0x9b => '\u{203a}',
+        0x9c => '\u{0153}', 0x9e => '\u{017e}', 0x9f => '\u{0178}',
+        _ => return None,
+    };
+    Some(c)
+}
+
+// `s` follows "&#", returns the character and the number of bytes used
+fn numeric_reference(s: &str) -> Option<(char, usize)> {
+    let (radix, start) = if s.starts_with('x') || s.starts_with('X') { (16, 1) } else { (10, 0) };
+    let digits_len = s[start..].find(|c: char| !c.is_digit(radix)).unwrap_or(s.len() - start);
+    if digits_len == 0 {
+        return None;
+    }
+    let digits = &s[start..start + digits_len];
+    let mut len = start + digits_len;
+    if s[len..].starts_with(';') {
+        len += 1; // the semicolon is optional
+    }
+    // too many digits overflow into an invalid code point
+    let code = u32::from_str_radix(digits, radix).unwrap_or(u32::MAX);
+    Some((numeric_char(code), len))
+}
+
+// `s` follows "&", returns the character and the number of bytes used
+fn named_reference(s: &str, in_attribute: bool) -> Option<(char, usize)> {
+    let name_len = s.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(s.len());
+    let name = &s[..name_len];
+    if s[name_len..].starts_with(';') {
+        if let Some(c) = named_entity(name) {
+            return Some((c, name_len + 1));
+        }
+    }
+
+    let legacy = LEGACY_ENTITIES.iter().find(|legacy| name.starts_with(*legacy))?;
+    let next = s[legacy.len()..].chars().next();
+    if in_attribute && next.is_some_and(|c| c.is_ascii_alphanumeric() || c == '=') {
+        return None; // "?a=1&copy=2" in a URL stays as written
+    }
+    named_entity(legacy).map(|c| (c, legacy.len()))
+}
+
+// replace character references in text, leaving unknown ones as written
+pub fn decode_entities(s: &str) -> String {
+    decode_references(s, false)
+}
+
+fn decode_references(s: &str, in_attribute: bool) -> String {
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp + 1..];
+        let decoded = if let Some(number) = rest.strip_prefix('#') {
+            numeric_reference(number).map(|(c, len)| (c, len + 1))
+        } else {
+            named_reference(rest, in_attribute)
+        };
+        match decoded {
+            Some((c, len)) => {
+                result.push(c);
+                rest = &rest[len..];
             }
-            nodes.push(self.parse_node());
+            None => result.push('&'),
         }
-        nodes
     }
+    result.push_str(rest);
+    result
+}
+
+// https://html.spec.whatwg.org/multipage/syntax.html#optional-tags
+fn has_optional_end_tag(name: &str) -> bool {
+    match name {
+        "p" | "li" | "dt" | "dd" | "option" | "optgroup" |
+        "tr" | "td" | "th" | "thead" | "tbody" | "tfoot" => true,
+        _ => false
+    }
+}
+
+// whether a start tag `next` implicitly closes an open `current` element
+fn closes_implicitly(current: &str, next: &str) -> bool {
+    match current {
+        "p" => match next {
+            "address" | "article" | "aside" | "blockquote" | "details" | "div" |
+            "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "form" |
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "header" | "hgroup" |
+            "hr" | "main" | "menu" | "nav" | "ol" | "p" | "pre" | "section" |
+            "table" | "ul" => true,
+            _ => false
+        },
+        "li" => next == "li",
+        "dt" | "dd" => next == "dt" || next == "dd",
+        "option" => next == "option" || next == "optgroup",
+        "optgroup" => next == "optgroup",
+        "tr" => next == "tr" || next == "thead" || next == "tbody" || next == "tfoot",
+        "td" | "th" => match next {
+            "td" | "th" | "tr" | "thead" | "tbody" | "tfoot" => true,
+            _ => false
+        },
+        "thead" | "tbody" => next == "tbody" || next == "tfoot",
+        _ => false
+    }
+}
+
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    StartTag { name: String, attrs: dom::AttrMap, self_closing: bool }, // self_closing for "<br/>"
+    EndTag { name: String },
+    Text(String),
+    Comment(String),
+    Doctype(String),
+}
+
+// Splits the source into tokens, see the Iterator impl
+pub struct Tokenizer<'a> {
+    pos: usize,    // relative to input
+    input: String, // whole source, or the buffered part of it when streaming
+    options: ParseOptions,
+    raw_text_end: Option<String>, // set inside <script> and <style>
 
-    fn parse_node(&mut self) -> dom::Node {
-        match self.next_char() {
-            '<' => self.parse_element(),
-            _ => self.parse_text(),
+    // streaming state
+    source: Option<Box<dyn Read + 'a>>,
+    pending: Vec<u8>,   // bytes of an incomplete utf-8 sequence
+    discarded: usize,   // bytes already dropped from the front of input
+    io_error: Option<HtmlParseError>,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: String, options: ParseOptions) -> Tokenizer<'a> {
+        Tokenizer {
+            pos: 0,
+            input: input,
+            options: options,
+            raw_text_end: None,
+            source: None,
+            pending: Vec::new(),
+            discarded: 0,
+            io_error: None,
         }
     }
 
-    fn parse_element(&mut self) -> dom::Node {
-        assert_eq!(self.consume_char(), '<');
-        self.consume_whitespace();
-        let name = self.parse_tag_attr_name();
-        let attrs = self.parse_attributes();
-        assert_eq!(self.consume_char(), '>');
+    pub fn from_reader<R: Read + 'a>(reader: R, options: ParseOptions) -> Tokenizer<'a> {
+        let mut tokenizer = Tokenizer::new(String::new(), options);
+        tokenizer.source = Some(Box::new(reader));
+        tokenizer
+    }
 
-        if is_self_closing_tag(name.as_str()) {
-            return dom::Node::elem(name, attrs, Vec::new());
+    // byte offset in the whole source
+    pub fn offset(&self) -> usize {
+        self.discarded + self.pos
+    }
+
+    fn next_token(&mut self) -> ParseResult<Token> {
+        if let Some(end) = self.raw_text_end.take() {
+            // contents are not markup, keep them as a single text token
+            let text = self.consume_until(&end);
+            if !text.is_empty() {
+                return Ok(Token::Text(text));
+            }
         }
 
-        let children = self.parse_nodes();
+        if !self.starts_with("<") {
+            return Ok(Token::Text(self.parse_text()));
+        }
+        if self.starts_with("<!--") {
+            self.pos += "<!--".len();
+            let comment = self.consume_until("-->");
+            if !self.eof() {
+                self.pos += "-->".len();
+            }
+            return Ok(Token::Comment(comment));
+        }
+        if self.starts_with_ignore_case("<!doctype") {
+            self.pos += "<!".len();
+            let doctype = self.consume_while(|c| c != '>');
+            self.expect('>')?;
+            return Ok(Token::Doctype(doctype));
+        }
+        if self.starts_with("<?") || self.starts_with("<!") {
+            // processing instructions, CDATA and other `<!` markup, the `?` is kept
+            // https://html.spec.whatwg.org/multipage/parsing.html#bogus-comment-state
+            self.pos += if self.starts_with("<?") { 1 } else { 2 };
+            return Ok(Token::Comment(self.parse_bogus_comment()));
+        }
+        if self.starts_with("</") {
+            self.pos += "</".len();
+            self.consume_whitespace();
+            match self.peek_char() {
+                Some(c) if c.is_ascii_alphabetic() => {}
+                Some('>') => {
+                    // `</>` is dropped, an empty comment never reaches the tree
+                    self.pos += 1;
+                    return Ok(Token::Comment(String::new()));
+                }
+                _ => return Ok(Token::Comment(self.parse_bogus_comment())),
+            }
+            let name = self.parse_tag_name()?;
+            self.consume_while(|c| c != '>');
+            self.expect('>')?;
+            return Ok(Token::EndTag { name: name });
+        }
 
-        assert_eq!(self.consume_char(), '<');
-        assert_eq!(self.consume_char(), '/');
-        assert_eq!(self.parse_tag_attr_name(), name);
-        assert_eq!(self.consume_char(), '>');
+        self.expect('<')?;
+        match self.peek_char() {
+            Some(c) if c.is_ascii_alphabetic() => {}
+            // a `<` that does not start a tag is just text
+            _ => return Ok(Token::Text(format!("<{}", self.parse_text()))),
+        }
+        let name = self.parse_tag_name()?;
+        let (attrs, self_closing) = self.parse_attributes()?;
+        self.expect('>')?;
+        if is_raw_text_tag(&name.to_ascii_lowercase()) && !self_closing {
+            self.raw_text_end = Some(format!("</{}", name));
+        }
+        Ok(Token::StartTag { name: name, attrs: attrs, self_closing: self_closing })
+    }
 
-        dom::Node::elem(name, attrs, children)
+    // make at least `n` bytes available after pos unless the source runs out
+    fn fill(&mut self, n: usize) {
+        while self.input.len() - self.pos < n {
+            let reader = match self.source {
+                Some(ref mut reader) => reader,
+                None => return,
+            };
+            let mut chunk = [0; READ_CHUNK_SIZE];
+            let len = match reader.read(&mut chunk) {
+                Ok(0) => {
+                    // a truncated utf-8 sequence at the very end
+                    self.input.push_str(&String::from_utf8_lossy(&self.pending));
+                    self.pending.clear();
+                    self.source = None;
+                    return;
+                }
+                Ok(len) => len,
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.io_error = Some(HtmlParseError::Io {
+                        pos: self.discarded + self.input.len(),
+                        message: err.to_string(),
+                    });
+                    self.source = None;
+                    return;
+                }
+            };
+            self.pending.extend_from_slice(&chunk[..len]);
+            self.decode_pending();
+        }
     }
 
-    fn parse_tag_attr_name(&mut self) -> String {
-        // assume tag and attributes names have no "-" or "_"
-        self.consume_while(|c| c.is_alphanumeric())
+    // move complete utf-8 sequences from pending into input
+    fn decode_pending(&mut self) {
+        loop {
+            match str::from_utf8(&self.pending) {
+                Ok(s) => {
+                    self.input.push_str(s);
+                    self.pending.clear();
+                    return;
+                }
+                Err(err) => {
+                    let valid = err.valid_up_to();
+                    self.input.push_str(str::from_utf8(&self.pending[..valid]).unwrap());
+                    match err.error_len() {
+                        Some(len) => { // invalid sequence
+                            self.input.push(std::char::REPLACEMENT_CHARACTER);
+                            self.pending.drain(..valid + len);
+                        }
+                        None => { // incomplete sequence, wait for more bytes
+                            self.pending.drain(..valid);
+                            return;
+                        }
+                    }
+                }
+            }
+        }
     }
 
-    fn parse_attributes(&mut self) -> dom::AttrMap {
-        let mut attrs = HashMap::new();
+    // drop the tokenized prefix of a streamed input
+    fn compact(&mut self) {
+        if self.source.is_some() && self.pos >= READ_CHUNK_SIZE {
+            self.input.drain(..self.pos);
+            self.discarded += self.pos;
+            self.pos = 0;
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#tag-name-state
+    fn parse_tag_name(&mut self) -> ParseResult<String> {
+        if !self.eof() && !self.next_char().is_ascii_alphabetic() {
+            return Err(HtmlParseError::MissingName { pos: self.offset() });
+        }
+        self.parse_name(valid_tag_name_char)
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#attribute-name-state
+    fn parse_attr_name(&mut self) -> ParseResult<String> {
+        self.parse_name(valid_attr_name_char)
+    }
+
+    // an empty name is an error
+    fn parse_name<F>(&mut self, test: F) -> ParseResult<String>
+    where
+        F: Fn(char) -> bool,
+    {
+        let pos = self.offset();
+        let mut name = self.consume_while(test);
+        if !self.options.preserve_case {
+            name.make_ascii_lowercase();
+        }
+        if name.is_empty() {
+            return Err(if self.eof() {
+                HtmlParseError::UnexpectedEof { pos: pos }
+            } else {
+                HtmlParseError::MissingName { pos: pos }
+            });
+        }
+        Ok(name)
+    }
+
+    // attributes and whether the tag ends with "/>"
+    fn parse_attributes(&mut self) -> ParseResult<(dom::AttrMap, bool)> {
+        let mut attrs = dom::AttrMap::new();
+        let mut self_closing = false;
         loop {
             self.consume_whitespace();
-            if self.next_char() == '>' {
-                break;
+            if self.eof() {
+                return Err(HtmlParseError::UnexpectedEof { pos: self.offset() });
+            }
+            match self.next_char() {
+                '>' => break,
+                '/' => {
+                    self.consume_char();
+                    self_closing = self.starts_with(">"); // a lone "/" is ignored
+                }
+                _ => {
+                    let (name, value) = self.parse_attr()?;
+                    attrs.insert(name, value); // a duplicate attribute is dropped
+                    self_closing = false;
+                }
             }
-            // if self.eof() {
-            //     panic!("Unclosed tag:< found");
-            // }
-            let (name, value) = self.parse_attr();
-            attrs.insert(name, value);
         }
-        attrs
+        Ok((attrs, self_closing))
     }
 
-    fn parse_attr(&mut self) -> (String, String) {
-        let name = self.parse_tag_attr_name();
+    fn parse_attr(&mut self) -> ParseResult<(String, String)> {
+        let name = self.parse_attr_name()?;
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), '=');
+        if self.eof() || self.next_char() != '=' {
+            return Ok((name, String::new())); // boolean attribute like <input disabled>
+        }
+        self.expect('=')?;
         self.consume_whitespace();
-        let value = self.parse_attr_value();
-        (name, value)
+        let value = self.parse_attr_value()?;
+        Ok((name, value))
     }
 
-    fn parse_attr_value(&mut self) -> String {
-        let open_quote = self.consume_char();
-        println!("{}", open_quote);
-        assert!(open_quote == '"' || open_quote == '\'');
-        let value = self.consume_while(|c| c != open_quote);
-        assert_eq!(self.consume_char(), open_quote);
-        value
+    // https://html.spec.whatwg.org/multipage/syntax.html#attributes-2
+    fn parse_attr_value(&mut self) -> ParseResult<String> {
+        if self.eof() {
+            return Err(HtmlParseError::UnexpectedEof { pos: self.offset() });
+        }
+        let value = match self.next_char() {
+            open_quote @ '"' | open_quote @ '\'' => {
+                self.consume_char();
+                let value = self.consume_while(|c| c != open_quote);
+                self.expect(open_quote)?;
+                value
+            }
+            _ => self.consume_while(|c| match c {
+                '"' | '\'' | '=' | '<' | '>' | '`' => false,
+                c => !c.is_whitespace(),
+            }),
+        };
+        Ok(self.decode(value, true))
+    }
+
+    // everything up to the next `>`, an unterminated one runs to the end of input
+    fn parse_bogus_comment(&mut self) -> String {
+        let comment = self.consume_while(|c| c != '>');
+        if !self.eof() {
+            self.pos += 1;
+        }
+        comment
     }
 
-    fn parse_text(&mut self) -> dom::Node {
-        dom::Node::text(self.consume_while(|c| c != '<'))
+    fn parse_text(&mut self) -> String {
+        let text = self.consume_while(|c| c != '<');
+        self.decode(text, false)
+    }
+
+    fn decode(&self, s: String, in_attribute: bool) -> String {
+        if self.options.decode_entities && s.contains('&') {
+            decode_references(&s, in_attribute)
+        } else {
+            s
+        }
     }
 
     fn consume_whitespace(&mut self) {
@@ -127,23 +660,602 @@ impl Parser {
         result
     }
 
+    // ascii case-insensitive, so "</script" also ends at "</SCRIPT"
+    fn consume_until(&mut self, s: &str) -> String {
+        let mut result = String::new();
+        while !self.eof() && !self.starts_with_ignore_case(s) {
+            result.push(self.consume_char());
+        }
+        result
+    }
+
+    fn expect(&mut self, expected: char) -> ParseResult<()> {
+        if self.eof() {
+            return Err(HtmlParseError::UnexpectedEof { pos: self.offset() });
+        }
+        let pos = self.offset();
+        match self.consume_char() {
+            c if c == expected => Ok(()),
+            found => Err(HtmlParseError::UnexpectedChar {
+                pos: pos,
+                expected: expected,
+                found: found,
+            }),
+        }
+    }
+
     fn consume_char(&mut self) -> char {
-        let mut iter = self.input[self.pos..].char_indices();
-        let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
-        self.pos += next_pos;
+        let cur_char = self.next_char();
+        self.pos += cur_char.len_utf8();
         cur_char
     }
 
-    fn next_char(&self) -> char {
+    fn next_char(&mut self) -> char {
+        self.fill(1);
         self.input[self.pos..].chars().next().unwrap()
     }
 
-    fn starts_with(&self, s: &str) -> bool {
+    fn peek_char(&mut self) -> Option<char> {
+        if self.eof() { None } else { Some(self.next_char()) }
+    }
+
+    fn starts_with(&mut self, s: &str) -> bool {
+        self.fill(s.len());
         self.input[self.pos..].starts_with(s)
     }
 
+    fn starts_with_ignore_case(&mut self, s: &str) -> bool {
+        self.fill(s.len());
+        self.input[self.pos..].get(..s.len()).is_some_and(|head| head.eq_ignore_ascii_case(s))
+    }
+
     fn eof(&mut self) -> bool {
+        self.fill(1);
         self.pos >= self.input.len()
     }
-}
\ No newline at end of file
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = ParseResult<Token>;
+
+    // None at the end of input, an error ends the token stream
+    fn next(&mut self) -> Option<ParseResult<Token>> {
+        self.compact();
+        if self.eof() {
+            return self.io_error.take().map(Err);
+        }
+        let token = if self.discarded + self.input.len() > self.options.max_input_size {
+            Err(HtmlParseError::TooLarge {
+                pos: self.offset(),
+                limit: self.options.max_input_size,
+            })
+        } else {
+            self.next_token()
+        };
+        if token.is_err() {
+            self.pos = self.input.len();
+            self.source = None;
+        }
+        Some(token)
+    }
+}
+
+struct OpenElement {
+    key: String, // lowercased name used by the tree construction rules
+    name: String,
+    attrs: dom::AttrMap,
+    children: Vec<dom::Node>,
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#the-insertion-mode (simplified)
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum InsertionMode {
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+    Fragment, // no implied html/head/body
+}
+
+// Builds the DOM from tokens, inferring omitted end tags
+struct TreeBuilder {
+    open: Vec<OpenElement>, // outermost first
+    nodes: Vec<dom::Node>,  // top-level nodes
+    mode: InsertionMode,
+}
+
+impl TreeBuilder {
+    // the result is a single html element holding head and body
+    fn document() -> TreeBuilder {
+        let mut builder = TreeBuilder::fragment();
+        builder.mode = InsertionMode::BeforeHead;
+        builder.push("html".to_string(), dom::AttrMap::new());
+        builder
+    }
+
+    fn fragment() -> TreeBuilder {
+        TreeBuilder {
+            open: Vec::new(),
+            nodes: Vec::new(),
+            mode: InsertionMode::Fragment,
+        }
+    }
+
+    // the open element stack replaces recursion, so only max_depth bounds nesting
+    fn build(mut self, mut tokens: Tokenizer) -> ParseResult<Vec<dom::Node>> {
+        let max_depth = tokens.options.max_depth;
+        while let Some(token) = tokens.next() {
+            self.process(token?);
+            if self.open.len() > max_depth {
+                return Err(HtmlParseError::TooDeep { pos: tokens.offset(), limit: max_depth });
+            }
+        }
+        self.insert_missing_sections();
+        // close unclosed elements at EOF
+        while !self.open.is_empty() {
+            self.close_current();
+        }
+        Ok(self.nodes)
+    }
+
+    // a document always ends up with both head and body
+    fn insert_missing_sections(&mut self) {
+        if self.mode == InsertionMode::BeforeHead {
+            self.push("head".to_string(), dom::AttrMap::new());
+            self.mode = InsertionMode::InHead;
+        }
+        if self.mode == InsertionMode::InHead {
+            self.close_to(1);
+            self.mode = InsertionMode::AfterHead;
+        }
+        if self.mode == InsertionMode::AfterHead {
+            self.push("body".to_string(), dom::AttrMap::new());
+            self.mode = InsertionMode::InBody;
+        }
+    }
+
+    fn process(&mut self, token: Token) {
+        if !self.insert_implied(&token) {
+            return;
+        }
+        match token {
+            Token::StartTag { name, attrs, self_closing } => {
+                let key = name.to_ascii_lowercase();
+                if let Some(depth) = self.implied_end(&key) {
+                    self.close_to(depth);
+                }
+                if self_closing || is_self_closing_tag(&key) {
+                    self.append(dom::Node::elem(name, attrs, Vec::new()));
+                } else {
+                    self.push(name, attrs);
+                }
+            }
+            Token::EndTag { name } => {
+                // stray end tags are ignored
+                let key = name.to_ascii_lowercase();
+                if let Some(depth) = self.open.iter().rposition(|elem| elem.key == key) {
+                    self.close_to(depth);
+                }
+            }
+            Token::Text(text) => {
+                // whitespace is kept here and handled by dom::normalize_whitespace
+                if !text.is_empty() {
+                    self.append(dom::Node::text(text));
+                }
+            }
+            Token::Comment(_) | Token::Doctype(_) => {}
+        }
+    }
+
+    // synthesize html/head/body around the token, false if the token is consumed
+    fn insert_implied(&mut self, token: &Token) -> bool {
+        let is_whitespace = match *token {
+            Token::Text(ref text) => text.trim_matches(|c: char| c.is_ascii_whitespace()).is_empty(),
+            _ => false,
+        };
+        let start_tag = match *token {
+            Token::StartTag { ref name, .. } => Some(name.to_ascii_lowercase()),
+            _ => None,
+        };
+        let end_tag = match *token {
+            Token::EndTag { ref name } => Some(name.to_ascii_lowercase()),
+            _ => None,
+        };
+
+        if self.mode == InsertionMode::Fragment {
+            return true;
+        }
+        if start_tag.as_deref() == Some("html") {
+            self.merge_attrs(0, token);
+            return false;
+        }
+        if end_tag.as_deref() == Some("html") || end_tag.as_deref() == Some("body") {
+            return false; // keep trailing content in body
+        }
+
+        if self.mode == InsertionMode::BeforeHead {
+            match *token {
+                _ if is_whitespace => return false,
+                Token::Comment(_) | Token::Doctype(_) => return false,
+                Token::StartTag { ref name, ref attrs, .. } if name.eq_ignore_ascii_case("head") => {
+                    self.push(name.clone(), attrs.clone());
+                    self.mode = InsertionMode::InHead;
+                    return false;
+                }
+                _ => {
+                    self.push("head".to_string(), dom::AttrMap::new());
+                    self.mode = InsertionMode::InHead;
+                }
+            }
+        }
+
+        if self.mode == InsertionMode::InHead {
+            if self.open.last().is_some_and(|elem| elem.key != "head") {
+                return true; // inside <title> etc.
+            }
+            match *token {
+                _ if is_whitespace => return false,
+                Token::Comment(_) | Token::Doctype(_) => return false,
+                _ if start_tag.as_deref().is_some_and(is_metadata_tag) => return true,
+                _ => {
+                    self.close_to(1);
+                    self.mode = InsertionMode::AfterHead;
+                    if end_tag.as_deref() == Some("head") {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        if self.mode == InsertionMode::AfterHead {
+            match *token {
+                _ if is_whitespace => return false,
+                Token::Comment(_) | Token::Doctype(_) => return false,
+                Token::StartTag { ref name, ref attrs, .. } if name.eq_ignore_ascii_case("body") => {
+                    self.push(name.clone(), attrs.clone());
+                    self.mode = InsertionMode::InBody;
+                    return false;
+                }
+                _ => {
+                    self.push("body".to_string(), dom::AttrMap::new());
+                    self.mode = InsertionMode::InBody;
+                }
+            }
+        }
+
+        match start_tag.as_deref() {
+            Some("body") => {
+                self.merge_attrs(1, token);
+                false
+            }
+            Some("head") => false,
+            _ => true,
+        }
+    }
+
+    // attributes of a repeated html/body start tag are added if not already present
+    fn merge_attrs(&mut self, depth: usize, token: &Token) {
+        if let (Some(elem), &Token::StartTag { ref attrs, .. }) = (self.open.get_mut(depth), token) {
+            for &(ref name, ref value) in attrs {
+                elem.attrs.insert(name.clone(), value.clone());
+            }
+        }
+    }
+
+    // depth of the outermost open element closed by the start tag `next`
+    fn implied_end(&self, next: &str) -> Option<usize> {
+        for (depth, current) in self.open.iter().enumerate().rev() {
+            if closes_implicitly(&current.key, next) {
+                return Some(depth);
+            }
+            if !has_optional_end_tag(&current.key) {
+                break;
+            }
+        }
+        None
+    }
+
+    // close open elements until only `depth` remain
+    fn close_to(&mut self, depth: usize) {
+        while self.open.len() > depth {
+            self.close_current();
+        }
+    }
+
+    fn push(&mut self, name: String, attrs: dom::AttrMap) {
+        self.open.push(OpenElement {
+            key: name.to_ascii_lowercase(),
+            name: name,
+            attrs: attrs,
+            children: Vec::new(),
+        });
+    }
+
+    fn close_current(&mut self) {
+        let elem = self.open.pop().unwrap();
+        if elem.key == "template" {
+            self.append(dom::Node::template(elem.name, elem.attrs, elem.children));
+        } else {
+            self.append(dom::Node::elem(elem.name, elem.attrs, elem.children));
+        }
+    }
+
+    fn append(&mut self, node: dom::Node) {
+        let siblings = match self.open.last_mut() {
+            Some(parent) => &mut parent.children,
+            None => &mut self.nodes,
+        };
+        // text split around a stray `<` ends up in one node
+        if let (Some(&mut dom::Node { data: dom::NodeType::Text(ref mut last), .. }), &dom::NodeType::Text(ref body)) =
+            (siblings.last_mut(), &node.data)
+        {
+            last.push_str(body);
+            return;
+        }
+        siblings.push(node);
+    }
+}
+
+fn document_node(mut nodes: Vec<dom::Node>) -> dom::Node {
+    nodes.swap_remove(0) // the html element built by TreeBuilder::document
+}
+
+#[test]
+fn test_decode_entities() {
+    let src = "<p title=\"a &amp; b\">&lt;hi&gt; &#x27;x&#39; &unknown; &amp</p>";
+    let root = parse(src.to_string());
+    assert_eq!(root.text_content(), "<hi> 'x' &unknown; &");
+    match root.children[1].children[0].data { // html > body > p
+        dom::NodeType::Element(ref elem) => assert_eq!(elem.attrs["title"], "a & b"),
+        _ => panic!("expected element"),
+    }
+
+    let options = ParseOptions { decode_entities: false, ..ParseOptions::default() };
+    let raw = parse_with_options(src.to_string(), options);
+    assert_eq!(raw.text_content(), "&lt;hi&gt; &#x27;x&#39; &unknown; &amp");
+}
+
+#[test]
+fn test_character_reference_edge_cases() {
+    let cases = [
+        ("&#38;", "&"),
+        ("&#x26;", "&"),
+        ("&#X3c;", "<"),
+        ("&#0038;", "&"),
+        ("&#38 x", "& x"),          // missing semicolon
+        ("&#x41B", "\u{41b}"),      // hex digits run to the first non-digit
+        ("&#;", "&#;"),              // no digits is not a reference
+        ("&#x;", "&#x;"),
+        ("&#0;", "\u{fffd}"),
+        ("&#xd800;", "\u{fffd}"),   // surrogate
+        ("&#x110000;", "\u{fffd}"), // beyond unicode
+        ("&#99999999999;", "\u{fffd}"),
+        ("&#x80;", "\u{20ac}"),     // windows-1252 for C1 controls
+        ("&#x81;", "\u{81}"),
+        ("&amp", "&"),
+        ("&notanentity;", "&notanentity;"),
+        ("&copyx", "\u{a9}x"),
+    ];
+    for &(src, expected) in cases.iter() {
+        assert_eq!(decode_entities(src), expected, "decoding {}", src);
+    }
+    assert_eq!(decode_references("?a=1&copy=2&lt;", true), "?a=1&copy=2<");
+}
+
+#[test]
+fn test_boolean_and_unquoted_attributes() {
+    let nodes = parse_fragment("<input disabled width=100 checked name='a'>".to_string()).unwrap();
+    match nodes[0].data {
+        dom::NodeType::Element(ref elem) => {
+            assert_eq!(elem.attrs["disabled"], "");
+            assert_eq!(elem.attrs["width"], "100");
+            assert_eq!(elem.attrs["checked"], "");
+            assert_eq!(elem.attrs["name"], "a");
+        }
+        _ => panic!("expected element"),
+    }
+}
+
+#[test]
+fn test_implied_end_tags() {
+    let src = "<div><ul><li>one<li>two</ul><p>a<p>b</span><div>c";
+    let root = &parse_fragment(src.to_string()).unwrap()[0];
+    let expected = "<div>\n  <ul>\n    <li>\n      #text: one\n    <li>\n      #text: two\n  \
+        <p>\n    #text: a\n  <p>\n    #text: b\n  <div>\n    #text: c\n";
+    assert_eq!(format!("{}", root), expected);
+}
+
+#[test]
+fn test_self_closing_syntax() {
+    let src = "<div><br/><img src=\"x\" /><span/>a<p>b</p></div>";
+    let root = &parse_fragment(src.to_string()).unwrap()[0];
+    let expected = "<div>\n  <br>\n  <img src=\"x\">\n  <span>\n  #text: a\n  <p>\n    #text: b\n";
+    assert_eq!(format!("{}", root), expected);
+}
+
+#[test]
+fn test_case_insensitive_names() {
+    let src = "<DIV Class=a><P>x</p><SCRIPT>a</b</Script></div>";
+    let root = &parse_fragment(src.to_string()).unwrap()[0];
+    let expected = "<div class=\"a\">\n  <p>\n    #text: x\n  <script>\n    #text: a</b\n";
+    assert_eq!(format!("{}", root), expected);
+
+    let options = ParseOptions { preserve_case: true, ..ParseOptions::default() };
+    let root = parse_with_options(src.to_string(), options);
+    let expected = "<DIV Class=\"a\">\n  <P>\n    #text: x\n  <SCRIPT>\n    #text: a</b\n";
+    assert_eq!(format!("{}", root.children[1].children[0]), expected);
+}
+
+#[test]
+fn test_whitespace_modes() {
+    let src = "<div>\n  <span> a \n  b </span>\n  <pre> x\n  y </pre>\n</div>";
+    let collapsed = parse(src.to_string());
+    let expected = "<div>\n  <span>\n    #text:  a b \n  <pre>\n    #text:  x\\n  y \n";
+    assert_eq!(format!("{}", collapsed.children[1].children[0]), expected);
+
+    let options = ParseOptions { whitespace: dom::WhitespaceMode::Trim, ..ParseOptions::default() };
+    let trimmed = parse_with_options(src.to_string(), options);
+    assert_eq!(trimmed.children[1].children[0].children[0].text_content(), "a b");
+
+    let options = ParseOptions { whitespace: dom::WhitespaceMode::Preserve, ..ParseOptions::default() };
+    let preserved = parse_with_options(src.to_string(), options);
+    assert_eq!(preserved.children[1].children[0].children.len(), 5);
+
+    // the space between inline elements is kept, the ones around the block are not
+    let root = parse("<div>\n<p><b>bold</b> <i>italic</i>\n</p>\n</div>".to_string());
+    assert_eq!(root.children[1].children[0].children.len(), 1);
+    assert_eq!(root.children[1].children[0].text_content(), "bold italic");
+}
+
+#[test]
+fn test_custom_element_and_data_attribute_names() {
+    let src = "<my-component data-id=\"7\" aria-label=x xlink:href=\"#a\" _private>\
+        <svg:rect></svg:rect></my-component>";
+    let root = &parse_fragment(src.to_string()).unwrap()[0];
+    match root.data {
+        dom::NodeType::Element(ref elem) => {
+            assert_eq!(elem.tag_name, "my-component");
+            assert_eq!(elem.attrs["data-id"], "7");
+            assert_eq!(elem.attrs["aria-label"], "x");
+            assert_eq!(elem.attrs["xlink:href"], "#a");
+            assert_eq!(elem.attrs["_private"], "");
+        }
+        _ => panic!("expected element"),
+    }
+    assert_eq!(format!("{}", root.children[0]), "<svg:rect>\n");
+}
+
+#[test]
+fn test_attribute_order_and_duplicates() {
+    let root = &parse_fragment("<p b=1 a=2 B=3 c>x</p>".to_string()).unwrap()[0];
+    assert_eq!(format!("{}", root), "<p b=\"1\" a=\"2\" c=\"\">\n  #text: x\n");
+}
+
+#[test]
+fn test_template_content() {
+    let root = parse("<ul id=list></ul><template id=row><li> item </li></template>".to_string());
+    let template = root.get_element_by_id("row").unwrap();
+    assert!(template.children.is_empty());
+    match template.data {
+        dom::NodeType::Element(ref elem) => {
+            let content = elem.template_content.as_ref().unwrap();
+            assert_eq!(format!("{}", content[0]), "<li>\n  #text:  item \n");
+        }
+        _ => panic!("expected element"),
+    }
+    assert_eq!(template.to_html(), "<template id=\"row\"><li> item </li></template>");
+}
+
+#[test]
+fn test_parse_bytes_encodings() {
+    // "café" in Latin-1 and Shift_JIS "日本"
+    let latin1 = b"<meta charset=\"iso-8859-1\"><p>caf\xe9</p>";
+    assert_eq!(parse_bytes(latin1).unwrap().text_content(), "caf\u{e9}");
+
+    let sjis = b"<meta http-equiv=Content-Type content='text/html; charset=Shift_JIS'><p>\x93\xfa\x96\x7b</p>";
+    assert_eq!(sniff_encoding(sjis).name(), "Shift_JIS");
+    assert_eq!(parse_bytes(sjis).unwrap().text_content(), "\u{65e5}\u{672c}");
+
+    let bom = b"\xef\xbb\xbf<meta charset=latin1><p>\xc3\xa9</p>";
+    assert_eq!(parse_bytes(bom).unwrap().text_content(), "\u{e9}");
+}
+
+#[test]
+fn test_depth_and_size_limits() {
+    let deep = "<div>".repeat(1000);
+    match try_parse(deep.clone()) {
+        Err(HtmlParseError::TooDeep { pos, limit: 512 }) => assert_eq!(pos, 511 * 5), // + html, body
+        result => panic!("unexpected {:?}", result),
+    }
+    let options = ParseOptions { max_depth: 2000, ..ParseOptions::default() };
+    assert!(try_parse_with_options(deep, options).is_ok());
+
+    let options = ParseOptions { max_input_size: 16, ..ParseOptions::default() };
+    match try_parse_with_options("<p>some longer text</p>".to_string(), options) {
+        Err(HtmlParseError::TooLarge { limit: 16, .. }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn test_implied_document_structure() {
+    let root = parse("<title>t</title><link rel=x> <p>a</p></body>b".to_string());
+    let expected = "<html>\n  <head>\n    <title>\n      #text: t\n    <link rel=\"x\">\n  \
+        <body>\n    <p>\n      #text: a\n    #text: b\n";
+    assert_eq!(format!("{}", root), expected);
+
+    let root = parse("<html lang=en><head></head><body class=c><p>x</html>".to_string());
+    let expected = "<html lang=\"en\">\n  <head>\n  <body class=\"c\">\n    <p>\n      #text: x\n";
+    assert_eq!(format!("{}", root), expected);
+}
+
+#[test]
+fn test_try_parse_errors() {
+    assert_eq!(
+        try_parse("<div class='a".to_string()).unwrap_err(),
+        HtmlParseError::UnexpectedEof { pos: 13 }
+    );
+    assert_eq!(
+        try_parse("<p>a</p><div id=x =y>".to_string()).unwrap_err(),
+        HtmlParseError::MissingName { pos: 18 }
+    );
+    assert!(try_parse("<p>fine</p>".to_string()).is_ok());
+}
+
+#[test]
+fn test_parse_from_reader() {
+    // a reader handing out a few bytes at a time, splitting multi-byte chars
+    struct Trickle<'a>(&'a [u8]);
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.0.len().min(buf.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    let mut src = String::from("<ul>");
+    for i in 0..2000 {
+        src.push_str(&format!("<li class=\"item\">こんにちは {}</li>", i));
+    }
+    src.push_str("</ul>");
+
+    let streamed = parse_from_reader(Trickle(src.as_bytes())).unwrap();
+    assert_eq!(format!("{}", streamed), format!("{}", parse(src)));
+}
+
+#[test]
+fn test_tokenize() {
+    let src = "<!DOCTYPE html><p id=a>x &amp; y<!-- note --></p><script>1 < 2</script>";
+    let tokens: Vec<Token> = Tokenizer::new(src.to_string(), ParseOptions::default())
+        .map(|token| token.unwrap()).collect();
+    let mut attrs = dom::AttrMap::new();
+    attrs.insert("id".to_string(), "a".to_string());
+    assert_eq!(tokens, vec![
+        Token::Doctype("DOCTYPE html".to_string()),
+        Token::StartTag { name: "p".to_string(), attrs: attrs, self_closing: false },
+        Token::Text("x & y".to_string()),
+        Token::Comment(" note ".to_string()),
+        Token::EndTag { name: "p".to_string() },
+        Token::StartTag { name: "script".to_string(), attrs: dom::AttrMap::new(), self_closing: false },
+        Token::Text("1 < 2".to_string()),
+        Token::EndTag { name: "script".to_string() },
+    ]);
+}
+
+#[test]
+fn test_bogus_markup() {
+    let src = "<?xml version=\"1.0\"?><!DOCTYPE html><![CDATA[x]]><p>a < b <3 </ 3></>c<!foo></p>";
+    let root = parse(src.to_string());
+    let p = &root.children[1].children[0];
+    assert_eq!(p.children.len(), 1);
+    assert_eq!(p.text_content(), "a < b <3 c");
+
+    let tokens: Vec<Token> = Tokenizer::new("<?php echo 1 ?><!x".to_string(), ParseOptions::default())
+        .map(|token| token.unwrap()).collect();
+    assert_eq!(tokens, vec![
+        Token::Comment("?php echo 1 ?".to_string()),
+        Token::Comment("x".to_string()),
+    ]);
+}
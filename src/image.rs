@@ -0,0 +1,113 @@
+use crate::css::Color;
+use crate::dom::NodeType;
+use crate::style::StyledNode;
+use crate::url;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::{fmt, fs, io};
+
+// the intrinsic width and height of the loaded images by their src as written
+pub type ImageSizes = HashMap<String, (f64, f64)>;
+
+// decoded pixels, row by row from the top left
+#[derive(Serialize, Deserialize)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+// without the pixels
+impl fmt::Debug for Image {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Image({}x{})", self.width, self.height)
+    }
+}
+
+// the decoded images by their src or url as written
+pub type Images = HashMap<String, Rc<Image>>;
+
+// load every <img> and background-image of the styled tree, `document_url` is
+// the path of the html file and urls are relative to its directory (or to <base href>)
+pub fn load_images(root: &StyledNode, document_url: &str) -> Images {
+    let mut urls = Vec::new();
+    collect_image_urls(root, &mut urls);
+
+    let mut images = Images::new();
+    for url in urls {
+        if images.contains_key(url) {
+            continue;
+        }
+        let path = root.node.resolve_url(document_url, url);
+        match load_image(&path) {
+            Ok(image) => {
+                images.insert(url.to_string(), Rc::new(image));
+            }
+            Err(err) => eprintln!("cannot load image {}: {}", path, err),
+        }
+    }
+    images
+}
+
+// the intrinsic sizes of the decoded images, for layout
+pub fn sizes_of(images: &Images) -> ImageSizes {
+    images.iter().map(|(src, image)| (src.clone(), (image.width as f64, image.height as f64))).collect()
+}
+
+fn collect_image_urls<'a>(node: &'a StyledNode, urls: &mut Vec<&'a str>) {
+    if let NodeType::Element(ref elem) = node.node.data {
+        if elem.tag_name == "img" {
+            if let Some(src) = elem.attrs.get("src") {
+                urls.push(src);
+            }
+        }
+    }
+    if let Some(ref background) = node.computed.background_image {
+        urls.push(&background.url);
+    }
+    for child in &node.children {
+        collect_image_urls(child, urls);
+    }
+}
+
+fn load_image(path: &str) -> io::Result<Image> {
+    let bytes = read_local(path)?;
+    decode(&bytes).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "cannot decode image"))
+}
+
+// only local files for now
+fn read_local(path: &str) -> io::Result<Vec<u8>> {
+    let path = path.trim_start_matches("file://");
+    if url::is_absolute(path) {
+        return Err(io::Error::other("only local files are supported"));
+    }
+    fs::read(path)
+}
+
+// png, jpeg, gif and the other formats the image crate knows
+pub fn decode(bytes: &[u8]) -> Option<Image> {
+    let rgba = ::image::load_from_memory(bytes).ok()?.to_rgba8();
+    Some(Image {
+        width: rgba.width(),
+        height: rgba.height(),
+        pixels: rgba.pixels().map(|p| Color { r: p[0], g: p[1], b: p[2], a: p[3] }).collect(),
+    })
+}
+
+#[test]
+fn test_decode() {
+    let mut png = Vec::new();
+    let pixels = ::image::RgbaImage::from_fn(2, 1, |x, _| ::image::Rgba([255, 0, 0, if x == 0 { 255 } else { 128 }]));
+    ::image::DynamicImage::ImageRgba8(pixels).write_to(&mut png, ::image::ImageOutputFormat::Png).unwrap();
+
+    let image = decode(&png).unwrap();
+    assert_eq!((image.width, image.height), (2, 1));
+    assert_eq!(image.pixels, [Color { r: 255, g: 0, b: 0, a: 255 }, Color { r: 255, g: 0, b: 0, a: 128 }]);
+    assert!(decode(b"not an image").is_none());
+
+    let mut images = Images::new();
+    images.insert("a.png".to_string(), Rc::new(image));
+    assert_eq!(sizes_of(&images)["a.png"], (2.0, 1.0));
+}
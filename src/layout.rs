@@ -1,31 +1,125 @@
-use crate::style::{Display, StyledNode};
-use crate::css::{Unit, Value};
+use crate::style::{Clear, ComputedStyle, Direction, Display, Float, Overflow, PageBreak, Position, Sides, StyledNode, TextAlign, WhiteSpace};
+use crate::css::{LengthContext, Unit, Value};
 use crate::css::Value::{Keyword, Length};
-use crate::dom::NodeType;
+use crate::dom::{AttrMap, NodeType};
+use crate::image::ImageSizes;
+use serde::{Deserialize, Serialize};
 use std::default::Default;
+use std::error;
 use std::fmt;
+use std::sync::Arc;
+use unicode_bidi::{BidiInfo, Level};
 
-pub struct LayoutBox<'a> {
+pub struct LayoutBox {
     pub dimensions: Dimensions,
-    pub box_type: BoxType<'a>,
-    pub children: Vec<LayoutBox<'a>>,
+    pub box_type: BoxType,
+    pub children: Vec<LayoutBox>,
+    pub lines: Vec<LineFragment>, // the pieces of a text box, one per line it spans
+    pub scrollable_overflow: Rect, // the padding box and whatever of the descendants sticks out of it
+    pub clip: Option<Rect>,        // the padding box when the overflow is hidden
+    pub intrinsic_widths: IntrinsicWidths, // measured before layout
 }
 
-pub enum BoxType<'a> {
-    BlockNode(&'a StyledNode<'a>),
-    InlineNode(&'a StyledNode<'a>),
+// the margin box width with a line break at every opportunity and with none,
+// what shrink-to-fit and table columns are sized from
+// https://www.w3.org/TR/css-sizing-3/#intrinsic-sizes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IntrinsicWidths {
+    pub min_content: f64,
+    pub max_content: f64,
+}
+
+// what layout and painting need of a styled node. the layout tree owns it,
+// so it lives on without the dom and the style tree and can go to other threads
+#[derive(Clone, Debug)]
+pub struct NodeStyle {
+    pub computed: ComputedStyle,
+    pub text: Option<String>,     // the body of a text node
+    pub tag_name: Option<String>, // of an element
+    pub attrs: AttrMap,           // empty for a text node
+}
+
+impl NodeStyle {
+    pub fn new(node: &StyledNode) -> NodeStyle {
+        let (text, tag_name, attrs) = match node.node.data {
+            NodeType::Text(ref body) => (Some(body.clone()), None, AttrMap::new()),
+            NodeType::Element(ref elem) => (None, Some(elem.tag_name.to_string()), elem.attrs.clone()),
+        };
+        NodeStyle {
+            computed: node.computed.clone(),
+            text: text,
+            tag_name: tag_name,
+            attrs: attrs,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LineFragment {
+    pub text: String,
+    pub rect: Rect,
+}
+
+pub enum BoxType {
+    BlockNode(Arc<NodeStyle>),
+    InlineNode(Arc<NodeStyle>),
+    InlineBlockNode(Arc<NodeStyle>),
+    ListItemNode(Arc<NodeStyle>),
+    TableNode(Arc<NodeStyle>),
+    TableRowGroupNode(Arc<NodeStyle>),
+    TableRowNode(Arc<NodeStyle>),
+    TableCellNode(Arc<NodeStyle>),
+    ReplacedNode(Arc<NodeStyle>, Option<(f64, f64)>), // an image and its intrinsic size if it loaded
+    Marker(Arc<NodeStyle>, String), // the bullet or number of a list item, styled like it
     AnonymousBlock,
 }
 
+impl BoxType {
+    // the display value that made the box
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BoxType::BlockNode(_) => "block",
+            BoxType::InlineNode(_) => "inline",
+            BoxType::InlineBlockNode(_) => "inline-block",
+            BoxType::ListItemNode(_) => "list-item",
+            BoxType::TableNode(_) => "table",
+            BoxType::TableRowGroupNode(_) => "table-row-group",
+            BoxType::TableRowNode(_) => "table-row",
+            BoxType::TableCellNode(_) => "table-cell",
+            BoxType::ReplacedNode(..) => "replaced",
+            BoxType::Marker(..) => "marker",
+            BoxType::AnonymousBlock => "anonymous",
+        }
+    }
+
+    // an anonymous block has no style of its own
+    fn style_node(&self) -> Option<&NodeStyle> {
+        match *self {
+            BoxType::BlockNode(ref node) | BoxType::InlineNode(ref node) | BoxType::InlineBlockNode(ref node)
+                | BoxType::ListItemNode(ref node) | BoxType::TableNode(ref node) | BoxType::TableRowGroupNode(ref node)
+                | BoxType::TableRowNode(ref node) | BoxType::TableCellNode(ref node) | BoxType::ReplacedNode(ref node, _)
+                | BoxType::Marker(ref node, _) => Some(node),
+            BoxType::AnonymousBlock => None,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Dimensions {
     pub content: Rect, // relative to the document origin
     pub padding: EdgeSizes,
     pub border: EdgeSizes,
     pub margin: EdgeSizes,
+    pub relative_offset: Offset, // how far position: relative moved it from its place in the flow
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Offset {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Rect {
     pub x : f64,
     pub y : f64,
@@ -41,78 +135,694 @@ pub struct EdgeSizes {
     pub bottom: f64,
 }
 
+// what a whole layout is done against. the style tree leaves vw, vh and rem
+// lengths for layout, so a new viewport needs a new layout but no restyle
+#[derive(Clone, Copy, Debug)]
+pub struct LayoutContext {
+    pub viewport: Dimensions, // https://www.w3.org/TR/CSS2/visudet.html#containing-block-details
+    pub root_font_size: f64,  // the font size of the root element, what rem refers to
+    pub dpi: f64,             // of the output, a css px is 1/96 inch at 96
+}
+
+impl LayoutContext {
+    pub fn new(viewport: Dimensions, root: &StyledNode) -> LayoutContext {
+        LayoutContext {
+            viewport: viewport,
+            root_font_size: root.computed.font.size,
+            dpi: 96.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutError {
+    RootNotRendered, // the root has display: none, there is no box to start from
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LayoutError::RootNotRendered => write!(f, "the root element has display: none"),
+        }
+    }
+}
+
+impl error::Error for LayoutError {}
+
 // Transform a style tree into a layout tree
-pub fn layout_tree<'a>(
-    node: &'a StyledNode<'a>, 
-    mut containing_block: Dimensions // https://www.w3.org/TR/CSS2/visudet.html#containing-block-details
-) -> LayoutBox<'a> {
+pub fn layout_tree(
+    node: &StyledNode, 
+    containing_block: Dimensions // the viewport
+) -> LayoutBox {
+    layout_tree_with_images(node, &LayoutContext::new(containing_block, node), &ImageSizes::new())
+}
+
+// images missing from `images` are sized by their attributes and css alone
+pub fn layout_tree_with_images(
+    node: &StyledNode,
+    context: &LayoutContext,
+    images: &ImageSizes,
+) -> LayoutBox {
+    try_layout_tree(node, context, images).unwrap_or_else(|err| panic!("{}", err))
+}
+
+// like layout_tree_with_images, with an error instead of a panic
+pub fn try_layout_tree(
+    node: &StyledNode,
+    context: &LayoutContext,
+    images: &ImageSizes,
+) -> Result<LayoutBox, LayoutError> {
+    if node.display() == Display::None {
+        return Err(LayoutError::RootNotRendered);
+    }
+    // the initial containing block is the viewport
+    let mut containing_block = context.viewport;
+    let initial = LengthContext {
+        font_size: DEFAULT_FONT_SIZE,
+        root_font_size: context.root_font_size,
+        percent_base: containing_block.content.width,
+        percent_height_base: Some(containing_block.content.height),
+        viewport_width: containing_block.content.width,
+        viewport_height: containing_block.content.height,
+    };
     containing_block.content.height = 0.0;
-    let mut root_box = make_layout_tree(node);
-    root_box.layout(containing_block);
-    root_box
+    let mut root_box = make_layout_tree(node, images, &mut Counters::default());
+
+    let ctx = root_box.length_context(containing_block, initial);
+    root_box.compute_intrinsic_widths(&ctx);
+    root_box.layout_in_context(containing_block, ctx, &mut Floats::default());
+    root_box.apply_relative_offset(&ctx);
+    root_box.compute_overflow();
+    Ok(root_box)
+}
+
+const DEFAULT_FONT_SIZE: f64 = 16.0; // `medium`
+
+// where each page after the first starts when the document is cut into
+// pages `page_height` tall. a page ends between boxes or between lines where
+// it can, not inside a border
+// https://www.w3.org/TR/css-break-3/#breaking-rules
+pub fn page_breaks(root: &LayoutBox, page_height: f64) -> Vec<f64> {
+    if page_height <= 0.0 {
+        return Vec::new();
+    }
+    let mut points = Vec::new();
+    root.collect_break_points(&mut points);
+    points.sort_by(|a, b| a.y.partial_cmp(&b.y).unwrap());
+    // the bottom of a box and the top of the next are the same place
+    let mut merged: Vec<BreakPoint> = Vec::new();
+    for point in points {
+        match merged.last_mut() {
+            Some(last) if point.y - last.y < 0.001 => {
+                last.forced |= point.forced;
+                last.avoid |= point.avoid;
+            }
+            _ => merged.push(point),
+        }
+    }
+
+    let margin_box = root.dimensions.margin_box();
+    let overflow = root.scrollable_overflow;
+    let end = f64::max(margin_box.y + margin_box.height, overflow.y + overflow.height);
+    let mut breaks = Vec::new();
+    let mut top = margin_box.y;
+    loop {
+        let bottom = top + page_height;
+        let candidates: Vec<&BreakPoint> = merged.iter()
+            .filter(|point| point.y > top + 0.001 && point.y < end - 0.001 && point.y <= bottom)
+            .collect();
+        let next = match candidates.iter().find(|point| point.forced) {
+            Some(forced) => forced.y,
+            None if bottom >= end => break,
+            // the last place that allows it, or the page cuts through whatever is there
+            None => candidates.iter().rev().find(|point| !point.avoid).map_or(bottom, |point| point.y),
+        };
+        breaks.push(next);
+        top = next;
+    }
+    breaks
+}
+
+// between two boxes or two lines
+// https://www.w3.org/TR/css-break-3/#possible-breaks
+struct BreakPoint {
+    y: f64,
+    forced: bool,
+    avoid: bool,
+}
+
+// the border box of the element with the id `node_id`, like getBoundingClientRect.
+// nothing scrolls, so viewport coordinates are the document ones
+// https://drafts.csswg.org/cssom-view/#dom-element-getboundingclientrect
+pub fn query_rect(root: &LayoutBox, node_id: &str) -> Option<Rect> {
+    match root.box_type {
+        // a marker has the style of its list item
+        BoxType::Marker(..) => {},
+        _ if root.get_style_node().and_then(|style| style.attrs.get("id")).is_some_and(|id| id == node_id) => {
+            return Some(root.dimensions.border_box());
+        }
+        _ => {},
+    }
+    root.children.iter().find_map(|child| query_rect(child, node_id))
 }
 
 // Make a layout tree but no layout calcualtions performed
-fn make_layout_tree<'a>(node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+fn make_layout_tree(node: &StyledNode, images: &ImageSizes, counters: &mut Counters) -> LayoutBox {
+    counters.update(node);
+    let node_style = Arc::new(NodeStyle::new(node));
     let mut root = LayoutBox::new(match node.display() {
-        Display::Block => BoxType::BlockNode(node),
-        Display::Inline => BoxType::InlineNode(node),
-        Display::None => panic!("Root node has display: none"),
+        Display::None => unreachable!("boxes aren't made for display: none"),
+        _ if is_replaced(node) => BoxType::ReplacedNode(node_style.clone(), intrinsic_size(node, images)),
+        Display::Block => BoxType::BlockNode(node_style.clone()),
+        Display::Inline => BoxType::InlineNode(node_style.clone()),
+        Display::InlineBlock => BoxType::InlineBlockNode(node_style.clone()),
+        Display::ListItem => BoxType::ListItemNode(node_style.clone()),
+        Display::Table => BoxType::TableNode(node_style.clone()),
+        Display::TableRowGroup => BoxType::TableRowGroupNode(node_style.clone()),
+        Display::TableRow => BoxType::TableRowNode(node_style.clone()),
+        Display::TableCell => BoxType::TableCellNode(node_style.clone()),
     });
+    if let BoxType::ListItemNode(_) = root.box_type {
+        if let Some(marker) = list_marker(node, counters.value("list-item")) {
+            root.children.push(LayoutBox::new(BoxType::Marker(node_style, marker)));
+        }
+    }
 
+    // the counters made by the children are gone after the last one
+    let scope = counters.scopes.len();
+    let inline_root = matches!(root.box_type, BoxType::InlineNode(_));
     for child in &node.children {
         match child.display() {
-            Display::Block => root.children.push(make_layout_tree(child)),
-            Display::Inline => root.get_inline_container()
-                .children.push(make_layout_tree(child)),
+            Display::Block | Display::ListItem | Display::Table | Display::TableRowGroup
+                | Display::TableRow | Display::TableCell => root.children.push(make_layout_tree(child, images, counters)),
+            // each run of inline content between the blocks goes in an anonymous block
+            Display::Inline if !inline_root && !is_replaced(child) => {
+                for piece in make_inline_boxes(child, images, counters) {
+                    match piece.box_type {
+                        BoxType::InlineNode(_) => root.get_inline_container().children.push(piece),
+                        _ => root.children.push(piece),
+                    }
+                }
+            }
+            // a block in an inline root stays inside it
+            Display::Inline | Display::InlineBlock => root.get_inline_container()
+                .children.push(make_layout_tree(child, images, counters)),
             Display::None => {},
         }
     }
+    counters.scopes.truncate(scope);
     root
 }
 
-impl<'a> LayoutBox<'a> {
-    pub fn new(box_type: BoxType<'a>) -> LayoutBox<'a> {
+// an inline element with blocks inside is broken around them, its pieces and
+// the blocks come out in order as siblings. the first and last boxes are
+// always pieces, empty ones too
+// https://www.w3.org/TR/CSS2/visuren.html#anonymous-block-level
+fn make_inline_boxes(node: &StyledNode, images: &ImageSizes, counters: &mut Counters) -> Vec<LayoutBox> {
+    counters.update(node);
+    let node_style = Arc::new(NodeStyle::new(node));
+    let mut boxes = vec![LayoutBox::new(BoxType::InlineNode(node_style.clone()))];
+    let scope = counters.scopes.len();
+    for child in &node.children {
+        // and whether each is block-level
+        let pieces: Vec<(LayoutBox, bool)> = match child.display() {
+            Display::None => continue,
+            Display::Inline if !is_replaced(child) => make_inline_boxes(child, images, counters).into_iter()
+                .map(|piece| {
+                    let block = !matches!(piece.box_type, BoxType::InlineNode(_));
+                    (piece, block)
+                })
+                .collect(),
+            Display::Inline | Display::InlineBlock => vec![(make_layout_tree(child, images, counters), false)],
+            _ => vec![(make_layout_tree(child, images, counters), true)],
+        };
+        for (piece, block) in pieces {
+            match block {
+                true => {
+                    boxes.push(piece);
+                    boxes.push(LayoutBox::new(BoxType::InlineNode(node_style.clone())));
+                }
+                false => boxes.last_mut().unwrap().children.push(piece),
+            }
+        }
+    }
+    counters.scopes.truncate(scope);
+    boxes
+}
+
+// the counters in scope while the tree is built, a counter made by an element
+// is seen by its descendants and its following siblings. the innermost of the
+// same name is last
+// https://www.w3.org/TR/css-lists-3/#auto-numbering
+#[derive(Default)]
+struct Counters {
+    scopes: Vec<(String, i32)>,
+}
+
+impl Counters {
+    // resets, then increments, then sets. a list item counts up list-item by itself
+    fn update(&mut self, node: &StyledNode) {
+        for (name, value) in counter_values(node.value("counter-reset"), 0) {
+            self.scopes.push((name, value));
+        }
+        let mut increments = counter_values(node.value("counter-increment"), 1);
+        if node.display() == Display::ListItem && !increments.iter().any(|&(ref name, _)| name == "list-item") {
+            increments.push(("list-item".to_string(), 1));
+        }
+        for (name, value) in increments {
+            *self.get_mut(&name) += value;
+        }
+        for (name, value) in counter_values(node.value("counter-set"), 0) {
+            *self.get_mut(&name) = value;
+        }
+    }
+
+    fn value(&self, name: &str) -> i32 {
+        self.scopes.iter().rev().find(|&&(ref n, _)| n == name).map_or(0, |&(_, value)| value)
+    }
+
+    // a counter used before any reset is made on the spot
+    fn get_mut(&mut self, name: &str) -> &mut i32 {
+        if !self.scopes.iter().any(|&(ref n, _)| n == name) {
+            self.scopes.push((name.to_string(), 0));
+        }
+        &mut self.scopes.iter_mut().rev().find(|&&mut (ref n, _)| n == name).unwrap().1
+    }
+}
+
+// the `name integer?` pairs of counter-reset, counter-increment or counter-set
+fn counter_values(value: Option<Value>, default: i32) -> Vec<(String, i32)> {
+    let components = match value {
+        Some(Value::List(components)) => components,
+        Some(component) => vec![component],
+        None => Vec::new(),
+    };
+    let mut counters: Vec<(String, i32)> = Vec::new();
+    for component in components {
+        match component {
+            Keyword(ref name) if name == "none" => {},
+            Keyword(name) => counters.push((name, default)),
+            Value::Number(n) => if let Some(counter) = counters.last_mut() {
+                counter.1 = n as i32;
+            },
+            _ => {},
+        }
+    }
+    counters
+}
+
+// only images are replaced for now, their content is outside of css
+// https://www.w3.org/TR/CSS2/conform.html#replaced-element
+fn is_replaced(node: &StyledNode) -> bool {
+    match node.node.data {
+        NodeType::Element(ref elem) => elem.tag_name == "img",
+        NodeType::Text(_) => false,
+    }
+}
+
+fn intrinsic_size(node: &StyledNode, images: &ImageSizes) -> Option<(f64, f64)> {
+    match node.node.data {
+        NodeType::Element(ref elem) => elem.attrs.get("src").and_then(|src| images.get(src)).cloned(),
+        NodeType::Text(_) => None,
+    }
+}
+
+// https://www.w3.org/TR/css-lists-3/#text-markers
+fn list_marker(node: &StyledNode, ordinal: i32) -> Option<String> {
+    let marker = match node.value("list-style-type") {
+        Some(Keyword(ref style)) => match &**style {
+            "none" => return None,
+            "decimal" => format!("{}.", ordinal),
+            "circle" => "\u{25e6}".to_string(),
+            "square" => "\u{25aa}".to_string(),
+            _ => "\u{2022}".to_string(),
+        },
+        _ => "\u{2022}".to_string(), // disc
+    };
+    Some(marker)
+}
+
+// TODO: fix the hardcodeds, half an em per char
+fn text_width(text: &str, font_size: f64) -> f64 {
+    text.chars().count() as f64 * font_size / 2.0
+}
+
+// a tab goes to the next multiple of 8 characters
+// https://www.w3.org/TR/css-text-3/#tab-size-property
+fn expand_tabs(text: &str) -> String {
+    let mut expanded = String::new();
+    for c in text.chars() {
+        match c {
+            '\t' => loop {
+                expanded.push(' ');
+                if expanded.chars().count().is_multiple_of(8) {
+                    break;
+                }
+            },
+            '\r' => {},
+            c => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+fn edge_sizes(sides: &Sides<Value>, ctx: &LengthContext) -> EdgeSizes {
+    EdgeSizes {
+        left: sides.left.to_px_in(ctx),
+        right: sides.right.to_px_in(ctx),
+        top: sides.top.to_px_in(ctx),
+        bottom: sides.bottom.to_px_in(ctx),
+    }
+}
+
+impl LayoutBox {
+    pub fn new(box_type: BoxType) -> LayoutBox {
         LayoutBox {
             dimensions: Default::default(),
             box_type: box_type,
             children: Vec::new(),
+            lines: Vec::new(),
+            scrollable_overflow: Default::default(),
+            clip: None,
+            intrinsic_widths: Default::default(),
+        }
+    }
+
+    // `parent` is the length context of the parent box, `floats` the ones
+    // placed so far in the block formatting context
+    fn layout(&mut self, containing_block: Dimensions, parent: LengthContext, floats: &mut Floats) {
+        let ctx = self.length_context(containing_block, parent);
+        self.layout_in_context(containing_block, ctx, floats);
+        self.apply_relative_offset(&ctx);
+    }
+
+    // moves the box from where the flow put it, nothing around it moves
+    // https://www.w3.org/TR/CSS2/visuren.html#relative-positioning
+    fn apply_relative_offset(&mut self, ctx: &LengthContext) {
+        let computed = match (&self.box_type, self.get_style_node()) {
+            (BoxType::Marker(..), _) | (_, None) => return,
+            (_, Some(style)) => &style.computed,
+        };
+        if computed.position != Position::Relative {
+            return;
         }
+        // percentages of an indefinite height count as auto
+        let length = |value: &Value, base: Option<f64>| match *value {
+            Length(f, Unit::Percent) => base.map(|base| f * base / 100.0),
+            Length(..) => Some(value.to_px_in(ctx)),
+            _ => None,
+        };
+        let offsets = &computed.offsets;
+        // left wins over right in left-to-right text, top over bottom
+        let x = length(&offsets.left, Some(ctx.percent_base))
+            .or_else(|| length(&offsets.right, Some(ctx.percent_base)).map(|right| -right))
+            .unwrap_or(0.0);
+        let y = length(&offsets.top, ctx.percent_height_base)
+            .or_else(|| length(&offsets.bottom, ctx.percent_height_base).map(|bottom| -bottom))
+            .unwrap_or(0.0);
+        self.translate(x, y);
+        self.dimensions.relative_offset = Offset { x: x, y: y };
     }
 
-    fn layout(&mut self, containing_block: Dimensions) {
+    fn layout_in_context(&mut self, containing_block: Dimensions, ctx: LengthContext, floats: &mut Floats) {
         match self.box_type {
-            BoxType::BlockNode(_) => self.layout_block(containing_block),
-            BoxType::InlineNode(_) => self.layout_inline(containing_block),
-            BoxType::AnonymousBlock => for child in &mut self.children {
-                child.layout(containing_block);
-                self.dimensions.content.width = child.dimensions.margin_box().width;
-                self.dimensions.content.height += child.dimensions.margin_box().height;
-            },
+            // rows and cells outside a table are laid out like blocks
+            BoxType::BlockNode(_) | BoxType::ListItemNode(_) | BoxType::TableRowGroupNode(_)
+                | BoxType::TableRowNode(_) | BoxType::TableCellNode(_) => self.layout_block(containing_block, ctx, floats),
+            BoxType::TableNode(_) => self.layout_table(containing_block, ctx),
+            BoxType::InlineNode(_) => {
+                let content = containing_block.content;
+                let mut line = LineCursor::new(content.x, content.y + content.height, content.width, floats);
+                self.layout_in_line(&mut line, containing_block, ctx);
+            }
+            BoxType::InlineBlockNode(_) => self.layout_inline_block(containing_block, ctx),
+            BoxType::ReplacedNode(..) => self.layout_replaced(containing_block, ctx),
+            BoxType::Marker(..) => self.layout_marker(containing_block, ctx),
+            BoxType::AnonymousBlock => self.layout_anonymous_block(containing_block, ctx, floats),
         }
     }
 
-    fn layout_block(&mut self, containing_block: Dimensions) {
-        self.calculate_block_width(containing_block);
-        self.calculate_block_position(containing_block); // position in its container
-        self.layout_block_children();  // dependent on its parent width
-        self.calculate_block_height(); // dependent on its children height
+    // the inline boxes are put in lines as wide as the block, text breaks at spaces
+    // https://www.w3.org/TR/CSS2/visuren.html#inline-formatting
+    fn layout_anonymous_block(&mut self, containing_block: Dimensions, ctx: LengthContext, floats: &Floats) {
+        let d = &mut self.dimensions;
+        d.content.x = containing_block.content.x;
+        d.content.y = containing_block.content.y + containing_block.content.height;
+        d.content.width = containing_block.content.width;
+        let (content, mut line) = (*d, LineCursor::new(d.content.x, d.content.y, d.content.width, floats));
+        for child in &mut self.children {
+            child.layout_in_line(&mut line, content, ctx);
+        }
+        line.finish();
+        self.dimensions.content.height = line.y - self.dimensions.content.y;
+        self.align_lines(&line.finished);
+    }
+
+    // text-align and direction are inherited, the inline boxes have the ones
+    // of the block around them. right-to-left lines are laid out and aligned
+    // left to right first, then flipped
+    // https://www.w3.org/TR/CSS2/visuren.html#inline-formatting
+    fn align_lines(&mut self, lines: &[LineBox]) {
+        let (text_align, direction) = match self.children.first().and_then(|child| child.get_style_node()) {
+            Some(style) => (style.computed.text_align, style.computed.direction),
+            None => return,
+        };
+        let rtl = direction == Direction::Rtl;
+        self.reorder_text(rtl);
+        for line in lines {
+            match text_align {
+                TextAlign::Left if rtl => self.align_line(line, &|_| line.free),
+                TextAlign::Right if rtl => {},
+                TextAlign::Left => {},
+                TextAlign::Right => self.align_line(line, &|_| line.free),
+                TextAlign::Center => self.align_line(line, &|_| line.free / 2.0),
+                // the spaces between the words grow, content that follows a gap
+                // moves by all the gaps before it
+                TextAlign::Justify if !line.last => {
+                    let mut items = Vec::new();
+                    self.line_items(line, &mut items);
+                    items.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                    let gaps: Vec<f64> = items.windows(2)
+                        .filter(|pair| pair[1].0 > pair[0].1 + 0.001)
+                        .map(|pair| pair[1].0)
+                        .collect();
+                    if gaps.is_empty() {
+                        continue;
+                    }
+                    let per_gap = line.free / gaps.len() as f64;
+                    self.align_line(line, &|x| per_gap * gaps.iter().filter(|&&gap| gap <= x).count() as f64);
+                }
+                TextAlign::Justify => {},
+            }
+            if rtl {
+                self.mirror_line(line);
+            }
+        }
+    }
+
+    // puts the characters of each text fragment in the order they are shown,
+    // runs of the other direction are reversed
+    // https://www.unicode.org/reports/tr9/#Reordering_Resolved_Levels
+    fn reorder_text(&mut self, rtl: bool) {
+        let level = if rtl { Level::rtl() } else { Level::ltr() };
+        for fragment in &mut self.lines {
+            let info = BidiInfo::new(&fragment.text, Some(level));
+            if let Some(paragraph) = info.paragraphs.first() {
+                if info.has_rtl() {
+                    fragment.text = info.reorder_line(paragraph, paragraph.range.clone()).into_owned();
+                }
+            }
+        }
+        for child in &mut self.children {
+            if let BoxType::InlineNode(_) = child.box_type {
+                child.reorder_text(rtl);
+            }
+        }
+    }
+
+    // flips what is on `line` around the middle of the line, the first box
+    // ends up at the right
+    fn mirror_line(&mut self, line: &LineBox) {
+        let mirror = |x: f64, width: f64| line.left + line.right - x - width;
+        match self.box_type {
+            BoxType::InlineBlockNode(_) | BoxType::ReplacedNode(..) => {
+                let margin_box = self.dimensions.margin_box();
+                if margin_box.y == line.y {
+                    self.translate(mirror(margin_box.x, margin_box.width) - margin_box.x, 0.0);
+                }
+                return;
+            }
+            BoxType::InlineNode(_) | BoxType::AnonymousBlock => {},
+            _ => return,
+        }
+        for fragment in self.lines.iter_mut().filter(|fragment| fragment.rect.y == line.y) {
+            fragment.rect.x = mirror(fragment.rect.x, fragment.rect.width);
+        }
+        for child in &mut self.children {
+            child.mirror_line(line);
+        }
+        let content = self.dimensions.content;
+        if !self.lines.is_empty() {
+            self.cover_lines(content.x, content.y);
+        } else if let BoxType::InlineNode(_) = self.box_type {
+            // a box broken across lines already spans them
+            if content.y == line.y && content.height <= line.height {
+                self.dimensions.content.x = mirror(content.x, content.width);
+            }
+        }
+    }
+
+    // the places inside this box a page may end
+    fn collect_break_points(&self, points: &mut Vec<BreakPoint>) {
+        match self.box_type {
+            // between the lines, above the first one is above the block
+            BoxType::AnonymousBlock => {
+                let mut tops = Vec::new();
+                self.line_tops(&mut tops);
+                tops.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                tops.dedup();
+                for &y in tops.iter().skip(1) {
+                    points.push(BreakPoint { y: y, forced: false, avoid: false });
+                }
+                return;
+            }
+            // the cells of a row end at different heights
+            BoxType::TableRowNode(_) | BoxType::InlineNode(_) | BoxType::InlineBlockNode(_)
+                | BoxType::ReplacedNode(..) | BoxType::Marker(..) => return,
+            _ => {},
+        }
+        // floats and markers are out of the flow
+        let children: Vec<&LayoutBox> = self.children.iter()
+            .filter(|child| !child.is_float() && !matches!(child.box_type, BoxType::Marker(..)))
+            .collect();
+        for (i, child) in children.iter().enumerate() {
+            let (before, after) = match child.get_style_node() {
+                Some(style) => (style.computed.break_before, style.computed.break_after),
+                None => (PageBreak::Auto, PageBreak::Auto),
+            };
+            // only between siblings, unless the break is forced
+            let margin_box = child.dimensions.margin_box();
+            if i > 0 || before == PageBreak::Always {
+                points.push(BreakPoint { y: margin_box.y, forced: before == PageBreak::Always, avoid: before == PageBreak::Avoid });
+            }
+            child.collect_break_points(points);
+            if i + 1 < children.len() || after == PageBreak::Always {
+                points.push(BreakPoint {
+                    y: margin_box.y + margin_box.height,
+                    forced: after == PageBreak::Always,
+                    avoid: after == PageBreak::Avoid,
+                });
+            }
+        }
+    }
+
+    fn line_tops(&self, tops: &mut Vec<f64>) {
+        match self.box_type {
+            BoxType::InlineBlockNode(_) | BoxType::ReplacedNode(..) => tops.push(self.dimensions.margin_box().y),
+            BoxType::InlineNode(_) | BoxType::AnonymousBlock => {
+                tops.extend(self.lines.iter().map(|fragment| fragment.rect.y));
+                for child in &self.children {
+                    child.line_tops(tops);
+                }
+            }
+            _ => {},
+        }
+    }
+
+    // the start and end of the text fragments and atomic boxes on `line`
+    fn line_items(&self, line: &LineBox, items: &mut Vec<(f64, f64)>) {
+        match self.box_type {
+            BoxType::InlineBlockNode(_) | BoxType::ReplacedNode(..) => {
+                let margin_box = self.dimensions.margin_box();
+                if margin_box.y == line.y {
+                    items.push((margin_box.x, margin_box.x + margin_box.width));
+                }
+            }
+            BoxType::InlineNode(_) | BoxType::AnonymousBlock => {
+                for fragment in self.lines.iter().filter(|fragment| fragment.rect.y == line.y) {
+                    items.push((fragment.rect.x, fragment.rect.x + fragment.rect.width));
+                }
+                for child in &self.children {
+                    child.line_items(line, items);
+                }
+            }
+            _ => {},
+        }
+    }
+
+    // moves what is on `line` right by `shift` of where it starts
+    fn align_line(&mut self, line: &LineBox, shift: &dyn Fn(f64) -> f64) {
+        match self.box_type {
+            BoxType::InlineBlockNode(_) | BoxType::ReplacedNode(..) => {
+                let margin_box = self.dimensions.margin_box();
+                if margin_box.y == line.y {
+                    self.translate(shift(margin_box.x), 0.0);
+                }
+                return;
+            }
+            BoxType::InlineNode(_) | BoxType::AnonymousBlock => {},
+            // a block inside an inline has lines of its own
+            _ => return,
+        }
+        for fragment in self.lines.iter_mut().filter(|fragment| fragment.rect.y == line.y) {
+            fragment.rect.x += shift(fragment.rect.x);
+        }
+        for child in &mut self.children {
+            child.align_line(line, shift);
+        }
+        let content = self.dimensions.content;
+        if !self.lines.is_empty() {
+            self.cover_lines(content.x, content.y);
+        } else if let BoxType::InlineNode(_) = self.box_type {
+            // a box broken across lines already spans them
+            if content.y == line.y && content.height <= line.height {
+                let (start, end) = (shift(content.x), shift(content.x + content.width));
+                self.dimensions.content.x += start;
+                self.dimensions.content.width += end - start;
+            }
+        }
+    }
+
+    // percentages refer to the containing block width, em to the font size of this box
+    fn length_context(&self, containing_block: Dimensions, parent: LengthContext) -> LengthContext {
+        let mut ctx = parent;
+        ctx.percent_base = containing_block.content.width;
+        if let Some(style) = self.get_style_node() {
+            ctx.font_size = style.computed.font.size;
+        }
+        ctx
+    }
+
+    fn layout_block(&mut self, containing_block: Dimensions, ctx: LengthContext, floats: &mut Floats) {
+        self.calculate_block_width(containing_block, ctx);
+        self.calculate_block_position(containing_block, ctx); // position in its container
+        self.layout_block_children(self.children_context(ctx), floats);  // dependent on its parent width
+        self.calculate_block_height(ctx); // dependent on its children height
     }
 
-    // TODO: checkout if not violate the regurations
     // https://www.w3.org/TR/CSS2/visudet.html#blockwidth
-    fn calculate_block_width(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
-        let auto = Keyword("auto".to_string()); // initial vaule
-        let zero = Length(0.0, Unit::Px);       // initial vaule for margin border padding
-
-        let mut width = style.value("width").unwrap_or(auto.clone());
-        let mut margin_left = style.lookup("margin-left", "margin", &zero);
-        let mut margin_right = style.lookup("margin-right", "margin", &zero);
-        let mut border_left = style.lookup("border-left-width", "border-width", &zero);
-        let mut border_right = style.lookup("border-right-width", "border-width", &zero);
-        let mut padding_left = style.lookup("padding-left", "padding", &zero);
-        let mut padding_right = style.lookup("padding-right", "padding", &zero);
+    fn calculate_block_width(&mut self, containing_block: Dimensions, ctx: LengthContext) {
+        let style = match self.get_style_node() {
+            Some(style) => &style.computed,
+            // an anonymous block has no edges and fills its container
+            None => {
+                self.dimensions.content.width = containing_block.content.width;
+                return;
+            }
+        };
+        let auto = Keyword("auto".to_string());
+
+        // a cell fills its columns, its width only counts in the first row
+        let mut width = match self.box_type {
+            BoxType::TableCellNode(_) => auto.clone(),
+            _ => style.width.resolve(&ctx),
+        };
+        let mut margin_left = style.margin.left.resolve(&ctx);
+        let mut margin_right = style.margin.right.resolve(&ctx);
+        let border_left = style.border_width.left.resolve(&ctx);
+        let border_right = style.border_width.right.resolve(&ctx);
+        let padding_left = style.padding.left.resolve(&ctx);
+        let padding_right = style.padding.right.resolve(&ctx);
 
         let total: f64 = [
             &margin_right,
@@ -124,40 +834,44 @@ impl<'a> LayoutBox<'a> {
             &width
         ].iter().map(|v| v.to_px()).sum();  // 0.0 if not Value::Length
 
-        let mut underflow = containing_block.content.width - total;
-        if underflow < 0.0 {
-            // 0.0 if auto
-            width = Length(width.to_px(), Unit::Px);
-            margin_left = Length(margin_left.to_px(), Unit::Px);
-            margin_right = Length(margin_right.to_px(), Unit::Px);
-            border_left = Length(border_left.to_px(), Unit::Px);
-            border_right = Length(border_right.to_px(), Unit::Px);
-            padding_left = Length(padding_left.to_px(), Unit::Px);
-            padding_right = Length(padding_right.to_px(), Unit::Px);
-
-            // reduce the length from the rightmost
-            underflow = self.consume_underflow(&mut underflow, &mut margin_right);
-            underflow = self.consume_underflow(&mut underflow, &mut border_right);
-            underflow = self.consume_underflow(&mut underflow, &mut padding_right);
-            underflow = self.consume_underflow(&mut underflow, &mut padding_left);
-            underflow = self.consume_underflow(&mut underflow, &mut border_left);
-            underflow = self.consume_underflow(&mut underflow, &mut margin_left);
-            self.consume_underflow(&mut underflow, &mut width);
-        } else {
-            if width == auto {
-                // only width consumes the length of underflow
-                width = Length(underflow, Unit::Px);
-
-                // 0.0 if auto
+        // a box wider than its container has nothing to share out
+        if width != auto && total > containing_block.content.width {
+            if margin_left == auto {
+                margin_left = Length(0.0, Unit::Px);
+            }
+            if margin_right == auto {
+                margin_right = Length(0.0, Unit::Px);
+            }
+        }
+
+        let underflow = containing_block.content.width - total;
+        let rtl = style.direction == Direction::Rtl;
+        match (width == auto, margin_left == auto, margin_right == auto) {
+            // over-constrained, the margin at the end gives way
+            (false, false, false) if rtl => margin_left = Length(margin_left.to_px() + underflow, Unit::Px),
+            (false, false, false) => margin_right = Length(margin_right.to_px() + underflow, Unit::Px),
+            // a single auto takes what is left
+            (false, false, true) => margin_right = Length(underflow, Unit::Px),
+            (false, true, false) => margin_left = Length(underflow, Unit::Px),
+            // both margins auto center the box
+            (false, true, true) => {
+                margin_left = Length(underflow / 2.0, Unit::Px);
+                margin_right = Length(underflow / 2.0, Unit::Px);
+            }
+            // an auto width fills the container, auto margins are zero
+            (true, _, _) => {
                 margin_left = Length(margin_left.to_px(), Unit::Px);
                 margin_right = Length(margin_right.to_px(), Unit::Px);
-                border_left = Length(border_left.to_px(), Unit::Px);
-                border_right = Length(border_right.to_px(), Unit::Px);
-                padding_left = Length(padding_left.to_px(), Unit::Px);
-                padding_right = Length(padding_right.to_px(), Unit::Px);        
-            } else {
-                // TODO: handle auto combinations
-                margin_right = Length(margin_right.to_px() + underflow, Unit::Px)
+                if underflow >= 0.0 {
+                    width = Length(underflow, Unit::Px);
+                } else if rtl {
+                    width = Length(0.0, Unit::Px);
+                    margin_left = Length(margin_left.to_px() + underflow, Unit::Px);
+                } else {
+                    // width can't be negative
+                    width = Length(0.0, Unit::Px);
+                    margin_right = Length(margin_right.to_px() + underflow, Unit::Px);
+                }
             }
         }
 
@@ -171,30 +885,21 @@ impl<'a> LayoutBox<'a> {
         d.padding.right = padding_right.to_px();
     }
 
-    fn consume_underflow(&mut self, underflow: &mut f64, value: &mut Value) -> f64 {
-        let flow = value.to_px() + *underflow;
-        if flow > 0.0 {
-            *value = Length(flow, Unit::Px);
-            0.0
-        } else {
-            *value = Length(0.0, Unit::Px);
-            flow
-        }
-    }
-
     // TODO: checkout if not violate the regurations
     // https://www.w3.org/TR/CSS2/visudet.html#normal-block
-    fn calculate_block_position(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
-        let zero = Length(0.0, Unit::Px); // initial vaule for margin border padding
+    fn calculate_block_position(&mut self, containing_block: Dimensions, ctx: LengthContext) {
         let d = &mut self.dimensions;
 
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
-        d.border.top = style.lookup("border-top-width", "border-width", &zero).to_px();
-        d.border.bottom = style.lookup("border-bottom-width", "border-width", &zero).to_px();
-        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
-        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+        // vertical margins and paddings also refer to the containing block width
+        if let Some(style) = self.box_type.style_node() { // leaves the dimensions free to change
+            let style = &style.computed;
+            d.margin.top = style.margin.top.to_px_in(&ctx);
+            d.margin.bottom = style.margin.bottom.to_px_in(&ctx);
+            d.border.top = style.border_width.top.to_px_in(&ctx);
+            d.border.bottom = style.border_width.bottom.to_px_in(&ctx);
+            d.padding.top = style.padding.top.to_px_in(&ctx);
+            d.padding.bottom = style.padding.bottom.to_px_in(&ctx);
+        }
 
         d.content.x = containing_block.content.x // TODO: previous children dimension must be added
             + d.margin.left + d.border.left + d.padding.left;
@@ -203,81 +908,598 @@ impl<'a> LayoutBox<'a> {
             + containing_block.content.y + d.margin.top + d.border.top + d.padding.top;
     }
 
-    fn layout_block_children(&mut self) {
+    fn layout_block_children(&mut self, ctx: LengthContext, floats: &mut Floats) {
         let d = &mut self.dimensions;
-        let child_x_pos = d.content.x;
         for child in &mut self.children {
-            child.layout(*d);
-            child.dimensions.content.x += child_x_pos;
+            let clearance = child.clear().and_then(|clear| floats.clearance(clear));
+            if child.is_float() {
+                child.layout_float(*d, ctx, floats, clearance);
+                continue;
+            }
+            // a cleared box starts below the floats
+            if let Some(bottom) = clearance {
+                d.content.height = f64::max(d.content.height, bottom - d.content.y);
+            }
+            child.layout(*d, ctx, floats);
+            // a marker is outside the content, it takes no room in the flow
+            if let BoxType::Marker(..) = child.box_type {
+                continue;
+            }
             d.content.height += child.dimensions.margin_box().height; // add up
         }
     }
 
-    fn calculate_block_height(&mut self) {
-        if let Some(Length(h, Unit::Px)) = self.get_style_node().value("height") {
-            self.dimensions.content.height = h; // override the height by children if explicitly set
+    fn calculate_block_height(&mut self, ctx: LengthContext) {
+        // override the height by children if explicitly set
+        if let Some(height) = self.specified_height(&ctx) {
+            self.dimensions.content.height = height;
         }
     }
 
-    fn layout_inline(&mut self, containing_block: Dimensions) {
-        self.calculate_inline_position(containing_block); // position in its container
-        self.layout_inline_children();
-        
-        // if the node is text, the width and height of the text become of the node
-        match self.get_style_node().node.data {
-            NodeType::Element(_) => {}
-            NodeType::Text(ref body) => {
-                // TODO: fix the hardcodeds
-                self.dimensions.content.width = body.len() as f64 * 8.0;
-                self.dimensions.content.height = 16.0;
-            }
+    // a percentage needs a containing block height that doesn't depend on
+    // the content, otherwise it works like auto
+    // https://www.w3.org/TR/CSS2/visudet.html#the-height-property
+    fn specified_height(&self, ctx: &LengthContext) -> Option<f64> {
+        match self.get_style_node().map(|style| &style.computed.height) {
+            Some(&Length(f, Unit::Percent)) => ctx.percent_height_base.map(|base| f * base / 100.0),
+            Some(height @ &Length(..)) => Some(height.to_px_in(ctx)),
+            _ => None,
         }
     }
 
-    // TODO: checkout if not violate the regurations
+    // this box is the containing block of its children
+    fn children_context(&self, ctx: LengthContext) -> LengthContext {
+        LengthContext {
+            percent_height_base: self.specified_height(&ctx),
+            ..ctx
+        }
+    }
+
+    // places this box and its descendants at `line`, moving on to new lines as they fill up
     // https://www.w3.org/TR/CSS2/visudet.html#inline-width
-    fn calculate_inline_position(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
+    fn layout_in_line(&mut self, line: &mut LineCursor, containing_block: Dimensions, parent: LengthContext) {
+        let ctx = self.length_context(containing_block, parent);
+        let style = match self.box_type {
+            BoxType::InlineNode(ref style) => style.clone(),
+            BoxType::InlineBlockNode(_) | BoxType::ReplacedNode(..) => {
+                // atomic, it goes to the next line as a whole
+                self.layout_in_context(containing_block, ctx, &mut Floats::default());
+                let margin_box = self.dimensions.margin_box();
+                let gap = if line.space_before && line.placed { text_width(" ", parent.font_size) } else { 0.0 };
+                if !line.fits(gap + margin_box.width) {
+                    line.break_line();
+                }
+                let x = line.place(gap + margin_box.width, margin_box.height) + gap;
+                line.space_before = false;
+                self.translate(x - margin_box.x, line.y - margin_box.y);
+                self.apply_relative_offset(&ctx);
+                return;
+            }
+            // a block inside an inline ends the line and takes its own lines,
+            // floats in it stay inside
+            _ => {
+                line.finish();
+                let mut block = containing_block;
+                block.content.height = line.y - block.content.y;
+                self.layout_in_context(block, ctx, &mut Floats::default());
+                line.y += self.dimensions.margin_box().height;
+                return;
+            }
+        };
+        if let Some(ref body) = style.text {
+            let computed = &style.computed;
+            match computed.white_space {
+                WhiteSpace::Pre => self.layout_preformatted_text(body, line, ctx.font_size, TextLayout::new(computed)),
+                _ => self.layout_text(body, line, ctx.font_size, TextLayout::new(computed)),
+            }
+            return;
+        }
+
+        // margins, borders and paddings only push the content sideways
+        let computed = &style.computed;
         let d = &mut self.dimensions;
-        let zero = Length(0.0, Unit::Px); // initial vaule for margin border padding
+        d.margin = edge_sizes(&computed.margin, &ctx);
+        d.border = edge_sizes(&computed.border_width, &ctx);
+        d.padding = edge_sizes(&computed.padding, &ctx);
+        line.x += d.margin.left + d.border.left + d.padding.left;
+        let (x, y) = (line.x, line.y);
+        for child in &mut self.children {
+            child.layout_in_line(line, containing_block, ctx);
+        }
+        // a box broken across lines is given the width of the lines it spans
+        let d = &mut self.dimensions;
+        d.content = match line.y == y {
+            true => Rect { x: x, y: y, width: line.x - x, height: ctx.font_size },
+            false => Rect { x: line.left, y: y, width: line.right - line.left, height: line.y - y + ctx.font_size },
+        };
+        line.x += d.padding.right + d.border.right + d.margin.right;
+        self.apply_relative_offset(&ctx);
+    }
+
+    fn layout_text(&mut self, text: &str, line: &mut LineCursor, font_size: f64, options: TextLayout) {
+        let space = options.width(" ", font_size);
+        let mut space_before = line.space_before || text.starts_with(char::is_whitespace);
+        self.lines.clear();
+        for word in text.split_whitespace() {
+            let width = options.width(word, font_size);
+            let mut gap = if space_before && line.placed { space } else { 0.0 };
+            if !line.fits(gap + width) && !options.nowrap {
+                line.break_line();
+                gap = 0.0;
+            }
+            let x = line.place(gap + width, font_size) + gap;
+            match self.lines.last_mut() {
+                // the next word on the same line
+                Some(fragment) if fragment.rect.y == line.y && !options.word_fragments => {
+                    if gap > 0.0 {
+                        fragment.text.push(' ');
+                    }
+                    fragment.text.push_str(word);
+                    fragment.rect.width = x + width - fragment.rect.x;
+                }
+                _ => self.lines.push(LineFragment {
+                    text: word.to_string(),
+                    rect: Rect { x: x, y: line.y, width: width, height: font_size },
+                }),
+            }
+            space_before = true;
+        }
+        line.space_before = match self.lines.is_empty() {
+            true => space_before,
+            false => text.ends_with(char::is_whitespace),
+        };
+        self.cover_lines(line.x, line.y);
+    }
+
+    // one fragment per line of the text with the spaces kept, a newline
+    // always breaks and nothing else does
+    fn layout_preformatted_text(&mut self, text: &str, line: &mut LineCursor, font_size: f64, options: TextLayout) {
+        self.lines.clear();
+        for (i, segment) in text.split('\n').enumerate() {
+            if i > 0 {
+                // an empty line still takes its height
+                line.height = f64::max(line.height, font_size);
+                line.end_line(true);
+            }
+            let segment = expand_tabs(segment);
+            if segment.is_empty() {
+                continue;
+            }
+            let width = options.width(&segment, font_size);
+            line.fits(width); // only to get past floats on an empty line
+            let x = line.place(width, font_size);
+            self.lines.push(LineFragment {
+                text: segment,
+                rect: Rect { x: x, y: line.y, width: width, height: font_size },
+            });
+        }
+        line.space_before = false;
+        self.cover_lines(line.x, line.y);
+    }
 
-        d.margin.left = style.lookup("margin-left", "margin", &zero).to_px();
-        d.margin.right = style.lookup("margin-right", "margin", &zero).to_px();
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+    // the box covers all of its fragments, an empty box is at x, y
+    fn cover_lines(&mut self, x: f64, y: f64) {
+        self.dimensions.content = match (self.lines.first(), self.lines.last()) {
+            (Some(first), Some(last)) => {
+                let left = self.lines.iter().map(|fragment| fragment.rect.x).fold(f64::INFINITY, f64::min);
+                let right = self.lines.iter().map(|fragment| fragment.rect.x + fragment.rect.width).fold(0.0, f64::max);
+                Rect {
+                    x: left,
+                    y: first.rect.y,
+                    width: right - left,
+                    height: last.rect.y + last.rect.height - first.rect.y,
+                }
+            }
+            _ => Rect { x: x, y: y, width: 0.0, height: 0.0 },
+        };
+    }
 
-        // Inline has no border and padding left/right?
-        d.border.top = style.lookup("border-top-width", "border-width", &zero).to_px();
-        d.border.bottom = style.lookup("border-bottom-width", "border-width", &zero).to_px();
-        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
-        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+    // once everything is in place. a clipped child adds only its border box
+    // https://www.w3.org/TR/css-overflow-3/#scrollable
+    fn compute_overflow(&mut self) {
+        let padding_box = self.dimensions.padding_box();
+        let mut area = padding_box;
+        for fragment in &self.lines {
+            area = area.union(&fragment.rect);
+        }
+        for child in &mut self.children {
+            child.compute_overflow();
+            area = area.union(&child.dimensions.border_box());
+            if child.clip.is_none() {
+                area = area.union(&child.scrollable_overflow);
+            }
+        }
+        self.scrollable_overflow = area;
+        // overflow doesn't apply to inline boxes
+        self.clip = match self.box_type {
+            BoxType::InlineNode(_) | BoxType::Marker(..) => None,
+            _ => match self.get_style_node() {
+                Some(style) if style.computed.overflow != Overflow::Visible => Some(padding_box),
+                _ => None,
+            },
+        };
+    }
 
-        d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
+    // moves the box with everything in it
+    fn translate(&mut self, dx: f64, dy: f64) {
+        self.dimensions.content.x += dx;
+        self.dimensions.content.y += dy;
+        for fragment in &mut self.lines {
+            fragment.rect.x += dx;
+            fragment.rect.y += dy;
+        }
+        for child in &mut self.children {
+            child.translate(dx, dy);
+        }
+    }
+
+    // placed on the line like an inline box, laid out inside like a block
+    // https://www.w3.org/TR/CSS2/visudet.html#inlineblock-width
+    fn layout_inline_block(&mut self, containing_block: Dimensions, ctx: LengthContext) {
+        let style = match self.get_style_node() {
+            Some(style) => &style.computed,
+            None => return,
+        };
+        let margin = edge_sizes(&style.margin, &ctx);
+        let border = edge_sizes(&style.border_width, &ctx);
+        let padding = edge_sizes(&style.padding, &ctx);
+        let edges = margin.left + margin.right + border.left + border.right + padding.left + padding.right;
+
+        let width = match style.width {
+            ref width @ Length(..) => width.to_px_in(&ctx),
+            // shrink-to-fit: min(max(preferred minimum, available), preferred)
+            _ => {
+                let available = containing_block.content.width - edges;
+                let intrinsic = self.intrinsic_widths;
+                available.max(intrinsic.min_content - edges).min(intrinsic.max_content - edges)
+            }
+        };
+
+        let d = &mut self.dimensions;
+        d.margin = margin;
+        d.border = border;
+        d.padding = padding;
+        d.content.width = width.max(0.0);
+        d.content.x = containing_block.content.x + margin.left + border.left + padding.left;
         d.content.y = containing_block.content.height // add up the previous boxes in the container
-            + containing_block.content.y + d.margin.top + d.border.top + d.padding.top;
+            + containing_block.content.y + margin.top + border.top + padding.top;
+
+        // the floats inside are its own, an auto height grows to hold them
+        // https://www.w3.org/TR/CSS2/visudet.html#root-height
+        let mut floats = Floats::default();
+        self.layout_block_children(self.children_context(ctx), &mut floats);
+        if let Some(bottom) = floats.clearance(Clear::Both) {
+            let d = &mut self.dimensions;
+            d.content.height = f64::max(d.content.height, bottom - d.content.y);
+        }
+        self.calculate_block_height(ctx);
+    }
+
+    // the columns take their widths from the first row, the rows stack up
+    // and each is as tall as its tallest cell
+    // https://www.w3.org/TR/CSS2/tables.html#fixed-table-layout
+    fn layout_table(&mut self, containing_block: Dimensions, ctx: LengthContext) {
+        self.calculate_block_width(containing_block, ctx);
+        self.calculate_block_position(containing_block, ctx);
+        let children_ctx = self.children_context(ctx);
+        let columns = self.column_widths(children_ctx);
+        let d = &mut self.dimensions;
+        // columns wider than the table widen it
+        d.content.width = f64::max(d.content.width, columns.iter().sum());
+        for child in &mut self.children {
+            match child.box_type {
+                BoxType::TableRowGroupNode(_) => child.layout_row_group(*d, children_ctx, &columns),
+                BoxType::TableRowNode(_) => child.layout_table_row(*d, children_ctx, &columns),
+                _ => child.layout(*d, children_ctx, &mut Floats::default()), // a caption
+            }
+            d.content.height += child.dimensions.margin_box().height;
+        }
+        // the height is a minimum, the rows don't shrink
+        if let Some(height) = self.specified_height(&ctx) {
+            self.dimensions.content.height = f64::max(self.dimensions.content.height, height);
+        }
+    }
+
+    // the widths of the first row cells are split over the columns they span,
+    // the columns left over share the rest of the table but are never
+    // narrower than the min-content width of their cells
+    fn column_widths(&self, ctx: LengthContext) -> Vec<f64> {
+        let rows = self.table_rows();
+        let count = rows.iter()
+            .map(|row| row.children.iter().map(|cell| cell.colspan()).sum())
+            .fold(0, usize::max);
+        let mut widths = vec![None; count];
+        let mut column = 0;
+        for cell in rows.first().map_or(&[][..], |row| &row.children[..]) {
+            let span = cell.colspan();
+            if let BoxType::TableCellNode(ref style) = cell.box_type {
+                let computed = &style.computed;
+                if let Length(..) = computed.width {
+                    let (margin, border, padding) = (
+                        edge_sizes(&computed.margin, &ctx),
+                        edge_sizes(&computed.border_width, &ctx),
+                        edge_sizes(&computed.padding, &ctx),
+                    );
+                    let width = computed.width.to_px_in(&ctx) + margin.left + margin.right
+                        + border.left + border.right + padding.left + padding.right;
+                    for width_of_column in &mut widths[column..column + span] {
+                        *width_of_column = Some(width / span as f64);
+                    }
+                }
+            }
+            column += span;
+        }
+
+        let mut minimums = vec![0.0; count];
+        for row in &rows {
+            let mut column = 0;
+            for cell in &row.children {
+                let span = cell.colspan();
+                if span == 1 && column < count {
+                    minimums[column] = f64::max(minimums[column], cell.intrinsic_widths.min_content);
+                }
+                column += span;
+            }
+        }
+
+        let fixed: f64 = widths.iter().filter_map(|&width| width).sum();
+        let auto = widths.iter().filter(|width| width.is_none()).count();
+        let rest = f64::max(self.dimensions.content.width - fixed, 0.0);
+        match auto {
+            // a table wider than its columns widens all of them
+            0 if count > 0 => widths.iter().map(|width| width.unwrap() + rest / count as f64).collect(),
+            _ => widths.iter().zip(&minimums)
+                .map(|(width, &minimum)| width.unwrap_or(f64::max(rest / auto as f64, minimum)))
+                .collect(),
+        }
     }
 
-    fn layout_inline_children(&mut self) {
+    // the rows of a table in order, whether in a row group or not
+    fn table_rows(&self) -> Vec<&LayoutBox> {
+        let mut rows = Vec::new();
+        for child in &self.children {
+            match child.box_type {
+                BoxType::TableRowNode(_) => rows.push(child),
+                BoxType::TableRowGroupNode(_) => for row in &child.children {
+                    if let BoxType::TableRowNode(_) = row.box_type {
+                        rows.push(row);
+                    }
+                },
+                _ => {},
+            }
+        }
+        rows
+    }
+
+    fn colspan(&self) -> usize {
+        let span = match self.box_type {
+            BoxType::TableCellNode(ref style) => style.attrs.get("colspan").and_then(|span| span.trim().parse().ok()),
+            _ => None,
+        };
+        // https://html.spec.whatwg.org/multipage/tables.html#attr-tdth-colspan
+        span.unwrap_or(1).clamp(1, 1000)
+    }
+
+    // rows and row groups have no margins, borders or paddings of their own
+    // https://www.w3.org/TR/CSS2/tables.html#table-layout
+    fn layout_row_group(&mut self, table: Dimensions, parent: LengthContext, columns: &[f64]) {
+        let ctx = self.length_context(table, parent);
         let d = &mut self.dimensions;
+        d.content = Rect {
+            x: table.content.x,
+            y: table.content.y + table.content.height,
+            width: table.content.width,
+            height: 0.0,
+        };
         for child in &mut self.children {
-            child.layout(*d);
-            d.content.width += child.dimensions.margin_box().width; // TODO
-            d.content.height = f64::max(d.content.height, child.dimensions.margin_box().height);
-            // d.content.height += child.dimensions.margin_box().height; // add up the height
+            match child.box_type {
+                BoxType::TableRowNode(_) => child.layout_table_row(*d, ctx, columns),
+                _ => child.layout(*d, ctx, &mut Floats::default()),
+            }
+            d.content.height += child.dimensions.margin_box().height;
+        }
+        self.apply_relative_offset(&ctx);
+    }
+
+    fn layout_table_row(&mut self, group: Dimensions, parent: LengthContext, columns: &[f64]) {
+        let ctx = self.length_context(group, parent);
+        let (y, mut x) = (group.content.y + group.content.height, group.content.x);
+        let mut height = self.specified_height(&ctx).unwrap_or(0.0);
+        let mut column = 0;
+        for cell in &mut self.children {
+            // a cell spanning past the last column is cut short
+            let span = cell.colspan().min(columns.len() - column);
+            let width: f64 = columns[column..column + span].iter().sum();
+            let slot = Dimensions {
+                content: Rect { x: x, y: y, width: width, height: 0.0 },
+                ..Default::default()
+            };
+            cell.layout(slot, ctx, &mut Floats::default());
+            height = f64::max(height, cell.dimensions.margin_box().height);
+            x += width;
+            column += span;
+        }
+        // the cells stretch to the height of the row
+        for cell in &mut self.children {
+            let margin_box = cell.dimensions.margin_box();
+            cell.dimensions.content.height += height - margin_box.height;
+        }
+        self.dimensions.content = Rect { x: group.content.x, y: y, width: group.content.width, height: height };
+        self.apply_relative_offset(&ctx);
+    }
+
+    // the content is the image, the box sits on the line or in the flow like an inline-block
+    // https://www.w3.org/TR/CSS2/visudet.html#inline-replaced-width
+    fn layout_replaced(&mut self, containing_block: Dimensions, ctx: LengthContext) {
+        let (width, height) = self.replaced_size(&ctx);
+        let d = &mut self.dimensions;
+        if let Some(style) = self.box_type.style_node() {
+            let style = &style.computed;
+            d.margin = edge_sizes(&style.margin, &ctx);
+            d.border = edge_sizes(&style.border_width, &ctx);
+            d.padding = edge_sizes(&style.padding, &ctx);
+        }
+        d.content = Rect {
+            x: containing_block.content.x + d.margin.left + d.border.left + d.padding.left,
+            y: containing_block.content.y + containing_block.content.height
+                + d.margin.top + d.border.top + d.padding.top,
+            width: width,
+            height: height,
+        };
+    }
+
+    // css width and height first, then the width and height attributes, then
+    // the image. with one side given the other keeps the aspect ratio
+    // https://www.w3.org/TR/CSS2/visudet.html#inline-replaced-height
+    fn replaced_size(&self, ctx: &LengthContext) -> (f64, f64) {
+        let (style, intrinsic) = match self.box_type {
+            BoxType::ReplacedNode(ref style, intrinsic) => (style, intrinsic),
+            _ => return (0.0, 0.0),
+        };
+        let attr = |name: &str| style.attrs.get(name).and_then(|value| value.trim().parse::<f64>().ok());
+        let width = match style.computed.width {
+            ref width @ Length(..) => Some(width.to_px_in(ctx)),
+            _ => attr("width"),
+        };
+        let height = self.specified_height(ctx).or_else(|| attr("height"));
+        // a broken image still has the ratio of its attributes
+        let ratio = intrinsic.or_else(|| attr("width").and_then(|w| attr("height").map(|h| (w, h))))
+            .and_then(|(w, h)| if h > 0.0 { Some(w / h) } else { None });
+        let (intrinsic_width, intrinsic_height) = intrinsic.unwrap_or((0.0, 0.0));
+        match (width, height, ratio) {
+            (Some(width), None, Some(ratio)) => (width, width / ratio),
+            (None, Some(height), Some(ratio)) => (height * ratio, height),
+            (width, height, _) => (width.unwrap_or(intrinsic_width), height.unwrap_or(intrinsic_height)),
         }
     }
 
-    fn get_style_node(&self) -> &'a StyledNode<'a> {
+    // sized like an inline-block, then moved to its side as high as it fits
+    // https://www.w3.org/TR/CSS2/visuren.html#float-position
+    fn layout_float(&mut self, containing_block: Dimensions, parent: LengthContext, floats: &mut Floats, clearance: Option<f64>) {
+        let ctx = self.length_context(containing_block, parent);
         match self.box_type {
-            BoxType::BlockNode(node) | BoxType::InlineNode(node) => node,
-            BoxType::AnonymousBlock => panic!("Anonymous block box has no style node"),
+            BoxType::ReplacedNode(..) => self.layout_replaced(containing_block, ctx),
+            _ => self.layout_inline_block(containing_block, ctx),
         }
+        let margin_box = self.dimensions.margin_box();
+        let content = containing_block.content;
+        let top = f64::max(content.y + content.height, clearance.unwrap_or(0.0));
+        let side = self.get_style_node().map_or(Float::None, |style| style.computed.float);
+        let (x, y) = floats.place(side, margin_box, top, content.x, content.x + content.width);
+        self.translate(x - margin_box.x, y - margin_box.y);
+        self.apply_relative_offset(&ctx);
     }
 
-    fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
+    fn is_float(&self) -> bool {
         match self.box_type {
-            BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
-            BoxType::BlockNode(_) => { // requires AnonymousBlock to host an inline box
+            BoxType::BlockNode(ref style) | BoxType::ListItemNode(ref style)
+                | BoxType::ReplacedNode(ref style, _) => style.computed.float != Float::None,
+            _ => false,
+        }
+    }
+
+    fn clear(&self) -> Option<Clear> {
+        match self.box_type {
+            BoxType::BlockNode(ref style) | BoxType::ListItemNode(ref style) | BoxType::TableNode(ref style)
+                | BoxType::ReplacedNode(ref style, _) if style.computed.clear != Clear::None => {
+                Some(style.computed.clear)
+            }
+            _ => None,
+        }
+    }
+
+    // the intrinsic widths of this box and its descendants, the children
+    // first. percentages are unknown before layout and count as zero
+    // https://www.w3.org/TR/CSS2/visudet.html#shrink-to-fit-float
+    fn compute_intrinsic_widths(&mut self, parent: &LengthContext) {
+        for child in &mut self.children {
+            child.compute_intrinsic_widths(parent);
+        }
+        self.intrinsic_widths = IntrinsicWidths {
+            min_content: self.intrinsic_width(parent, true),
+            max_content: self.intrinsic_width(parent, false),
+        };
+    }
+
+    // one of the intrinsic widths from those of the children, a line break at
+    // every opportunity when `minimum`
+    fn intrinsic_width(&self, parent: &LengthContext, minimum: bool) -> f64 {
+        let children = self.children.iter().map(|child| match minimum {
+            true => child.intrinsic_widths.min_content,
+            false => child.intrinsic_widths.max_content,
+        });
+        let style = match self.box_type {
+            BoxType::Marker(..) => return 0.0,
+            // inline boxes share a line unless it may break
+            BoxType::AnonymousBlock if minimum => return children.fold(0.0, f64::max),
+            BoxType::AnonymousBlock => return children.sum(),
+            BoxType::BlockNode(ref style) | BoxType::InlineNode(ref style)
+                | BoxType::InlineBlockNode(ref style) | BoxType::ListItemNode(ref style)
+                | BoxType::TableNode(ref style) | BoxType::TableRowGroupNode(ref style)
+                | BoxType::TableRowNode(ref style) | BoxType::TableCellNode(ref style)
+                | BoxType::ReplacedNode(ref style, _) => style,
+        };
+        let font_size = style.computed.font.size;
+        if let Some(ref body) = style.text {
+            let text = TextLayout::new(&style.computed);
+            return match (style.computed.white_space, minimum) {
+                (WhiteSpace::Pre, _) => body.split('\n')
+                    .map(|segment| text.width(&expand_tabs(segment), font_size))
+                    .fold(0.0, f64::max),
+                (WhiteSpace::Nowrap, _) | (WhiteSpace::Normal, false) => text.width(body, font_size),
+                (WhiteSpace::Normal, true) => body.split_whitespace().map(|word| text.width(word, font_size)).fold(0.0, f64::max),
+            };
+        }
+
+        let ctx = LengthContext {
+            font_size: font_size,
+            percent_base: 0.0,
+            percent_height_base: None,
+            ..*parent
+        };
+        let computed = &style.computed;
+        let content = match (&computed.width, &self.box_type) {
+            (_, &BoxType::ReplacedNode(..)) => self.replaced_size(&ctx).0,
+            (&Length(_, Unit::Percent), _) => 0.0,
+            (width @ &Length(..), _) => width.to_px_in(&ctx),
+            (_, &BoxType::InlineNode(_)) if !minimum => children.sum(),
+            (_, &BoxType::TableRowNode(_)) => children.sum(), // the cells never wrap
+            _ => children.fold(0.0, f64::max),
+        };
+        let (margin, border, padding) = (
+            edge_sizes(&computed.margin, &ctx),
+            edge_sizes(&computed.border_width, &ctx),
+            edge_sizes(&computed.padding, &ctx),
+        );
+        content + margin.left + margin.right + border.left + border.right + padding.left + padding.right
+    }
+
+    // outside the list item, ending half an em before its content box
+    // https://www.w3.org/TR/css-lists-3/#list-style-position-outside
+    fn layout_marker(&mut self, list_item: Dimensions, ctx: LengthContext) {
+        let width = match self.box_type {
+            BoxType::Marker(_, ref text) => text_width(text, ctx.font_size),
+            _ => 0.0,
+        };
+        let d = &mut self.dimensions;
+        d.content.width = width;
+        d.content.height = ctx.font_size;
+        d.content.x = list_item.content.x - width - ctx.font_size / 2.0;
+        d.content.y = list_item.content.y;
+    }
+
+    // None for an anonymous block
+    pub fn get_style_node(&self) -> Option<&NodeStyle> {
+        self.box_type.style_node()
+    }
+
+    fn get_inline_container(&mut self) -> &mut LayoutBox {
+        match self.box_type {
+            BoxType::InlineNode(_) | BoxType::AnonymousBlock | BoxType::Marker(..) => self,
+            // requires AnonymousBlock to host an inline box
+            BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) | BoxType::ListItemNode(_)
+                | BoxType::TableNode(_) | BoxType::TableRowGroupNode(_) | BoxType::TableRowNode(_)
+                | BoxType::TableCellNode(_) | BoxType::ReplacedNode(..) => {
                 match self.children.last() {
                     Some(&LayoutBox {
                         box_type: BoxType::AnonymousBlock,
@@ -292,8 +1514,213 @@ impl<'a> LayoutBox<'a> {
 
 }
 
+// the floats placed so far in a block formatting context, their margin boxes
+// https://www.w3.org/TR/CSS2/visuren.html#floats
+#[derive(Default)]
+struct Floats {
+    boxes: Vec<(Float, Rect)>,
+}
+
+impl Floats {
+    fn beside(&self, y: f64, height: f64) -> impl Iterator<Item = &(Float, Rect)> {
+        self.boxes.iter().filter(move |&&(_, rect)| {
+            (rect.y <= y || rect.y < y + height) && y < rect.y + rect.height
+        })
+    }
+
+    // the room the floats beside y..y + height leave between `left` and `right`
+    fn available(&self, y: f64, height: f64, left: f64, right: f64) -> (f64, f64) {
+        self.beside(y, height).fold((left, right), |(left, right), &(side, rect)| match side {
+            Float::Right => (left, f64::min(right, rect.x)),
+            _ => (f64::max(left, rect.x + rect.width), right),
+        })
+    }
+
+    // where the first of the floats beside y..y + height ends
+    fn next_bottom(&self, y: f64, height: f64) -> Option<f64> {
+        self.beside(y, height).map(|&(_, rect)| rect.y + rect.height).fold(None, |bottom, b| match bottom {
+            Some(bottom) => Some(f64::min(bottom, b)),
+            None => Some(b),
+        })
+    }
+
+    // the bottom of the floats `clear` has to get past
+    fn clearance(&self, clear: Clear) -> Option<f64> {
+        self.boxes.iter()
+            .filter(|&&(side, _)| match (clear, side) {
+                (Clear::Both, _) | (Clear::Left, Float::Left) | (Clear::Right, Float::Right) => true,
+                _ => false,
+            })
+            .map(|&(_, rect)| rect.y + rect.height)
+            .fold(None, |bottom, b| Some(bottom.map_or(b, |bottom: f64| bottom.max(b))))
+    }
+
+    // the highest spot from `top` where the margin box fits, never above an earlier float
+    fn place(&mut self, side: Float, margin_box: Rect, top: f64, left: f64, right: f64) -> (f64, f64) {
+        let mut y = self.boxes.last().map_or(top, |&(_, rect)| f64::max(top, rect.y));
+        loop {
+            let (l, r) = self.available(y, margin_box.height, left, right);
+            if r - l >= margin_box.width {
+                break;
+            }
+            match self.next_bottom(y, margin_box.height) {
+                Some(bottom) => y = bottom,
+                None => break,
+            }
+        }
+        let (l, r) = self.available(y, margin_box.height, left, right);
+        let x = match side {
+            Float::Right => r - margin_box.width,
+            _ => l,
+        };
+        self.boxes.push((side, Rect { x: x, y: y, ..margin_box }));
+        (x, y)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct TextLayout {
+    nowrap: bool,         // the text stays on the line it starts on
+    word_fragments: bool, // a fragment per word, for justifying and word-spacing
+    letter_spacing: f64,
+    word_spacing: f64,
+}
+
+impl TextLayout {
+    fn new(computed: &ComputedStyle) -> TextLayout {
+        TextLayout {
+            nowrap: computed.white_space == WhiteSpace::Nowrap,
+            word_fragments: computed.text_align == TextAlign::Justify || computed.word_spacing != 0.0,
+            letter_spacing: computed.letter_spacing,
+            word_spacing: computed.word_spacing,
+        }
+    }
+
+    // letter-spacing goes after every character, word-spacing after every space
+    // https://www.w3.org/TR/css-text-3/#spacing
+    fn width(&self, text: &str, font_size: f64) -> f64 {
+        let spaces = text.chars().filter(|&c| c == ' ').count() as f64;
+        text_width(text, font_size) + self.letter_spacing * text.chars().count() as f64 + self.word_spacing * spaces
+    }
+}
+
+// a finished line
+struct LineBox {
+    y: f64,
+    height: f64,
+    left: f64, // the edges beside the floats
+    right: f64,
+    free: f64,  // the room left at the end
+    last: bool, // before a forced break or the end of the block
+}
+
+// where the next inline content goes in an inline formatting context
+struct LineCursor<'f> {
+    floats: &'f Floats, // lines beside them are shorter
+    start: f64,         // the edges of the block
+    end: f64,
+    left: f64,          // the edges of the current line
+    right: f64,
+    x: f64,
+    y: f64,      // top of the current line
+    height: f64, // of the tallest content on the line so far
+    placed: bool,
+    space_before: bool, // the last text ended with whitespace
+    finished: Vec<LineBox>,
+}
+
+impl<'f> LineCursor<'f> {
+    fn new(x: f64, y: f64, width: f64, floats: &'f Floats) -> LineCursor<'f> {
+        let mut line = LineCursor {
+            floats: floats,
+            start: x,
+            end: x + width,
+            left: x,
+            right: x + width,
+            x: x,
+            y: y,
+            height: 0.0,
+            placed: false,
+            space_before: false,
+            finished: Vec::new(),
+        };
+        line.fit_to_floats();
+        line
+    }
+
+    fn fit_to_floats(&mut self) {
+        let (left, right) = self.floats.available(self.y, 0.0, self.start, self.end);
+        self.left = left;
+        self.right = right;
+        self.x = left;
+    }
+
+    // anything fits on an empty line, it overflows when too wide. an empty
+    // line too narrow beside floats moves down past them first
+    fn fits(&mut self, width: f64) -> bool {
+        while !self.placed && self.x + width > self.right {
+            match self.floats.next_bottom(self.y, 0.0) {
+                Some(bottom) => {
+                    self.y = bottom;
+                    self.fit_to_floats();
+                }
+                None => break,
+            }
+        }
+        !self.placed || self.x + width <= self.right
+    }
+
+    // returns where the content starts
+    fn place(&mut self, width: f64, height: f64) -> f64 {
+        let x = self.x;
+        self.x += width;
+        self.height = f64::max(self.height, height);
+        self.placed = true;
+        x
+    }
+
+    fn break_line(&mut self) {
+        self.end_line(false);
+    }
+
+    // `forced` by a newline or by what comes after the line
+    fn end_line(&mut self, forced: bool) {
+        if self.placed {
+            self.finished.push(LineBox {
+                y: self.y,
+                height: self.height,
+                left: self.left,
+                right: self.right,
+                free: f64::max(self.right - self.x, 0.0),
+                last: forced,
+            });
+        }
+        self.y += self.height;
+        self.fit_to_floats();
+        self.height = 0.0;
+        self.placed = false;
+        self.space_before = false;
+    }
+
+    // ends the last line if anything is on it
+    fn finish(&mut self) {
+        if self.placed {
+            self.end_line(true);
+        }
+    }
+}
+
 impl Dimensions {
-    fn margin_box(&self) -> Rect {
+    // the content box where the normal flow put it
+    pub fn static_position(&self) -> Rect {
+        Rect {
+            x: self.content.x - self.relative_offset.x,
+            y: self.content.y - self.relative_offset.y,
+            ..self.content
+        }
+    }
+
+    pub fn margin_box(&self) -> Rect {
         self.border_box().expanded_by(self.margin)
     }
 
@@ -301,29 +1728,765 @@ impl Dimensions {
         self.padding_box().expanded_by(self.border)
     }
 
-    fn padding_box(&self) -> Rect {
+    pub fn padding_box(&self) -> Rect {
         self.content.expanded_by(self.padding)
     }
 }
 
 impl Rect {
+    // empty when they don't overlap
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let (x, y) = (f64::max(self.x, other.x), f64::max(self.y, other.y));
+        Rect {
+            x: x,
+            y: y,
+            width: f64::max(f64::min(self.x + self.width, other.x + other.width) - x, 0.0),
+            height: f64::max(f64::min(self.y + self.height, other.y + other.height) - y, 0.0),
+        }
+    }
+
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x && other.y >= self.y
+            && other.x + other.width <= self.x + self.width && other.y + other.height <= self.y + self.height
+    }
+
+    // the smallest rect around both
+    pub fn union(&self, other: &Rect) -> Rect {
+        let (x, y) = (f64::min(self.x, other.x), f64::min(self.y, other.y));
+        Rect {
+            x: x,
+            y: y,
+            width: f64::max(self.x + self.width, other.x + other.width) - x,
+            height: f64::max(self.y + self.height, other.y + other.height) - y,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0.0 || self.height <= 0.0
+    }
+
     fn expanded_by(&self, edge: EdgeSizes) -> Rect {
         Rect {
             x: self.x - edge.left,
             y: self.y - edge.top,
             width: self.width + edge.left + edge.right,
-            height: self.height + edge.top * edge.bottom,
+            height: self.height + edge.top + edge.bottom,
         }
     }
 }
 
-impl<'a> fmt::Display for LayoutBox<'a> { // type Result = Result<(), Error>;
-    // TODO: implement more later
+impl fmt::Display for Rect {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "{:?}", self.dimensions)?;
-        for child in &self.children {
-            write!(f, "{}", child)?;
+        write!(f, "({}, {}) {}x{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl LayoutBox {
+    // what the box came from, `div#a.x.y` for an element, `#text` or `::marker`
+    fn origin(&self) -> String {
+        let style = match (&self.box_type, self.get_style_node()) {
+            (BoxType::Marker(..), _) => return "::marker".to_string(),
+            (_, Some(style)) => style,
+            (_, None) => return String::new(),
+        };
+        let mut origin = match style.tag_name {
+            Some(ref tag_name) => tag_name.clone(),
+            None => return "#text".to_string(),
+        };
+        if let Some(id) = style.attrs.get("id") {
+            origin.push('#');
+            origin.push_str(id);
+        }
+        for class in style.attrs.get("class").iter().flat_map(|classes| classes.split_whitespace()) {
+            origin.push('.');
+            origin.push_str(class);
+        }
+        origin
+    }
+
+    // the tree with every box and its edges, numbers as rust prints them
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        self.write_json(&mut json);
+        json
+    }
+
+    fn write_json(&self, json: &mut String) {
+        let rect = |rect: Rect| format!(
+            "{{\"x\":{},\"y\":{},\"width\":{},\"height\":{}}}", rect.x, rect.y, rect.width, rect.height
+        );
+        let edges = |edges: EdgeSizes| format!(
+            "{{\"top\":{},\"right\":{},\"bottom\":{},\"left\":{}}}", edges.top, edges.right, edges.bottom, edges.left
+        );
+        let d = self.dimensions;
+        json.push_str(&format!("{{\"type\":{},\"origin\":{}", json_string(self.box_type.name()), json_string(&self.origin())));
+        if let BoxType::Marker(_, ref text) = self.box_type {
+            json.push_str(&format!(",\"text\":{}", json_string(text)));
+        }
+        json.push_str(&format!(
+            ",\"content\":{},\"padding\":{},\"border\":{},\"margin\":{}",
+            rect(d.content), edges(d.padding), edges(d.border), edges(d.margin)
+        ));
+        if !self.lines.is_empty() {
+            let lines: Vec<String> = self.lines.iter()
+                .map(|fragment| format!("{{\"text\":{},\"rect\":{}}}", json_string(&fragment.text), rect(fragment.rect)))
+                .collect();
+            json.push_str(&format!(",\"lines\":[{}]", lines.join(",")));
+        }
+        json.push_str(",\"children\":[");
+        for (i, child) in self.children.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            child.write_json(json);
+        }
+        json.push_str("]}");
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc8259#section-7
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn walk(layout_box: &LayoutBox, indent: usize, f: &mut fmt::Formatter) -> fmt::Result {
+    let d = layout_box.dimensions;
+    let edges = |edges: EdgeSizes| format!("{} {} {} {}", edges.top, edges.right, edges.bottom, edges.left);
+    let name = match layout_box.origin() {
+        origin if origin.is_empty() => layout_box.box_type.name().to_string(),
+        origin => format!("{} {}", layout_box.box_type.name(), origin),
+    };
+    writeln!(
+        f, "{:indent$}{} {} margin {} border {} padding {}",
+        "", name, d.content,
+        edges(d.margin), edges(d.border), edges(d.padding), indent = indent
+    )?;
+    for fragment in &layout_box.lines {
+        writeln!(f, "{:indent$}\"{}\" {}", "", fragment.text, fragment.rect, indent = indent + 2)?;
+    }
+    for child in &layout_box.children {
+        walk(child, indent + 2, f)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for LayoutBox { // type Result = Result<(), Error>;
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        walk(self, 0, f)
+    }
+}
+
+#[test]
+fn test_box_edges() {
+    let d = Dimensions {
+        content: Rect { x: 10.0, y: 20.0, width: 100.0, height: 50.0 },
+        padding: EdgeSizes { left: 1.0, right: 2.0, top: 3.0, bottom: 4.0 },
+        border: EdgeSizes { left: 1.0, right: 1.0, top: 2.0, bottom: 2.0 },
+        margin: EdgeSizes { left: 5.0, right: 5.0, top: 6.0, bottom: 8.0 },
+        ..Default::default()
+    };
+    // the height grows by top + bottom, it used to be top * bottom
+    let padding_box = d.padding_box();
+    assert_eq!((padding_box.x, padding_box.y, padding_box.width, padding_box.height), (9.0, 17.0, 103.0, 57.0));
+    let margin_box = d.margin_box();
+    assert_eq!((margin_box.x, margin_box.y, margin_box.width, margin_box.height), (3.0, 9.0, 115.0, 75.0));
+}
+
+#[test]
+fn test_relative_lengths() {
+    use crate::{css, html, style};
+
+    let root = html::parse_fragment("<div><p>x</p></div>".to_string()).unwrap().remove(0);
+    let stylesheet = css::parse("div { display: block; font-size: 2em; width: 50%; padding: 1em; } \
+        p { display: block; font-size: 0.5rem; width: 10vw; height: 25vh; margin-left: 1em; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    viewport.content.height = 600.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let div = layout.dimensions;
+    assert_eq!(div.content.width, 400.0);
+    assert_eq!(div.padding.left, 32.0);
+
+    let p = layout.children[0].dimensions;
+    assert_eq!(p.content.width, 80.0);
+    assert_eq!(p.content.height, 150.0);
+    assert_eq!(p.margin.left, 16.0); // 0.5rem of a 32px root
+}
+
+#[test]
+fn test_inline_block_and_list_item() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<body><span class=w>ab cdef</span><ol><li>x</li><li>y</li></ol></body>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } ol { margin: 0; } \
+        .w { display: inline-block; padding: 0 4px; border-left: 2px solid; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    // shrinks to the text, 7 chars at half of 16px
+    let body = &layout.children[0];
+    let span = &body.children[0].children[0];
+    assert_eq!(span.dimensions.content.width, 56.0);
+    assert_eq!(span.dimensions.border_box().width, 66.0);
+
+    // each item starts with a numbered marker left of its content
+    let items = &body.children[1].children;
+    let marker = &items[1].children[0];
+    match marker.box_type {
+        BoxType::Marker(_, ref text) => assert_eq!(text, "2."),
+        _ => panic!("no marker"),
+    }
+    let text = &items[1].children[1].children[0];
+    assert_eq!(marker.dimensions.content.x + 24.0, text.dimensions.content.x);
+    assert_eq!(marker.dimensions.content.y, items[1].dimensions.content.y);
+}
+
+#[test]
+fn test_line_breaking() {
+    use crate::{css, html, style};
+
+    // 8px per char at 16px, ten to a line
+    let root = html::parse("<div>aaaa bbbb <b>cccc dddd</b> ee</div><p>x</p>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } div { width: 80px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let div = &layout.children[0].children[0];
+    let anonymous = &div.children[0];
+    let fragments: Vec<(&str, f64, f64)> = anonymous.children[0].lines.iter()
+        .chain(&anonymous.children[1].children[0].lines)
+        .chain(&anonymous.children[2].lines)
+        .map(|fragment| (&*fragment.text, fragment.rect.x, fragment.rect.y))
+        .collect();
+    assert_eq!(fragments, [
+        ("aaaa bbbb", 0.0, 0.0),
+        ("cccc dddd", 0.0, 16.0),
+        ("ee", 0.0, 32.0),
+    ]);
+    assert_eq!(div.dimensions.content.height, 48.0);
+    assert_eq!(layout.children[0].children[1].dimensions.content.y, 48.0);
+}
+
+#[test]
+fn test_percentage_sizes() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<div class=a><div class=b></div></div><div class=c><div class=b></div></div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } .a { width: 50%; height: 200px; } \
+        .b { width: 25%; height: 50%; } .c { height: 10%; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    viewport.content.height = 600.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let body = &layout.children[0];
+    let (a, c) = (&body.children[0], &body.children[1]);
+    assert_eq!(a.children[0].dimensions.content.width, 100.0);
+    assert_eq!(a.children[0].dimensions.content.height, 100.0);
+    // body has an auto height, so 10% of it is auto too
+    assert_eq!(c.dimensions.content.height, 0.0);
+    assert_eq!(c.children[0].dimensions.content.height, 0.0);
+    assert_eq!(layout.dimensions.content.height, 200.0);
+}
+
+#[test]
+fn test_auto_margins() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<div class=center></div><div class=left></div><div class=wide></div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } .center { width: 200px; margin: 0 auto; } \
+        .left { width: 100px; margin-right: 50px; margin-left: auto; } \
+        .wide { width: 1000px; margin: 0 auto; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let margins: Vec<(f64, f64, f64)> = layout.children[0].children.iter()
+        .map(|child| (child.dimensions.margin.left, child.dimensions.content.x, child.dimensions.margin.right))
+        .collect();
+    assert_eq!(margins, [(300.0, 300.0, 300.0), (650.0, 650.0, 50.0), (0.0, 0.0, -200.0)]);
+}
+
+#[test]
+fn test_floats() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<div class=box><div class=l></div><span class=r>r</span>\
+        <p>aaaa bbbb cccc</p><div class=c></div></div>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } .box { width: 200px; } \
+        .l { float: left; width: 40px; height: 20px; } .r { float: right; width: 60px; height: 40px; } \
+        .c { clear: both; height: 10px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let parts = &layout.children[0].children[0].children;
+    let position = |i: usize| (parts[i].dimensions.content.x, parts[i].dimensions.content.y);
+    assert_eq!(position(0), (0.0, 0.0));
+    assert_eq!(position(1), (140.0, 0.0)); // the span is blockified
+    // the lines of the paragraph fit between the floats
+    let text = &parts[2].children[0].children[0];
+    let fragments: Vec<(&str, f64, f64)> = text.lines.iter()
+        .map(|fragment| (&*fragment.text, fragment.rect.x, fragment.rect.y))
+        .collect();
+    assert_eq!(fragments, [("aaaa bbbb", 40.0, 0.0), ("cccc", 40.0, 16.0)]);
+    assert_eq!(parts[2].dimensions.content.height, 32.0);
+    assert_eq!(position(3), (0.0, 40.0));
+}
+
+#[test]
+fn test_relative_position() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<div class=a><p>x</p></div><div class=b></div><span class=c>y</span>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } div { height: 20px; } \
+        .a { position: relative; top: 10px; left: 5%; bottom: 99px; } \
+        .c { position: relative; right: 2em; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let body = &layout.children[0];
+    let a = body.children[0].dimensions;
+    assert_eq!((a.content.x, a.content.y), (40.0, 10.0));
+    assert_eq!((a.static_position().x, a.static_position().y), (0.0, 0.0));
+    // the content moves along, the next block stays in place
+    assert_eq!(body.children[0].children[0].dimensions.content.y, 10.0);
+    assert_eq!(body.children[1].dimensions.content.y, 20.0);
+    let c = body.children[2].children[0].dimensions;
+    assert_eq!(c.relative_offset, Offset { x: -32.0, y: 0.0 });
+    assert_eq!(c.content.x, -32.0);
+}
+
+#[test]
+fn test_table_layout() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<table><tr><td class=a>x</td><td class=t>y</td><td>z</td></tr>\
+        <tr><td colspan=2>wide</td><td class=a>w</td></tr></table><p>after</p>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } table { width: 300px; } .a { width: 50px; } \
+        .t { height: 40px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let body = &layout.children[0];
+    let table = &body.children[0];
+    let cells: Vec<Vec<(f64, f64, f64, f64)>> = table.children.iter().map(|row| row.children.iter().map(|cell| {
+        let content = cell.dimensions.content;
+        (content.x, content.y, content.width, content.height)
+    }).collect()).collect();
+    // the first row sets 50px for the first column, the later width is ignored
+    assert_eq!(cells, [
+        vec![(0.0, 0.0, 50.0, 40.0), (50.0, 0.0, 125.0, 40.0), (175.0, 0.0, 125.0, 40.0)],
+        vec![(0.0, 40.0, 175.0, 16.0), (175.0, 40.0, 125.0, 16.0)],
+    ]);
+    assert_eq!(table.dimensions.content.height, 56.0);
+    assert_eq!(body.children[1].dimensions.content.y, 56.0);
+
+    // a huge colspan is clamped instead of allocating that many columns
+    let root = html::parse("<table><tr><td colspan=2000000000>x</td></tr></table>".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let layout = layout_tree(&styled, viewport);
+    let cell = &layout.children[0].children[0].children[0].children[0];
+    assert!((cell.dimensions.content.width - 300.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_replaced_size() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<p><img src=a.png><img src=a.png width=100><img class=h src=a.png>\
+        <img src=missing.png width=30 height=20><img src=missing.png width=30></p>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } .h { height: 10px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let mut images = ImageSizes::new();
+    images.insert("a.png".to_string(), (200.0, 50.0));
+    let layout = layout_tree_with_images(&styled, &LayoutContext::new(viewport, &styled), &images);
+
+    let line = &layout.children[0].children[0].children[0];
+    let boxes: Vec<(f64, f64, f64)> = line.children.iter()
+        .map(|image| (image.dimensions.content.x, image.dimensions.content.width, image.dimensions.content.height))
+        .collect();
+    assert_eq!(boxes, [(0.0, 200.0, 50.0), (200.0, 100.0, 25.0), (300.0, 40.0, 10.0), (340.0, 30.0, 20.0), (370.0, 30.0, 0.0)]);
+    assert_eq!(layout.children[0].children[0].dimensions.content.height, 50.0);
+}
+
+#[test]
+fn test_list_counters() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<ol start=3><li>a</li><li value=10>b</li><li>c</li></ol>\
+        <ol><li class=skip>d</li><li>e<ol><li>f</li></ol></li><li>g</li></ol>".to_string());
+    let stylesheet = css::parse(".skip { counter-increment: list-item 2; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    fn markers(layout_box: &LayoutBox, texts: &mut Vec<String>) {
+        if let BoxType::Marker(_, ref text) = layout_box.box_type {
+            texts.push(text.clone());
+        }
+        for child in &layout_box.children {
+            markers(child, texts);
         }
-        Ok(())
     }
-}
\ No newline at end of file
+    let mut texts = Vec::new();
+    markers(&layout, &mut texts);
+    assert_eq!(texts, ["3.", "10.", "11.", "2.", "3.", "1.", "4."]);
+}
+
+#[test]
+fn test_overflow() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<div class=a><div class=b></div></div><div class=c><div class=b></div></div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } .a, .c { width: 100px; height: 50px; } \
+        .a { overflow: hidden; padding: 5px; } .b { width: 300px; height: 80px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let rect = |rect: Rect| (rect.x, rect.y, rect.width, rect.height);
+    let body = &layout.children[0];
+    let (a, c) = (&body.children[0], &body.children[1]);
+    assert_eq!(a.clip.map(rect), Some((0.0, 0.0, 110.0, 60.0)));
+    assert_eq!(rect(a.scrollable_overflow), (0.0, 0.0, 305.0, 85.0));
+    assert_eq!(c.clip.map(rect), None);
+    assert_eq!(rect(c.scrollable_overflow), (0.0, 60.0, 300.0, 80.0));
+    // the clipped box counts with its border box, the other with what sticks out of it
+    assert_eq!(rect(body.scrollable_overflow), (0.0, 0.0, 800.0, 140.0));
+}
+
+#[test]
+fn test_white_space() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<pre>a  b\n\n\tc</pre><div class=n>aaaa bbbb cccc</div><p>x</p>".to_string());
+    let stylesheet = css::parse("body, pre, p { margin: 0; } div, pre { width: 80px; } .n { white-space: nowrap; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let body = &layout.children[0];
+    let fragments = |text: &LayoutBox| text.lines.iter()
+        .map(|fragment| (fragment.text.clone(), fragment.rect.x, fragment.rect.y))
+        .collect::<Vec<_>>();
+    let pre = &body.children[0];
+    assert_eq!(fragments(&pre.children[0].children[0]), [
+        ("a  b".to_string(), 0.0, 0.0),
+        ("        c".to_string(), 0.0, 32.0),
+    ]);
+    assert_eq!(pre.dimensions.content.height, 48.0);
+    // one line running past the 80px
+    let nowrap = &body.children[1];
+    assert_eq!(fragments(&nowrap.children[0].children[0]), [("aaaa bbbb cccc".to_string(), 0.0, 48.0)]);
+    assert_eq!(body.children[2].dimensions.content.y, 64.0);
+}
+
+#[test]
+fn test_text_align() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<div class=c>aaaa</div><div class=r>aa <b>bb</b></div><div class=j>aa bb ccc dd</div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } div { width: 80px; } .c { text-align: center; } \
+        .r { text-align: right; } .j { text-align: justify; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let divs = &layout.children[0].children;
+    let text = |div: usize, child: usize| &divs[div].children[0].children[child];
+    assert_eq!(text(0, 0).lines[0].rect.x, 24.0);
+    // 40px of text on an 80px line
+    assert_eq!(text(1, 0).lines[0].rect.x, 40.0);
+    let b = text(1, 1).dimensions.content;
+    assert_eq!((b.x, b.width), (56.0, 24.0)); // with the space before bb
+    assert_eq!(text(1, 1).children[0].lines[0].rect.x, 64.0);
+    let fragments: Vec<(&str, f64, f64)> = text(2, 0).lines.iter()
+        .map(|fragment| (&*fragment.text, fragment.rect.x, fragment.rect.y))
+        .collect();
+    assert_eq!(fragments, [("aa", 0.0, 32.0), ("bb", 28.0, 32.0), ("ccc", 56.0, 32.0), ("dd", 0.0, 48.0)]);
+}
+
+#[test]
+fn test_owned_layout_tree() {
+    use crate::{css, html, style};
+    use std::thread;
+
+    let layout = {
+        let root = html::parse("<p class=a>x</p>".to_string());
+        let stylesheet = css::parse("body, p { margin: 0; } .a { height: 20px; }".to_string());
+        let styled = style::style_tree(&root, &stylesheet);
+        let mut viewport: Dimensions = Default::default();
+        viewport.content.width = 800.0;
+        layout_tree(&styled, viewport)
+    };
+    // the dom and the style tree are gone, the layout tree goes to another thread
+    let (height, class) = thread::spawn(move || {
+        let p = &layout.children[0].children[0];
+        (p.dimensions.content.height, p.get_style_node().and_then(|style| style.attrs.get("class").cloned()))
+    }).join().unwrap();
+    assert_eq!(height, 20.0);
+    assert_eq!(class, Some("a".to_string()));
+}
+
+#[test]
+fn test_query_rect() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<ul><li id=item>a <span id=word>b</span></li></ul>".to_string());
+    let stylesheet = css::parse("body, ul { margin: 0; } li { padding: 2px; border-top: 3px solid; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    assert_eq!(query_rect(&layout, "item"), Some(Rect { x: 40.0, y: 0.0, width: 760.0, height: 23.0 }));
+    assert_eq!(query_rect(&layout, "word"), Some(Rect { x: 50.0, y: 5.0, width: 16.0, height: 16.0 }));
+    assert_eq!(query_rect(&layout, "missing"), None);
+}
+
+#[test]
+fn test_page_breaks() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<div class=a></div><div class=b></div><p>aaaa bbbb cccc dddd</p><div class=d></div>".to_string());
+    let css = "body, p { margin: 0; } p { width: 80px; } .a { height: 60px; } .b { height: 30px; } \
+        .d { height: 10px; page-break-before: always; }";
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+
+    let stylesheet = css::parse(css.to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let layout = layout_tree(&styled, viewport);
+    // the first page ends above the paragraph, the second before the forced break
+    assert_eq!(page_breaks(&layout, 100.0), [90.0, 122.0]);
+    // the paragraph breaks between its lines
+    assert_eq!(page_breaks(&layout, 110.0), [106.0, 122.0]);
+
+    let stylesheet = css::parse(format!("{} .b {{ break-after: avoid; }}", css));
+    let styled = style::style_tree(&root, &stylesheet);
+    let layout = layout_tree(&styled, viewport);
+    assert_eq!(page_breaks(&layout, 100.0), [60.0, 122.0]);
+}
+
+#[test]
+fn test_intrinsic_widths() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<div><span class=b>aaa bb</span></div>\
+        <table><tr><td class=a>x</td><td>abcdefghij</td></tr></table>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } .b { display: inline-block; padding: 0 2px; } \
+        table { width: 100px; } .a { width: 80px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let body = &layout.children[0];
+    let block = &body.children[0].children[0].children[0];
+    // 8px a char at 16px, the longest word and the whole text
+    assert_eq!(block.intrinsic_widths, IntrinsicWidths { min_content: 28.0, max_content: 52.0 });
+    assert_eq!(block.dimensions.content.width, 48.0);
+
+    // the auto column takes its longest word over its share of the table
+    let cells = &body.children[1].children[0].children;
+    assert_eq!(cells[1].dimensions.content.x, 80.0);
+    assert_eq!(cells[1].dimensions.content.width, 80.0);
+    assert_eq!(body.children[1].dimensions.content.width, 160.0);
+}
+
+#[test]
+fn test_layout_context() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<div></div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } div { width: 10rem; height: 50vh; padding-left: 1em; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    viewport.content.height = 600.0;
+    let mut context = LayoutContext::new(viewport, &styled);
+    assert_eq!(context.root_font_size, 16.0);
+    context.root_font_size = 20.0;
+    let div = |context: &LayoutContext| {
+        let layout = layout_tree_with_images(&styled, context, &ImageSizes::new());
+        let d = layout.children[0].children[0].dimensions;
+        (d.content.width, d.content.height, d.padding.left)
+    };
+    assert_eq!(div(&context), (200.0, 300.0, 16.0));
+
+    // the same style tree in a smaller viewport
+    context.viewport.content.height = 200.0;
+    assert_eq!(div(&context), (200.0, 100.0, 16.0));
+}
+
+#[test]
+fn test_direction_rtl() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<p>abc <b>de</b></p><p class=h>\u{5e9}\u{5dc}\u{5d5}\u{5dd} ab</p><div class=m></div>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } body { direction: rtl; width: 200px; } \
+        .m { width: 100px; height: 10px; margin: 0 10px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    // the body is placed from the right edge too
+    let body = &layout.children[0];
+    assert_eq!(body.dimensions.content.x, 600.0);
+    let first = &body.children[0].children[0];
+    // the first box at the right edge, the next one to its left
+    let text = &first.children[0].lines[0];
+    assert_eq!((text.rect.x, text.rect.x + text.rect.width), (776.0, 800.0));
+    let bold = &first.children[1];
+    assert_eq!(bold.children[0].lines[0].rect.x, 752.0);
+    // the hebrew word is reversed, the latin one is kept
+    let mixed = &body.children[1].children[0].children[0].lines[0];
+    assert_eq!(mixed.text, "ab \u{5dd}\u{5d5}\u{5dc}\u{5e9}");
+    assert_eq!(mixed.rect.x + mixed.rect.width, 800.0);
+    // the left margin takes what is left over
+    let margin = body.children[2].dimensions;
+    assert_eq!((margin.margin.left, margin.content.x), (90.0, 690.0));
+}
+
+#[test]
+fn test_layout_dump() {
+    use crate::{css, html, style};
+
+    let root = html::parse_fragment("<div id=a class=\"x y\"><p>hi</p></div>".to_string()).unwrap().remove(0);
+    let stylesheet = css::parse("div { display: block; padding: 2px; } p { display: block; margin: 1px 0; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 100.0;
+    let layout = layout_tree(&styled, viewport);
+
+    assert_eq!(format!("{}", layout), "\
+block div#a.x.y (2, 2) 96x18 margin 0 0 0 0 border 0 0 0 0 padding 2 2 2 2
+  block p (2, 3) 96x16 margin 1 0 1 0 border 0 0 0 0 padding 0 0 0 0
+    anonymous (2, 3) 96x16 margin 0 0 0 0 border 0 0 0 0 padding 0 0 0 0
+      inline #text (2, 3) 16x16 margin 0 0 0 0 border 0 0 0 0 padding 0 0 0 0
+        \"hi\" (2, 3) 16x16
+");
+    let json = layout.children[0].children[0].children[0].to_json();
+    assert_eq!(json, "{\"type\":\"inline\",\"origin\":\"#text\",\
+        \"content\":{\"x\":2,\"y\":3,\"width\":16,\"height\":16},\
+        \"padding\":{\"top\":0,\"right\":0,\"bottom\":0,\"left\":0},\
+        \"border\":{\"top\":0,\"right\":0,\"bottom\":0,\"left\":0},\
+        \"margin\":{\"top\":0,\"right\":0,\"bottom\":0,\"left\":0},\
+        \"lines\":[{\"text\":\"hi\",\"rect\":{\"x\":2,\"y\":3,\"width\":16,\"height\":16}}],\"children\":[]}");
+    assert_eq!(json_string("a\"b\\\n"), "\"a\\\"b\\\\\\n\"");
+}
+
+#[test]
+fn test_try_layout_tree() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<p>x</p>".to_string());
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+
+    let hidden = css::parse("html { display: none; }".to_string());
+    let styled = style::style_tree(&root, &hidden);
+    let context = LayoutContext::new(viewport, &styled);
+    let err = try_layout_tree(&styled, &context, &ImageSizes::new()).err();
+    assert_eq!(err, Some(LayoutError::RootNotRendered));
+
+    let stylesheet = css::parse(String::new());
+    let styled = style::style_tree(&root, &stylesheet);
+    let layout = try_layout_tree(&styled, &context, &ImageSizes::new()).unwrap();
+    let anonymous = &layout.children[0].children[0].children[0];
+    assert_eq!(anonymous.box_type.name(), "anonymous");
+    assert!(anonymous.get_style_node().is_none());
+}
+
+#[test]
+fn test_letter_and_word_spacing() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<p class=l>ab cd</p><p class=w>ab cd ef</p>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } p { width: 60px; } .l { letter-spacing: 2px; } \
+        .w { word-spacing: 0.5em; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let lines = |p: &LayoutBox| -> Vec<(String, f64, f64, f64)> {
+        p.children[0].children[0].lines.iter()
+            .map(|fragment| (fragment.text.clone(), fragment.rect.x, fragment.rect.y, fragment.rect.width))
+            .collect()
+    };
+    let body = &layout.children[0];
+    // 10px a character, 10px more for the space, it still fits
+    assert_eq!(lines(&body.children[0]), [("ab cd".to_string(), 0.0, 0.0, 50.0)]);
+    // the spaces are 16px wide, the third word goes to the next line
+    assert_eq!(lines(&body.children[1]), [
+        ("ab".to_string(), 0.0, 16.0, 16.0),
+        ("cd".to_string(), 32.0, 16.0, 16.0),
+        ("ef".to_string(), 0.0, 32.0, 16.0),
+    ]);
+}
+
+#[test]
+fn test_anonymous_blocks() {
+    use crate::{css, html, style};
+
+    let root = html::parse("<div class=m>a<p>b</p>c<span>d</span><p>e</p>f</div>\
+        <div class=s><span>g<p>h</p>i</span></div>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } span { padding-left: 4px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout = layout_tree(&styled, viewport);
+
+    let names = |layout_box: &LayoutBox| -> Vec<(&str, usize)> {
+        layout_box.children.iter().map(|child| (child.box_type.name(), child.children.len())).collect()
+    };
+    let body = &layout.children[0];
+    // c and the span share the anonymous block between the paragraphs
+    assert_eq!(names(&body.children[0]), [
+        ("anonymous", 1), ("block", 1), ("anonymous", 2), ("block", 1), ("anonymous", 1),
+    ]);
+
+    // the span is broken around the paragraph, both pieces keep its style
+    let broken = &body.children[1];
+    assert_eq!(names(broken), [("anonymous", 1), ("block", 1), ("anonymous", 1)]);
+    let pieces: Vec<(f64, f64)> = [&broken.children[0], &broken.children[2]].iter().map(|anonymous| {
+        let span = &anonymous.children[0];
+        (span.dimensions.content.y, span.dimensions.padding.left)
+    }).collect();
+    assert_eq!(pieces, [(80.0, 4.0), (112.0, 4.0)]);
+    assert_eq!(broken.children[1].dimensions.content.y, 96.0);
+}
@@ -1,10 +1,17 @@
+pub mod atom;
+#[cfg(test)]
+mod conformance;
 pub mod css;
 pub mod dom;
 pub mod html;
+pub mod image;
 pub mod layout;
 pub mod painter;
 pub mod renderer;
+#[cfg(feature = "js")]
+pub mod script;
 pub mod style;
+pub mod url;
 
 // extern crate cairo;
 // extern crate gtk;
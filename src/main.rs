@@ -1,18 +1,20 @@
 
 use rust_browser::css;
 use rust_browser::html;
+use rust_browser::image;
 use rust_browser::layout;
 use rust_browser::painter;
-use rust_browser::renderer;
+use rust_browser::renderer::{PdfRenderer, RasterFormat, RasterRenderer, Renderer, TerminalRenderer};
 use rust_browser::style;
 
 use clap::{App, Arg};
 
 use std::default::Default;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
 
-const VERSION_STR: &'static str = env!("CARGO_PKG_VERSION");
+const VERSION_STR: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
     let app = App::new("rust_browser")
@@ -22,46 +24,145 @@ fn main() {
         .arg(Arg::with_name("FILE")
             .help("Input file")
             .index(1)
+            .default_value("./example/test.html")
+        )
+        .arg(Arg::with_name("css")
+            .long("css")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("A stylesheet to use instead of the <link>ed ones, can be given more than once")
+        )
+        .arg(Arg::with_name("viewport")
+            .long("viewport")
+            .takes_value(true)
+            .value_name("WxH")
+            .default_value("480x360")
+            .validator(|value| parse_viewport(&value).map(|_| ()).ok_or_else(|| format!("{} isn't WIDTHxHEIGHT in px", value)))
+            .help("The size of the layout viewport, each page is as tall as it")
+        )
+        .arg(Arg::with_name("media")
+            .long("media")
+            .takes_value(true)
+            .possible_values(&["screen", "print"])
+            .default_value("screen")
+            .help("The media type @media rules are matched against")
+        )
+        .arg(Arg::with_name("terminal")
+            .long("terminal")
+            .help("Prints the pages in the terminal instead of writing a pdf")
+        )
+        .arg(Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["pdf", "ppm", "bmp"])
+            .default_value("pdf")
+            .help("The file to write, ppm and bmp are an image of the first page")
+        )
+        .arg(Arg::with_name("output")
+            .long("output")
+            .short("o")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Where to write the file, FILE with the extension of --format by default")
         );
-    let _app_matches = app.get_matches();
+    let app_matches = app.get_matches();
     
+    let path = app_matches.value_of("FILE").unwrap();
+
     println!("HTML:");
-    let mut html_source = "".to_string();
-    OpenOptions::new()
-        .read(true)
-        .open("./example/test.html")
-        .unwrap()
-        .read_to_string(&mut html_source)
-        .ok()
-        .expect("cannot read file");
-    let html_tree = html::parse(html_source);
+    let html_source = fs::read(path).unwrap_or_else(|err| {
+        eprintln!("rust_browser: cannot read {}: {}", path, err);
+        process::exit(1);
+    });
+    #[allow(unused_mut)]
+    let mut html_tree = html::parse_bytes(&html_source).unwrap_or_else(|err| {
+        eprintln!("rust_browser: {}: {}", path, err);
+        process::exit(1);
+    });
+    #[cfg(feature = "js")]
+    rust_browser::script::execute_scripts(&mut html_tree);
     println!("{}", html_tree);
 
+    // <style> elements are part of the document and apply either way
     println!("CSS:");
-    let mut css_source = "".to_string();
-    OpenOptions::new()
-        .read(true)
-        .open("example/test.css")
-        .unwrap()
-        .read_to_string(&mut css_source)
-        .ok()
-        .expect("cannot read file");
-    let stylesheet = css::parse(css_source);
-    css::show_css(&stylesheet);
+    let stylesheet = match app_matches.values_of("css") {
+        Some(css_paths) => {
+            let mut stylesheet = css::Stylesheet::new();
+            for css_path in css_paths {
+                let source = fs::read_to_string(css_path).unwrap_or_else(|err| {
+                    eprintln!("rust_browser: cannot read {}: {}", css_path, err);
+                    process::exit(1);
+                });
+                stylesheet.append(css::parse(source));
+            }
+            stylesheet
+        }
+        None => style::linked_stylesheets(&html_tree, path),
+    };
+    print!("{}", stylesheet);
 
     println!("LAYOUT:");
     let mut viewport: layout::Dimensions = Default::default();
-    viewport.content.width = 480.0;
-    viewport.content.height = 360.0;
+    let viewport_arg = app_matches.value_of("viewport").unwrap();
+    let (width, height) = parse_viewport(viewport_arg).unwrap_or_else(|| {
+        eprintln!("rust_browser: {} isn't WIDTHxHEIGHT in px", viewport_arg);
+        process::exit(1);
+    });
+    viewport.content.width = width;
+    viewport.content.height = height;
 
-    let style_tree = style::style_tree(&html_tree, &stylesheet);
-    let layout_tree = layout::layout_tree(&style_tree, viewport);
+    let media = css::MediaContext {
+        viewport_width: viewport.content.width,
+        viewport_height: viewport.content.height,
+        media_type: app_matches.value_of("media").unwrap_or("screen").to_string(),
+    };
+    let style_tree = style::style_tree_with_media(&html_tree, &stylesheet, &media);
+    let decoded_images = image::load_images(&style_tree, path);
+    let images = image::sizes_of(&decoded_images);
+    let context = layout::LayoutContext::new(viewport, &style_tree);
+    let layout_tree = layout::try_layout_tree(&style_tree, &context, &images).unwrap_or_else(|err| {
+        eprintln!("rust_browser: {}", err);
+        process::exit(1);
+    });
     println!("{}", layout_tree);
 
     println!("DISPLAY:");
     let mut display_list = Vec::new();
-    painter::render_layout_box_tree(&mut display_list, &layout_tree);
+    painter::render_layout_box_tree_with_images(&mut display_list, &layout_tree, &decoded_images);
     println!("{:?}", display_list); 
 
-    renderer::render(&display_list, &viewport);
-}
\ No newline at end of file
+    let pages = painter::paginate(&layout_tree, viewport.content.height, &decoded_images);
+    let format = app_matches.value_of("format").unwrap();
+    let output = match app_matches.value_of("output") {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(path).with_extension(format),
+    };
+    let mut pdf = PdfRenderer::new(&output);
+    pdf.options.base_url = Some(path.to_string());
+    let mut terminal = TerminalRenderer::new(80);
+    let mut ppm = RasterRenderer::new(&output, RasterFormat::Ppm);
+    let mut bmp = RasterRenderer::new(&output, RasterFormat::Bmp);
+    let backend: &mut dyn Renderer = match format {
+        _ if app_matches.is_present("terminal") => &mut terminal,
+        "ppm" => &mut ppm,
+        "bmp" => &mut bmp,
+        _ => &mut pdf,
+    };
+    if let Err(err) = backend.render_pages(&pages, &viewport) {
+        eprintln!("rust_browser: {}", err);
+        process::exit(1);
+    }
+}
+
+// `1280x720`, both sides positive px
+fn parse_viewport(value: &str) -> Option<(f64, f64)> {
+    let mut sides = value.splitn(2, ['x', 'X']);
+    let width: f64 = sides.next()?.trim().parse().ok()?;
+    let height: f64 = sides.next()?.trim().parse().ok()?;
+    if width > 0.0 && height > 0.0 && width.is_finite() && height.is_finite() {
+        Some((width, height))
+    } else {
+        None
+    }
+}
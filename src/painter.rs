@@ -1,24 +1,203 @@
-use crate::css::{Color};
-use crate::dom::NodeType;
-use crate::layout::{BoxType, LayoutBox, Rect};
+use crate::css::{Color, Unit, Value};
+use crate::image::{Image, Images};
+use crate::layout::{self, BoxType, LayoutBox, Rect};
+use crate::style::{self, BackgroundClip, BackgroundImage, BackgroundSize, BorderStyle, ComputedStyle, Display, Float, FontStyle, ImageRendering, Position, TextDecoration, TransformFunction, Visibility};
 
+use rusttype::{point, Font, Scale};
+use serde::{Deserialize, Serialize};
+use std::{env, fs};
+use std::rc::Rc;
 
 pub struct Canvas {
     pub pixels: Vec<Color>,
     pub width: usize,
     pub height: usize,
+    layers: Vec<(Vec<Color>, f64)>, // the pixels under each open layer, with its opacity
+    clips: Vec<Rect>, // each inside the one before, only the last one is painted in
+    transforms: Vec<Transform>, // each with the ones before it applied, the last one is used
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum DisplayCommand {
     SolidColor(Color, Rect),
-    Text(String, Rect),
+    Border(Border),
+    Text(String, Rect, TextStyle), // the rect is as tall as the font size
+    Image(Rc<Image>, Rect, Rect, ImageRendering), // the image is scaled to the first rect, only the second one shows
+    PushClip(Rect), // the commands up to the matching PopClip paint inside it
+    PopClip,
+    PushLayer(f64), // the commands up to the matching PopLayer are painted apart, then blended with the opacity
+    PopLayer,
+    PushTransform(Transform), // the commands up to the matching PopTransform are moved and scaled by it
+    PopTransform,
+    Bullet(BulletShape, Rect, Color), // a list marker, fonts like the builtin pdf ones have no glyph for it
+    Link(String, Rect), // the href as written, clicking the rect follows it. nothing is painted
+    Anchor(String, Rect), // where a link to #name goes, for an id or <a name>
+}
+
+// the border of a box, the pdf strokes it with its styles and round corners,
+// the canvas fills its sides
+// https://www.w3.org/TR/css-backgrounds-3/#borders
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Border {
+    pub rect: Rect, // the border box
+    pub widths: [f64; 4], // top, right, bottom and left
+    pub colors: [Option<Color>; 4], // None where the side isn't drawn
+    pub styles: [BorderStyle; 4],
+    pub radii: [f64; 4], // of the outer edge, top left, top right, bottom right and bottom left
+}
+
+impl Border {
+    // each drawn side as a rect with square corners, left, right, top then bottom
+    pub fn sides(&self) -> Vec<DisplayCommand> {
+        let [top, right, bottom, left] = self.widths;
+        let rect = self.rect;
+        let sides = [
+            (3, Rect { width: left, ..rect }),
+            (1, Rect { x: rect.x + rect.width - right, width: right, ..rect }),
+            (0, Rect { height: top, ..rect }),
+            (2, Rect { y: rect.y + rect.height - bottom, height: bottom, ..rect }),
+        ];
+        sides.iter()
+            .filter_map(|&(side, rect)| self.colors[side].map(|color| DisplayCommand::SolidColor(color, rect)))
+            .collect()
+    }
+
+    // a negative scale swaps the sides and corners it flips
+    fn transformed(&self, transform: &Transform) -> Border {
+        let (scale_x, scale_y) = (transform.scale_x.abs(), transform.scale_y.abs());
+        let [top, right, bottom, left] = self.widths;
+        let mut border = Border {
+            rect: transform.apply(self.rect),
+            widths: [top * scale_y, right * scale_x, bottom * scale_y, left * scale_x],
+            radii: self.radii.map(|radius| radius * scale_x.min(scale_y)),
+            ..self.clone()
+        };
+        if transform.scale_x < 0.0 {
+            border.widths.swap(1, 3);
+            border.colors.swap(1, 3);
+            border.styles.swap(1, 3);
+            border.radii.swap(0, 1);
+            border.radii.swap(2, 3);
+        }
+        if transform.scale_y < 0.0 {
+            border.widths.swap(0, 2);
+            border.colors.swap(0, 2);
+            border.styles.swap(0, 2);
+            border.radii.swap(0, 3);
+            border.radii.swap(1, 2);
+        }
+        border
+    }
+}
+
+// https://www.w3.org/TR/css-lists-3/#text-markers
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BulletShape {
+    Disc,
+    Circle,
+    Square,
+}
+
+// a 2d matrix without rotation or skew, a point (x, y) goes to
+// (x * scale_x + translate_x, y * scale_y + translate_y)
+// https://www.w3.org/TR/css-transforms-1/#transform-rendering
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    pub scale_x: f64,
+    pub scale_y: f64,
+    pub translate_x: f64,
+    pub translate_y: f64,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform { scale_x: 1.0, scale_y: 1.0, translate_x: 0.0, translate_y: 0.0 }
+    }
+
+    fn translate(x: f64, y: f64) -> Transform {
+        Transform { translate_x: x, translate_y: y, ..Transform::identity() }
+    }
+
+    // `inner` first, then this one
+    pub fn then(&self, inner: &Transform) -> Transform {
+        Transform {
+            scale_x: self.scale_x * inner.scale_x,
+            scale_y: self.scale_y * inner.scale_y,
+            translate_x: self.scale_x * inner.translate_x + self.translate_x,
+            translate_y: self.scale_y * inner.translate_y + self.translate_y,
+        }
+    }
+
+    // a negative scale flips the rect, its width and height stay positive
+    pub fn apply(&self, rect: Rect) -> Rect {
+        let (x0, x1) = (rect.x * self.scale_x + self.translate_x, (rect.x + rect.width) * self.scale_x + self.translate_x);
+        let (y0, y1) = (rect.y * self.scale_y + self.translate_y, (rect.y + rect.height) * self.scale_y + self.translate_y);
+        Rect { x: x0.min(x1), y: y0.min(y1), width: (x1 - x0).abs(), height: (y1 - y0).abs() }
+    }
+}
+
+// what the css says the text looks like
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextStyle {
+    pub color: Color,
+    pub font: style::Font,
+}
+
+impl TextStyle {
+    // black when no color is set
+    fn new(computed: &ComputedStyle) -> TextStyle {
+        TextStyle {
+            color: computed.color.unwrap_or(Color { r: 0, g: 0, b: 0, a: 255 }),
+            font: computed.font.clone(),
+        }
+    }
+
+    pub fn is_bold(&self) -> bool {
+        self.font.weight >= 600
+    }
+
+    pub fn is_italic(&self) -> bool {
+        self.font.style != FontStyle::Normal
+    }
 }
 
 pub type DisplayList = Vec<DisplayCommand>;
 
+impl DisplayCommand {
+    // where the command paints, None for the clip and layer commands
+    fn bounds(&self) -> Option<Rect> {
+        match *self {
+            DisplayCommand::SolidColor(_, rect) | DisplayCommand::Text(_, rect, _) => Some(rect),
+            DisplayCommand::Image(_, _, visible, _) => Some(visible),
+            DisplayCommand::Border(ref border) => Some(border.rect),
+            DisplayCommand::Bullet(_, rect, _) => Some(rect),
+            _ => None,
+        }
+    }
+
+    // the command with its rects moved by `transform`. text grows with the
+    // vertical scale, images aren't mirrored
+    fn transformed(&self, transform: &Transform) -> DisplayCommand {
+        match *self {
+            DisplayCommand::SolidColor(color, rect) => DisplayCommand::SolidColor(color, transform.apply(rect)),
+            DisplayCommand::Border(ref border) => DisplayCommand::Border(border.transformed(transform)),
+            DisplayCommand::Text(ref text, rect, ref text_style) => {
+                let mut text_style = text_style.clone();
+                text_style.font.size *= transform.scale_y.abs();
+                DisplayCommand::Text(text.clone(), transform.apply(rect), text_style)
+            }
+            DisplayCommand::Image(ref image, rect, visible, rendering) => DisplayCommand::Image(image.clone(), transform.apply(rect), transform.apply(visible), rendering),
+            DisplayCommand::PushClip(rect) => DisplayCommand::PushClip(transform.apply(rect)),
+            DisplayCommand::Bullet(shape, rect, color) => DisplayCommand::Bullet(shape, transform.apply(rect), color),
+            DisplayCommand::Link(ref href, rect) => DisplayCommand::Link(href.clone(), transform.apply(rect)),
+            DisplayCommand::Anchor(ref name, rect) => DisplayCommand::Anchor(name.clone(), transform.apply(rect)),
+            ref command => command.clone(),
+        }
+    }
+}
+
 impl Canvas {
-    fn new(width: usize, height: usize) -> Canvas {
+    pub fn new(width: usize, height: usize) -> Canvas {
         let white = Color {
             r: 255,
             g: 255,
@@ -29,132 +208,1435 @@ impl Canvas {
             pixels: vec![white; width * height],
             width: width,
             height: height,
+            layers: Vec::new(),
+            clips: Vec::new(),
+            transforms: Vec::new(),
         }
     }
 
-    fn paint_pixels_by_display_command(&mut self, display_command: &DisplayCommand) {
+    // None outside the canvas
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Color> {
+        if x < self.width && y < self.height {
+            Some(self.pixels[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    // four bytes a pixel, red, green, blue and alpha, row by row from the top
+    // left like a png wants them
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|pixel| vec![pixel.r, pixel.g, pixel.b, pixel.a]).collect()
+    }
+
+    // a copy of the part of the canvas under `rect`, cut to the canvas. the
+    // edges are rounded out to whole pixels
+    pub fn sub_image(&self, rect: Rect) -> Canvas {
+        let x_left = rect.x.floor().max(0.0).min(self.width as f64) as usize;
+        let y_top = rect.y.floor().max(0.0).min(self.height as f64) as usize;
+        let x_right = (rect.x + rect.width).ceil().max(x_left as f64).min(self.width as f64) as usize;
+        let y_bottom = (rect.y + rect.height).ceil().max(y_top as f64).min(self.height as f64) as usize;
+
+        let mut pixels = Vec::with_capacity((x_right - x_left) * (y_bottom - y_top));
+        for y in y_top..y_bottom {
+            pixels.extend_from_slice(&self.pixels[y * self.width + x_left..y * self.width + x_right]);
+        }
+        Canvas {
+            pixels: pixels,
+            width: x_right - x_left,
+            height: y_bottom - y_top,
+            layers: Vec::new(),
+            clips: Vec::new(),
+            transforms: Vec::new(),
+        }
+    }
+
+    // the pixel range of `rect` that is on the canvas and inside the clip
+    fn pixel_bounds(&self, rect: Rect) -> (usize, usize, usize, usize) {
+        let rect = self.clips.last().map_or(rect, |clip| clip.intersection(&rect));
+        let x_left = rect.x.max(0.0).min(self.width as f64) as usize;
+        let y_top = rect.y.max(0.0).min(self.height as f64) as usize;
+        let x_right = (rect.x + rect.width).max(0.0).min(self.width as f64) as usize;
+        let y_bottom = (rect.y + rect.height).max(0.0).min(self.height as f64) as usize;
+        (x_left, y_top, x_right, y_bottom)
+    }
+
+    // text needs a font, without one it is left out
+    pub(crate) fn paint_pixels_by_display_command(&mut self, display_command: &DisplayCommand, font: Option<&Font>) {
         match *display_command {
-            DisplayCommand::SolidColor(color, rect) => {
-                // clip out the canvas rectangle boundaries.
-                let x_left = rect.x.max(0.0).min(self.width as f64) as usize;
-                let y_top = rect.y.max(0.0).min(self.height as f64) as usize;
-                let x_right = (rect.x + rect.width).max(0.0).min(self.width as f64) as usize;
-                let y_bottom = (rect.y + rect.height).max(0.0).min(self.height as f64) as usize;
+            DisplayCommand::PushTransform(transform) => {
+                let transform = self.transforms.last().map_or(transform, |outer| outer.then(&transform));
+                self.transforms.push(transform);
+            }
+            DisplayCommand::PopTransform => {
+                self.transforms.pop();
+            }
+            _ => match self.transforms.last() {
+                Some(transform) => {
+                    let display_command = display_command.transformed(transform);
+                    self.paint_untransformed(&display_command, font);
+                }
+                None => self.paint_untransformed(display_command, font),
+            },
+        }
+    }
 
+    fn paint_untransformed(&mut self, display_command: &DisplayCommand, font: Option<&Font>) {
+        match *display_command {
+            DisplayCommand::Text(ref text, rect, ref text_style) => {
+                if let Some(font) = font {
+                    self.paint_text(text, rect, text_style, font);
+                }
+            }
+            DisplayCommand::SolidColor(color, rect) => {
+                let (x_left, y_top, x_right, y_bottom) = self.pixel_bounds(rect);
+                if x_left >= x_right {
+                    return;
+                }
                 for y in y_top..y_bottom {
-                    for x in x_left..x_right {
-                        self.pixels[y * self.width + x] = color;
+                    let row = &mut self.pixels[y * self.width + x_left..y * self.width + x_right];
+                    // an opaque color hides what is under it, the row is filled at once
+                    if color.a == 255 {
+                        row.fill(color);
+                    } else {
+                        for pixel in row {
+                            blend(pixel, color, color.a as f64 / 255.0);
+                        }
                     }
                 }
             }
-            _ => {}
+            DisplayCommand::Image(ref image, rect, visible, rendering) => self.paint_image(image, rect, visible, rendering),
+            DisplayCommand::Border(ref border) => {
+                for side in border.sides() {
+                    self.paint_untransformed(&side, font);
+                }
+            }
+            DisplayCommand::Bullet(BulletShape::Square, rect, color) => self.paint_untransformed(&DisplayCommand::SolidColor(color, rect), font),
+            DisplayCommand::Bullet(shape, rect, color) => self.paint_round_bullet(shape, rect, color),
+            DisplayCommand::PushClip(rect) => {
+                let clip = self.clips.last().map_or(rect, |clip| clip.intersection(&rect));
+                self.clips.push(clip);
+            }
+            DisplayCommand::PopClip => {
+                self.clips.pop();
+            }
+            // a layer starts out transparent
+            DisplayCommand::PushLayer(opacity) => {
+                let transparent = Color { r: 0, g: 0, b: 0, a: 0 };
+                let under = std::mem::replace(&mut self.pixels, vec![transparent; self.width * self.height]);
+                self.layers.push((under, opacity));
+            }
+            DisplayCommand::PopLayer => {
+                if let Some((under, opacity)) = self.layers.pop() {
+                    let layer = std::mem::replace(&mut self.pixels, under);
+                    for (pixel, color) in self.pixels.iter_mut().zip(layer) {
+                        blend(pixel, color, color.a as f64 / 255.0 * opacity);
+                    }
+                }
+            }
+            DisplayCommand::PushTransform(_) | DisplayCommand::PopTransform => {}
+            DisplayCommand::Link(..) | DisplayCommand::Anchor(..) => {}
+        }
+    }
+
+    // each pixel takes the image at its center, the nearest image pixel when
+    // pixelated and the four around it weighted by distance when smooth
+    fn paint_image(&mut self, image: &Image, rect: Rect, visible: Rect, rendering: ImageRendering) {
+        if image.width == 0 || image.height == 0 {
+            return;
+        }
+        let (x_left, y_top, x_right, y_bottom) = self.pixel_bounds(visible);
+        // in image pixels, from the center of the first one
+        let source = |at: usize, start: f64, length: f64, size: u32| {
+            ((at as f64 + 0.5 - start) / length * size as f64 - 0.5).max(0.0).min(size as f64 - 1.0)
+        };
+        let pixel = |x: usize, y: usize| image.pixels[y * image.width as usize + x];
+        for y in y_top..y_bottom {
+            let v = source(y, rect.y, rect.height, image.height);
+            for x in x_left..x_right {
+                let u = source(x, rect.x, rect.width, image.width);
+                let color = match rendering {
+                    ImageRendering::Pixelated => pixel(u.round() as usize, v.round() as usize),
+                    ImageRendering::Smooth => {
+                        let (u0, v0) = (u.floor() as usize, v.floor() as usize);
+                        let (u1, v1) = ((u0 + 1).min(image.width as usize - 1), (v0 + 1).min(image.height as usize - 1));
+                        let (fu, fv) = (u - u0 as f64, v - v0 as f64);
+                        let mix = |channel: fn(Color) -> u8| {
+                            let top = channel(pixel(u0, v0)) as f64 * (1.0 - fu) + channel(pixel(u1, v0)) as f64 * fu;
+                            let bottom = channel(pixel(u0, v1)) as f64 * (1.0 - fu) + channel(pixel(u1, v1)) as f64 * fu;
+                            (top * (1.0 - fv) + bottom * fv).round() as u8
+                        };
+                        Color { r: mix(|c| c.r), g: mix(|c| c.g), b: mix(|c| c.b), a: mix(|c| c.a) }
+                    }
+                };
+                blend(&mut self.pixels[y * self.width + x], color, color.a as f64 / 255.0);
+            }
+        }
+    }
+
+    // the largest circle in the rect, each pixel is blended with the color by
+    // how far inside it its center is. the ring of a circle is a third of the
+    // radius thick
+    fn paint_round_bullet(&mut self, shape: BulletShape, rect: Rect, color: Color) {
+        let radius = rect.width.min(rect.height) / 2.0;
+        let (center_x, center_y) = (rect.x + rect.width / 2.0, rect.y + rect.height / 2.0);
+        // the edge pixels are partly covered
+        let (x_left, y_top, x_right, y_bottom) = self.pixel_bounds(Rect { x: rect.x - 1.0, y: rect.y - 1.0, width: rect.width + 2.0, height: rect.height + 2.0 });
+        for y in y_top..y_bottom {
+            for x in x_left..x_right {
+                let distance = (x as f64 + 0.5 - center_x).hypot(y as f64 + 0.5 - center_y);
+                let inside = match shape {
+                    BulletShape::Circle => (radius - distance).min(distance - radius * 2.0 / 3.0),
+                    _ => radius - distance,
+                };
+                let coverage = (inside + 0.5).clamp(0.0, 1.0);
+                if coverage > 0.0 {
+                    blend(&mut self.pixels[y * self.width + x], color, coverage * color.a as f64 / 255.0);
+                }
+            }
+        }
+    }
+
+    // the glyphs sit on a baseline an ascent below the top of the rect, each
+    // pixel is blended with the color by how much of it the glyph covers. the
+    // one font stands in for every family, weight and style
+    fn paint_text(&mut self, text: &str, rect: Rect, text_style: &TextStyle, font: &Font) {
+        let color = text_style.color;
+        let (x_left, y_top, x_right, y_bottom) = self.pixel_bounds(Rect { x: 0.0, y: 0.0, width: self.width as f64, height: self.height as f64 });
+        // a glyph is rasterized whole, huge font sizes are drawn at this size
+        let scale = Scale::uniform(text_style.font.size.min(MAX_GLYPH_SIZE) as f32);
+        let baseline = rect.y as f32 + font.v_metrics(scale).ascent;
+        for glyph in font.layout(text, scale, point(rect.x as f32, baseline)) {
+            let bounds = match glyph.pixel_bounding_box() {
+                Some(bounds) => bounds,
+                None => continue, // a space
+            };
+            // outside the clip, nothing to rasterize
+            if bounds.max.x <= x_left as i32 || bounds.max.y <= y_top as i32 || bounds.min.x >= x_right as i32 || bounds.min.y >= y_bottom as i32 {
+                continue;
+            }
+            glyph.draw(|x, y, coverage| {
+                let (x, y) = (bounds.min.x + x as i32, bounds.min.y + y as i32);
+                if x < x_left as i32 || y < y_top as i32 || x >= x_right as i32 || y >= y_bottom as i32 {
+                    return;
+                }
+                let alpha = coverage as f64 * color.a as f64 / 255.0;
+                blend(&mut self.pixels[y as usize * self.width + x as usize], color, alpha);
+            });
         }
     }
 }
 
+// the color over the pixel, `alpha` is the color's own alpha times how much
+// of the pixel it covers. pixels are only transparent inside a layer
+// https://www.w3.org/TR/compositing-1/#porterduffcompositingoperators_srcover
+fn blend(pixel: &mut Color, color: Color, alpha: f64) {
+    let under = pixel.a as f64 / 255.0 * (1.0 - alpha);
+    let total = alpha + under;
+    if total == 0.0 {
+        return;
+    }
+    let mix = |below: u8, over: u8| ((below as f64 * under + over as f64 * alpha) / total).round() as u8;
+    pixel.r = mix(pixel.r, color.r);
+    pixel.g = mix(pixel.g, color.g);
+    pixel.b = mix(pixel.b, color.b);
+    pixel.a = (total * 255.0).round() as u8;
+}
+
+const MAX_GLYPH_SIZE: f64 = 2048.0;
+
+// the first of these that loads draws the text on a canvas,
+// after the file named by RUST_BROWSER_FONT if it is set
+const FONT_PATHS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+pub fn load_font() -> Option<Font<'static>> {
+    let configured = env::var("RUST_BROWSER_FONT").ok();
+    configured.iter().map(|path| path.as_str()).chain(FONT_PATHS.iter().cloned())
+        .filter_map(|path| fs::read(path).ok())
+        .find_map(|bytes| Font::from_bytes(bytes).ok())
+}
+
 // make a pixel array from the layout tree
 pub fn paint(layout_root: &LayoutBox, boundary: Rect) -> Canvas {
+    paint_with_font(layout_root, boundary, load_font().as_ref(), &Images::new())
+}
+
+pub fn paint_with_font(layout_root: &LayoutBox, boundary: Rect, font: Option<&Font>, images: &Images) -> Canvas {
     let mut display_command_list = Vec::new();
-    render_layout_box_tree(&mut display_command_list, layout_root);
+    render_layout_box_tree_with_images(&mut display_command_list, layout_root, images);
 
     let mut canvas = Canvas::new(boundary.width as usize, boundary.height as usize);
     for display_command in &display_command_list {
-        canvas.paint_pixels_by_display_command(display_command);
+        canvas.paint_pixels_by_display_command(display_command, font);
     }
     canvas
 }
 
+// paint the list again, but only inside `dirty`. what is outside is left as it was
+pub fn repaint(canvas: &mut Canvas, display_list: &DisplayList, dirty: Rect) {
+    repaint_with_font(canvas, display_list, dirty, load_font().as_ref());
+}
+
+pub fn repaint_with_font(canvas: &mut Canvas, display_list: &DisplayList, dirty: Rect, font: Option<&Font>) {
+    let white = Color { r: 255, g: 255, b: 255, a: 255 };
+    canvas.paint_pixels_by_display_command(&DisplayCommand::PushClip(dirty), font);
+    canvas.paint_pixels_by_display_command(&DisplayCommand::SolidColor(white, dirty), font);
+    for display_command in display_list {
+        let bounds = display_command.bounds().map(|rect| canvas.transforms.last().map_or(rect, |transform| transform.apply(rect)));
+        let misses = bounds.is_some_and(|rect| dirty.intersection(&rect).is_empty());
+        if !misses {
+            canvas.paint_pixels_by_display_command(display_command, font);
+        }
+    }
+    canvas.paint_pixels_by_display_command(&DisplayCommand::PopClip, font);
+}
+
+// the list as json, enough to paint it again without the html and css. images
+// are written out pixel by pixel
+pub fn dump_json(display_list: &DisplayList) -> String {
+    serde_json::to_string(display_list).expect("display commands always serialize")
+}
+
+pub fn parse_json(json: &str) -> serde_json::Result<DisplayList> {
+    serde_json::from_str(json)
+}
+
+// paint a list, a parsed one say, on top of what is on the canvas
+pub fn replay(display_list: &DisplayList, canvas: &mut Canvas) {
+    let font = load_font();
+    for display_command in display_list {
+        canvas.paint_pixels_by_display_command(display_command, font.as_ref());
+    }
+}
+
+// the list without clip and transform commands, what the clips cut off is
+// gone and the rest is moved where the transforms put it. text is kept whole
+// when any of it is inside. the canvas and the pdf clip and transform by
+// themselves, pages are cut with this
+pub fn apply_clips(list: &DisplayList) -> DisplayList {
+    let mut clips: Vec<Rect> = Vec::new(); // each inside the one before
+    let mut transforms: Vec<Transform> = Vec::new(); // each with the ones before it applied
+    let mut clipped = Vec::new();
+    for command in list {
+        let clip = clips.last().cloned();
+        let command = match *command {
+            DisplayCommand::PushTransform(transform) => {
+                transforms.push(transforms.last().map_or(transform, |outer| outer.then(&transform)));
+                continue;
+            }
+            DisplayCommand::PopTransform => {
+                transforms.pop();
+                continue;
+            }
+            ref command => transforms.last().map_or(command.clone(), |transform| command.transformed(transform)),
+        };
+        match command {
+            DisplayCommand::PushClip(rect) => clips.push(clip.map_or(rect, |clip| clip.intersection(&rect))),
+            DisplayCommand::PopClip => {
+                clips.pop();
+            }
+            DisplayCommand::SolidColor(color, rect) => {
+                let rect = clip.map_or(rect, |clip| clip.intersection(&rect));
+                if !rect.is_empty() {
+                    clipped.push(DisplayCommand::SolidColor(color, rect));
+                }
+            }
+            DisplayCommand::Text(_, rect, _) => {
+                if clip.is_none_or(|clip| !clip.intersection(&rect).is_empty()) {
+                    clipped.push(command);
+                }
+            }
+            // a border the clip cuts into falls back to its clipped sides
+            DisplayCommand::Border(border) => match clip {
+                Some(clip) if !clip.contains(&border.rect) => {
+                    for side in border.sides() {
+                        if let DisplayCommand::SolidColor(color, rect) = side {
+                            let rect = clip.intersection(&rect);
+                            if !rect.is_empty() {
+                                clipped.push(DisplayCommand::SolidColor(color, rect));
+                            }
+                        }
+                    }
+                }
+                _ => clipped.push(DisplayCommand::Border(border)),
+            },
+            DisplayCommand::Image(image, rect, visible, rendering) => {
+                let visible = clip.map_or(visible, |clip| clip.intersection(&visible));
+                if !visible.is_empty() {
+                    clipped.push(DisplayCommand::Image(image, rect, visible, rendering));
+                }
+            }
+            DisplayCommand::Bullet(_, rect, _) => {
+                // a bullet is kept whole, like text
+                if clip.is_none_or(|clip| !clip.intersection(&rect).is_empty()) {
+                    clipped.push(command);
+                }
+            }
+            DisplayCommand::Link(href, rect) => {
+                let rect = clip.map_or(rect, |clip| clip.intersection(&rect));
+                if !rect.is_empty() {
+                    clipped.push(DisplayCommand::Link(href, rect));
+                }
+            }
+            // an anchor stays with its top left corner, an empty <a name> is still a place to go
+            DisplayCommand::Anchor(_, rect) => {
+                let inside = |clip: Rect| rect.x >= clip.x && rect.x <= clip.x + clip.width && rect.y >= clip.y && rect.y < clip.y + clip.height;
+                if clip.is_none_or(inside) {
+                    clipped.push(command);
+                }
+            }
+            DisplayCommand::PushLayer(_) | DisplayCommand::PopLayer => clipped.push(command),
+            DisplayCommand::PushTransform(_) | DisplayCommand::PopTransform => {}
+        }
+    }
+    clipped
+}
+
+// one display list per page of `page_height`, each moved up to the top of its page
+pub fn paginate(layout_root: &LayoutBox, page_height: f64, images: &Images) -> Vec<DisplayList> {
+    let mut list = Vec::new();
+    render_layout_box_tree_with_images(&mut list, layout_root, images);
+
+    let overflow = layout_root.scrollable_overflow;
+    let mut starts = vec![layout_root.dimensions.margin_box().y];
+    starts.extend(layout::page_breaks(layout_root, page_height));
+    starts.iter().enumerate().map(|(i, &start)| {
+        // what sticks out above the document goes on the first page
+        let top = if i == 0 { f64::min(start, overflow.y) } else { start };
+        let bottom = starts.get(i + 1).cloned().unwrap_or(overflow.y + overflow.height);
+        let mut page = vec![DisplayCommand::PushClip(Rect { x: overflow.x, y: top, width: overflow.width, height: bottom - top })];
+        page.extend(list.iter().cloned());
+        page.push(DisplayCommand::PopClip);
+        apply_clips(&page).into_iter().map(|command| match command {
+            DisplayCommand::SolidColor(color, rect) => DisplayCommand::SolidColor(color, Rect { y: rect.y - start, ..rect }),
+            DisplayCommand::Border(border) => DisplayCommand::Border(Border { rect: Rect { y: border.rect.y - start, ..border.rect }, ..border }),
+            DisplayCommand::Text(text, rect, text_style) => DisplayCommand::Text(text, Rect { y: rect.y - start, ..rect }, text_style),
+            DisplayCommand::Image(image, rect, visible, rendering) => DisplayCommand::Image(image, Rect { y: rect.y - start, ..rect }, Rect { y: visible.y - start, ..visible }, rendering),
+            DisplayCommand::Bullet(shape, rect, color) => DisplayCommand::Bullet(shape, Rect { y: rect.y - start, ..rect }, color),
+            DisplayCommand::Link(href, rect) => DisplayCommand::Link(href, Rect { y: rect.y - start, ..rect }),
+            DisplayCommand::Anchor(name, rect) => DisplayCommand::Anchor(name, Rect { y: rect.y - start, ..rect }),
+            command => command,
+        }).collect()
+    }).collect()
+}
+
+// a positioned, translucent or transformed box and what it paints as one
+// unit, in front of or behind the rest of its parent context by z-index
+// https://www.w3.org/TR/CSS2/zindex.html
+pub struct StackingContext<'b> {
+    pub layout_box: &'b LayoutBox,
+    pub z_index: i32,
+    pub opacity: f64,
+    pub transform: Option<Transform>, // of what the context paints, the clip stays put
+    pub clip: Option<Rect>, // of the ancestors that hide their overflow, intersected
+    pub children: Vec<StackingContext<'b>>, // by z-index, in tree order when equal
+}
+
+// positioned boxes with z-index auto get a context too, at level 0. strictly
+// their positioned descendants belong to the parent context
+pub fn stacking_tree<'b>(root: &'b LayoutBox) -> StackingContext<'b> {
+    stacking_context(root, None)
+}
+
+fn stacking_context<'b>(root: &'b LayoutBox, clip: Option<Rect>) -> StackingContext<'b> {
+    let transform = stacking_style(root).and_then(|style| transform(&style.transform, root.dimensions.border_box()));
+    // the children are painted inside the transform, the clips from outside it
+    // are already pushed
+    let inherited = if transform.is_some() { None } else { clip };
+    let mut children = Vec::new();
+    collect_stacking_contexts(root, intersect_clips(inherited, root.clip), &mut children);
+    children.sort_by_key(|context| context.z_index); // stable
+    StackingContext {
+        layout_box: root,
+        z_index: positioned_style(root).and_then(|style| style.z_index).unwrap_or(0),
+        opacity: stacking_style(root).map_or(1.0, |style| style.opacity),
+        transform: transform,
+        clip: clip,
+        children: children,
+    }
+}
+
+// the outermost boxes with a context of their own under `layout_box`, they
+// are painted apart from it but still clipped by it
+fn collect_stacking_contexts<'b>(layout_box: &'b LayoutBox, clip: Option<Rect>, contexts: &mut Vec<StackingContext<'b>>) {
+    for child in &layout_box.children {
+        match stacking_style(child) {
+            Some(_) => contexts.push(stacking_context(child, clip)),
+            None => collect_stacking_contexts(child, intersect_clips(clip, child.clip), contexts),
+        }
+    }
+}
+
+fn intersect_clips(a: Option<Rect>, b: Option<Rect>) -> Option<Rect> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.intersection(&b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// the functions one after the other around the center of the border box,
+// transform-origin isn't supported. None for `none`
+// https://www.w3.org/TR/css-transforms-1/#transform-property
+fn transform(functions: &[TransformFunction], border_box: Rect) -> Option<Transform> {
+    if functions.is_empty() {
+        return None;
+    }
+    let (origin_x, origin_y) = (border_box.x + border_box.width / 2.0, border_box.y + border_box.height / 2.0);
+    let matrix = functions.iter().fold(Transform::translate(origin_x, origin_y), |matrix, function| {
+        let step = match *function {
+            TransformFunction::Translate(ref x, ref y) => Transform::translate(
+                to_px(x, border_box.width).unwrap_or(0.0),
+                to_px(y, border_box.height).unwrap_or(0.0),
+            ),
+            TransformFunction::Scale(x, y) => Transform { scale_x: x, scale_y: y, ..Transform::identity() },
+        };
+        matrix.then(&step)
+    });
+    Some(matrix.then(&Transform::translate(-origin_x, -origin_y)))
+}
+
+fn positioned_style(layout_box: &LayoutBox) -> Option<&ComputedStyle> {
+    stacking_style(layout_box).filter(|style| style.position != Position::Static)
+}
+
+// an opacity below 1 or a transform makes a stacking context as well, at level 0
+// https://www.w3.org/TR/css-color-3/#transparency
+fn stacking_style(layout_box: &LayoutBox) -> Option<&ComputedStyle> {
+    let style = match layout_box.box_type {
+        BoxType::BlockNode(ref style) | BoxType::InlineNode(ref style)
+            | BoxType::InlineBlockNode(ref style) | BoxType::ListItemNode(ref style)
+            | BoxType::TableNode(ref style) | BoxType::TableRowGroupNode(ref style)
+            | BoxType::TableRowNode(ref style) | BoxType::TableCellNode(ref style)
+            | BoxType::ReplacedNode(ref style, _) => &style.computed,
+        BoxType::Marker(..) | BoxType::AnonymousBlock => return None,
+    };
+    match style.position {
+        Position::Static if style.opacity >= 1.0 && style.transform.is_empty() => None,
+        _ => Some(style),
+    }
+}
+
+// negative z-index contexts go first, then the context's own boxes, then the rest
 pub fn render_layout_box_tree(list: &mut DisplayList, layout_box: &LayoutBox) {
-    render_text(list, layout_box);
+    render_layout_box_tree_with_images(list, layout_box, &Images::new());
+}
+
+// background images missing from `images` aren't painted
+pub fn render_layout_box_tree_with_images(list: &mut DisplayList, layout_box: &LayoutBox, images: &Images) {
+    render_stacking_context(list, &stacking_tree(layout_box), images);
+}
+
+pub fn render_stacking_context(list: &mut DisplayList, context: &StackingContext, images: &Images) {
+    if let Some(clip) = context.clip {
+        list.push(DisplayCommand::PushClip(clip));
+    }
+    if let Some(transform) = context.transform {
+        list.push(DisplayCommand::PushTransform(transform));
+    }
+    if context.opacity < 1.0 {
+        list.push(DisplayCommand::PushLayer(context.opacity));
+    }
+    let (below, above): (Vec<_>, Vec<_>) = context.children.iter().partition(|child| child.z_index < 0);
+    if is_visible(context.layout_box) {
+        render_decorations(list, context.layout_box, images);
+    }
+    for child in below {
+        render_stacking_context(list, child, images);
+    }
+    render_content(list, context.layout_box, images);
+    for child in above {
+        render_stacking_context(list, child, images);
+    }
+    if context.opacity < 1.0 {
+        list.push(DisplayCommand::PopLayer);
+    }
+    if context.transform.is_some() {
+        list.push(DisplayCommand::PopTransform);
+    }
+    if context.clip.is_some() {
+        list.push(DisplayCommand::PopClip);
+    }
+}
+
+// the steps of painting the descendants of a context that have none of their own
+// https://www.w3.org/TR/CSS2/zindex.html#painting-order
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    Blocks,  // backgrounds and borders of the block-level boxes
+    Floats,  // each one whole
+    Inlines, // inline boxes and text, inline-blocks whole
+}
+
+// what is inside the box, leaving out what has a context of its own. the box
+// itself isn't clipped, only what is inside
+fn render_content(list: &mut DisplayList, layout_box: &LayoutBox, images: &Images) {
+    if let Some(clip) = layout_box.clip {
+        list.push(DisplayCommand::PushClip(clip));
+    }
+    render_phase(list, layout_box, images, Phase::Blocks);
+    render_phase(list, layout_box, images, Phase::Floats);
+    if is_visible(layout_box) {
+        render_text(list, layout_box);
+    }
+    render_phase(list, layout_box, images, Phase::Inlines);
+    if layout_box.clip.is_some() {
+        list.push(DisplayCommand::PopClip);
+    }
+}
+
+// visibility inherits, so a visible descendant of a hidden box is still painted
+fn render_phase(list: &mut DisplayList, layout_box: &LayoutBox, images: &Images, phase: Phase) {
+    for child in &layout_box.children {
+        if stacking_style(child).is_some() {
+            continue;
+        }
+        // floats and inline-blocks paint as if they were contexts
+        let float = child.get_style_node().is_some_and(|style| style.computed.float != Float::None);
+        if float || is_inline_block(child) {
+            if phase == if float { Phase::Floats } else { Phase::Inlines } {
+                if is_visible(child) {
+                    render_decorations(list, child, images);
+                }
+                render_content(list, child, images);
+            }
+            continue;
+        }
+        if is_visible(child) {
+            match phase {
+                Phase::Blocks if !is_inline_level(child) => render_decorations(list, child, images),
+                Phase::Inlines => {
+                    if is_inline_level(child) {
+                        render_decorations(list, child, images);
+                    }
+                    render_text(list, child);
+                }
+                _ => {}
+            }
+        }
+        if let Some(clip) = child.clip {
+            list.push(DisplayCommand::PushClip(clip));
+        }
+        render_phase(list, child, images, phase);
+        if child.clip.is_some() {
+            list.push(DisplayCommand::PopClip);
+        }
+    }
+}
+
+fn render_decorations(list: &mut DisplayList, layout_box: &LayoutBox, images: &Images) {
     render_background(list, layout_box);
+    render_background_image(list, layout_box, images);
     render_border(list, layout_box);
-    for child in &layout_box.children {
-        render_layout_box_tree(list, child);
+    render_replaced(list, layout_box, images);
+    render_links(list, layout_box);
+}
+
+// links over the border box of <a href>, and the places they can go to
+fn render_links(list: &mut DisplayList, layout_box: &LayoutBox) {
+    let style = match layout_box.box_type {
+        BoxType::Marker(..) | BoxType::AnonymousBlock => return,
+        _ => match layout_box.get_style_node() {
+            Some(style) => style,
+            None => return,
+        },
+    };
+    let rect = layout_box.dimensions.border_box();
+    if let Some(id) = style.attrs.get("id") {
+        list.push(DisplayCommand::Anchor(id.clone(), rect));
+    }
+    if style.tag_name.as_ref().is_some_and(|tag| tag == "a") {
+        if let Some(name) = style.attrs.get("name") {
+            list.push(DisplayCommand::Anchor(name.clone(), rect));
+        }
+        if let Some(href) = style.attrs.get("href") {
+            list.push(DisplayCommand::Link(href.clone(), rect));
+        }
+    }
+}
+
+fn is_inline_block(layout_box: &LayoutBox) -> bool {
+    match layout_box.box_type {
+        BoxType::InlineBlockNode(_) => true,
+        BoxType::ReplacedNode(ref style, _) => style.computed.display == Display::InlineBlock,
+        _ => false,
+    }
+}
+
+fn is_inline_level(layout_box: &LayoutBox) -> bool {
+    match layout_box.box_type {
+        BoxType::InlineNode(_) | BoxType::InlineBlockNode(_) | BoxType::Marker(..) => true,
+        BoxType::ReplacedNode(ref style, _) => style.computed.display == Display::Inline,
+        _ => false,
+    }
+}
+
+// the shape for the marker text layout::list_marker gives, numbers stay text
+fn bullet_shape(marker: &str) -> Option<BulletShape> {
+    match marker {
+        "\u{2022}" => Some(BulletShape::Disc),
+        "\u{25e6}" => Some(BulletShape::Circle),
+        "\u{25aa}" => Some(BulletShape::Square),
+        _ => None,
+    }
+}
+
+// a third of the font size across, in the middle of the marker box
+fn bullet_rect(marker_box: Rect, font_size: f64) -> Rect {
+    let size = font_size / 3.0;
+    Rect {
+        x: marker_box.x + (marker_box.width - size) / 2.0,
+        y: marker_box.y + (marker_box.height - size) / 2.0,
+        width: size,
+        height: size,
     }
 }
 
 fn render_text(list: &mut DisplayList, layout_box: &LayoutBox) {
     match layout_box.box_type {
-        BoxType::BlockNode(style_node) | BoxType::InlineNode(style_node)
-            => match style_node.node.data {
-                NodeType::Text(ref content) => list.push(
-                    DisplayCommand::Text(
-                        content.clone(), 
-                        layout_box.dimensions.border_box(),
-                )),
-                NodeType::Element(_) => (),
+        BoxType::Marker(ref style_node, ref marker) => {
+            let rect = layout_box.dimensions.border_box();
+            let text_style = TextStyle::new(&style_node.computed);
+            list.push(match bullet_shape(marker) {
+                Some(shape) => DisplayCommand::Bullet(shape, bullet_rect(rect, text_style.font.size), text_style.color),
+                None => DisplayCommand::Text(marker.clone(), rect, text_style),
+            });
+        }
+        BoxType::BlockNode(ref style_node) | BoxType::InlineNode(ref style_node)
+            | BoxType::InlineBlockNode(ref style_node) | BoxType::ListItemNode(ref style_node)
+            if style_node.text.is_some() => {
+                for fragment in &layout_box.lines {
+                    let text_style = TextStyle::new(&style_node.computed);
+                    // a line-through goes over the text, the others under it
+                    // https://www.w3.org/TR/css-text-decor-3/#painting-order
+                    let decoration = style_node.computed.text_decoration;
+                    render_text_decoration(list, fragment.rect, &text_style, TextDecoration { line_through: false, ..decoration });
+                    list.push(DisplayCommand::Text(fragment.text.clone(), fragment.rect, text_style.clone()));
+                    render_text_decoration(list, fragment.rect, &text_style, TextDecoration { line_through: decoration.line_through, ..Default::default() });
+                }
             }
         _ => (),
     }
 }
 
+// thin rects across the fragment at fractions of the font size, the baseline
+// is near the bottom of the rect
+fn render_text_decoration(list: &mut DisplayList, rect: Rect, text_style: &TextStyle, decoration: TextDecoration) {
+    let size = text_style.font.size;
+    let lines = [
+        (decoration.overline, rect.y),
+        (decoration.line_through, rect.y + size * 0.65),
+        (decoration.underline, rect.y + size),
+    ];
+    for &(_, y) in lines.iter().filter(|&&(drawn, _)| drawn) {
+        list.push(DisplayCommand::SolidColor(text_style.color, Rect {
+            x: rect.x,
+            y: y,
+            width: rect.width,
+            height: (size / 16.0).max(1.0),
+        }));
+    }
+}
+
 fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-    get_color(layout_box, "background").map(|color| 
+    if let Some(color) = get_color(layout_box) {
         list.push(DisplayCommand::SolidColor(
             color,
-            layout_box.dimensions.border_box(),
-        ))
-    );
+            background_painting_area(layout_box),
+        ));
+    }
 }
 
-fn render_border(list: &mut DisplayList, layout_box: &LayoutBox) {
-    let color = match get_color(layout_box, "border-color") {
-        Some(color) => color,
+// the border box unless background-clip says otherwise
+// https://www.w3.org/TR/css-backgrounds-3/#background-painting-area
+fn background_painting_area(layout_box: &LayoutBox) -> Rect {
+    let dimensions = &layout_box.dimensions;
+    match layout_box.get_style_node().map(|style| style.computed.background_clip) {
+        Some(BackgroundClip::PaddingBox) => dimensions.padding_box(),
+        Some(BackgroundClip::ContentBox) => dimensions.content,
+        _ => dimensions.border_box(),
+    }
+}
+
+// an <img> fills its content box
+fn render_replaced(list: &mut DisplayList, layout_box: &LayoutBox, images: &Images) {
+    let style = match layout_box.box_type {
+        BoxType::ReplacedNode(ref style, _) => style,
+        _ => return,
+    };
+    let image = match style.attrs.get("src").and_then(|src| images.get(src)) {
+        Some(image) => image,
+        None => return,
+    };
+    let rect = layout_box.dimensions.content;
+    list.push(DisplayCommand::Image(image.clone(), rect, rect, style.computed.image_rendering));
+}
+
+// positioned in the padding box and painted over the color in the painting area
+fn render_background_image(list: &mut DisplayList, layout_box: &LayoutBox, images: &Images) {
+    let background = match get_background_image(layout_box) {
+        Some(background) => background,
+        None => return,
+    };
+    let rendering = layout_box.get_style_node().map_or(ImageRendering::Smooth, |style| style.computed.image_rendering);
+    let image = match images.get(&background.url) {
+        Some(image) if image.width > 0 && image.height > 0 => image,
         _ => return,
     };
+    let area = layout_box.dimensions.padding_box();
+    let painted = background_painting_area(layout_box);
+    let (width, height) = background_size(&background.size, image, area);
+    if width <= 0.0 || height <= 0.0 {
+        return;
+    }
+    let x = area.x + to_px(&background.position.0, area.width - width).unwrap_or(0.0);
+    let y = area.y + to_px(&background.position.1, area.height - height).unwrap_or(0.0);
+
+    list.push(DisplayCommand::PushClip(painted));
+    for &tile_y in &tile_starts(y, height, painted.y, painted.height, background.repeat_y) {
+        for &tile_x in &tile_starts(x, width, painted.x, painted.width, background.repeat_x) {
+            let rect = Rect { x: tile_x, y: tile_y, width: width, height: height };
+            list.push(DisplayCommand::Image(image.clone(), rect, rect, rendering));
+        }
+    }
+    list.push(DisplayCommand::PopClip);
+}
+
+// the width and height of one copy of the image, `auto` keeps the aspect ratio
+// https://www.w3.org/TR/css-backgrounds-3/#the-background-size
+fn background_size(size: &BackgroundSize, image: &Image, area: Rect) -> (f64, f64) {
+    let (image_width, image_height) = (image.width as f64, image.height as f64);
+    match *size {
+        BackgroundSize::Cover | BackgroundSize::Contain => {
+            let (x_scale, y_scale) = (area.width / image_width, area.height / image_height);
+            let scale = if *size == BackgroundSize::Cover { x_scale.max(y_scale) } else { x_scale.min(y_scale) };
+            (image_width * scale, image_height * scale)
+        }
+        BackgroundSize::Explicit(ref width, ref height) => match (to_px(width, area.width), to_px(height, area.height)) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, width * image_height / image_width),
+            (None, Some(height)) => (height * image_width / image_height, height),
+            (None, None) => (image_width, image_height),
+        },
+    }
+}
+
+// None for `auto`
+fn to_px(value: &Value, percent_of: f64) -> Option<f64> {
+    match *value {
+        Value::Length(percent, Unit::Percent) => Some(percent * percent_of / 100.0),
+        Value::Length(..) => Some(value.to_px()),
+        _ => None,
+    }
+}
 
+// where the copies along one axis go, a repeating image is moved back by whole
+// copies to the start of `from..from + length` and repeated to its end
+fn tile_starts(start: f64, size: f64, from: f64, length: f64, repeat: bool) -> Vec<f64> {
+    if !repeat {
+        return vec![start];
+    }
+    let mut starts = Vec::new();
+    let mut tile = start - ((start - from) / size).ceil() * size;
+    while tile < from + length {
+        starts.push(tile);
+        tile += size;
+    }
+    starts
+}
+
+fn render_border(list: &mut DisplayList, layout_box: &LayoutBox) {
+    let computed = match layout_box.box_type {
+        BoxType::BlockNode(ref style) | BoxType::InlineNode(ref style)
+            | BoxType::InlineBlockNode(ref style) | BoxType::ListItemNode(ref style)
+            | BoxType::TableNode(ref style) | BoxType::TableRowGroupNode(ref style)
+            | BoxType::TableRowNode(ref style) | BoxType::TableCellNode(ref style)
+            | BoxType::ReplacedNode(ref style, _) => &style.computed,
+        BoxType::Marker(..) | BoxType::AnonymousBlock => return,
+    };
     let d = layout_box.dimensions;
     let border_box = d.border_box();
+    let colors = &computed.border_color;
+    if colors.top.is_none() && colors.right.is_none() && colors.bottom.is_none() && colors.left.is_none() {
+        return;
+    }
+
+    let styles = &computed.border_style;
+    list.push(DisplayCommand::Border(Border {
+        rect: border_box,
+        widths: [d.border.top, d.border.right, d.border.bottom, d.border.left],
+        colors: [colors.top, colors.right, colors.bottom, colors.left],
+        styles: [styles.top, styles.right, styles.bottom, styles.left],
+        radii: border_radii(&computed.border_radius, border_box),
+    }));
+}
+
+// percentages are of the shorter side, radii that don't fit are all scaled down
+// together until the two on each side do
+// https://www.w3.org/TR/css-backgrounds-3/#corner-overlap
+fn border_radii(radius: &[Value; 4], border_box: Rect) -> [f64; 4] {
+    let radii = radius.clone().map(|value| to_px(&value, border_box.width.min(border_box.height)).unwrap_or(0.0).max(0.0));
+    let fit = |length: f64, a: f64, b: f64| if a + b > length { length / (a + b) } else { 1.0 };
+    let scale = fit(border_box.width, radii[0], radii[1])
+        .min(fit(border_box.height, radii[1], radii[2]))
+        .min(fit(border_box.width, radii[2], radii[3]))
+        .min(fit(border_box.height, radii[3], radii[0]));
+    radii.map(|r| r * scale)
+}
 
-    // left border
-    list.push(DisplayCommand::SolidColor(
-        color,
-        Rect {
-            x: border_box.x,
-            y: border_box.y,
-            width: d.border.left,
-            height: border_box.height,
-        }
-    ));
-
-    // right border
-    list.push(DisplayCommand::SolidColor(
-        color,
-        Rect {
-            x: border_box.x + border_box.width - d.border.right,
-            y: border_box.y,
-            width: d.border.right,
-            height: border_box.height,
-        }
-    ));
-
-    // top border
-    list.push(DisplayCommand::SolidColor(
-        color,
-        Rect {
-            x: border_box.x,
-            y: border_box.y,
-            width: border_box.width,
-            height: d.border.top,
-        }
-    ));
-
-    // bottom border
-    list.push(DisplayCommand::SolidColor(
-        color,
-        Rect {
-            x: border_box.x,
-            y: border_box.y + border_box.height - d.border.bottom,
-            width: border_box.width,
-            height: d.border.bottom,
-        }
-    ));
-}
-
-fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
+fn is_visible(layout_box: &LayoutBox) -> bool {
     match layout_box.box_type {
-        BoxType::BlockNode(style) | BoxType::InlineNode(style) 
-            => style.get_color(name),
-        BoxType::AnonymousBlock => None,
+        BoxType::BlockNode(ref style) | BoxType::InlineNode(ref style) | BoxType::InlineBlockNode(ref style)
+            | BoxType::ListItemNode(ref style) | BoxType::TableNode(ref style) | BoxType::TableRowGroupNode(ref style)
+            | BoxType::TableRowNode(ref style) | BoxType::TableCellNode(ref style) | BoxType::ReplacedNode(ref style, _)
+            | BoxType::Marker(ref style, _)
+            => style.computed.visibility == Visibility::Visible,
+        BoxType::AnonymousBlock => true,
+    }
+}
+
+fn get_color(layout_box: &LayoutBox) -> Option<Color> {
+    match layout_box.box_type {
+        BoxType::BlockNode(ref style) | BoxType::InlineNode(ref style)
+            | BoxType::InlineBlockNode(ref style) | BoxType::ListItemNode(ref style)
+            | BoxType::TableNode(ref style) | BoxType::TableRowGroupNode(ref style)
+            | BoxType::TableRowNode(ref style) | BoxType::TableCellNode(ref style)
+            | BoxType::ReplacedNode(ref style, _) => style.computed.background_color,
+        BoxType::Marker(..) | BoxType::AnonymousBlock => None,
+    }
+}
+
+fn get_background_image(layout_box: &LayoutBox) -> Option<&BackgroundImage> {
+    match layout_box.box_type {
+        BoxType::BlockNode(ref style) | BoxType::InlineNode(ref style)
+            | BoxType::InlineBlockNode(ref style) | BoxType::ListItemNode(ref style)
+            | BoxType::TableNode(ref style) | BoxType::TableRowGroupNode(ref style)
+            | BoxType::TableRowNode(ref style) | BoxType::TableCellNode(ref style)
+            | BoxType::ReplacedNode(ref style, _) => style.computed.background_image.as_ref(),
+        BoxType::Marker(..) | BoxType::AnonymousBlock => None,
+    }
+}
+
+#[test]
+fn test_visibility_hidden() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div class=h>a<p class=v>b</p></div><p>c</p>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } .h { visibility: hidden; background: #ff0000; height: 30px; } \
+        .v { visibility: visible; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let mut list = Vec::new();
+    render_layout_box_tree(&mut list, &layout_root);
+    let painted: Vec<String> = list.iter().map(|command| match *command {
+        DisplayCommand::Text(ref text, rect, _) => format!("{} at {}", text, rect.y),
+        _ => "solid".to_string(),
+    }).collect();
+    // the hidden div keeps its 30px, the text under it is painted below
+    assert_eq!(painted, ["b at 16", "c at 30"]);
+}
+
+#[test]
+fn test_stacking_order() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div id=a></div><div id=b></div><div id=c></div><div id=d><p id=e></p></div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } div, p { height: 10px; } \
+        #a { position: relative; z-index: 2; background: #ff0000; } \
+        #b { position: relative; top: -10px; background: #00ff00; } \
+        #c { position: relative; z-index: -1; background: #0000ff; } \
+        #d { background: #000000; } #e { position: relative; z-index: 5; margin: 0; background: #ffffff; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let stacking = stacking_tree(&layout_root);
+    let levels: Vec<i32> = stacking.children.iter().map(|context| context.z_index).collect();
+    assert_eq!(levels, [-1, 0, 2, 5]);
+
+    let mut list = Vec::new();
+    render_layout_box_tree(&mut list, &layout_root);
+    // the ids are anchors for links, nothing is painted for them
+    let painted: Vec<u8> = list.iter().filter(|command| !matches!(command, DisplayCommand::Anchor(..))).map(|command| match *command {
+        DisplayCommand::SolidColor(color, _) => color.r / 255 * 4 + color.g / 255 * 2 + color.b / 255,
+        _ => panic!("no text or clips"),
+    }).collect();
+    // blue, black, green, red, white
+    assert_eq!(painted, [1, 0, 2, 4, 7]);
+}
+
+#[test]
+fn test_paint_order() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div id=a>x</div><div id=b></div><div id=f>y</div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } div { height: 10px; } #a { background: #ff0000; } \
+        #b { margin-top: -10px; background: #00ff00; } #f { float: left; width: 10px; background: #0000ff; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let mut list = Vec::new();
+    render_layout_box_tree(&mut list, &layout_root);
+    let painted: Vec<String> = list.iter().filter(|command| !matches!(command, DisplayCommand::Anchor(..))).map(|command| match *command {
+        DisplayCommand::SolidColor(color, _) => format!("{}{}{}", color.r / 255, color.g / 255, color.b / 255),
+        DisplayCommand::Text(ref text, _, _) => text.clone(),
+        _ => panic!("no clips"),
+    }).collect();
+    // block backgrounds, then the float with its text, then the text in flow
+    assert_eq!(painted, ["100", "010", "001", "y", "x"]);
+}
+
+#[test]
+fn test_text_decoration() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<p><a>ab</a> <span>c</span></p>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } span { text-decoration: line-through overline; color: #ff0000; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let mut list = Vec::new();
+    render_layout_box_tree(&mut list, &layout_root);
+    let painted: Vec<String> = list.iter().map(|command| match *command {
+        DisplayCommand::SolidColor(color, rect) => format!("{} {} {} {}x{}", color.r, rect.x, rect.y, rect.width, rect.height),
+        DisplayCommand::Text(ref text, _, _) => text.clone(),
+        _ => panic!("no clips"),
+    }).collect();
+    // links are underlined right under the text, the span starts after the space
+    assert_eq!(painted, ["0 0 16 16x1", "ab", "255 24 0 8x1", "c", "255 24 10.4 8x1"]);
+}
+
+#[test]
+fn test_overflow_clip() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div class=a><p class=b></p><p class=c></p></div>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } .a { width: 100px; height: 50px; overflow: hidden; } \
+        .b { width: 300px; height: 20px; background: #ff0000; } \
+        .c { position: relative; left: 60px; width: 80px; height: 20px; background: #0000ff; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let mut list = Vec::new();
+    render_layout_box_tree(&mut list, &layout_root);
+    let painted: Vec<(f64, f64, f64)> = apply_clips(&list).iter().map(|command| match *command {
+        DisplayCommand::SolidColor(_, rect) => (rect.x, rect.y, rect.width),
+        _ => panic!("no text or clips"),
+    }).collect();
+    // the positioned box is painted apart but clipped all the same
+    assert_eq!(painted, [(0.0, 0.0, 100.0), (60.0, 20.0, 40.0)]);
+}
+
+#[test]
+fn test_paginate() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div class=x></div><div class=y>z</div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } div { height: 60px; } .x { background: #ff0000; } \
+        .y { background: #0000ff; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let pages: Vec<Vec<String>> = paginate(&layout_root, 100.0, &Images::new()).iter().map(|page| page.iter().map(|command| match *command {
+        DisplayCommand::SolidColor(color, rect) => format!("{} at {} to {}", color.r, rect.y, rect.y + rect.height),
+        DisplayCommand::Text(ref text, rect, _) => format!("{} at {}", text, rect.y),
+        _ => panic!("clips are applied"),
+    }).collect()).collect();
+    assert_eq!(pages, [vec!["255 at 0 to 60"], vec!["0 at 0 to 60", "z at 0"]]);
+}
+
+#[test]
+fn test_text_style() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<p>a<em>b</em></p>".to_string());
+    let stylesheet = css::parse("p { color: #00ff00; font-family: monospace; font-size: 20px; font-weight: bold; } \
+        em { font-style: italic; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let mut list = Vec::new();
+    render_layout_box_tree(&mut list, &layout::layout_tree(&styled, viewport));
+
+    let styles: Vec<(String, TextStyle)> = list.into_iter().filter_map(|command| match command {
+        DisplayCommand::Text(text, _, text_style) => Some((text, text_style)),
+        _ => None,
+    }).collect();
+    let green = Color { r: 0, g: 255, b: 0, a: 255 };
+    let font = style::Font { family: vec!["monospace".to_string()], size: 20.0, weight: 700, style: FontStyle::Normal };
+    assert_eq!(styles, [
+        ("a".to_string(), TextStyle { color: green, font: font.clone() }),
+        ("b".to_string(), TextStyle { color: green, font: style::Font { style: FontStyle::Italic, ..font } }),
+    ]);
+}
+
+#[test]
+fn test_background_image() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div></div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } div { width: 30px; height: 20px; \
+        background: url(a.png) repeat-x right bottom; background-size: 8px auto; \
+        image-rendering: pixelated; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    // red on the left and blue on the right
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    let blue = Color { r: 0, g: 0, b: 255, a: 255 };
+    let mut images = Images::new();
+    images.insert("a.png".to_string(), Rc::new(Image { width: 2, height: 1, pixels: vec![red, blue] }));
+
+    let mut list = Vec::new();
+    render_layout_box_tree_with_images(&mut list, &layout_root, &images);
+    let tiles: Vec<(f64, f64, f64)> = apply_clips(&list).iter().filter_map(|command| match *command {
+        DisplayCommand::Image(_, rect, visible, _) => Some((rect.x, visible.x, rect.y)),
+        _ => None,
+    }).collect();
+    // one lands in the bottom right corner, the others repeat from there
+    assert_eq!(tiles, [(-2.0, 0.0, 16.0), (6.0, 6.0, 16.0), (14.0, 14.0, 16.0), (22.0, 22.0, 16.0)]);
+
+    let canvas = paint_with_font(&layout_root, Rect { x: 0.0, y: 0.0, width: 40.0, height: 20.0 }, None, &images);
+    let pixel = |x: usize, y: usize| canvas.pixel(x, y).unwrap();
+    assert_eq!((pixel(0, 17), pixel(3, 17), pixel(6, 19), pixel(29, 16)), (red, blue, red, blue));
+    assert_eq!((pixel(0, 15).g, pixel(32, 17).g), (255, 255));
+}
+
+#[test]
+fn test_paint_img() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<img src=a.png><img class=p src=a.png>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } img { width: 4px; height: 1px; } .p { image-rendering: pixelated; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 800.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    let blue = Color { r: 0, g: 0, b: 255, a: 255 };
+    let mut images = Images::new();
+    images.insert("a.png".to_string(), Rc::new(Image { width: 2, height: 1, pixels: vec![red, blue] }));
+
+    let mut list = Vec::new();
+    render_layout_box_tree_with_images(&mut list, &layout_root, &images);
+    let rects: Vec<Rect> = list.iter().filter_map(|command| match *command {
+        DisplayCommand::Image(_, rect, _, _) => Some(rect),
+        _ => None,
+    }).collect();
+    assert_eq!(rects.iter().map(|rect| (rect.x, rect.width, rect.height)).collect::<Vec<_>>(), [(0.0, 4.0, 1.0), (4.0, 4.0, 1.0)]);
+
+    let canvas = paint_with_font(&layout_root, Rect { x: 0.0, y: 0.0, width: 8.0, height: 20.0 }, None, &images);
+    let row: Vec<(u8, u8)> = (0..8).map(|x| canvas.pixel(x, rects[0].y as usize).unwrap()).map(|pixel| (pixel.r, pixel.b)).collect();
+    // blended between the two pixels, then the nearest one
+    assert_eq!(row, [(255, 0), (191, 64), (64, 191), (0, 255), (255, 0), (255, 0), (0, 255), (0, 255)]);
+}
+
+#[test]
+fn test_opacity() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div id=a></div><div id=b><div id=c></div></div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } div { height: 10px; } #a { background: rgba(255, 0, 0, 0.5); } \
+        #b { opacity: 0.5; background: #0000ff; } #c { background: #00ff00; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 10.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let canvas = paint_with_font(&layout_root, Rect { x: 0.0, y: 0.0, width: 10.0, height: 20.0 }, None, &Images::new());
+    // half of the red over the white
+    assert_eq!(canvas.pixel(0, 0), Some(Color { r: 255, g: 127, b: 127, a: 255 }));
+    // the green covers the blue before the group is blended
+    assert_eq!(canvas.pixel(0, 15), Some(Color { r: 128, g: 255, b: 128, a: 255 }));
+}
+
+#[test]
+fn test_fill_rows() {
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    let mut canvas = Canvas::new(4, 3);
+    canvas.paint_pixels_by_display_command(&DisplayCommand::SolidColor(red, Rect { x: 1.0, y: 1.0, width: 2.0, height: 5.0 }), None);
+    // a negative width fills nothing, a transparent color changes nothing
+    canvas.paint_pixels_by_display_command(&DisplayCommand::SolidColor(red, Rect { x: 3.0, y: 0.0, width: -2.0, height: 1.0 }), None);
+    canvas.paint_pixels_by_display_command(&DisplayCommand::SolidColor(Color { a: 0, ..red }, Rect { x: 0.0, y: 0.0, width: 4.0, height: 3.0 }), None);
+
+    let painted: Vec<bool> = canvas.pixels.iter().map(|&pixel| pixel == red).collect();
+    assert_eq!(painted, [
+        false, false, false, false,
+        false, true, true, false,
+        false, true, true, false,
+    ]);
+}
+
+#[test]
+fn test_pixel_access() {
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    let mut canvas = Canvas::new(4, 3);
+    canvas.paint_pixels_by_display_command(&DisplayCommand::SolidColor(red, Rect { x: 1.0, y: 1.0, width: 1.0, height: 1.0 }), None);
+
+    assert_eq!((canvas.pixel(1, 1), canvas.pixel(0, 1)), (Some(red), Some(Color { r: 255, g: 255, b: 255, a: 255 })));
+    assert_eq!((canvas.pixel(4, 0), canvas.pixel(0, 3)), (None, None));
+    assert_eq!(&canvas.to_rgba8()[16..24], [255, 255, 255, 255, 255, 0, 0, 255]);
+
+    // rounded out, then cut to the canvas
+    let sub = canvas.sub_image(Rect { x: 0.5, y: 0.5, width: 1.0, height: 10.0 });
+    assert_eq!((sub.width, sub.height), (2, 3));
+    assert_eq!((sub.pixel(1, 0), sub.pixel(1, 1)), (canvas.pixel(1, 0), Some(red)));
+    assert_eq!(canvas.sub_image(Rect { x: 10.0, y: 0.0, width: 2.0, height: 2.0 }).pixels.len(), 0);
+}
+
+#[test]
+fn test_clip_state() {
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    let blue = Color { r: 0, g: 0, b: 255, a: 255 };
+    let mut canvas = Canvas::new(10, 10);
+    for command in &[
+        DisplayCommand::PushClip(Rect { x: 2.0, y: 2.0, width: 6.0, height: 6.0 }),
+        DisplayCommand::PushClip(Rect { x: 0.0, y: 0.0, width: 4.0, height: 10.0 }),
+        DisplayCommand::SolidColor(red, Rect { x: 0.0, y: 0.0, width: 10.0, height: 10.0 }),
+        DisplayCommand::PopClip,
+        DisplayCommand::SolidColor(blue, Rect { x: 5.0, y: 0.0, width: 5.0, height: 10.0 }),
+        DisplayCommand::PopClip,
+        DisplayCommand::SolidColor(blue, Rect { x: 9.0, y: 9.0, width: 1.0, height: 1.0 }),
+    ] {
+        canvas.paint_pixels_by_display_command(command, None);
     }
-}
\ No newline at end of file
+    let pixel = |x: usize, y: usize| canvas.pixel(x, y).unwrap();
+    // inside both clips, then only the outer one
+    assert_eq!((pixel(2, 2), pixel(3, 7), pixel(4, 4), pixel(1, 1)), (red, red, pixel(0, 0), pixel(0, 0)));
+    assert_eq!((pixel(5, 2), pixel(7, 7), pixel(8, 5), pixel(9, 9)), (blue, blue, pixel(0, 0), blue));
+    assert_eq!(pixel(0, 0).g, 255);
+}
+
+#[test]
+fn test_paint_text() {
+    // a bundled font with only an "H" made of three rectangles, the system fonts may be missing
+    let font = Font::from_bytes(&include_bytes!("../tests/fonts/h.ttf")[..]).unwrap();
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    let mut canvas = Canvas::new(40, 20);
+    let rect = Rect { x: 2.0, y: 2.0, width: 16.0, height: 16.0 };
+    let text_style = TextStyle {
+        color: red,
+        font: style::Font { family: vec!["sans-serif".to_string()], size: 16.0, weight: 400, style: FontStyle::Normal },
+    };
+    canvas.paint_pixels_by_display_command(&DisplayCommand::Text("H".to_string(), rect, text_style.clone()), Some(&font));
+
+    let painted: Vec<(usize, Color)> = canvas.pixels.iter().cloned().enumerate()
+        .filter(|&(_, pixel)| pixel.g < 255)
+        .collect();
+    // the stems are mostly covered, the edges blended toward red
+    assert!(painted.iter().any(|&(_, pixel)| pixel.g < 64));
+    // and only inside the rect
+    assert!(painted.iter().all(|&(i, pixel)| pixel.r == 255 && pixel.g == pixel.b && (2..18).contains(&(i % 40))));
+    assert!(painted.iter().all(|&(i, _)| (2..18).contains(&(i / 40))));
+
+    // without a font the text is left out
+    let mut canvas = Canvas::new(40, 20);
+    canvas.paint_pixels_by_display_command(&DisplayCommand::Text("H".to_string(), rect, text_style.clone()), None);
+    assert!(canvas.pixels.iter().all(|pixel| pixel.g == 255));
+
+    // a huge font only rasterizes the capped glyphs that reach the canvas
+    let huge = TextStyle { font: style::Font { size: 100000.0, ..text_style.font.clone() }, ..text_style };
+    let mut canvas = Canvas::new(40, 20);
+    canvas.paint_pixels_by_display_command(&DisplayCommand::Text("H H H".to_string(), rect, huge), Some(&font));
+    assert!(canvas.pixels.iter().all(|pixel| pixel.g == 255 || pixel.r == 255));
+}
+
+#[test]
+fn test_repaint() {
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    let blue = Color { r: 0, g: 0, b: 255, a: 255 };
+    let everywhere = Rect { x: 0.0, y: 0.0, width: 10.0, height: 10.0 };
+    let mut canvas = Canvas::new(10, 10);
+    canvas.paint_pixels_by_display_command(&DisplayCommand::SolidColor(red, everywhere), None);
+
+    // the whole list is blue, only the dirty part takes it
+    let list = vec![
+        DisplayCommand::SolidColor(blue, everywhere),
+        DisplayCommand::SolidColor(red, Rect { x: 8.0, y: 8.0, width: 2.0, height: 2.0 }),
+    ];
+    repaint_with_font(&mut canvas, &list, Rect { x: 2.0, y: 3.0, width: 4.0, height: 5.0 }, None);
+    for y in 0..10 {
+        for x in 0..10 {
+            let dirty = (2..6).contains(&x) && (3..8).contains(&y);
+            assert_eq!(canvas.pixel(x, y), Some(if dirty { blue } else { red }), "at {} {}", x, y);
+        }
+    }
+}
+
+#[test]
+fn test_dump_and_replay() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div class=a><p>hi</p></div><img src=a.png>".to_string());
+    let stylesheet = css::parse("body, p { margin: 0; } .a { height: 10px; overflow: hidden; background: rgba(255, 0, 0, 0.5); } \
+        img { width: 2px; height: 2px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 40.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+    let mut images = Images::new();
+    images.insert("a.png".to_string(), Rc::new(Image { width: 1, height: 1, pixels: vec![Color { r: 0, g: 0, b: 255, a: 255 }] }));
+
+    let mut list = Vec::new();
+    render_layout_box_tree_with_images(&mut list, &layout_root, &images);
+    let json = dump_json(&list);
+    assert!(json.starts_with("[{\"SolidColor\":[{\"r\":255,\"b\":0,\"g\":0,\"a\":128},"), "{}", json);
+    let parsed = parse_json(&json).unwrap();
+    assert_eq!(dump_json(&parsed), json);
+
+    let boundary = Rect { x: 0.0, y: 0.0, width: 40.0, height: 30.0 };
+    let mut canvas = Canvas::new(40, 30);
+    replay(&parsed, &mut canvas);
+    assert_eq!(canvas.pixels, paint_with_font(&layout_root, boundary, load_font().as_ref(), &images).pixels);
+    assert!(parse_json("[{\"Circle\":[]}]").is_err());
+}
+
+
+#[test]
+fn test_transform() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div id=a><div id=b></div></div><div id=c></div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } div { width: 10px; height: 10px; } #a { transform: translate(10px, 50%) scale(2); } \
+        #b { background: #ff0000; } #c { background: #0000ff; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 40.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let mut list = Vec::new();
+    render_layout_box_tree(&mut list, &layout_root);
+    let painted: Vec<Rect> = apply_clips(&list).iter().filter(|command| !matches!(command, DisplayCommand::Anchor(..))).map(|command| match *command {
+        DisplayCommand::SolidColor(_, rect) => rect,
+        _ => panic!("clips and transforms are applied"),
+    }).collect();
+    // #c stays where layout put it, #a is doubled around its center, then moved.
+    // it has a context of its own and goes on top
+    assert_eq!(painted, vec![
+        Rect { x: 0.0, y: 10.0, width: 10.0, height: 10.0 },
+        Rect { x: 5.0, y: 0.0, width: 20.0, height: 20.0 },
+    ]);
+
+    let canvas = paint_with_font(&layout_root, Rect { x: 0.0, y: 0.0, width: 40.0, height: 30.0 }, None, &Images::new());
+    assert_eq!(canvas.pixel(24, 19), Some(Color { r: 255, g: 0, b: 0, a: 255 }));
+    assert_eq!(canvas.pixel(4, 4), Some(Color { r: 255, g: 255, b: 255, a: 255 }));
+}
+
+#[test]
+fn test_background_clip() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div id=a></div><div id=b></div><div id=c></div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } div { width: 10px; height: 10px; padding: 2px; border: 1px solid #000000; } \
+        #a { background: #ff0000; } #b { background: #ff0000 padding-box; } #c { background: #ff0000; background-clip: content-box; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 40.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let mut list = Vec::new();
+    render_layout_box_tree(&mut list, &layout_root);
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    let backgrounds: Vec<Rect> = list.iter().filter_map(|command| match *command {
+        DisplayCommand::SolidColor(color, rect) if color == red => Some(rect),
+        _ => None,
+    }).collect();
+    assert_eq!(backgrounds, vec![
+        Rect { x: 0.0, y: 0.0, width: 16.0, height: 16.0 },
+        Rect { x: 1.0, y: 17.0, width: 14.0, height: 14.0 },
+        Rect { x: 3.0, y: 35.0, width: 10.0, height: 10.0 },
+    ]);
+}
+
+#[test]
+fn test_border() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<div id=a></div><div id=b></div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } div { width: 20px; height: 10px; } \
+        #a { border: 2px dashed #000000; border-radius: 4px 8px; } \
+        #b { border: 1px solid #000000; border-bottom-style: dotted; border-left-style: none; border-radius: 10px; width: 10px; height: 30px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 100.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let mut list = Vec::new();
+    render_layout_box_tree(&mut list, &layout_root);
+    let black = Some(Color { r: 0, g: 0, b: 0, a: 255 });
+    let borders: Vec<Border> = list.into_iter().filter_map(|command| match command {
+        DisplayCommand::Border(border) => Some(border),
+        _ => None,
+    }).collect();
+    assert_eq!(borders, vec![
+        Border {
+            rect: Rect { x: 0.0, y: 0.0, width: 24.0, height: 14.0 },
+            widths: [2.0; 4],
+            colors: [black; 4],
+            styles: [BorderStyle::Dashed; 4],
+            radii: [4.0, 8.0, 4.0, 8.0],
+        },
+        // the radii don't fit the 12px width, so all of them shrink
+        Border {
+            rect: Rect { x: 0.0, y: 14.0, width: 12.0, height: 32.0 },
+            widths: [1.0; 4],
+            colors: [black, black, black, None],
+            styles: [BorderStyle::Solid, BorderStyle::Solid, BorderStyle::Dotted, BorderStyle::Solid],
+            radii: [6.0; 4],
+        },
+    ]);
+    // the canvas fills the drawn sides
+    assert_eq!(borders[1].sides().len(), 3);
+}
+
+#[test]
+fn test_list_markers() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<ul><li>a</li></ul><ol><li>b</li></ol>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } ul, ol { margin: 0; } li { color: #ff0000; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = 100.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let mut list = Vec::new();
+    render_layout_box_tree(&mut list, &layout_root);
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    // the bullet is a shape in the middle of its marker box, the number is text
+    match list[0] {
+        DisplayCommand::Bullet(BulletShape::Disc, rect, color) => {
+            assert_eq!(color, red);
+            assert_eq!((rect.x + rect.width / 2.0, rect.y + rect.height / 2.0), (28.0, 8.0));
+        }
+        ref command => panic!("{:?} isn't a bullet", command),
+    }
+    let texts: Vec<&str> = list.iter().filter_map(|command| match *command {
+        DisplayCommand::Text(ref text, _, _) => Some(&**text),
+        _ => None,
+    }).collect();
+    assert_eq!(texts, vec!["a", "1.", "b"]);
+
+    let canvas = paint_with_font(&layout_root, Rect { x: 0.0, y: 0.0, width: 100.0, height: 32.0 }, None, &Images::new());
+    assert_eq!(canvas.pixel(27, 7), Some(red));
+    assert_eq!(canvas.pixel(24, 1), Some(Color { r: 255, g: 255, b: 255, a: 255 }));
+}
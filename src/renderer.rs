@@ -1,49 +1,445 @@
-use crate::layout::Dimensions;
-use crate::painter::{DisplayCommand, DisplayList};
+use crate::layout::{Dimensions, Rect};
+use crate::css;
+use crate::painter::{self, Border, BulletShape, Canvas, DisplayCommand, DisplayList, TextStyle, Transform};
+use crate::style::{BorderStyle, ImageRendering};
+use crate::url;
 
 use printpdf::*;
-use std::fs::File;
-use std::io::BufWriter;
+use std::collections::HashMap;
+use std::error;
+use std::fmt::{self, Write as _};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::iter;
+use std::path::PathBuf;
+use std::slice;
 
-pub fn render(list: &DisplayList, viewport: &Dimensions) {
+// what a renderer made of the display list
+#[derive(Clone, Debug, PartialEq)]
+pub enum Output {
+    File(PathBuf),  // written there
+    Bytes(Vec<u8>), // kept in memory, an encoded image say
+    Shown,          // painted on the screen, nothing is kept
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderError {
+    Io { path: PathBuf, message: String },
+    Backend(String), // the backend's own failure, as its message
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderError::Io { ref path, ref message } => write!(f, "cannot write {}: {}", path.display(), message),
+            RenderError::Backend(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl error::Error for RenderError {}
+
+// a backend for display lists, the pipeline doesn't care which. embedders can
+// bring their own
+pub trait Renderer {
+    fn render(&mut self, list: &DisplayList, viewport: &Dimensions) -> Result<Output, RenderError>;
+
+    // one output for the pages of painter::paginate. a backend without pages
+    // only gets the first one
+    fn render_pages(&mut self, pages: &[DisplayList], viewport: &Dimensions) -> Result<Output, RenderError> {
+        self.render(pages.first().unwrap_or(&Vec::new()), viewport)
+    }
+}
+
+// how the px of the layout map to the page
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    pub dpi: f64, // px per inch, at 96 a px is as big as css says
+    pub scale: f64, // device pixels per px when rasterizing, 2 for a high-dpi screenshot
+    pub base_url: Option<String>, // of the document, relative links are resolved against it
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions { dpi: 96.0, scale: 1.0, base_url: None }
+    }
+}
+
+// a pdf with a page for each display list
+pub struct PdfRenderer {
+    pub path: Option<PathBuf>, // None keeps the pdf in memory, as Output::Bytes
+    pub options: RenderOptions,
+}
+
+impl PdfRenderer {
+    pub fn new<P: Into<PathBuf>>(path: P) -> PdfRenderer {
+        PdfRenderer { path: Some(path.into()), options: Default::default() }
+    }
+
+    pub fn in_memory() -> PdfRenderer {
+        PdfRenderer { path: None, options: Default::default() }
+    }
+}
+
+impl Renderer for PdfRenderer {
+    fn render(&mut self, list: &DisplayList, viewport: &Dimensions) -> Result<Output, RenderError> {
+        self.render_pages(slice::from_ref(list), viewport)
+    }
+
+    fn render_pages(&mut self, pages: &[DisplayList], viewport: &Dimensions) -> Result<Output, RenderError> {
+        match self.path {
+            Some(ref path) => {
+                let file = File::create(path).map_err(|err| RenderError::Io { path: path.clone(), message: err.to_string() })?;
+                write_pdf(pages, viewport, &self.options, file)?;
+                Ok(Output::File(path.clone()))
+            }
+            None => render_to_vec(pages, viewport, &self.options).map(Output::Bytes),
+        }
+    }
+}
+
+// an uncompressed image of the first page, written without any image library
+// https://netpbm.sourceforge.net/doc/ppm.html
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RasterFormat {
+    Ppm, // binary, P6
+    Bmp, // 24 bits per pixel
+}
+
+pub struct RasterRenderer {
+    pub path: Option<PathBuf>, // None keeps the image in memory, as Output::Bytes
+    pub format: RasterFormat,
+    pub options: RenderOptions,
+}
+
+impl RasterRenderer {
+    pub fn new<P: Into<PathBuf>>(path: P, format: RasterFormat) -> RasterRenderer {
+        RasterRenderer { path: Some(path.into()), format: format, options: Default::default() }
+    }
+
+    pub fn in_memory(format: RasterFormat) -> RasterRenderer {
+        RasterRenderer { path: None, format: format, options: Default::default() }
+    }
+}
+
+impl Renderer for RasterRenderer {
+    fn render(&mut self, list: &DisplayList, viewport: &Dimensions) -> Result<Output, RenderError> {
+        let canvas = rasterize(list, viewport, &self.options);
+        let bytes = match self.format {
+            RasterFormat::Ppm => to_ppm(&canvas),
+            RasterFormat::Bmp => to_bmp(&canvas),
+        };
+        match self.path {
+            Some(ref path) => {
+                fs::write(path, bytes).map_err(|err| RenderError::Io { path: path.clone(), message: err.to_string() })?;
+                Ok(Output::File(path.clone()))
+            }
+            None => Ok(Output::Bytes(bytes)),
+        }
+    }
+}
+
+// the header, then the rgb of each pixel row by row from the top. alpha is dropped
+pub fn to_ppm(canvas: &Canvas) -> Vec<u8> {
+    let mut ppm = format!("P6\n{} {}\n255\n", canvas.width, canvas.height).into_bytes();
+    for pixel in &canvas.pixels {
+        ppm.extend_from_slice(&[pixel.r, pixel.g, pixel.b]);
+    }
+    ppm
+}
+
+// a file header and a BITMAPINFOHEADER, then the rows from the bottom up in
+// bgr, each padded to 4 bytes. alpha is dropped
+// https://learn.microsoft.com/en-us/windows/win32/gdi/bitmap-storage
+pub fn to_bmp(canvas: &Canvas) -> Vec<u8> {
+    const HEADERS: u32 = 14 + 40;
+    let row = (canvas.width * 3).div_ceil(4) * 4;
+    let size = (row * canvas.height) as u32;
+    let mut bmp = Vec::with_capacity((HEADERS + size) as usize);
+    bmp.extend_from_slice(b"BM");
+    for field in &[HEADERS + size, 0, HEADERS, 40, canvas.width as u32, canvas.height as u32] {
+        bmp.extend_from_slice(&field.to_le_bytes());
+    }
+    bmp.extend_from_slice(&1u16.to_le_bytes()); // planes
+    bmp.extend_from_slice(&24u16.to_le_bytes()); // bits per pixel
+    // no compression, the image size, 72 dpi both ways, no palette
+    for field in &[0, size, 2835, 2835, 0, 0] {
+        bmp.extend_from_slice(&field.to_le_bytes());
+    }
+    for y in (0..canvas.height).rev() {
+        for pixel in &canvas.pixels[y * canvas.width..(y + 1) * canvas.width] {
+            bmp.extend_from_slice(&[pixel.b, pixel.g, pixel.r]);
+        }
+        bmp.resize(bmp.len() + row - canvas.width * 3, 0);
+    }
+    bmp
+}
+
+// a quick look in the terminal, without a pdf viewer. needs 24-bit color
+pub struct TerminalRenderer {
+    pub columns: usize, // the page is scaled down to this many characters across
+    pub options: RenderOptions,
+}
+
+impl TerminalRenderer {
+    pub fn new(columns: usize) -> TerminalRenderer {
+        TerminalRenderer { columns: columns, options: Default::default() }
+    }
+}
+
+impl Renderer for TerminalRenderer {
+    fn render(&mut self, list: &DisplayList, viewport: &Dimensions) -> Result<Output, RenderError> {
+        let canvas = rasterize(list, viewport, &self.options);
+        io::stdout().write_all(to_ansi(&canvas, self.columns).as_bytes()).map_err(|err| RenderError::Backend(err.to_string()))?;
+        Ok(Output::Shown)
+    }
+
+    // one after the other, with an empty line between them
+    fn render_pages(&mut self, pages: &[DisplayList], viewport: &Dimensions) -> Result<Output, RenderError> {
+        for (i, list) in pages.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            self.render(list, viewport)?;
+        }
+        Ok(Output::Shown)
+    }
+}
+
+// the list painted on a canvas the size of the viewport times the scale, the
+// layout stays the same and every command is scaled up to the new pixels
+pub fn rasterize(list: &DisplayList, viewport: &Dimensions, options: &RenderOptions) -> Canvas {
+    let scale = options.scale;
+    let mut canvas = Canvas::new((viewport.content.width * scale).round() as usize, (viewport.content.height * scale).round() as usize);
+    let font = painter::load_font();
+    let zoom = DisplayCommand::PushTransform(Transform { scale_x: scale, scale_y: scale, translate_x: 0.0, translate_y: 0.0 });
+    for command in iter::once(&zoom).chain(list).chain(iter::once(&DisplayCommand::PopTransform)) {
+        canvas.paint_pixels_by_display_command(command, font.as_ref());
+    }
+    canvas
+}
+
+// each character is two pixels of the scaled down canvas, an upper half block
+// in the color of the top one over the bottom one. a pixel is the average of
+// the canvas pixels under it
+pub fn to_ansi(canvas: &Canvas, columns: usize) -> String {
+    let mut ansi = String::new();
+    if canvas.width == 0 || canvas.height == 0 {
+        return ansi;
+    }
+    let columns = columns.max(1).min(canvas.width);
+    let scale = canvas.width as f64 / columns as f64;
+    let rows = (canvas.height as f64 / scale).ceil() as usize;
+    let average = |x: usize, y: usize| {
+        let (x0, x1) = ((x as f64 * scale) as usize, (((x + 1) as f64 * scale) as usize).min(canvas.width));
+        let (y0, y1) = ((y as f64 * scale) as usize, (((y + 1) as f64 * scale) as usize).min(canvas.height));
+        let (mut sum, mut count) = ([0usize; 3], 0);
+        for y in y0..y1 {
+            for pixel in &canvas.pixels[y * canvas.width + x0..y * canvas.width + x1] {
+                sum[0] += pixel.r as usize;
+                sum[1] += pixel.g as usize;
+                sum[2] += pixel.b as usize;
+                count += 1;
+            }
+        }
+        // below the canvas is blank
+        if count == 0 {
+            return [255, 255, 255];
+        }
+        [sum[0] / count, sum[1] / count, sum[2] / count]
+    };
+    for y in (0..rows).step_by(2) {
+        for x in 0..columns {
+            let (top, bottom) = (average(x, y), average(x, y + 1));
+            let _ = write!(ansi, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}", top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]);
+        }
+        ansi.push_str("\x1b[0m\n");
+    }
+    ansi
+}
+
+// the pdf bytes, to send over http say
+pub fn render_to_vec(pages: &[DisplayList], viewport: &Dimensions, options: &RenderOptions) -> Result<Vec<u8>, RenderError> {
+    let mut bytes = Vec::new();
+    write_pdf(pages, viewport, options, &mut bytes)?;
+    Ok(bytes)
+}
+
+// a page for each display list, see painter::paginate
+pub fn write_pdf<W: Write>(pages: &[DisplayList], viewport: &Dimensions, options: &RenderOptions, mut sink: W) -> Result<(), RenderError> {
+    let page = Page { height: viewport.content.height, options: options.clone() };
     let (doc, page1, layer1) = PdfDocument::new(
         "printpdf title",
-        Mm(viewport.content.width),
-        Mm(viewport.content.height),
+        page.mm(viewport.content.width),
+        page.mm(viewport.content.height),
         "Initial layer name"
     );
-    let current_layer = doc.get_page(page1).get_layer(layer1);
+    let mut layers = vec![doc.get_page(page1).get_layer(layer1)];
+    for _ in 1..pages.len() {
+        let (added, layer) = doc.add_page(page.mm(viewport.content.width), page.mm(viewport.content.height), "Initial layer name");
+        layers.push(doc.get_page(added).get_layer(layer));
+    }
 
-    for display_command in list {
-        render_points_by_display_command(&doc, &current_layer, &display_command, viewport);
+    for (list, current_layer) in pages.iter().zip(&layers) {
+        let mut i = 0;
+        while i < list.len() {
+            let texts: Vec<(&str, Rect, &TextStyle)> = list[i..].iter().map_while(|command| match *command {
+                DisplayCommand::Text(ref text, rect, ref text_style) => Some((text.as_str(), rect, text_style)),
+                _ => None,
+            }).collect();
+            let line = text_line_length(&texts);
+            if line > 0 {
+                render_text_line(&doc, current_layer, &texts[..line], &page)?;
+                i += line;
+            } else {
+                render_points_by_display_command(&doc, current_layer, &list[i], &page)?;
+                i += 1;
+            }
+        }
     }
-    doc.save(&mut BufWriter::new(File::create("pritpdf.pdf").unwrap())).unwrap();
+    let links = collect_links(pages, &page);
+    if links.iter().all(|links| links.is_empty()) {
+        return doc.save(&mut BufWriter::new(sink)).map_err(|err| RenderError::Backend(err.to_string()));
+    }
+    let mut pdf = Vec::new();
+    doc.save(&mut BufWriter::new(&mut pdf)).map_err(|err| RenderError::Backend(err.to_string()))?;
+    append_links(&mut pdf, &links).ok_or_else(|| RenderError::Backend("cannot add the links to the pdf".to_string()))?;
+    sink.write_all(&pdf).map_err(|err| RenderError::Backend(err.to_string()))
+}
+
+// a link annotation, in points from the bottom left of the page
+struct PdfLink {
+    rect: [f64; 4], // left, bottom, right, top
+    target: LinkTarget,
+}
+
+enum LinkTarget {
+    Uri(String),
+    Page(usize, f64), // the index of the page and how far up it the anchor is
+}
+
+// the links of each page. `#name` goes to the first anchor of that name and is
+// left out when there is none, the rest goes to the url resolved against the
+// base url
+fn collect_links(pages: &[DisplayList], page: &Page) -> Vec<Vec<PdfLink>> {
+    let pages: Vec<DisplayList> = pages.iter().map(painter::apply_clips).collect();
+    let mut anchors = HashMap::new();
+    for (index, list) in pages.iter().enumerate() {
+        for command in list {
+            if let DisplayCommand::Anchor(ref name, rect) = *command {
+                anchors.entry(name.clone()).or_insert((index, page.pt(page.height - rect.y)));
+            }
+        }
+    }
+    pages.iter().map(|list| list.iter().filter_map(|command| {
+        let (href, rect) = match *command {
+            DisplayCommand::Link(ref href, rect) => (href, rect),
+            _ => return None,
+        };
+        let target = if let Some(anchor) = href.strip_prefix('#') {
+            let &(index, top) = anchors.get(anchor)?;
+            LinkTarget::Page(index, top)
+        } else {
+            LinkTarget::Uri(match page.options.base_url {
+                Some(ref base) => url::resolve(base, href),
+                None => href.clone(),
+            })
+        };
+        Some(PdfLink {
+            rect: [page.pt(rect.x), page.pt(page.height - rect.y - rect.height), page.pt(rect.x + rect.width), page.pt(page.height - rect.y)],
+            target: target,
+        })
+    }).collect()).collect()
+}
+
+// printpdf can't write annotations, so they are appended as an incremental
+// update: the annotations, the pages again with an /Annots array, and a
+// cross-reference section for them with the old one as /Prev. None when the
+// pdf isn't laid out like printpdf writes it
+// https://www.adobe.com/content/dam/acom/en/devnet/pdf/pdfs/PDF32000_2008.pdf#page=51
+fn append_links(pdf: &mut Vec<u8>, links: &[Vec<PdfLink>]) -> Option<()> {
+    let startxref = rfind(pdf, b"startxref")?;
+    let previous: usize = ascii(&pdf[startxref + b"startxref".len()..])?.split_whitespace().next()?.parse().ok()?;
+    let trailer = ascii(&pdf[rfind(pdf, b"trailer")? + b"trailer".len()..startxref])?;
+    // the rest of the trailer stays, /Size grows
+    let trailer = trailer.trim().strip_prefix("<<")?.strip_suffix(">>")?;
+    let size_at = trailer.find("/Size")?;
+    let size_digits = trailer[size_at + "/Size".len()..].trim_start();
+    let size_len = size_digits.find(|c: char| !c.is_ascii_digit()).unwrap_or(size_digits.len());
+    let mut next_id: u32 = size_digits[..size_len].parse().ok()?;
+    let trailer = format!("{}{}", &trailer[..size_at], &size_digits[size_len..]);
+
+    let pages_at = find(pdf, b"/Type/Pages", 0)?;
+    let kids_at = find(pdf, b"/Kids[", pages_at)? + b"/Kids[".len();
+    let kids = ascii(&pdf[kids_at..kids_at + find(&pdf[kids_at..], b"]", 0)?])?;
+    let page_ids: Vec<u32> = kids.split_whitespace().step_by(3).map(|id| id.parse().ok()).collect::<Option<_>>()?;
+
+    if !pdf.ends_with(b"\n") {
+        pdf.push(b'\n');
+    }
+    let mut offsets = Vec::new();
+    for (page_links, &page_id) in links.iter().zip(&page_ids) {
+        if page_links.is_empty() {
+            continue;
+        }
+        let header = format!("\n{} 0 obj", page_id);
+        let start = find(pdf, header.as_bytes(), 0)? + header.len();
+        let page = ascii(&pdf[start..start + find(&pdf[start..], b"endobj", 0)?])?.trim().strip_suffix(">>")?.to_string();
+
+        let mut annots = Vec::new();
+        for link in page_links {
+            let action = match link.target {
+                LinkTarget::Uri(ref uri) => format!("/A<</S/URI/URI({})>>", escape_pdf_string(uri)),
+                LinkTarget::Page(index, top) => format!("/Dest[{} 0 R/XYZ 0 {:.2} null]", page_ids.get(index)?, top),
+            };
+            let [left, bottom, right, top] = link.rect;
+            offsets.push((next_id, pdf.len()));
+            writeln!(pdf, "{} 0 obj<</Type/Annot/Subtype/Link/Rect[{:.2} {:.2} {:.2} {:.2}]/Border[0 0 0]{}>>endobj",
+                next_id, left, bottom, right, top, action).ok()?;
+            annots.push(format!("{} 0 R", next_id));
+            next_id += 1;
+        }
+        offsets.push((page_id, pdf.len()));
+        writeln!(pdf, "{} 0 obj{}/Annots[{}]>>endobj", page_id, page, annots.join(" ")).ok()?;
+    }
+
+    // a subsection for each object, the entries are 20 bytes
+    offsets.sort();
+    let xref = pdf.len();
+    pdf.extend_from_slice(b"xref\n");
+    for (id, offset) in offsets {
+        write!(pdf, "{} 1\n{:010} 00000 n \n", id, offset).ok()?;
+    }
+    write!(pdf, "trailer\n<<{}/Size {}/Prev {}>>\nstartxref\n{}\n%%EOF\n", trailer, next_id, previous, xref).ok()
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack[from..].windows(needle.len()).position(|window| window == needle).map(|at| at + from)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|window| window == needle)
+}
+
+fn ascii(bytes: &[u8]) -> Option<&str> {
+    std::str::from_utf8(bytes).ok()
+}
+
+// a literal string, backslashes and parentheses are escaped
+fn escape_pdf_string(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('(', "\\(").replace(')', "\\)")
 }
 
 fn render_points_by_display_command(
     doc: &types::pdf_document::PdfDocumentReference,
     layer: &types::pdf_layer::PdfLayerReference,
     display_command: &DisplayCommand,
-    viewport: &Dimensions
-) {
+    page: &Page
+) -> Result<(), RenderError> {
     match display_command {
         &DisplayCommand::SolidColor(ref color, rect) => {
-            let y_top = Mm(360.0 - (rect.y + rect.height));
-            let y_bottom = Mm(360.0 - rect.y);
-            // x and y positions from the bottom left corner clockwise
-            let points = vec![
-                (Point::new(Mm(rect.x), y_bottom), false),
-                (Point::new(Mm(rect.x), y_top), false),
-                (Point::new(Mm(rect.x + rect.width), y_top), false),
-                (Point::new(Mm(rect.x + rect.width), y_bottom), false),
-            ];
-            layer.set_fill_color(Color::Rgb(
-                Rgb::new(
-                    color.r as f64 / 255.0,
-                    color.g as f64 / 255.0,
-                    color.b as f64 / 255.0,
-                    None
-            )));
+            let points = rect_points(rect, page);
+            layer.set_fill_color(rgb(color));
             layer.add_shape(Line {
                 points: points,
                 is_closed: true,
@@ -52,19 +448,538 @@ fn render_points_by_display_command(
                 is_clipping_path: false,
             });
         }
-        &DisplayCommand::Text(ref content, rect) => {
-            let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
-            
-            layer.set_fill_color(Color::Rgb(
-                Rgb::new(0.0, 0.0, 0.0, None) // enum Color from printpdf
+        // each side is stroked down its middle in its own style. dashes are
+        // whole points, a dot is a dash of length 0 with round caps
+        &DisplayCommand::Border(ref border) => {
+            for side in 0..4 {
+                let color = match border.colors[side] {
+                    Some(color) if border.widths[side] > 0.0 => color,
+                    _ => continue,
+                };
+                let width = page.pt(border.widths[side]);
+                layer.save_graphics_state();
+                layer.set_outline_color(rgb(&color));
+                layer.set_outline_thickness(width);
+                match border.styles[side] {
+                    BorderStyle::Solid => {}
+                    BorderStyle::Dashed => {
+                        let dash = ((width * 3.0).round() as i64).max(1);
+                        layer.set_line_dash_pattern(LineDashPattern { dash_1: Some(dash), gap_1: Some(dash), ..LineDashPattern::default() });
+                    }
+                    BorderStyle::Dotted => {
+                        layer.set_line_cap_style(LineCapStyle::Round);
+                        let gap = ((width * 2.0).round() as i64).max(1);
+                        layer.set_line_dash_pattern(LineDashPattern { dash_1: Some(0), gap_1: Some(gap), ..LineDashPattern::default() });
+                    }
+                }
+                layer.add_shape(Line {
+                    points: border_side_path(border, side).into_iter().map(|(x, y, handle)| (Point::new(page.mm(x), page.y(y)), handle)).collect(),
+                    is_closed: false,
+                    has_fill: false,
+                    has_stroke: true,
+                    is_clipping_path: false,
+                });
+                layer.restore_graphics_state();
+            }
+        }
+        &DisplayCommand::Text(ref content, rect, ref text_style) => {
+            render_text_line(doc, layer, &[(content.as_str(), rect, text_style)], page)?;
+        }
+        // the image is cut to the visible part with a clipping path. the alpha
+        // channel is dropped
+        &DisplayCommand::Image(ref image, rect, visible, rendering) => {
+            layer.save_graphics_state();
+            layer.add_shape(Line {
+                points: rect_points(visible, page),
+                is_closed: true,
+                has_fill: false,
+                has_stroke: false,
+                is_clipping_path: true,
+            });
+            let image_data = image.pixels.iter().flat_map(|pixel| vec![pixel.r, pixel.g, pixel.b]).collect();
+            Image::from(ImageXObject {
+                width: Px(image.width as usize),
+                height: Px(image.height as usize),
+                color_space: ColorSpace::Rgb,
+                bits_per_component: ColorBits::Bit8,
+                interpolate: rendering == ImageRendering::Smooth,
+                image_data: image_data,
+                image_filter: None,
+                clipping_bbox: None,
+            }).add_to_layer(
+                layer.clone(),
+                Some(page.mm(rect.x)),
+                Some(page.y(rect.y + rect.height)),
+                None,
+                Some(rect.width / image.width as f64),
+                Some(rect.height / image.height as f64),
+                Some(page.options.dpi), // a pixel of the image is a px of the page
+            );
+            layer.restore_graphics_state();
+        }
+        // the clipping path holds until the graphics state it was saved with is restored
+        &DisplayCommand::PushClip(rect) => {
+            layer.save_graphics_state();
+            layer.add_shape(Line {
+                points: rect_points(rect, page),
+                is_closed: true,
+                has_fill: false,
+                has_stroke: false,
+                is_clipping_path: true,
+            });
+        }
+        &DisplayCommand::PopClip => layer.restore_graphics_state(),
+        // printpdf has no transparency groups, the layers are painted opaque
+        &DisplayCommand::PushLayer(_) | &DisplayCommand::PopLayer => {}
+        // pdf y goes up from the bottom of the page, so a point at y = height - y'
+        // has to end up at height - (y' * scale_y + translate_y). the matrix
+        // scales first, then moves
+        &DisplayCommand::PushTransform(transform) => {
+            layer.save_graphics_state();
+            layer.set_ctm(CurTransMat::Translate(
+                page.mm(transform.translate_x),
+                page.mm(page.height * (1.0 - transform.scale_y) - transform.translate_y),
             ));
-            layer.use_text(
-                content.as_str(),
-                16 * 3, // font size
-                Mm(rect.x),
-                Mm(360.0 - rect.y - rect.height),
-                &font // font: &IndirectFontRef
+            layer.set_ctm(CurTransMat::Scale(transform.scale_x, transform.scale_y));
+        }
+        &DisplayCommand::PopTransform => layer.restore_graphics_state(),
+        // added as annotations once the document is saved, see append_links
+        &DisplayCommand::Link(..) | &DisplayCommand::Anchor(..) => {}
+        // the same shapes as on the canvas, a circle is stroked down the middle of its ring.
+        // the colors and thickness are restored after it like after a border
+        &DisplayCommand::Bullet(shape, rect, ref color) => {
+            layer.save_graphics_state();
+            let radius = rect.width.min(rect.height) / 2.0;
+            let (center_x, center_y) = (page.mm(rect.x + rect.width / 2.0), page.y(rect.y + rect.height / 2.0));
+            let (points, has_fill, has_stroke) = match shape {
+                BulletShape::Disc => (utils::calculate_points_for_circle(page.mm(radius), center_x, center_y), true, false),
+                BulletShape::Circle => {
+                    layer.set_outline_color(rgb(color));
+                    layer.set_outline_thickness(Pt::from(page.mm(radius / 3.0)).0);
+                    (utils::calculate_points_for_circle(page.mm(radius * 5.0 / 6.0), center_x, center_y), false, true)
+                }
+                BulletShape::Square => (rect_points(rect, page), true, false),
+            };
+            layer.set_fill_color(rgb(color));
+            layer.add_shape(Line {
+                points: points,
+                is_closed: true,
+                has_fill: has_fill,
+                has_stroke: has_stroke,
+                is_clipping_path: false,
+            });
+            layer.restore_graphics_state();
+        }
+    }
+    Ok(())
+}
+
+// how many of the texts from the first one are one line, left to right on the
+// same baseline. write_pdf puts them in one text object
+fn text_line_length(texts: &[(&str, Rect, &TextStyle)]) -> usize {
+    let mut end: Option<(f64, f64)> = None; // the baseline and the right edge so far
+    texts.iter().take_while(|&&(_, rect, text_style)| {
+        let line = baseline(rect, text_style.font.size, builtin_font(text_style));
+        let follows = end.is_none_or(|(y, right)| (line - y).abs() < 0.01 && rect.x >= right - 0.01);
+        end = Some((line, rect.x + rect.width));
+        follows
+    }).count()
+}
+
+// a text object for the pieces of a line, so a pdf viewer selects and copies
+// them as the line they are. each piece has its computed size and color, the
+// size rounded to whole points, and starts at its x in the layout. a piece
+// with a gap after it gets a space
+fn render_text_line(
+    doc: &types::pdf_document::PdfDocumentReference,
+    layer: &types::pdf_layer::PdfLayerReference,
+    texts: &[(&str, Rect, &TextStyle)],
+    page: &Page
+) -> Result<(), RenderError> {
+    layer.begin_text_section();
+    for (i, &(content, rect, text_style)) in texts.iter().enumerate() {
+        let builtin = builtin_font(text_style);
+        let font = doc.add_builtin_font(builtin).map_err(|err| RenderError::Backend(err.to_string()))?;
+        if i == 0 || texts[i - 1].2 != text_style {
+            layer.set_fill_color(rgb(&text_style.color));
+            layer.set_font(
+                &font,
+                (text_style.font.size * 72.0 / page.options.dpi).round(), // font size
             );
         }
+        // Td moves from where the piece before started
+        match i {
+            0 => layer.set_text_cursor(page.mm(rect.x), page.y(baseline(rect, text_style.font.size, builtin))),
+            _ => layer.set_text_cursor(page.mm(rect.x - texts[i - 1].1.x), Mm(0.0)),
+        }
+        match texts.get(i + 1) {
+            Some(&(_, next, _)) if next.x > rect.x + rect.width + 0.01 => layer.write_text(format!("{} ", content), &font),
+            _ => layer.write_text(content, &font),
+        }
+    }
+    layer.end_text_section();
+    Ok(())
+}
+
+// enum Color from printpdf, the alpha is dropped
+fn rgb(color: &css::Color) -> Color {
+    Color::Rgb(Rgb::new(
+        color.r as f64 / 255.0,
+        color.g as f64 / 255.0,
+        color.b as f64 / 255.0,
+        None
+    ))
+}
+
+// layout px to page units, pdf y goes up from the bottom of the page
+struct Page {
+    height: f64, // px
+    options: RenderOptions,
+}
+
+impl Page {
+    fn mm(&self, px: f64) -> Mm {
+        Mm(px * 25.4 / self.options.dpi)
+    }
+
+    fn pt(&self, px: f64) -> f64 {
+        px * 72.0 / self.options.dpi
+    }
+
+    // px down from the top to mm up from the bottom
+    fn y(&self, px: f64) -> Mm {
+        self.mm(self.height - px)
+    }
+}
+
+// x and y positions from the bottom left corner clockwise
+fn rect_points(rect: Rect, page: &Page) -> Vec<(Point, bool)> {
+    let y_top = page.y(rect.y + rect.height);
+    let y_bottom = page.y(rect.y);
+    vec![
+        (Point::new(page.mm(rect.x), y_bottom), false),
+        (Point::new(page.mm(rect.x), y_top), false),
+        (Point::new(page.mm(rect.x + rect.width), y_top), false),
+        (Point::new(page.mm(rect.x + rect.width), y_bottom), false),
+    ]
+}
+
+// the middle of a side of the border in px, from the left or top end to the
+// other. the top and bottom sides go round the corners, whose arcs are cubic
+// beziers. a corner that isn't wider than its border is square. a point is
+// marked where a curve starts, like printpdf wants
+fn border_side_path(border: &Border, side: usize) -> Vec<(f64, f64, bool)> {
+    // how far along the tangents the control points of a quarter circle go
+    const KAPPA: f64 = 0.5523;
+    let rect = border.rect;
+    let [top, right, bottom, left] = border.widths;
+    let (x0, y0, x1, y1) = (rect.x, rect.y, rect.x + rect.width, rect.y + rect.height);
+    let (middle_x0, middle_y0, middle_x1, middle_y1) = (x0 + left / 2.0, y0 + top / 2.0, x1 - right / 2.0, y1 - bottom / 2.0);
+    // the radii of each corner down the middle of the border, None for a square one
+    let radii: Vec<Option<(f64, f64)>> = [(left, top), (right, top), (right, bottom), (left, bottom)].iter().zip(&border.radii)
+        .map(|(&(vertical, horizontal), &radius)| {
+            let (rx, ry) = (radius - vertical / 2.0, radius - horizontal / 2.0);
+            if rx > 0.0 && ry > 0.0 { Some((rx, ry)) } else { None }
+        })
+        .collect();
+    let arc = |path: &mut Vec<(f64, f64, bool)>, (corner_x, corner_y): (f64, f64), (x, y): (f64, f64)| {
+        let start = path.len() - 1;
+        path[start].2 = true;
+        let (start_x, start_y, _) = path[start];
+        path.push((start_x + (corner_x - start_x) * KAPPA, start_y + (corner_y - start_y) * KAPPA, true));
+        path.push((x + (corner_x - x) * KAPPA, y + (corner_y - y) * KAPPA, false));
+        path.push((x, y, false));
+    };
+
+    let mut path = Vec::new();
+    match side {
+        0 | 2 => {
+            let (y, (left_corner, right_corner), sign) = if side == 0 { (middle_y0, (0, 1), 1.0) } else { (middle_y1, (3, 2), -1.0) };
+            match radii[left_corner] {
+                Some((rx, ry)) => {
+                    path.push((middle_x0, y + ry * sign, false));
+                    arc(&mut path, (middle_x0, y), (middle_x0 + rx, y));
+                }
+                None => path.push((x0, y, false)),
+            }
+            match radii[right_corner] {
+                Some((rx, ry)) => {
+                    path.push((middle_x1 - rx, y, false));
+                    arc(&mut path, (middle_x1, y), (middle_x1, y + ry * sign));
+                }
+                None => path.push((x1, y, false)),
+            }
+        }
+        _ => {
+            let (x, (top_corner, bottom_corner)) = if side == 3 { (middle_x0, (0, 3)) } else { (middle_x1, (1, 2)) };
+            path.push((x, radii[top_corner].map_or(y0, |(_, ry)| middle_y0 + ry), false));
+            path.push((x, radii[bottom_corner].map_or(y1, |(_, ry)| middle_y1 - ry), false));
+        }
     }
-}
\ No newline at end of file
+    path
+}
+
+// pdf text sits on its baseline, which is the ascent below the top of the
+// rect like on the canvas. the ascents are from the afm files of the fonts
+fn baseline(rect: Rect, font_size: f64, font: BuiltinFont) -> f64 {
+    let ascent = match font {
+        BuiltinFont::TimesRoman | BuiltinFont::TimesBold | BuiltinFont::TimesItalic | BuiltinFont::TimesBoldItalic => 0.683,
+        BuiltinFont::Courier | BuiltinFont::CourierBold | BuiltinFont::CourierOblique | BuiltinFont::CourierBoldOblique => 0.629,
+        _ => 0.718,
+    };
+    rect.y + font_size * ascent
+}
+
+// the first family one of the builtin pdf fonts can stand in for
+fn builtin_font(text_style: &TextStyle) -> BuiltinFont {
+    let (bold, italic) = (text_style.is_bold(), text_style.is_italic());
+    for family in &text_style.font.family {
+        match family.to_lowercase().as_str() {
+            "serif" | "times" | "times new roman" => return match (bold, italic) {
+                (false, false) => BuiltinFont::TimesRoman,
+                (true, false) => BuiltinFont::TimesBold,
+                (false, true) => BuiltinFont::TimesItalic,
+                (true, true) => BuiltinFont::TimesBoldItalic,
+            },
+            "monospace" | "courier" | "courier new" => return match (bold, italic) {
+                (false, false) => BuiltinFont::Courier,
+                (true, false) => BuiltinFont::CourierBold,
+                (false, true) => BuiltinFont::CourierOblique,
+                (true, true) => BuiltinFont::CourierBoldOblique,
+            },
+            "sans-serif" | "helvetica" | "arial" => break,
+            _ => {}
+        }
+    }
+    match (bold, italic) {
+        (false, false) => BuiltinFont::Helvetica,
+        (true, false) => BuiltinFont::HelveticaBold,
+        (false, true) => BuiltinFont::HelveticaOblique,
+        (true, true) => BuiltinFont::HelveticaBoldOblique,
+    }
+}
+
+#[test]
+fn test_pdf_renderer() {
+    use crate::css::Color as CssColor;
+    use crate::painter::DisplayCommand;
+    use std::fs;
+
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 480.0;
+    viewport.content.height = 360.0;
+    let red = CssColor { r: 255, g: 0, b: 0, a: 255 };
+    let list = vec![DisplayCommand::SolidColor(red, Rect { x: 0.0, y: 0.0, width: 10.0, height: 10.0 })];
+
+    let path = std::env::temp_dir().join("rust_browser_test_pdf_renderer.pdf");
+    let output = PdfRenderer::new(path.clone()).render_pages(&[list.clone(), list.clone()], &viewport);
+    assert_eq!(output, Ok(Output::File(path.clone())));
+    assert!(fs::read(&path).unwrap().starts_with(b"%PDF"));
+    fs::remove_file(&path).unwrap();
+
+    // the same document without the file
+    let bytes = render_to_vec(std::slice::from_ref(&list), &viewport, &Default::default()).unwrap();
+    assert!(bytes.starts_with(b"%PDF"));
+    // 480 by 360px at 96 dpi is 5 by 3.75 inches
+    assert!(String::from_utf8_lossy(&bytes).contains("/MediaBox[0 0 360.00 270.00]"));
+    match PdfRenderer::in_memory().render(&list, &viewport) {
+        Ok(Output::Bytes(rendered)) => assert!(rendered.starts_with(b"%PDF")),
+        output => panic!("{:?} isn't in memory", output),
+    }
+
+    let missing = std::env::temp_dir().join("rust_browser_no_such_dir").join("a.pdf");
+    match PdfRenderer::new(missing.clone()).render(&list, &viewport) {
+        Err(RenderError::Io { path, .. }) => assert_eq!(path, missing),
+        output => panic!("{:?} isn't an io error", output),
+    }
+}
+
+#[test]
+fn test_border_side_path() {
+    use crate::css::Color as CssColor;
+
+    let black = Some(CssColor { r: 0, g: 0, b: 0, a: 255 });
+    let mut border = Border {
+        rect: Rect { x: 0.0, y: 0.0, width: 100.0, height: 50.0 },
+        widths: [2.0; 4],
+        colors: [black; 4],
+        styles: [BorderStyle::Solid; 4],
+        radii: [0.0; 4],
+    };
+    // square corners, the sides go the whole way and overlap
+    assert_eq!(border_side_path(&border, 0), vec![(0.0, 1.0, false), (100.0, 1.0, false)]);
+    assert_eq!(border_side_path(&border, 3), vec![(1.0, 0.0, false), (1.0, 50.0, false)]);
+
+    // the top side curves down to where the left and right ones start
+    border.radii = [11.0; 4];
+    let top = border_side_path(&border, 0);
+    assert_eq!(top.len(), 8);
+    assert_eq!((top[0], top[3]), ((1.0, 11.0, true), (11.0, 1.0, false)));
+    assert_eq!((top[4], top[7]), ((89.0, 1.0, true), (99.0, 11.0, false)));
+    assert_eq!(border_side_path(&border, 3), vec![(1.0, 11.0, false), (1.0, 39.0, false)]);
+    let bottom = border_side_path(&border, 2);
+    assert_eq!((bottom[0], bottom[7]), ((1.0, 39.0, true), (99.0, 39.0, false)));
+}
+
+#[test]
+fn test_text_baseline() {
+    use crate::css::Color as CssColor;
+    use crate::style::Font;
+
+    let rect = Rect { x: 0.0, y: 10.0, width: 80.0, height: 20.0 };
+    let text_style = |family: &str| TextStyle {
+        color: CssColor { r: 0, g: 0, b: 0, a: 255 },
+        font: Font { family: vec![family.to_string()], size: 20.0, ..Default::default() },
+    };
+    let serif = text_style("serif");
+    assert_eq!(builtin_font(&serif), BuiltinFont::TimesRoman);
+    assert_eq!(baseline(rect, serif.font.size, builtin_font(&serif)), 10.0 + 20.0 * 0.683);
+    let sans = text_style("sans-serif");
+    assert_eq!(baseline(rect, sans.font.size, builtin_font(&sans)), 10.0 + 20.0 * 0.718);
+}
+
+#[test]
+fn test_text_lines() {
+    use crate::css::Color as CssColor;
+    use crate::style::Font;
+
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 200.0;
+    viewport.content.height = 100.0;
+    let text_style = TextStyle {
+        color: CssColor { r: 0, g: 0, b: 0, a: 255 },
+        font: Font { family: vec!["sans-serif".to_string()], size: 16.0, ..Default::default() },
+    };
+    let text = |text: &str, x: f64, y: f64| DisplayCommand::Text(text.to_string(), Rect { x: x, y: y, width: 40.0, height: 16.0 }, text_style.clone());
+    // justified words are one line, the next line and the one going back left aren't
+    let list = vec![text("hello", 0.0, 0.0), text("world", 56.0, 0.0), text("again", 0.0, 16.0), text("x", 40.0, 16.0), text("y", 0.0, 16.0)];
+    let texts: Vec<(&str, Rect, &TextStyle)> = list.iter().map(|command| match *command {
+        DisplayCommand::Text(ref text, rect, ref text_style) => (text.as_str(), rect, text_style),
+        _ => unreachable!(),
+    }).collect();
+    assert_eq!(text_line_length(&texts), 2);
+    assert_eq!(text_line_length(&texts[2..]), 2);
+    assert_eq!(text_line_length(&texts[4..]), 1);
+
+    let pdf = String::from_utf8_lossy(&render_to_vec(std::slice::from_ref(&list), &viewport, &Default::default()).unwrap()).into_owned();
+    let content = &pdf[pdf.find("/OC /MC0 BDC").unwrap()..];
+    assert_eq!(content.matches("\nBT\n").count(), 3);
+    // "hello " with the space for the gap, then 42pt on to "world"
+    assert!(content.contains("<68656C6C6F20> Tj\n42.00 0.00 Td\n<776F726C64> Tj\nET\n"));
+}
+
+#[test]
+fn test_bullet_graphics_state() {
+    use crate::css::Color as CssColor;
+
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 200.0;
+    viewport.content.height = 100.0;
+    let red = CssColor { r: 255, g: 0, b: 0, a: 255 };
+    let list = vec![
+        DisplayCommand::Bullet(BulletShape::Circle, Rect { x: 0.0, y: 0.0, width: 6.0, height: 6.0 }, red),
+        DisplayCommand::SolidColor(red, Rect { x: 10.0, y: 0.0, width: 20.0, height: 20.0 }),
+    ];
+    let pdf = String::from_utf8_lossy(&render_to_vec(&[list], &viewport, &Default::default()).unwrap()).into_owned();
+    let content = &pdf[pdf.find("/OC /MC0 BDC").unwrap()..];
+    // the ring's stroke color and width end with it, before the rect is filled
+    let ring = content.find(" w\n").unwrap();
+    let restored = ring + content[ring..].find("\nQ\n").unwrap();
+    assert!(content[..ring].contains("\nq\n"));
+    assert!(restored < content.find("7.50 75.00 m").unwrap());
+}
+
+#[test]
+fn test_links() {
+    use crate::{css, html, layout, style};
+
+    let root = html::parse("<p><a href=b.html>b</a> <a href=#end>end</a> <a href=#nowhere>x</a></p><div id=end>c</div>".to_string());
+    let stylesheet = css::parse("body { margin: 0; } p { margin: 0; height: 400px; } div { height: 100px; }".to_string());
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 480.0;
+    viewport.content.height = 360.0;
+    let layout_root = layout::layout_tree(&styled, viewport);
+
+    let pages = painter::paginate(&layout_root, 360.0, &Default::default());
+    assert_eq!(pages.len(), 2);
+    let options = RenderOptions { base_url: Some("http://example.com/a/".to_string()), ..Default::default() };
+    let pdf = render_to_vec(&pages, &viewport, &options).unwrap();
+    let text = String::from_utf8_lossy(&pdf);
+
+    // the first page gets both links, #nowhere has no anchor. the second page
+    // starts at 360px, #end is 40px down it
+    let kids = &text[text.find("/Kids[").unwrap() + 6..];
+    let second_page = kids.split_whitespace().nth(3).unwrap();
+    assert!(text.contains("/Subtype/Link/Rect[0.00 258.00 6.00 270.00]/Border[0 0 0]/A<</S/URI/URI(http://example.com/a/b.html)>>"));
+    assert!(text.contains(&format!("/Dest[{} 0 R/XYZ 0 240.00 null]", second_page)));
+    assert_eq!(text.matches("/Subtype/Link").count(), 2);
+    assert!(text.contains("/Annots["));
+    assert!(text.trim_end().ends_with("%%EOF"));
+}
+
+#[test]
+fn test_rasterize() {
+    use crate::css::Color as CssColor;
+
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 8.0;
+    viewport.content.height = 6.0;
+    let red = CssColor { r: 255, g: 0, b: 0, a: 255 };
+    let white = CssColor { r: 255, g: 255, b: 255, a: 255 };
+    let list = vec![DisplayCommand::SolidColor(red, Rect { x: 2.0, y: 2.0, width: 2.0, height: 2.0 })];
+
+    let canvas = rasterize(&list, &viewport, &Default::default());
+    assert_eq!((canvas.width, canvas.height), (8, 6));
+    assert_eq!((canvas.pixels[2 * 8 + 2], canvas.pixels[4 * 8 + 4]), (red, white));
+
+    // twice the pixels each way for the same layout
+    let options = RenderOptions { scale: 2.0, ..Default::default() };
+    let canvas = rasterize(&list, &viewport, &options);
+    assert_eq!((canvas.width, canvas.height), (16, 12));
+    assert_eq!((canvas.pixels[4 * 16 + 4], canvas.pixels[7 * 16 + 7], canvas.pixels[8 * 16 + 8]), (red, red, white));
+}
+
+#[test]
+fn test_raster_formats() {
+    use crate::css::Color as CssColor;
+
+    let mut canvas = Canvas::new(2, 2);
+    let red = CssColor { r: 255, g: 0, b: 0, a: 255 };
+    canvas.paint_pixels_by_display_command(&DisplayCommand::SolidColor(red, Rect { x: 0.0, y: 0.0, width: 1.0, height: 1.0 }), None);
+
+    let mut ppm = b"P6\n2 2\n255\n".to_vec();
+    ppm.extend_from_slice(&[255, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255]);
+    assert_eq!(to_ppm(&canvas), ppm);
+
+    // rows of 6 bytes are padded to 8, the top row with red in it comes last
+    let bmp = to_bmp(&canvas);
+    assert_eq!(bmp.len(), 54 + 16);
+    assert_eq!(&bmp[..2], b"BM");
+    assert_eq!(&bmp[2..6], &70u32.to_le_bytes());
+    assert_eq!(&bmp[54..], &[255, 255, 255, 255, 255, 255, 0, 0, 0, 0, 255, 255, 255, 255, 0, 0][..]);
+
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = 2.0;
+    viewport.content.height = 2.0;
+    let list = vec![DisplayCommand::SolidColor(red, Rect { x: 0.0, y: 0.0, width: 1.0, height: 1.0 })];
+    assert_eq!(RasterRenderer::in_memory(RasterFormat::Ppm).render(&list, &viewport), Ok(Output::Bytes(ppm)));
+}
+
+#[test]
+fn test_to_ansi() {
+    use crate::css::Color as CssColor;
+
+    let mut canvas = Canvas::new(4, 6);
+    let red = CssColor { r: 255, g: 0, b: 0, a: 255 };
+    let blue = CssColor { r: 0, g: 0, b: 255, a: 255 };
+    canvas.paint_pixels_by_display_command(&DisplayCommand::SolidColor(red, Rect { x: 0.0, y: 0.0, width: 4.0, height: 2.0 }), None);
+    canvas.paint_pixels_by_display_command(&DisplayCommand::SolidColor(blue, Rect { x: 0.0, y: 2.0, width: 2.0, height: 2.0 }), None);
+
+    // halved to 2 by 3 pixels, the last row has blank under it
+    let red_over_blue = "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m\u{2580}";
+    let red_over_white = "\x1b[38;2;255;0;0m\x1b[48;2;255;255;255m\u{2580}";
+    let white_over_white = "\x1b[38;2;255;255;255m\x1b[48;2;255;255;255m\u{2580}";
+    assert_eq!(to_ansi(&canvas, 2), format!("{}{}\x1b[0m\n{}{}\x1b[0m\n",
+        red_over_blue, red_over_white, white_over_white, white_over_white));
+}
@@ -0,0 +1,390 @@
+// Execute <script> contents with boa before styling (enabled by the "js" feature)
+//
+// Scripts run in document order against the live document, what one script
+// writes, appends or sets is there for the next one to find. `document.write`
+// output is inserted right after the running script element. Only the scripts
+// of the parsed document run, not the ones scripts add.
+use crate::dom::{AttrMap, Node, NodeType};
+use crate::html;
+use crate::style;
+
+use boa_engine::object::ObjectInitializer;
+use boa_engine::property::Attribute;
+use boa_engine::{js_string, Context, JsArgs, JsError, JsNativeError, JsResult, JsString, JsValue, NativeFunction, Source};
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+// the document while the scripts run. a node keeps its index however the tree
+// around it changes, so the objects handed to scripts stay attached to it
+struct Tree {
+    nodes: Vec<TreeNode>,
+}
+
+struct TreeNode {
+    data: NodeType,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+const ROOT: usize = 0;
+
+// the node index a node object carries, not enumerable or writable
+const NODE_KEY: &str = "__node";
+
+struct Document {
+    tree: Tree,
+    script: usize, // the running script element
+    written: usize, // nodes document.write has put after it so far
+}
+
+type SharedDocument = Rc<RefCell<Document>>;
+
+pub fn execute_scripts(root: &mut Node) {
+    let tree = Tree::new(root.clone());
+    let scripts = tree.scripts(ROOT);
+    let document = Rc::new(RefCell::new(Document {
+        tree: tree,
+        script: ROOT,
+        written: 0,
+    }));
+    let mut context = new_context(&document);
+
+    for script in scripts {
+        let source = {
+            let mut document = document.borrow_mut();
+            document.script = script;
+            document.written = 0;
+            document.tree.text_content(script)
+        };
+        if let Err(err) = context.eval(Source::from_bytes(&source)) {
+            eprintln!("script error: {}", err);
+        }
+    }
+    *root = document.borrow().tree.to_node(ROOT);
+}
+
+impl Tree {
+    fn new(root: Node) -> Tree {
+        let mut tree = Tree { nodes: Vec::new() };
+        tree.add(root);
+        tree
+    }
+
+    // a detached copy of `node` and its descendants, returns its index
+    fn add(&mut self, node: Node) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(TreeNode {
+            data: node.data,
+            parent: None,
+            children: Vec::new(),
+        });
+        for child in node.children {
+            let child = self.add(child);
+            self.nodes[child].parent = Some(id);
+            self.nodes[id].children.push(child);
+        }
+        id
+    }
+
+    fn to_node(&self, id: usize) -> Node {
+        Node {
+            data: self.nodes[id].data.clone(),
+            children: self.nodes[id].children.iter().map(|&child| self.to_node(child)).collect(),
+        }
+    }
+
+    fn detach(&mut self, id: usize) {
+        if let Some(parent) = self.nodes[id].parent.take() {
+            self.nodes[parent].children.retain(|&child| child != id);
+        }
+    }
+
+    // moves `id` from wherever it is into `parent`, before the child at `index`
+    // once `id` is out of the way
+    // https://dom.spec.whatwg.org/#concept-node-pre-insert
+    fn insert(&mut self, parent: usize, index: usize, id: usize) -> JsResult<()> {
+        if let NodeType::Text(_) = self.nodes[parent].data {
+            return Err(hierarchy_error("a text node has no children"));
+        }
+        if self.ancestors(parent).any(|ancestor| ancestor == id) {
+            return Err(hierarchy_error("a node can't be put inside itself"));
+        }
+        self.detach(id);
+        let index = index.min(self.nodes[parent].children.len());
+        self.nodes[parent].children.insert(index, id);
+        self.nodes[id].parent = Some(parent);
+        Ok(())
+    }
+
+    fn insert_nodes(&mut self, parent: usize, index: usize, nodes: Vec<Node>) -> JsResult<()> {
+        for (i, node) in nodes.into_iter().enumerate() {
+            let id = self.add(node);
+            self.insert(parent, index.saturating_add(i), id)?;
+        }
+        Ok(())
+    }
+
+    fn replace_children(&mut self, id: usize, nodes: Vec<Node>) -> JsResult<()> {
+        for child in mem::take(&mut self.nodes[id].children) {
+            self.nodes[child].parent = None;
+        }
+        self.insert_nodes(id, 0, nodes)
+    }
+
+    // `id` and the nodes it is inside of
+    fn ancestors(&self, id: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut next = Some(id);
+        std::iter::from_fn(move || {
+            let current = next?;
+            next = self.nodes[current].parent;
+            Some(current)
+        })
+    }
+
+    fn index_in_parent(&self, id: usize) -> Option<(usize, usize)> {
+        let parent = self.nodes[id].parent?;
+        let index = self.nodes[parent].children.iter().position(|&child| child == id)?;
+        Some((parent, index))
+    }
+
+    fn text_content(&self, id: usize) -> String {
+        self.to_node(id).text_content()
+    }
+
+    // the script elements under `id` in document order
+    fn scripts(&self, id: usize) -> Vec<usize> {
+        let mut scripts = Vec::new();
+        if let NodeType::Element(ref elem) = self.nodes[id].data {
+            if elem.tag_name == "script" {
+                scripts.push(id);
+            }
+        }
+        for &child in &self.nodes[id].children {
+            scripts.extend(self.scripts(child));
+        }
+        scripts
+    }
+
+    fn get_element_by_id(&self, id: usize, element_id: &str) -> Option<usize> {
+        if let NodeType::Element(ref elem) = self.nodes[id].data {
+            if elem.id().map(|s| s.as_str()) == Some(element_id) {
+                return Some(id);
+            }
+        }
+        self.nodes[id].children.iter().find_map(|&child| self.get_element_by_id(child, element_id))
+    }
+
+    fn query_selector(&self, selectors: &str) -> JsResult<Option<usize>> {
+        let path = style::query_selector(&self.to_node(ROOT), selectors)
+            .map_err(|err| JsNativeError::syntax().with_message(err.to_string()))?;
+        Ok(path.map(|path| path.iter().fold(ROOT, |id, &i| self.nodes[id].children[i])))
+    }
+}
+
+fn hierarchy_error(message: &str) -> JsError {
+    JsNativeError::typ().with_message(format!("HierarchyRequestError: {}", message)).into()
+}
+
+fn parse_markup(markup: String) -> JsResult<Vec<Node>> {
+    html::parse_fragment(markup).map_err(|err| JsNativeError::syntax().with_message(err.to_string()).into())
+}
+
+fn string_arg(args: &[JsValue], i: usize, ctx: &mut Context<'_>) -> JsResult<String> {
+    Ok(args.get_or_undefined(i).to_string(ctx)?.to_std_string_escaped())
+}
+
+// the index of the node behind a node object
+fn node_arg(args: &[JsValue], i: usize, ctx: &mut Context<'_>) -> JsResult<usize> {
+    let object = args.get_or_undefined(i).as_object()
+        .ok_or_else(|| JsNativeError::typ().with_message("not a node"))?
+        .clone();
+    let id = object.get(JsString::from(NODE_KEY), ctx)?;
+    id.as_number().map(|id| id as usize).ok_or_else(|| JsNativeError::typ().with_message("not a node").into())
+}
+
+// a native function that works on the document
+fn document_function<F>(document: &SharedDocument, f: F) -> NativeFunction
+where
+    F: Fn(&SharedDocument, &[JsValue], &mut Context<'_>) -> JsResult<JsValue> + 'static,
+{
+    let document = document.clone();
+    // safe as long as the closure captures no gc-traced values: `document` is
+    // plain rust data and the callers only capture node indices
+    unsafe { NativeFunction::from_closure(move |_this, args, ctx| f(&document, args, ctx)) }
+}
+
+fn new_context(document: &SharedDocument) -> Context<'static> {
+    let mut context = Context::default();
+
+    let write = document_function(document, |document, args, ctx| {
+        let nodes = parse_markup(string_arg(args, 0, ctx)?)?;
+        let mut document = document.borrow_mut();
+        let count = nodes.len();
+        if let Some((parent, index)) = document.tree.index_in_parent(document.script) {
+            let written = document.written;
+            document.tree.insert_nodes(parent, index + 1 + written, nodes)?;
+        }
+        document.written += count;
+        Ok(JsValue::undefined())
+    });
+    let get_element_by_id = document_function(document, |document, args, ctx| {
+        let element_id = string_arg(args, 0, ctx)?;
+        let found = document.borrow().tree.get_element_by_id(ROOT, &element_id);
+        Ok(found.map_or(JsValue::null(), |id| node_object(document, id, ctx)))
+    });
+    let query_selector = document_function(document, |document, args, ctx| {
+        let selectors = string_arg(args, 0, ctx)?;
+        let found = document.borrow().tree.query_selector(&selectors)?;
+        Ok(found.map_or(JsValue::null(), |id| node_object(document, id, ctx)))
+    });
+    let create_element = document_function(document, |document, args, ctx| {
+        let tag_name = string_arg(args, 0, ctx)?.to_ascii_lowercase();
+        let id = document.borrow_mut().tree.add(Node::elem(tag_name, AttrMap::new(), Vec::new()));
+        Ok(node_object(document, id, ctx))
+    });
+    let create_text_node = document_function(document, |document, args, ctx| {
+        let data = string_arg(args, 0, ctx)?;
+        let id = document.borrow_mut().tree.add(Node::text(data));
+        Ok(node_object(document, id, ctx))
+    });
+
+    let body = document.borrow().tree.query_selector("body").ok().flatten();
+    let body = body.map_or(JsValue::null(), |id| node_object(document, id, &mut context));
+    let document = ObjectInitializer::new(&mut context)
+        .function(write, js_string!("write"), 1)
+        .function(get_element_by_id, js_string!("getElementById"), 1)
+        .function(query_selector, js_string!("querySelector"), 1)
+        .function(create_element, js_string!("createElement"), 1)
+        .function(create_text_node, js_string!("createTextNode"), 1)
+        .property(js_string!("body"), body, Attribute::READONLY)
+        .build();
+    context
+        .register_global_property(js_string!("document"), document, Attribute::all())
+        .expect("cannot register document object");
+    context
+}
+
+// an element or text node of the live document
+fn node_object(document: &SharedDocument, id: usize, ctx: &mut Context<'_>) -> JsValue {
+    let (tag_name, element_id) = match document.borrow().tree.nodes[id].data {
+        NodeType::Element(ref elem) => (elem.tag_name.to_ascii_uppercase(), elem.id().cloned().unwrap_or_default()),
+        NodeType::Text(_) => (String::new(), String::new()),
+    };
+
+    let get_text_content = document_function(document, move |document, _args, _ctx| {
+        Ok(JsString::from(document.borrow().tree.text_content(id).as_str()).into())
+    });
+    // https://dom.spec.whatwg.org/#dom-node-textcontent
+    let set_text_content = document_function(document, move |document, args, ctx| {
+        let text = string_arg(args, 0, ctx)?;
+        let mut document = document.borrow_mut();
+        match document.tree.nodes[id].data {
+            NodeType::Text(ref mut data) => *data = text,
+            NodeType::Element(_) => {
+                let nodes = if text.is_empty() { Vec::new() } else { vec![Node::text(text)] };
+                document.tree.replace_children(id, nodes)?;
+            }
+        }
+        Ok(JsValue::undefined())
+    });
+    let get_inner_html = document_function(document, move |document, _args, _ctx| {
+        Ok(JsString::from(document.borrow().tree.to_node(id).inner_html().as_str()).into())
+    });
+    let set_inner_html = document_function(document, move |document, args, ctx| {
+        let nodes = parse_markup(string_arg(args, 0, ctx)?)?;
+        document.borrow_mut().tree.replace_children(id, nodes)?;
+        Ok(JsValue::undefined())
+    });
+    // https://dom.spec.whatwg.org/#dom-node-appendchild
+    let append_child = document_function(document, move |document, args, ctx| {
+        let child = node_arg(args, 0, ctx)?;
+        document.borrow_mut().tree.insert(id, usize::MAX, child)?;
+        Ok(args.get_or_undefined(0).clone())
+    });
+    // https://html.spec.whatwg.org/multipage/dynamic-markup-insertion.html#dom-element-insertadjacenthtml
+    let insert_adjacent_html = document_function(document, move |document, args, ctx| {
+        let position = string_arg(args, 0, ctx)?.to_ascii_lowercase();
+        let nodes = parse_markup(string_arg(args, 1, ctx)?)?;
+        let mut document = document.borrow_mut();
+        let tree = &mut document.tree;
+        match &*position {
+            "afterbegin" => tree.insert_nodes(id, 0, nodes)?,
+            "beforeend" => tree.insert_nodes(id, usize::MAX, nodes)?,
+            "beforebegin" | "afterend" => if let Some((parent, index)) = tree.index_in_parent(id) {
+                let index = if position == "afterend" { index + 1 } else { index };
+                tree.insert_nodes(parent, index, nodes)?;
+            },
+            _ => return Err(JsNativeError::syntax().with_message(format!("unknown position {}", position)).into()),
+        }
+        Ok(JsValue::undefined())
+    });
+
+    let realm = ctx.realm().clone();
+    ObjectInitializer::new(ctx)
+        .property(JsString::from(NODE_KEY), id as f64, Attribute::empty())
+        .property(js_string!("id"), JsString::from(element_id.as_str()), Attribute::READONLY)
+        .property(js_string!("tagName"), JsString::from(tag_name.as_str()), Attribute::READONLY)
+        .accessor(
+            js_string!("textContent"),
+            Some(get_text_content.to_js_function(&realm)),
+            Some(set_text_content.to_js_function(&realm)),
+            Attribute::ENUMERABLE,
+        )
+        .accessor(
+            js_string!("innerHTML"),
+            Some(get_inner_html.to_js_function(&realm)),
+            Some(set_inner_html.to_js_function(&realm)),
+            Attribute::ENUMERABLE,
+        )
+        .function(append_child, js_string!("appendChild"), 1)
+        .function(insert_adjacent_html, js_string!("insertAdjacentHTML"), 2)
+        .build()
+        .into()
+}
+
+#[test]
+fn test_document_write() {
+    let mut root = html::parse("<div id=a><script>document.write('<p>x</p>' + (1 + 1));</script><p>y</p></div>".to_string());
+    execute_scripts(&mut root);
+    // the written nodes follow the script and are not scanned for scripts
+    let div = format!("{}", root.get_element_by_id("a").unwrap());
+    assert!(div.ends_with("\n  <p>\n    #text: x\n  #text: 2\n  <p>\n    #text: y\n"), "{}", div);
+}
+
+#[test]
+fn test_insert_adjacent_html() {
+    let mut root = html::parse("<div id=w><p id=a>x</p><script>var a = document.getElementById('a');\
+        a.insertAdjacentHTML('beforebegin', '<i>1</i>'); a.insertAdjacentHTML('afterBegin', '<i>2</i>');\
+        a.insertAdjacentHTML('beforeend', '<i>3</i>'); a.insertAdjacentHTML('afterend', '<i>4</i>');\
+        a.insertAdjacentHTML('nowhere', '<i>5</i>');</script></div>".to_string());
+    execute_scripts(&mut root);
+    let div = format!("{}", root.get_element_by_id("w").unwrap());
+    let expected = "<div id=\"w\">\n  <i>\n    #text: 1\n  <p id=\"a\">\n    <i>\n      #text: 2\n    #text: x\n    \
+        <i>\n      #text: 3\n  <i>\n    #text: 4\n  <script>\n";
+    assert!(div.starts_with(expected), "{}", div);
+    assert!(!div.contains("#text: 5"));
+}
+
+#[test]
+fn test_dom_mutation() {
+    let mut root = html::parse("<ul id=list></ul><p class=out>old</p><div id=box><b>x</b></div>\
+        <script>var list = document.getElementById('list');\
+        ['a', 'b', 'c'].forEach(function (name) {\
+            var li = document.createElement('LI'); li.appendChild(document.createTextNode(name)); list.appendChild(li);\
+        });\
+        document.querySelector('p.out').textContent = list.textContent;\
+        document.getElementById('box').innerHTML = '<i>' + document.getElementById('box').innerHTML + '</i>';\
+        document.write('<span id=later>w</span>');</script>\
+        <script>document.body.appendChild(document.getElementById('later'));\
+        try { list.appendChild(document.body); } catch (e) { document.body.appendChild(document.createTextNode('no')); }</script>".to_string());
+    execute_scripts(&mut root);
+    let body = &root.children[1];
+    assert_eq!(format!("{}", body.children[0]), "<ul id=\"list\">\n  <li>\n    #text: a\n  <li>\n    #text: b\n  <li>\n    #text: c\n");
+    assert_eq!(body.children[1].text_content(), "abc");
+    assert_eq!(body.children[2].to_html(), "<div id=\"box\"><i><b>x</b></i></div>");
+    // the span written by the first script is found and moved by the second
+    let tail: Vec<String> = body.children[body.children.len() - 2..].iter().map(|node| node.to_html()).collect();
+    assert_eq!(tail, ["<span id=\"later\">w</span>", "no"]);
+}
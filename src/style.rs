@@ -1,40 +1,565 @@
-use crate::dom::{ElementData, Node, NodeType};
-use crate::css::{Color, Rule, Selector, SimpleSelector, Specificity, Stylesheet, Value};
+use crate::dom::{ElementData, ElementState, Node, NodeType};
+use crate::css::{self, Color, Declaration, LengthContext, MediaContext, Origin, PseudoClass, Rule, Selector, SimpleSelector, Specificity, Stylesheet, Value};
+use crate::url;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::{fs, io};
 
 type PropertyMap = HashMap<String, Value>;
 
 pub struct StyledNode<'a> {
     pub node: &'a Node,
     pub specified_values: PropertyMap,
+    pub computed: ComputedStyle,
     pub children: Vec<StyledNode<'a>>,
 }
 
-#[derive(Debug, PartialEq)]
+// the properties layout and painting use, with shorthands, inheritance and
+// initial values already applied. em is resolved to px, other lengths stay
+// values since percentages and `auto` are only known against the containing
+// block, and rem and the viewport units at layout
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComputedStyle {
+    pub display: Display,
+    pub float: Float,
+    pub clear: Clear,
+    pub position: Position,
+    pub offsets: Sides<Value>, // top, right, bottom and left, `auto` by default
+    pub z_index: Option<i32>,  // None for `auto`
+    pub visibility: Visibility,
+    pub overflow: Overflow,
+    pub white_space: WhiteSpace,
+    pub text_align: TextAlign,
+    pub direction: Direction,
+    pub letter_spacing: f64, // px added after each character, `normal` is 0
+    pub word_spacing: f64,   // px added to each space
+    pub break_before: PageBreak,
+    pub break_after: PageBreak,
+    pub width: Value,  // a length or `auto`
+    pub height: Value,
+    pub margin: Sides<Value>,
+    pub padding: Sides<Value>,
+    pub border_width: Sides<Value>,
+    pub border_color: Sides<Option<Color>>, // None when the side isn't drawn
+    pub border_style: Sides<BorderStyle>,
+    pub border_radius: [Value; 4], // top left, top right, bottom right and bottom left
+    pub color: Option<Color>,
+    pub background_color: Option<Color>,
+    pub background_image: Option<BackgroundImage>, // None for `none` and gradients
+    pub background_clip: BackgroundClip, // of the first layer, the color is painted in it too
+    pub opacity: f64, // of the box and its descendants as a group, 0 to 1
+    pub transform: Vec<TransformFunction>, // in order, empty for `none`
+    pub text_decoration: TextDecoration, // its own and those of its ancestors
+    pub image_rendering: ImageRendering,
+    pub font: Font, // text nodes get the font of their parent
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sides<T> {
+    pub top: T,
+    pub right: T,
+    pub bottom: T,
+    pub left: T,
+}
+
+impl<T> Sides<T> {
+    fn from_fn<F: Fn(&str) -> T>(f: F) -> Sides<T> {
+        Sides {
+            top: f("top"),
+            right: f("right"),
+            bottom: f("bottom"),
+            left: f("left"),
+        }
+    }
+}
+
+// https://www.w3.org/TR/css-fonts-3/#font-prop
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Font {
+    pub family: Vec<String>, // in order of preference
+    pub size: f64,           // px
+    pub weight: u16,         // 100 to 900, 400 is normal and 700 bold
+    pub style: FontStyle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+impl Default for Font {
+    fn default() -> Font {
+        Font {
+            family: vec!["serif".to_string()],
+            size: 16.0, // medium
+            weight: 400,
+            style: FontStyle::Normal,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Display {
     Inline,
     Block,
+    InlineBlock,
+    ListItem,
+    Table,
+    TableRowGroup,
+    TableRow,
+    TableCell,
     None,
 }
 
-impl<'a> StyledNode<'a> {
-    pub fn display(&self) -> Display {
-        match self.value("display") {
-            Some(Value::Keyword(s)) => match &*s {
-                "block" => Display::Block,
-                "none" => Display::None,
-                _ => Display::Inline,
-            }
+// https://www.w3.org/TR/CSS2/visuren.html#choose-position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Position {
+    Static,
+    Relative,
+}
+
+// https://www.w3.org/TR/CSS2/visuren.html#float-position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Float {
+    None,
+    Left,
+    Right,
+}
+
+// https://www.w3.org/TR/CSS2/visuren.html#flow-control
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clear {
+    None,
+    Left,
+    Right,
+    Both,
+}
+
+// a hidden box still takes its space, only painting is skipped
+// https://www.w3.org/TR/CSS2/visufx.html#visibility
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Visibility {
+    Visible,
+    Hidden, // `collapse` too, rows and columns aren't collapsed yet
+}
+
+// what happens to content that doesn't fit the padding box, scroll and
+// auto clip like hidden since there are no scrollbars
+// https://www.w3.org/TR/css-overflow-3/#overflow-properties
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+    Visible,
+    Hidden,
+    Scroll,
+    Auto,
+}
+
+// pre keeps the spaces and newlines of the text and only breaks at newlines,
+// nowrap collapses them like normal but never breaks. whitespace outside
+// pre and textarea is already collapsed by the parser
+// https://www.w3.org/TR/CSS2/text.html#white-space-prop
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    Nowrap,
+}
+
+// how the content of a line shares out the room left on it
+// https://www.w3.org/TR/CSS2/text.html#alignment-prop
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Right,
+    Center,
+    Justify, // the last line is aligned left
+}
+
+// the inline base direction, it also decides which side start and end are
+// https://www.w3.org/TR/css-writing-modes-3/#direction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+// whether a page may or must end before or after a box
+// https://www.w3.org/TR/CSS2/page.html#page-break-props
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PageBreak {
+    Auto,
+    Always,
+    Avoid,
+}
+
+// lines drawn through the text in its color. they aren't inherited but
+// propagate to the text of all descendants, floats and inline-blocks included
+// https://www.w3.org/TR/css-text-decor-3/#line-decoration
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TextDecoration {
+    pub underline: bool,
+    pub overline: bool,
+    pub line_through: bool,
+}
+
+// how images are scaled
+// https://www.w3.org/TR/css-images-3/#the-image-rendering
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ImageRendering {
+    Smooth,    // bilinear, for `auto` and `smooth` too
+    Pixelated, // nearest neighbour, for `crisp-edges` too
+}
+
+// the first layer of the background, the others aren't painted
+// https://www.w3.org/TR/css-backgrounds-3/#backgrounds
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackgroundImage {
+    pub url: String, // as written
+    pub repeat_x: bool,
+    pub repeat_y: bool,
+    pub position: (Value, Value), // lengths, or percentages of the room the image leaves
+    pub size: BackgroundSize,
+}
+
+// https://www.w3.org/TR/css-backgrounds-3/#the-background-size
+#[derive(Clone, Debug, PartialEq)]
+pub enum BackgroundSize {
+    Cover,
+    Contain,
+    Explicit(Value, Value), // width and height, each a length, a percentage or `auto`
+}
+
+// the box the background is painted in
+// https://www.w3.org/TR/css-backgrounds-3/#the-background-clip
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BackgroundClip {
+    BorderBox,
+    PaddingBox,
+    ContentBox,
+}
+
+// how a drawn border side is stroked, none and hidden leave its color None instead
+// https://www.w3.org/TR/css-backgrounds-3/#border-style
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BorderStyle {
+    Solid, // double, groove, ridge, inset and outset too
+    Dashed,
+    Dotted,
+}
+
+// https://www.w3.org/TR/css-transforms-1/#transform-functions
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransformFunction {
+    Translate(Value, Value), // lengths, or percentages of the border box
+    Scale(f64, f64),
+}
+
+impl ComputedStyle {
+    fn new(values: &PropertyMap, font: Font, mut lengths: LengthContext, parent_decoration: TextDecoration) -> ComputedStyle {
+        let auto = Value::Keyword("auto".to_string());
+        let zero = Value::Length(0.0, css::Unit::Px);
+        lengths.font_size = font.size;
+        // rem, like vw and vh, is left for layout
+        let length = |name: String, initial: &Value| match values.get(&name) {
+            Some(value @ &Value::Length(_, css::Unit::Em)) => value.resolve(&lengths),
+            Some(value) => value.clone(),
+            None => initial.clone(),
+        };
+        let color = |name: &str| match values.get(name) {
+            Some(&Value::Color(color)) => Some(color),
+            _ => None,
+        };
+        let text_color = color("color");
+        let keyword = |name: &str| match values.get(name) {
+            Some(&Value::Keyword(ref s)) => s.to_string(),
+            _ => String::new(),
+        };
+        let float = match &*keyword("float") {
+            "left" => Float::Left,
+            "right" => Float::Right,
+            _ => Float::None,
+        };
+        // break-before and break-after replace the page-break- ones
+        // https://www.w3.org/TR/css-break-3/#page-break-properties
+        let page_break = |side: &str| match (&*keyword(&format!("break-{}", side)), &*keyword(&format!("page-break-{}", side))) {
+            ("page", _) | ("left", _) | ("right", _) | ("recto", _) | ("verso", _) => PageBreak::Always,
+            ("avoid", _) | ("avoid-page", _) => PageBreak::Avoid,
+            (_, "always") | (_, "left") | (_, "right") => PageBreak::Always,
+            (_, "avoid") => PageBreak::Avoid,
+            _ => PageBreak::Auto,
+        };
+        // https://www.w3.org/TR/CSS2/text.html#spacing-props
+        let spacing = |name: &str| match values.get(name) {
+            Some(value @ &Value::Length(..)) => value.to_px_in(&lengths),
+            _ => 0.0,
+        };
+        // the shorthand sets the line with the color and style, which are left out
+        let decoration_lines: Vec<String> = ["text-decoration", "text-decoration-line"].iter()
+            .flat_map(|name| match values.get(*name) {
+                Some(&Value::List(ref values)) => values.clone(),
+                Some(value) => vec![value.clone()],
+                None => Vec::new(),
+            })
+            .filter_map(|value| match value {
+                Value::Keyword(keyword) => Some(keyword.to_ascii_lowercase()),
+                _ => None,
+            })
+            .collect();
+        let decorated = |line: &str| decoration_lines.iter().any(|keyword| keyword == line);
+        let direction = match &*keyword("direction") {
+            "rtl" => Direction::Rtl,
+            _ => Direction::Ltr,
+        };
+        let display = match &*keyword("display") {
+            "block" => Display::Block,
+            "inline-block" => Display::InlineBlock,
+            "list-item" => Display::ListItem,
+            "table" => Display::Table,
+            "table-row-group" | "table-header-group" | "table-footer-group" => Display::TableRowGroup,
+            "table-row" => Display::TableRow,
+            "table-cell" => Display::TableCell,
+            "none" => Display::None,
             _ => Display::Inline,
+        };
+
+        ComputedStyle {
+            // a float is always block-level
+            // https://www.w3.org/TR/CSS2/visuren.html#dis-pos-flo
+            display: match display {
+                Display::Inline | Display::InlineBlock if float != Float::None => Display::Block,
+                display => display,
+            },
+            float: float,
+            clear: match &*keyword("clear") {
+                "left" => Clear::Left,
+                "right" => Clear::Right,
+                "both" => Clear::Both,
+                _ => Clear::None,
+            },
+            position: match &*keyword("position") {
+                "relative" => Position::Relative,
+                _ => Position::Static,
+            },
+            offsets: Sides::from_fn(|side| length(side.to_string(), &auto)),
+            z_index: match values.get("z-index") {
+                Some(&Value::Number(n)) => Some(n as i32),
+                _ => None,
+            },
+            visibility: match values.get("visibility") {
+                Some(&Value::Keyword(ref s)) if s == "hidden" || s == "collapse" => Visibility::Hidden,
+                _ => Visibility::Visible,
+            },
+            overflow: match &*keyword("overflow") {
+                "hidden" | "clip" => Overflow::Hidden,
+                "scroll" => Overflow::Scroll,
+                "auto" => Overflow::Auto,
+                _ => Overflow::Visible,
+            },
+            white_space: match &*keyword("white-space") {
+                "pre" => WhiteSpace::Pre,
+                "nowrap" => WhiteSpace::Nowrap,
+                _ => WhiteSpace::Normal,
+            },
+            text_align: match (&*keyword("text-align"), direction) {
+                ("right", _) | ("end", Direction::Ltr) => TextAlign::Right,
+                ("center", _) => TextAlign::Center,
+                ("justify", _) => TextAlign::Justify,
+                ("left", _) | ("end", Direction::Rtl) | (_, Direction::Ltr) => TextAlign::Left,
+                (_, Direction::Rtl) => TextAlign::Right, // start
+            },
+            direction: direction,
+            letter_spacing: spacing("letter-spacing"),
+            word_spacing: spacing("word-spacing"),
+            break_before: page_break("before"),
+            break_after: page_break("after"),
+            width: length("width".to_string(), &auto),
+            height: length("height".to_string(), &auto),
+            margin: Sides::from_fn(|side| length(format!("margin-{}", side), &zero)),
+            padding: Sides::from_fn(|side| length(format!("padding-{}", side), &zero)),
+            border_width: Sides::from_fn(|side| length(format!("border-{}-width", side), &zero)),
+            // a border-style of none or hidden hides the side, currentcolor is the text color
+            border_color: Sides::from_fn(|side| {
+                match values.get(&format!("border-{}-style", side)) {
+                    Some(&Value::Keyword(ref style)) if style == "none" || style == "hidden" => return None,
+                    _ => {}
+                }
+                match values.get(&format!("border-{}-color", side)) {
+                    Some(&Value::Color(color)) => Some(color),
+                    Some(&Value::Keyword(ref keyword)) if keyword.eq_ignore_ascii_case("currentcolor") => text_color,
+                    _ => None,
+                }
+            }),
+            border_style: Sides::from_fn(|side| match &*keyword(&format!("border-{}-style", side)) {
+                "dashed" => BorderStyle::Dashed,
+                "dotted" => BorderStyle::Dotted,
+                _ => BorderStyle::Solid,
+            }),
+            // the horizontal and vertical radii are taken to be the same
+            // https://www.w3.org/TR/css-backgrounds-3/#border-radius
+            border_radius: [
+                length("border-top-left-radius".to_string(), &zero),
+                length("border-top-right-radius".to_string(), &zero),
+                length("border-bottom-right-radius".to_string(), &zero),
+                length("border-bottom-left-radius".to_string(), &zero),
+            ],
+            color: text_color,
+            background_color: color("background-color"),
+            background_image: background_image(values, &lengths),
+            background_clip: match first_layer(values.get("background-clip")).first() {
+                Some(&Value::Keyword(ref keyword)) if keyword.eq_ignore_ascii_case("padding-box") => BackgroundClip::PaddingBox,
+                Some(&Value::Keyword(ref keyword)) if keyword.eq_ignore_ascii_case("content-box") => BackgroundClip::ContentBox,
+                _ => BackgroundClip::BorderBox,
+            },
+            // https://www.w3.org/TR/css-color-3/#transparency
+            text_decoration: TextDecoration {
+                underline: parent_decoration.underline || decorated("underline"),
+                overline: parent_decoration.overline || decorated("overline"),
+                line_through: parent_decoration.line_through || decorated("line-through"),
+            },
+            image_rendering: match &*keyword("image-rendering") {
+                "pixelated" | "crisp-edges" => ImageRendering::Pixelated,
+                _ => ImageRendering::Smooth,
+            },
+            opacity: match values.get("opacity") {
+                Some(&Value::Number(n)) => n.clamp(0.0, 1.0),
+                Some(&Value::Length(percent, css::Unit::Percent)) => (percent / 100.0).clamp(0.0, 1.0),
+                _ => 1.0,
+            },
+            transform: transform(values, &lengths),
+            font: font,
         }
     }
+}
 
-    pub fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
-        self.value(name).unwrap_or_else(||
-            self.value(fallback_name).unwrap_or_else(||
-                default.clone()
-            )
-        )
+// the values up to the first comma
+fn first_layer(value: Option<&Value>) -> Vec<Value> {
+    match value {
+        Some(&Value::List(ref values)) => values.iter().take_while(|value| **value != Value::Comma).cloned().collect(),
+        Some(value) => vec![value.clone()],
+        None => Vec::new(),
+    }
+}
+
+fn background_image(values: &PropertyMap, lengths: &LengthContext) -> Option<BackgroundImage> {
+    let url = match first_layer(values.get("background-image")).first() {
+        Some(&Value::Url(ref url)) => url.clone(),
+        _ => return None,
+    };
+    let keywords = |name: &str| -> Vec<String> {
+        first_layer(values.get(name)).iter().map(|value| match *value {
+            Value::Keyword(ref keyword) => keyword.to_ascii_lowercase(),
+            _ => String::new(),
+        }).collect()
+    };
+    let length = |value: Value| match value {
+        Value::Length(_, css::Unit::Em) => value.resolve(lengths),
+        value => value,
+    };
+    let percent = |p: f64| Value::Length(p, css::Unit::Percent);
+
+    // space and round repeat too
+    // https://www.w3.org/TR/css-backgrounds-3/#background-repeat
+    let repeat = keywords("background-repeat");
+    let (repeat_x, repeat_y) = match repeat.iter().map(|s| &**s).collect::<Vec<_>>()[..] {
+        ["repeat-x"] => (true, false),
+        ["repeat-y"] => (false, true),
+        ["no-repeat"] => (false, false),
+        [x, y] => (x != "no-repeat", y != "no-repeat"),
+        _ => (true, true),
+    };
+
+    // keywords are percentages, a single one is centered on the other axis and
+    // a vertical one first is swapped. the three and four value forms are left out
+    // https://www.w3.org/TR/css-backgrounds-3/#background-position
+    let position = first_layer(values.get("background-position"));
+    let component = |value: &Value| match *value {
+        Value::Keyword(ref keyword) => match &*keyword.to_ascii_lowercase() {
+            "left" => (Some(false), percent(0.0)),
+            "right" => (Some(false), percent(100.0)),
+            "top" => (Some(true), percent(0.0)),
+            "bottom" => (Some(true), percent(100.0)),
+            _ => (None, percent(50.0)),
+        },
+        ref value => (None, length(value.clone())),
+    };
+    let position = match position.iter().map(component).collect::<Vec<_>>()[..] {
+        [(Some(true), ref y)] => (percent(50.0), y.clone()),
+        [(_, ref x)] => (x.clone(), percent(50.0)),
+        [(Some(true), ref y), (_, ref x)] | [(_, ref y), (Some(false), ref x)] => (x.clone(), y.clone()),
+        [(_, ref x), (_, ref y)] => (x.clone(), y.clone()),
+        _ => (percent(0.0), percent(0.0)),
+    };
+
+    let auto = Value::Keyword("auto".to_string());
+    let size = match &*keywords("background-size").join(" ") {
+        "cover" => BackgroundSize::Cover,
+        "contain" => BackgroundSize::Contain,
+        _ => match first_layer(values.get("background-size"))[..] {
+            [ref width] => BackgroundSize::Explicit(length(width.clone()), auto),
+            [ref width, ref height] => BackgroundSize::Explicit(length(width.clone()), length(height.clone())),
+            _ => BackgroundSize::Explicit(auto.clone(), auto),
+        },
+    };
+
+    Some(BackgroundImage {
+        url: url,
+        repeat_x: repeat_x,
+        repeat_y: repeat_y,
+        position: position,
+        size: size,
+    })
+}
+
+// rotate, skew and matrix aren't supported and are left out, the rest of the
+// list still applies
+fn transform(values: &PropertyMap, lengths: &LengthContext) -> Vec<TransformFunction> {
+    let functions = match values.get("transform") {
+        Some(&Value::List(ref values)) => values.clone(),
+        Some(value) => vec![value.clone()],
+        None => Vec::new(),
+    };
+    let length = |value: &Value| match *value {
+        Value::Length(_, css::Unit::Em) => value.resolve(lengths),
+        ref value => value.clone(),
+    };
+    let zero = Value::Length(0.0, css::Unit::Px);
+    functions.iter().filter_map(|function| {
+        let (name, args) = match *function {
+            Value::Function(ref name, ref args) => (name, args),
+            _ => return None,
+        };
+        let args: Vec<&Value> = args.iter().filter(|arg| **arg != Value::Comma).collect();
+        // a percentage scales like the number
+        let factor = |value: &Value| match *value {
+            Value::Number(n) => Some(n),
+            Value::Length(percent, css::Unit::Percent) => Some(percent / 100.0),
+            _ => None,
+        };
+        Some(match (&**name, &args[..]) {
+            ("translate", [x]) => TransformFunction::Translate(length(x), zero.clone()),
+            ("translate", [x, y]) => TransformFunction::Translate(length(x), length(y)),
+            ("translatex", [x]) => TransformFunction::Translate(length(x), zero.clone()),
+            ("translatey", [y]) => TransformFunction::Translate(zero.clone(), length(y)),
+            ("scale", [s]) => TransformFunction::Scale(factor(s)?, factor(s)?),
+            ("scale", [x, y]) => TransformFunction::Scale(factor(x)?, factor(y)?),
+            ("scalex", [x]) => TransformFunction::Scale(factor(x)?, 1.0),
+            ("scaley", [y]) => TransformFunction::Scale(1.0, factor(y)?),
+            _ => return None,
+        })
+    }).collect()
+}
+
+impl<'a> StyledNode<'a> {
+    pub fn computed(&self) -> &ComputedStyle {
+        &self.computed
+    }
+
+    pub fn display(&self) -> Display {
+        self.computed.display
     }
 
     pub fn has_text_node(&self) -> bool {
@@ -56,54 +581,593 @@ impl<'a> StyledNode<'a> {
     }
 }
 
+// https://html.spec.whatwg.org/multipage/rendering.html
+const USER_AGENT_CSS: &str = "
+    html, body, div, p, address, blockquote, pre, form, hr, ul, ol, dl, dt, dd,
+    h1, h2, h3, h4, h5, h6, header, footer, main, nav, section, article, aside,
+    figure, figcaption, caption, center { display: block; }
+    head, script, style, title, meta, link, base, template, noscript { display: none; }
+    body { margin: 8px; }
+    p, blockquote, ul, ol, dl, figure { margin: 1em 0; }
+    li { display: list-item; }
+    table { display: table; }
+    thead { display: table-header-group; }
+    tbody { display: table-row-group; }
+    tfoot { display: table-footer-group; }
+    tr { display: table-row; }
+    td, th { display: table-cell; }
+    ul, ol { padding-left: 40px; counter-reset: list-item; }
+    ul { list-style-type: disc; }
+    ol { list-style-type: decimal; }
+    ul ul, ol ul { list-style-type: circle; }
+    h1 { font-size: 2em; margin: 0.67em 0; font-weight: bold; }
+    h2 { font-size: 1.5em; margin: 0.83em 0; font-weight: bold; }
+    h3 { font-size: 1.17em; margin: 1em 0; font-weight: bold; }
+    h4 { margin: 1.33em 0; font-weight: bold; }
+    h5 { font-size: 0.83em; margin: 1.67em 0; font-weight: bold; }
+    h6 { font-size: 0.67em; margin: 2.33em 0; font-weight: bold; }
+    a { text-decoration: underline; }
+    b, strong, th { font-weight: bold; }
+    i, em, cite, var, address { font-style: italic; }
+    pre, code, kbd, samp, tt { font-family: monospace; }
+    pre, textarea { white-space: pre; }
+";
+
+// the default styles every document starts with
+pub fn user_agent_stylesheet() -> Stylesheet {
+    let mut stylesheet = css::parse(USER_AGENT_CSS.to_string());
+    stylesheet.set_origin(Origin::UserAgent);
+    stylesheet
+}
+
+// `stylesheet` comes before the <style> elements of the document in the cascade
 pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+    style_tree_with_media(root, stylesheet, &MediaContext::default())
+}
+
+// rules inside @media blocks only apply when `media` matches
+pub fn style_tree_with_media<'a>(
+    root: &'a Node,
+    stylesheet: &'a Stylesheet,
+    media: &MediaContext,
+) -> StyledNode<'a> {
+    style_document(root, stylesheet, media, false)
+}
+
+// same result as `style_tree_with_media`, sibling subtrees are styled concurrently
+#[cfg(feature = "parallel")]
+pub fn par_style_tree_with_media<'a>(
+    root: &'a Node,
+    stylesheet: &'a Stylesheet,
+    media: &MediaContext,
+) -> StyledNode<'a> {
+    style_document(root, stylesheet, media, true)
+}
+
+fn style_document<'a>(
+    root: &'a Node,
+    stylesheet: &'a Stylesheet,
+    media: &MediaContext,
+    parallel: bool,
+) -> StyledNode<'a> {
+    let mut cascade = user_agent_stylesheet();
+    cascade.append(stylesheet.clone());
+    cascade.append(embedded_stylesheet(root));
+    cascade.rules.retain(|rule| rule.applies_to(media));
+
+    let initial = Font::default();
+    let lengths = LengthContext {
+        font_size: initial.size,
+        root_font_size: initial.size,
+        percent_base: initial.size,
+        percent_height_base: None,
+        viewport_width: media.viewport_width,
+        viewport_height: media.viewport_height,
+    };
+    let mut context = MatchingContext {
+        ancestors: Vec::new(),
+        filter: AncestorFilter::new(),
+        parallel: parallel,
+    };
+    style_node(root, &cascade, &mut context, &PropertyMap::new(), &initial, TextDecoration::default(), lengths)
+}
+
+// the elements above the one being styled, kept up to date during the walk
+#[derive(Clone)]
+struct MatchingContext<'a> {
+    ancestors: Vec<&'a ElementData>, // outermost first
+    filter: AncestorFilter,
+    #[cfg_attr(not(feature = "parallel"), allow(dead_code))]
+    parallel: bool, // style the children of large nodes on the rayon pool
+}
+
+// A counting bloom filter of the tag names, ids and classes of the ancestors.
+// "not there" answers are certain, so a descendant selector that needs an
+// ancestor nobody has is rejected without walking up the tree. Counters let
+// elements be removed again when the walk leaves them, one that overflows stays set.
+// https://github.com/servo/servo/blob/main/components/selectors/bloom.rs
+#[derive(Clone)]
+pub struct AncestorFilter {
+    counters: Vec<u8>,
+}
+
+const FILTER_BITS: u32 = 12; // 4096 counters, two per name
+
+impl Default for AncestorFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AncestorFilter {
+    pub fn new() -> AncestorFilter {
+        AncestorFilter {
+            counters: vec![0; 1 << FILTER_BITS],
+        }
+    }
+
+    pub fn push(&mut self, elem: &ElementData) {
+        for hash in element_hashes(elem) {
+            for i in filter_indices(hash).iter() {
+                self.counters[*i] = self.counters[*i].saturating_add(1);
+            }
+        }
+    }
+
+    pub fn pop(&mut self, elem: &ElementData) {
+        for hash in element_hashes(elem) {
+            for i in filter_indices(hash).iter() {
+                if self.counters[*i] != u8::MAX {
+                    self.counters[*i] -= 1;
+                }
+            }
+        }
+    }
+
+    // false if no ancestor can match `selector`
+    pub fn might_match(&self, selector: &SimpleSelector) -> bool {
+        selector_hashes(selector).into_iter()
+            .all(|hash| filter_indices(hash).iter().all(|i| self.counters[*i] > 0))
+    }
+}
+
+fn filter_indices(hash: u64) -> [usize; 2] {
+    let mask = (1 << FILTER_BITS) - 1;
+    [(hash & mask) as usize, ((hash >> FILTER_BITS) & mask) as usize]
+}
+
+// the kind keeps `#a` and `.a` apart, tag names match case-insensitively
+fn name_hash(kind: char, name: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    kind.hash(&mut hasher);
+    name.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn element_hashes(elem: &ElementData) -> Vec<u64> {
+    let mut hashes = vec![name_hash('t', &elem.tag_name.to_ascii_lowercase())];
+    hashes.extend(elem.id().map(|id| name_hash('#', id)));
+    hashes.extend(elem.classes().into_iter().filter(|class| !class.is_empty()).map(|class| name_hash('.', class)));
+    hashes
+}
+
+fn selector_hashes(selector: &SimpleSelector) -> Vec<u64> {
+    let mut hashes: Vec<u64> = selector.tag_name.iter().map(|name| name_hash('t', name)).collect();
+    hashes.extend(selector.id.iter().map(|id| name_hash('#', id)));
+    hashes.extend(selector.class.iter().map(|class| name_hash('.', class)));
+    hashes
+}
+
+// https://www.w3.org/TR/CSS2/propidx.html, the "Inherited?" column
+const INHERITED_PROPERTIES: &[&str] = &[
+    "color", "cursor", "direction", "font-family", "font-size", "font-style", "font-variant",
+    "font-weight", "image-rendering", "letter-spacing", "line-height", "list-style-image", "list-style-position",
+    "list-style-type", "quotes", "text-align", "text-indent", "text-transform", "visibility",
+    "white-space", "word-spacing",
+];
+
+pub fn is_inherited(name: &str) -> bool {
+    INHERITED_PROPERTIES.contains(&name)
+}
+
+// what a child inherits is the computed value, the font is already computed
+fn inherited_value(name: &str, parent_values: &PropertyMap, parent_font: &Font) -> Option<Value> {
+    match name {
+        "font-size" => Some(Value::Length(parent_font.size, css::Unit::Px)),
+        "font-weight" => Some(Value::Number(parent_font.weight as f64)),
+        _ => parent_values.get(name).cloned(),
+    }
+}
+
+// the computed style of the first element in document order that matches
+// `selectors`, like getComputedStyle(document.querySelector(selectors)).
+// an invalid selector is an error, like querySelector's SyntaxError
+// https://drafts.csswg.org/cssom/#dom-window-getcomputedstyle
+pub fn computed_style_for<'a>(root: &'a StyledNode<'a>, selectors: &str) -> Result<Option<&'a ComputedStyle>, css::CssError> {
+    let selectors = css::parse_selectors(selectors)?;
+    let mut context = MatchingContext {
+        ancestors: Vec::new(),
+        filter: AncestorFilter::new(),
+        parallel: false,
+    };
+    Ok(find_styled_node(root, &selectors, &mut context).map(StyledNode::computed))
+}
+
+fn find_styled_node<'a>(
+    node: &'a StyledNode<'a>,
+    selectors: &[Selector],
+    context: &mut MatchingContext<'a>,
+) -> Option<&'a StyledNode<'a>> {
+    let elem = match node.node.data {
+        NodeType::Element(ref elem) => elem,
+        NodeType::Text(_) => return None,
+    };
+    if selectors.iter().any(|selector| matches(elem, selector, context)) {
+        return Some(node);
+    }
+    context.ancestors.push(elem);
+    context.filter.push(elem);
+    let found = node.children.iter().find_map(|child| find_styled_node(child, selectors, context));
+    context.ancestors.pop();
+    context.filter.pop(elem);
+    found
+}
+
+// the child indices from `root` down to the first element in document order
+// that matches `selectors`, like document.querySelector
+// https://dom.spec.whatwg.org/#dom-parentnode-queryselector
+pub fn query_selector(root: &Node, selectors: &str) -> Result<Option<Vec<usize>>, css::CssError> {
+    let selectors = css::parse_selectors(selectors)?;
+    let mut context = MatchingContext {
+        ancestors: Vec::new(),
+        filter: AncestorFilter::new(),
+        parallel: false,
+    };
+    let mut path = Vec::new();
+    Ok(if find_node(root, &selectors, &mut context, &mut path) { Some(path) } else { None })
+}
+
+fn find_node<'a>(
+    node: &'a Node,
+    selectors: &[Selector],
+    context: &mut MatchingContext<'a>,
+    path: &mut Vec<usize>,
+) -> bool {
+    let elem = match node.data {
+        NodeType::Element(ref elem) => elem,
+        NodeType::Text(_) => return false,
+    };
+    if selectors.iter().any(|selector| matches(elem, selector, context)) {
+        return true;
+    }
+    context.ancestors.push(elem);
+    context.filter.push(elem);
+    let mut found = false;
+    for (i, child) in node.children.iter().enumerate() {
+        path.push(i);
+        if find_node(child, selectors, context, path) {
+            found = true;
+            break;
+        }
+        path.pop();
+    }
+    context.ancestors.pop();
+    context.filter.pop(elem);
+    found
+}
+
+// `lengths` carries the root font size and the viewport for resolving font sizes
+fn style_node<'a>(
+    node: &'a Node,
+    stylesheet: &Stylesheet,
+    context: &mut MatchingContext<'a>,
+    parent_values: &PropertyMap,
+    parent_font: &Font,
+    parent_decoration: TextDecoration,
+    mut lengths: LengthContext,
+) -> StyledNode<'a> {
+    let mut specified_values = match node.data {
+        NodeType::Element(ref elem) => specified_values(elem, stylesheet, context),
+        NodeType::Text(_) => PropertyMap::new(),
+    };
+    // `inherit` works for any property
+    // https://www.w3.org/TR/css-cascade-4/#inheriting
+    let explicit: Vec<String> = specified_values.iter()
+        .filter(|&(_, value)| *value == Value::Keyword("inherit".to_string()))
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in explicit {
+        match inherited_value(&name, parent_values, parent_font) {
+            Some(value) => specified_values.insert(name, value),
+            None => specified_values.remove(&name),
+        };
+    }
+    let font = compute_font(&specified_values, parent_font, lengths);
+    for &name in INHERITED_PROPERTIES {
+        if !specified_values.contains_key(name) {
+            if let Some(value) = inherited_value(name, parent_values, parent_font) {
+                specified_values.insert(name.to_string(), value);
+            }
+        }
+    }
+    // rem refers to the font size of the root element
+    if context.ancestors.is_empty() {
+        lengths.root_font_size = font.size;
+    }
+
+    if let NodeType::Element(ref elem) = node.data {
+        context.ancestors.push(elem);
+        context.filter.push(elem);
+    }
+    let computed = ComputedStyle::new(&specified_values, font, lengths, parent_decoration);
+    let children = style_children(node, stylesheet, context, &specified_values, &computed.font, computed.text_decoration, lengths);
+    if let NodeType::Element(ref elem) = node.data {
+        context.ancestors.pop();
+        context.filter.pop(elem);
+    }
     StyledNode {
-        node: root,
-        specified_values: match root.data {
-            NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-            NodeType::Text(_) => PropertyMap::new(),
+        node: node,
+        computed: computed,
+        specified_values: specified_values,
+        children: children,
+    }
+}
+
+// fewer children are not worth handing to other threads
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_CHILDREN: usize = 8;
+
+fn style_children<'a>(
+    node: &'a Node,
+    stylesheet: &Stylesheet,
+    context: &mut MatchingContext<'a>,
+    parent_values: &PropertyMap,
+    parent_font: &Font,
+    parent_decoration: TextDecoration,
+    lengths: LengthContext,
+) -> Vec<StyledNode<'a>> {
+    // every rayon job gets its own copy of the ancestors, style_node leaves it as it found it
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+
+        if context.parallel && node.children.len() >= PARALLEL_MIN_CHILDREN {
+            let context = &*context;
+            return node.children.par_iter()
+                .map_init(|| context.clone(), |context, child| {
+                    style_node(child, stylesheet, context, parent_values, parent_font, parent_decoration, lengths)
+                })
+                .collect();
+        }
+    }
+    node.children.iter()
+        .map(|child| style_node(child, stylesheet, context, parent_values, parent_font, parent_decoration, lengths))
+        .collect()
+}
+
+// font properties inherit, relative values refer to the parent font
+fn compute_font(values: &PropertyMap, parent: &Font, mut lengths: LengthContext) -> Font {
+    let mut font = parent.clone();
+    lengths.font_size = parent.size;
+    lengths.percent_base = parent.size;
+
+    match values.get("font-size") {
+        Some(size @ &Value::Length(..)) => font.size = size.to_px_in(&lengths),
+        // https://www.w3.org/TR/css-fonts-3/#absolute-size-value
+        Some(&Value::Keyword(ref keyword)) => font.size = match &*keyword.to_ascii_lowercase() {
+            "xx-small" => 9.0,
+            "x-small" => 10.0,
+            "small" => 13.0,
+            "medium" => 16.0,
+            "large" => 18.0,
+            "x-large" => 24.0,
+            "xx-large" => 32.0,
+            "larger" => parent.size * 1.2,
+            "smaller" => parent.size / 1.2,
+            _ => parent.size,
+        },
+        _ => {}
+    }
+
+    // https://www.w3.org/TR/css-fonts-4/#relative-weights
+    match values.get("font-weight") {
+        Some(&Value::Number(weight)) if (1.0..=1000.0).contains(&weight) => font.weight = weight as u16,
+        Some(&Value::Keyword(ref keyword)) => font.weight = match &*keyword.to_ascii_lowercase() {
+            "normal" => 400,
+            "bold" => 700,
+            "bolder" if parent.weight < 350 => 400,
+            "bolder" if parent.weight < 550 => 700,
+            "bolder" => 900,
+            "lighter" if parent.weight < 550 => 100,
+            "lighter" if parent.weight < 750 => 400,
+            "lighter" => 700,
+            _ => parent.weight,
         },
-        children: root.children
-            .iter().map(|child| style_tree(child, stylesheet)).collect(),
+        _ => {}
     }
+
+    if let Some(&Value::Keyword(ref keyword)) = values.get("font-style") {
+        match &*keyword.to_ascii_lowercase() {
+            "normal" => font.style = FontStyle::Normal,
+            "italic" => font.style = FontStyle::Italic,
+            "oblique" => font.style = FontStyle::Oblique,
+            _ => {}
+        }
+    }
+
+    if let Some(family) = values.get("font-family") {
+        font.family = font_families(family);
+    }
+    font
+}
+
+// `"Noto Sans", Times New Roman, serif` becomes ["Noto Sans", "Times New Roman", "serif"]
+fn font_families(value: &Value) -> Vec<String> {
+    let components = match *value {
+        Value::List(ref values) => values.clone(),
+        ref single => vec![single.clone()],
+    };
+    let mut families = Vec::new();
+    for name in components.split(|component| *component == Value::Comma) {
+        let words: Vec<&str> = name.iter().filter_map(|word| match *word {
+            Value::Keyword(ref s) | Value::String(ref s) => Some(s.as_str()),
+            _ => None,
+        }).collect();
+        if !words.is_empty() {
+            families.push(words.join(" "));
+        }
+    }
+    families
+}
+
+// the rules of every <style> element in document order
+pub fn embedded_stylesheet(root: &Node) -> Stylesheet {
+    let mut stylesheet = Stylesheet::new();
+    collect_style_elements(root, &mut stylesheet);
+    stylesheet
 }
 
-fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
+fn collect_style_elements(node: &Node, stylesheet: &mut Stylesheet) {
+    match node.data {
+        NodeType::Element(ref elem) if elem.tag_name == "style" => {
+            stylesheet.append(css::parse(node.text_content()));
+        }
+        _ => for child in &node.children {
+            collect_style_elements(child, stylesheet);
+        },
+    }
+}
+
+// load every <link rel=stylesheet> in document order, `document_url` is the path
+// of the html file and hrefs are relative to its directory (or to <base href>)
+pub fn linked_stylesheets(root: &Node, document_url: &str) -> Stylesheet {
+    let mut hrefs = Vec::new();
+    collect_stylesheet_links(root, &mut hrefs);
+
+    let mut stylesheet = Stylesheet::new();
+    for href in hrefs {
+        let path = root.resolve_url(document_url, href);
+        match load_stylesheet(&path) {
+            Ok(source) => stylesheet.append(css::parse(source)),
+            Err(err) => eprintln!("cannot load stylesheet {}: {}", path, err),
+        }
+    }
+    stylesheet
+}
+
+fn collect_stylesheet_links<'a>(node: &'a Node, hrefs: &mut Vec<&'a str>) {
+    if let NodeType::Element(ref elem) = node.data {
+        if elem.tag_name == "link" && is_stylesheet_link(elem) {
+            if let Some(href) = elem.attrs.get("href") {
+                hrefs.push(href);
+            }
+        }
+    }
+    for child in &node.children {
+        collect_stylesheet_links(child, hrefs);
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/links.html#link-type-stylesheet
+fn is_stylesheet_link(elem: &ElementData) -> bool {
+    let rel = match elem.attrs.get("rel") {
+        Some(rel) => rel,
+        None => return false,
+    };
+    let has = |keyword: &str| rel.split_ascii_whitespace().any(|k| k.eq_ignore_ascii_case(keyword));
+    // alternate stylesheets are only applied when the user picks them
+    has("stylesheet") && !has("alternate")
+}
+
+// only local files for now
+fn load_stylesheet(path: &str) -> io::Result<String> {
+    let path = path.trim_start_matches("file://");
+    if url::is_absolute(path) {
+        return Err(io::Error::other("only local files are supported"));
+    }
+    fs::read_to_string(path)
+}
+
+fn specified_values(elem: &ElementData, stylesheet: &Stylesheet, context: &MatchingContext) -> PropertyMap {
     let mut values = HashMap::new();
-    let mut rules = matching_rules(elem, stylesheet);
-    rules.sort_by(|&(x, _), &(y, _)| x.cmp(&y));
+    let mut rules = matching_rules(elem, stylesheet, context);
+    // origin first, then specificity, then source order
+    rules.sort_by_key(|&(specificity, rule)| (rule.origin, specificity, rule.index));
 
-    for (_, rule) in rules { // rules: Vec<(Specificity, &'a Rule)>
+    // presentational hints go in front of the author rules
+    // https://html.spec.whatwg.org/multipage/rendering.html#presentational-hints
+    let (author, others): (Vec<_>, Vec<_>) = rules.into_iter().partition(|&(_, rule)| rule.origin == Origin::Author);
+    for (_, rule) in others {
+        for declaration in &rule.declarations {
+            values.insert(declaration.name.clone(), declaration.value.clone());
+        }
+    }
+    for declaration in presentational_hints(elem) {
+        values.insert(declaration.name, declaration.value);
+    }
+    for (_, rule) in author { // rules: Vec<(Specificity, &'a Rule)>
         for declaration in &rule.declarations {
             values.insert(declaration.name.clone(), declaration.value.clone());
         }
     }
+
+    // the style attribute wins over any stylesheet
+    if let Some(style) = elem.attrs.get("style") {
+        for declaration in css::parse_declarations(style.clone()) {
+            values.insert(declaration.name, declaration.value);
+        }
+    }
     values
 }
 
+// the list numbering attributes, as counters
+// https://html.spec.whatwg.org/multipage/rendering.html#lists
+fn presentational_hints(elem: &ElementData) -> Vec<Declaration> {
+    let integer = |name: &str| elem.attrs.get(name).and_then(|value| value.trim().parse::<i32>().ok());
+    let counter = |name: &str, value: i32| Declaration {
+        name: name.to_string(),
+        value: Value::List(vec![Value::Keyword("list-item".to_string()), Value::Number(value as f64)]),
+    };
+    match &*elem.tag_name {
+        "ol" => integer("start").map(|start| counter("counter-reset", start - 1)).into_iter().collect(),
+        "li" => integer("value").map(|value| counter("counter-set", value)).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
 type MatchedRule<'a> = (Specificity, &'a Rule);
 
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
+fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet, context: &MatchingContext) -> Vec<MatchedRule<'a>> {
     stylesheet.rules
-        .iter().filter_map(|rule| match_rule(elem, rule)).collect()
+        .iter().filter_map(|rule| match_rule(elem, rule, context)).collect()
 }
 
-fn match_rule<'a>(elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
+fn match_rule<'a>(elem: &ElementData, rule: &'a Rule, context: &MatchingContext) -> Option<MatchedRule<'a>> {
     rule.selectors
-        .iter().find(|selector| matches(elem, selector))
+        .iter().find(|selector| matches(elem, selector, context))
         .map(|selector| (selector.specificity(), rule))
 }
 
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+fn matches(elem: &ElementData, selector: &Selector, context: &MatchingContext) -> bool {
     match *selector {
         Selector::Simple(ref simple_selector) => match_simple_selector(elem, simple_selector),
+        Selector::Descendant(ref ancestors, ref simple_selector) => {
+            if !match_simple_selector(elem, simple_selector)
+                || !ancestors.iter().all(|ancestor| context.filter.might_match(ancestor)) {
+                return false;
+            }
+            // the innermost selector takes the closest ancestor that matches it,
+            // the next one has to match further up
+            let mut elements = context.ancestors.iter().rev();
+            ancestors.iter().rev().all(|ancestor| elements.any(|elem| match_simple_selector(elem, ancestor)))
+        }
     }
 }
 
+// `*` needs no check of its own, the other parts of the selector still have to match
 fn match_simple_selector(elem: &ElementData, simple_selector: &SimpleSelector) -> bool {
-    // call iter() on tag_name: Option<String> to take out &String
-    if simple_selector.tag_name.iter().any(|name| elem.tag_name != *name) {
-    // if simple_selector.tag_name != Some(elem.tag_name.clone()) {
+    // type selectors are lowercased by the parser, interned names compare by pointer
+    if simple_selector.tag_name.iter().any(|name| *name != elem.tag_name) {
         return false;
     }
 
@@ -117,5 +1181,287 @@ fn match_simple_selector(elem: &ElementData, simple_selector: &SimpleSelector) -
         return false;
     }
 
-    true
+    simple_selector.pseudo_classes.iter().all(|pseudo_class| match *pseudo_class {
+        PseudoClass::Hover => elem.state.contains(ElementState::HOVER),
+        PseudoClass::Focus => elem.state.contains(ElementState::FOCUS),
+        PseudoClass::Active => elem.state.contains(ElementState::ACTIVE),
+        PseudoClass::Unsupported(_) => false,
+    })
+}
+
+#[test]
+fn test_embedded_and_inline_styles() {
+    use crate::html;
+
+    let root = html::parse("<head><style>p { width: 10px; } #a { width: 20px; }</style></head>\
+        <body><p id=a style=\"width: 30px\">x</p><p class=b>y</p></body>".to_string());
+    let stylesheet = css::parse(".b { height: 5px; } p { width: 1px; }".to_string());
+    let styled = style_tree(&root, &stylesheet);
+    let body = &styled.children[1];
+    assert_eq!(body.children[0].value("width"), Some(Value::Length(30.0, css::Unit::Px)));
+    // <style> comes after the external sheet
+    assert_eq!(body.children[1].value("width"), Some(Value::Length(10.0, css::Unit::Px)));
+    assert_eq!(body.children[1].value("height"), Some(Value::Length(5.0, css::Unit::Px)));
+}
+
+#[test]
+fn test_linked_stylesheets() {
+    use crate::html;
+
+    let dir = std::env::temp_dir().join(format!("rust_browser_link_{}", std::process::id()));
+    fs::create_dir_all(dir.join("css")).unwrap();
+    fs::write(dir.join("css/a.css"), "p { width: 1px; }").unwrap();
+    fs::write(dir.join("css/b.css"), "p { height: 2px; }").unwrap();
+
+    let root = html::parse("<link rel=stylesheet href=css/a.css><link rel=\"alternate stylesheet\" href=x.css>\
+        <link rel=icon href=y.css><link REL=StyleSheet href=css/b.css><link rel=stylesheet href=missing.css>".to_string());
+    let document_url = dir.join("index.html");
+    let stylesheet = linked_stylesheets(&root, document_url.to_str().unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(stylesheet.rules.len(), 2);
+    assert_eq!(stylesheet.rules[0].declarations[0].name, "width");
+    assert_eq!(stylesheet.rules[1].declarations[0].name, "height");
+}
+
+#[test]
+fn test_computed_font() {
+    use crate::html;
+
+    let root = html::parse("<body><p>a<b>b<i>c</i></b></p></body>".to_string());
+    let stylesheet = css::parse("html { font-size: 20px; } \
+        p { font-size: 1.5em; font-family: \"Noto Sans\", Times New Roman, serif; } \
+        b { font-weight: bolder; font-size: 0.5rem; } i { font-style: italic; font-weight: 300; font-size: larger; }".to_string());
+    let styled = style_tree(&root, &stylesheet);
+    let p = &styled.children[1].children[0];
+    assert_eq!(p.computed.font.size, 30.0);
+    assert_eq!(p.computed.font.family, vec!["Noto Sans", "Times New Roman", "serif"]);
+    assert_eq!(p.children[0].computed.font, p.computed.font); // text inherits
+
+    let b = &p.children[1];
+    assert_eq!((b.computed.font.size, b.computed.font.weight, b.computed.font.style), (10.0, 700, FontStyle::Normal));
+    let i = &b.children[1];
+    assert_eq!((i.computed.font.size, i.computed.font.weight, i.computed.font.style), (12.0, 300, FontStyle::Italic));
+    assert_eq!(i.computed.font.family, p.computed.font.family);
+}
+
+#[test]
+fn test_user_agent_stylesheet() {
+    use crate::html;
+
+    let root = html::parse("<title>t</title><h1>a</h1><p>b <span>c</span></p>".to_string());
+    let stylesheet = css::parse("span { display: block; } * { margin: 0px; }".to_string());
+    let styled = style_tree(&root, &stylesheet);
+    let (head, body) = (&styled.children[0], &styled.children[1]);
+    assert_eq!(styled.display(), Display::Block);
+    assert_eq!(head.display(), Display::None);
+    assert_eq!(body.children[0].display(), Display::Block);
+    assert_eq!(body.children[0].computed.font.size, 32.0);
+    assert_eq!(body.children[0].computed.font.weight, 700);
+    assert_eq!(body.children[1].children[1].display(), Display::Block);
+    // the author `*` is less specific than the user agent `body` but still wins
+    assert_eq!(body.value("margin-top"), Some(Value::Length(0.0, css::Unit::Px)));
+}
+
+#[test]
+fn test_source_order_ties() {
+    use crate::html;
+
+    let root = html::parse("<p class=\"b a\">x</p>".to_string());
+    let mut stylesheet = css::parse(".a { width: 2px; } p { width: 1px; } .a { height: 1px; } .b { height: 2px; }".to_string());
+    stylesheet.append(css::parse("@media screen { .b { color: #000000; } } .a { color: #ffffff; }".to_string()));
+    assert_eq!(stylesheet.rules.iter().map(|rule| rule.index).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4, 5]);
+
+    let styled = style_tree(&root, &stylesheet);
+    let p = &styled.children[1].children[0];
+    assert_eq!(p.value("width"), Some(Value::Length(2.0, css::Unit::Px)));
+    assert_eq!(p.value("height"), Some(Value::Length(2.0, css::Unit::Px)));
+    assert_eq!(p.get_color("color"), Some(Color { r: 255, g: 255, b: 255, a: 255 }));
+}
+
+#[test]
+fn test_inherited_properties() {
+    use crate::html;
+
+    let root = html::parse("<div><p>a<span>b</span></p></div>".to_string());
+    let stylesheet = css::parse("div { color: #ff0000; text-align: center; width: 10px; font-size: 2em; } \
+        span { color: #00ff00; width: inherit; text-align: inherit; }".to_string());
+    let styled = style_tree(&root, &stylesheet);
+    let div = &styled.children[1].children[0];
+    let p = &div.children[0];
+    let (text, span) = (&p.children[0], &p.children[1]);
+    assert_eq!(text.get_color("color"), Some(Color { r: 255, g: 0, b: 0, a: 255 }));
+    assert_eq!(p.value("text-align"), Some(Value::Keyword("center".to_string())));
+    // width isn't inherited unless asked for, and the em font size isn't applied twice
+    assert_eq!(p.value("width"), None);
+    assert_eq!(p.value("font-size"), Some(Value::Length(32.0, css::Unit::Px)));
+    assert_eq!(span.computed.font.size, 32.0);
+    assert_eq!(span.get_color("color"), Some(Color { r: 0, g: 255, b: 0, a: 255 }));
+    assert_eq!(span.children[0].get_color("color"), Some(Color { r: 0, g: 255, b: 0, a: 255 }));
+    assert_eq!(span.value("width"), None);
+    assert_eq!(span.value("text-align"), Some(Value::Keyword("center".to_string())));
+}
+
+#[test]
+fn test_universal_selector() {
+    use crate::html;
+
+    let root = html::parse("<div class=a><p>x</p></div>".to_string());
+    // the later universal rule loses to the type selector, `*.a` only matches the div
+    let stylesheet = css::parse("p { width: 1px; } * { width: 2px; height: 3px; } *.a { height: 4px; }".to_string());
+    let styled = style_tree(&root, &stylesheet);
+    let div = &styled.children[1].children[0];
+    assert_eq!(styled.value("height"), Some(Value::Length(3.0, css::Unit::Px)));
+    assert_eq!(div.value("height"), Some(Value::Length(4.0, css::Unit::Px)));
+    assert_eq!(div.children[0].value("width"), Some(Value::Length(1.0, css::Unit::Px)));
+    assert_eq!(div.children[0].value("height"), Some(Value::Length(3.0, css::Unit::Px)));
+}
+
+#[test]
+fn test_computed_style() {
+    use crate::html;
+
+    let root = html::parse("<div>x</div>".to_string());
+    let stylesheet = css::parse("div { display: block; color: #ff0000; margin: 1px 2px; width: 50%; \
+        border: 3px solid; border-top-color: #0000ff; border-bottom-style: none; background: #00ff00; }".to_string());
+    let styled = style_tree(&root, &stylesheet);
+    let div = &styled.children[1].children[0].computed;
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    assert_eq!(div.display, Display::Block);
+    assert_eq!(div.width, Value::Length(50.0, css::Unit::Percent));
+    assert_eq!(div.height, Value::Keyword("auto".to_string()));
+    assert_eq!((&div.margin.top, &div.margin.left), (&Value::Length(1.0, css::Unit::Px), &Value::Length(2.0, css::Unit::Px)));
+    assert_eq!(div.padding.right, Value::Length(0.0, css::Unit::Px));
+    assert_eq!(div.border_width.bottom, Value::Length(3.0, css::Unit::Px));
+    assert_eq!(div.border_color, Sides {
+        top: Some(Color { r: 0, g: 0, b: 255, a: 255 }),
+        right: Some(red),
+        bottom: None,
+        left: Some(red),
+    });
+    assert_eq!(div.background_color, Some(Color { r: 0, g: 255, b: 0, a: 255 }));
+    // text inherits the color but not the background
+    let text = &styled.children[1].children[0].children[0].computed;
+    assert_eq!((text.color, text.background_color, text.display), (Some(red), None, Display::Inline));
+}
+
+#[test]
+fn test_descendant_matching() {
+    use crate::html;
+
+    let root = html::parse("<div id=main class=x><section><p>a</p></section></div><p>b</p>".to_string());
+    let stylesheet = css::parse("#main p { width: 1px; } div section p { height: 2px; } \
+        section div p { height: 3px; } .y p { color: #000000; }".to_string());
+    let styled = style_tree(&root, &stylesheet);
+    let body = &styled.children[1];
+    let inner = &body.children[0].children[0].children[0];
+    let outer = &body.children[1];
+    assert_eq!(inner.value("width"), Some(Value::Length(1.0, css::Unit::Px)));
+    assert_eq!(inner.value("height"), Some(Value::Length(2.0, css::Unit::Px)));
+    assert_eq!(inner.value("color"), None);
+    assert_eq!(outer.value("width"), None);
+}
+
+#[test]
+fn test_ancestor_filter() {
+    use crate::html;
+
+    let root = html::parse_fragment("<div id=a class=\"b c\"></div>".to_string()).unwrap();
+    let elem = match root[0].data {
+        NodeType::Element(ref elem) => elem,
+        NodeType::Text(_) => unreachable!(),
+    };
+    let selectors = css::parse("div, #a, .c, DIV.b, span, .d, div#z { }".to_string()).rules.remove(0).selectors;
+    let simple = |i: usize| match selectors[i] {
+        Selector::Simple(ref selector) => selector.clone(),
+        Selector::Descendant(..) => unreachable!(),
+    };
+    let mut filter = AncestorFilter::new();
+    assert!(!filter.might_match(&simple(0)));
+    filter.push(elem);
+    filter.push(elem);
+    filter.pop(elem);
+    // sorted by specificity: div#z, DIV.b, #a, .c, .d, div, span
+    let found: Vec<bool> = (0..selectors.len()).map(|i| filter.might_match(&simple(i))).collect();
+    assert_eq!(found, vec![false, true, true, true, false, true, false]);
+    filter.pop(elem);
+    assert!(!filter.might_match(&simple(5)));
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_parallel_style_tree() {
+    use crate::html;
+
+    fn dump(node: &StyledNode, out: &mut Vec<String>) {
+        let mut values: Vec<String> = node.specified_values.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+        values.sort();
+        out.push(format!("{:?} {}", node.computed, values.join("; ")));
+        for child in &node.children {
+            dump(child, out);
+        }
+    }
+
+    let items: String = (0..40).map(|i| format!("<li class=i{}>{}<b>x</b></li>", i % 3, i)).collect();
+    let root = html::parse(format!("<ul id=list>{}</ul><p>{}</p>", items, items));
+    let stylesheet = css::parse("#list li { color: #ff0000; } .i1 b { font-size: 2em; } li { margin: 1px; }".to_string());
+    let media = MediaContext::default();
+    let (mut sequential, mut parallel) = (Vec::new(), Vec::new());
+    dump(&style_tree_with_media(&root, &stylesheet, &media), &mut sequential);
+    dump(&par_style_tree_with_media(&root, &stylesheet, &media), &mut parallel);
+    assert_eq!(sequential, parallel);
+}
+
+#[test]
+fn test_pseudo_class_matching() {
+    use crate::html;
+
+    let mut root = html::parse("<ul><li><a>x</a></li><li><a>y</a></li></ul>".to_string());
+    // :visited isn't supported, it never matches
+    let stylesheet = css::parse("li:hover a { color: #ff0000; } a:focus { color: #00ff00; } a:visited { color: #0000ff; }".to_string());
+    let color = |root: &Node, index: usize| {
+        let styled = style_tree(root, &stylesheet);
+        styled.children[1].children[0].children[index].children[0].computed.color
+    };
+    let unstyled = None;
+    assert_eq!(color(&root, 0), unstyled);
+
+    root.set_state_at(&[1, 0, 0, 0], ElementState::HOVER);
+    assert_eq!(color(&root, 0), Some(Color { r: 255, g: 0, b: 0, a: 255 }));
+    assert_eq!(color(&root, 1), unstyled);
+
+    root.set_state_at(&[1, 0, 1, 0], ElementState::FOCUS);
+    assert_eq!(color(&root, 1), Some(Color { r: 0, g: 255, b: 0, a: 255 }));
+}
+
+#[test]
+fn test_computed_style_for() {
+    use crate::html;
+
+    let root = html::parse("<div id=a><p class=x>a</p><section><p class=x>b</p></section></div>".to_string());
+    let stylesheet = css::parse("html { font-size: 10px; } div { font-size: 2em; } \
+        section p { margin-left: 1.5em; padding-top: 2rem; width: 50%; }".to_string());
+    let styled = style_tree(&root, &stylesheet);
+
+    let p = computed_style_for(&styled, "#a section .x").unwrap().unwrap();
+    assert_eq!(p.margin.left, Value::Length(30.0, css::Unit::Px));
+    // rem is left for layout
+    assert_eq!(p.padding.top, Value::Length(2.0, css::Unit::Rem));
+    assert_eq!(p.width, Value::Length(50.0, css::Unit::Percent));
+    // the first match in document order, with the user agent `margin: 1em 0`
+    assert_eq!(computed_style_for(&styled, "section, .x").unwrap().unwrap().margin.top, Value::Length(20.0, css::Unit::Px));
+    assert!(computed_style_for(&styled, "span").unwrap().is_none());
+    assert!(computed_style_for(&styled, "p {").is_err());
+}
+
+#[test]
+fn test_query_selector() {
+    use crate::html;
+
+    let root = html::parse("<div id=a><p class=x>a</p><section><p class=x>b</p></section></div>".to_string());
+    // html > body > div > section > p
+    assert_eq!(query_selector(&root, "section .x").unwrap(), Some(vec![1, 0, 1, 0]));
+    assert_eq!(query_selector(&root, "span, #a").unwrap(), Some(vec![1, 0]));
+    assert_eq!(query_selector(&root, "span").unwrap(), None);
+    assert!(query_selector(&root, "p {").is_err());
 }
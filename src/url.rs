@@ -0,0 +1,163 @@
+// Relative URL resolution
+// https://www.rfc-editor.org/rfc/rfc3986#section-5.2
+//
+// Only string manipulation, no percent-decoding or host validation. A base
+// without a scheme (a plain file path) resolves like a path-only URL.
+
+#[derive(Clone, Debug, PartialEq)]
+struct Parts<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+fn split(url: &str) -> Parts<'_> {
+    let (rest, fragment) = match url.find('#') {
+        Some(i) => (&url[..i], Some(&url[i + 1..])),
+        None => (url, None),
+    };
+    let (rest, query) = match rest.find('?') {
+        Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+        None => (rest, None),
+    };
+    let (scheme, rest) = match rest.find(':') {
+        Some(i) if is_scheme(&rest[..i], &rest[i + 1..]) => (Some(&rest[..i]), &rest[i + 1..]),
+        _ => (None, rest),
+    };
+    let (authority, path) = if let Some(after) = rest.strip_prefix("//") {
+        let end = after.find('/').unwrap_or(after.len());
+        (Some(&after[..end]), &after[end..])
+    } else {
+        (None, rest)
+    };
+    Parts {
+        scheme: scheme,
+        authority: authority,
+        path: path,
+        query: query,
+        fragment: fragment,
+    }
+}
+
+// a single letter before a slash is a drive, so `C:\dir` stays a path
+fn is_scheme(s: &str, rest: &str) -> bool {
+    let mut chars = s.chars();
+    (s.len() > 1 || !rest.starts_with(['/', '\\']))
+        && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
+pub fn is_absolute(url: &str) -> bool {
+    split(url).scheme.is_some()
+}
+
+pub fn resolve(base: &str, reference: &str) -> String {
+    let base = split(base.trim());
+    let r = split(reference.trim());
+
+    let (scheme, authority, path, query) = if r.scheme.is_some() {
+        (r.scheme, r.authority, remove_dot_segments(r.path), r.query)
+    } else if r.authority.is_some() {
+        (base.scheme, r.authority, remove_dot_segments(r.path), r.query)
+    } else if r.path.is_empty() {
+        (base.scheme, base.authority, base.path.to_string(), r.query.or(base.query))
+    } else if r.path.starts_with('/') {
+        (base.scheme, base.authority, remove_dot_segments(r.path), r.query)
+    } else {
+        let merged = merge(&base, r.path);
+        (base.scheme, base.authority, remove_dot_segments(&merged), r.query)
+    };
+
+    let mut result = String::new();
+    if let Some(scheme) = scheme {
+        result.push_str(scheme);
+        result.push(':');
+    }
+    if let Some(authority) = authority {
+        result.push_str("//");
+        result.push_str(authority);
+    }
+    result.push_str(&path);
+    if let Some(query) = query {
+        result.push('?');
+        result.push_str(query);
+    }
+    if let Some(fragment) = r.fragment {
+        result.push('#');
+        result.push_str(fragment);
+    }
+    result
+}
+
+// https://www.rfc-editor.org/rfc/rfc3986#section-5.2.3
+fn merge(base: &Parts, path: &str) -> String {
+    if base.authority.is_some() && base.path.is_empty() {
+        return format!("/{}", path);
+    }
+    match base.path.rfind('/') {
+        Some(i) => format!("{}{}", &base.path[..i + 1], path),
+        None => path.to_string(),
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc3986#section-5.2.4
+fn remove_dot_segments(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let mut segments: Vec<&str> = Vec::new();
+    let parts: Vec<&str> = path.split('/').collect();
+    for (i, segment) in parts.iter().enumerate() {
+        let last = i == parts.len() - 1;
+        match *segment {
+            "." => if last { segments.push("") },
+            ".." => {
+                // a relative path keeps leading `..`, there is nothing to go up to
+                if segments.last().is_some_and(|s| !s.is_empty() && *s != "..") {
+                    segments.pop();
+                } else if !absolute {
+                    segments.push("..");
+                }
+                if last {
+                    segments.push("");
+                }
+            }
+            "" if i == 0 && absolute => {}
+            s => segments.push(s),
+        }
+    }
+    let joined = segments.join("/");
+    if absolute { format!("/{}", joined) } else { joined }
+}
+
+#[test]
+fn test_resolve() {
+    // https://www.rfc-editor.org/rfc/rfc3986#section-5.4.1
+    let base = "http://a/b/c/d;p?q";
+    let cases = [
+        ("g:h", "g:h"),
+        ("g", "http://a/b/c/g"),
+        ("./g", "http://a/b/c/g"),
+        ("g/", "http://a/b/c/g/"),
+        ("/g", "http://a/g"),
+        ("//g", "http://g"),
+        ("?y", "http://a/b/c/d;p?y"),
+        ("g?y", "http://a/b/c/g?y"),
+        ("#s", "http://a/b/c/d;p?q#s"),
+        ("", "http://a/b/c/d;p?q"),
+        (".", "http://a/b/c/"),
+        ("..", "http://a/b/"),
+        ("../g", "http://a/b/g"),
+        ("../../../g", "http://a/g"),
+        ("g/../h", "http://a/b/c/h"),
+    ];
+    for &(reference, expected) in cases.iter() {
+        assert_eq!(resolve(base, reference), expected, "resolving {:?}", reference);
+    }
+
+    assert_eq!(resolve("example/index.html", "css/a.css"), "example/css/a.css");
+    assert_eq!(resolve("example/index.html", "../img/x.png"), "img/x.png");
+    assert_eq!(resolve("index.html", "../x.png"), "../x.png");
+    assert!(is_absolute("https://example.com/"));
+    assert!(!is_absolute("C:/dir/a.html"));
+}